@@ -52,7 +52,7 @@
   ];
 
   patchPhase = ''
-    substituteInPlace ./src/lib.rs \
+    substituteInPlace ./nsc-core/src/lib.rs \
         --replace "/usr/share/app-info" "${nixos-appstream-data}/share/app-info"
   '';
 
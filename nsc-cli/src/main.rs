@@ -0,0 +1,708 @@
+use anyhow::{anyhow, Result};
+use clap::{self, FromArgMatches, Subcommand};
+use nix_data::config::configfile::NixDataConfig;
+use nsc_core::{
+    appstream,
+    config::{getconfig, FlakeSource},
+    db, flakes, holds, homemanager,
+    search::{ProvideKind, ProvideSource, SearchQuery},
+    BuildResult, Canceller, History, InstallType, InstalledState, Installer, PackageDb, PkgAction,
+    Progress, RebuildMode, SearchIndex, SystemPkgs, Updater, UserPkgs,
+};
+use serde::Serialize;
+use std::{cell::RefCell, collections::HashMap};
+
+#[derive(Subcommand, Debug)]
+enum SubCommands {
+    Search {
+        /// Search terms and filters like license:mit, maintainer:name, installed:, category:games,
+        /// platform:aarch64-linux, free:false or has:gui
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    Info {
+        /// Package attribute
+        package: String,
+    },
+    Provides {
+        /// Command, library or MIME type, like rg, libpng16.so or application/pdf
+        name: String,
+    },
+    Install {
+        /// Package attribute
+        package: String,
+        /// Add the package to the system configuration instead of the user profile
+        #[arg(short, long)]
+        system: bool,
+        /// Only run `nixos-rebuild` with dry-build, build or test, keeping the current configuration
+        #[arg(short, long, requires = "system")]
+        mode: Option<RebuildMode>,
+    },
+    Remove {
+        /// Package attribute
+        package: String,
+        /// Remove the package from the system configuration instead of the user profile
+        #[arg(short, long)]
+        system: bool,
+        /// Only run `nixos-rebuild` with dry-build, build or test, keeping the current configuration
+        #[arg(short, long, requires = "system")]
+        mode: Option<RebuildMode>,
+    },
+    Update {
+        /// Only update the system
+        #[arg(short, long)]
+        system: bool,
+        /// Only update user packages
+        #[arg(short, long)]
+        user: bool,
+        /// Only bump these inputs of the system flake
+        #[arg(short, long)]
+        input: Vec<String>,
+        /// Only update these user packages
+        packages: Vec<String>,
+    },
+    List {
+        /// Only list system packages
+        #[arg(short, long)]
+        system: bool,
+        /// Only list user packages
+        #[arg(short, long)]
+        user: bool,
+    },
+    History {
+        /// Include configuration diffs
+        #[arg(short, long)]
+        diff: bool,
+    },
+    Rollback {
+        /// Transaction id, defaults to the latest system transaction
+        id: Option<u64>,
+        /// Roll back even if later transactions or edits changed the configuration, undoing them too
+        #[arg(short, long)]
+        force: bool,
+    },
+    Hold {
+        /// Package attribute, or the package name with nix-env. Lists held packages when left out
+        package: Option<String>,
+    },
+    Unhold {
+        /// Package attribute, or the package name with nix-env
+        package: String,
+    },
+    Source {
+        #[command(subcommand)]
+        action: SourceCommands,
+    },
+    Appstream {
+        #[command(subcommand)]
+        action: AppstreamCommands,
+    },
+    HomeManager {
+        #[command(subcommand)]
+        action: HomeManagerCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SourceCommands {
+    List,
+    Add {
+        /// Name shown next to its packages, which are called <name>#<attribute>
+        name: String,
+        /// Flake reference, like github:owner/repo
+        url: String,
+    },
+    Remove {
+        /// Source name
+        name: String,
+    },
+    Refresh,
+}
+
+#[derive(Subcommand, Debug)]
+enum AppstreamCommands {
+    /// Show where AppStream data was found and how much of it is used
+    List,
+    Set {
+        /// An app-info or swcatalog directory, or a collection file, used before any found on the system
+        path: String,
+    },
+    Unset,
+}
+
+#[derive(Subcommand, Debug)]
+enum HomeManagerCommands {
+    /// Show whether user packages are installed to home.nix
+    Status,
+    /// Install user packages to home.nix instead of the user profile, when home-manager is set up
+    Enable,
+    /// Install user packages to the user profile again
+    Disable,
+}
+
+#[derive(Serialize)]
+struct SearchItem {
+    attribute: String,
+    pname: String,
+    name: String,
+    description: String,
+    version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    installeduser: bool,
+    installedsystem: bool,
+}
+
+#[derive(Serialize)]
+struct ProvidesItem {
+    attribute: String,
+    pname: String,
+    name: String,
+    description: String,
+    kind: ProvideKind,
+    source: ProvideSource,
+    installeduser: bool,
+    installedsystem: bool,
+}
+
+#[derive(Serialize)]
+struct InfoItem {
+    name: String,
+    summary: Option<String>,
+    developer: Option<String>,
+    agerating: Option<u32>,
+    installeduser: bool,
+    installedsystem: bool,
+    #[serde(flatten)]
+    details: db::PkgDetails,
+}
+
+#[derive(Serialize)]
+struct ActionResult {
+    package: String,
+    action: String,
+    pkgtype: String,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    cancelled: bool,
+}
+
+#[derive(Serialize)]
+struct UpdateResult {
+    system: Option<bool>,
+    user: Option<bool>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    cancelled: bool,
+}
+
+#[derive(Serialize)]
+struct ListResult {
+    system: Option<Vec<String>>,
+    user: Option<HashMap<String, String>>,
+}
+
+#[derive(Serialize)]
+struct RollbackResult {
+    id: u64,
+    success: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    cancelled: bool,
+}
+
+#[derive(Serialize)]
+struct HoldItem {
+    package: String,
+    version: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HomeManagerItem {
+    enabled: bool,
+    installed: bool,
+    homeconfig: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SourceItem {
+    name: String,
+    url: String,
+    packages: usize,
+}
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+    let cli = SubCommands::augment_subcommands(
+        clap::Command::new("nsc").about("Command-line interface for Nix Software Center"),
+    );
+    let matches = cli.get_matches();
+    let derived_subcommands = SubCommands::from_arg_matches(&matches)
+        .map_err(|err| err.exit())
+        .unwrap();
+
+    match run(derived_subcommands).await {
+        Ok((out, success)) => {
+            println!("{}", out);
+            if !success {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            println!("{}", serde_json::json!({ "error": e.to_string() }));
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run(cmd: SubCommands) -> Result<(String, bool)> {
+    let config = getconfig().unwrap_or(NixDataConfig {
+        systemconfig: None,
+        flake: None,
+        flakearg: None,
+        generations: None,
+    });
+    let syspkgtype = nsc_core::syspkgtype(&config);
+    let userpkgtype = nsc_core::userpkgtype();
+    let nixsystem = nsc_core::currentsystem();
+
+    let mut success = true;
+    let out = match cmd {
+        SubCommands::Search { query } => {
+            let appstream = appstream::load(&nixsystem);
+            let appdata = appstream.apps;
+            let index = SearchIndex::open(&db::pkgdb().await?, &appdata, &appstream.key).await?;
+            let installed =
+                InstalledState::load(&config.systemconfig, &syspkgtype, &userpkgtype).await;
+            let results = index
+                .search(
+                    &SearchQuery::parse(&query.join(" ")),
+                    &installed,
+                    &userpkgtype,
+                )
+                .await?;
+            let items = results
+                .into_iter()
+                .map(|r| SearchItem {
+                    name: db::displayname(&r.attribute, &r.pname, &appdata),
+                    installeduser: installed.userinstalled(&r.attribute, &r.pname, &userpkgtype),
+                    installedsystem: installed.systeminstalled(&r.attribute),
+                    attribute: r.attribute,
+                    pname: r.pname,
+                    description: r.description,
+                    version: r.version,
+                    source: r.source,
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_string_pretty(&items)?
+        }
+        SubCommands::Provides { name } => {
+            let pkgdb = PackageDb::open().await?;
+            let appstream = appstream::load(&nixsystem);
+            let appdata = appstream.apps;
+            let index = SearchIndex::open(&db::pkgdb().await?, &appdata, &appstream.key).await?;
+            let installed =
+                InstalledState::load(&config.systemconfig, &syspkgtype, &userpkgtype).await;
+            let mut items = vec![];
+            for provider in index.provides(&name).await? {
+                // nix-index also knows about packages that aren't in this nixpkgs
+                let details = match pkgdb.details(&provider.attribute).await {
+                    Ok(details) => details,
+                    Err(_) => continue,
+                };
+                items.push(ProvidesItem {
+                    name: db::displayname(&details.attribute, &details.pname, &appdata),
+                    installeduser: installed.userinstalled(
+                        &details.attribute,
+                        &details.pname,
+                        &userpkgtype,
+                    ),
+                    installedsystem: installed.systeminstalled(&details.attribute),
+                    attribute: details.attribute,
+                    pname: details.pname,
+                    description: details.description,
+                    kind: provider.kind,
+                    source: provider.source,
+                });
+            }
+            success = !items.is_empty();
+            serde_json::to_string_pretty(&items)?
+        }
+        SubCommands::Info { package } => {
+            let pkgdb = PackageDb::open().await?;
+            let appdata = appstream::load(&nixsystem).apps;
+            let details = pkgdb
+                .details(&package)
+                .await
+                .map_err(|_| anyhow!("Package {} not found", package))?;
+            let installed =
+                InstalledState::load(&config.systemconfig, &syspkgtype, &userpkgtype).await;
+            let data = appdata.get(&package);
+            let summary = data
+                .and_then(|x| x.localsummary())
+                .map(|x| x.to_string())
+                .or_else(|| {
+                    if details.description.is_empty() {
+                        None
+                    } else {
+                        Some(details.description.to_string())
+                    }
+                });
+            let item = InfoItem {
+                name: db::displayname(&details.attribute, &details.pname, &appdata),
+                summary,
+                developer: data.and_then(|x| x.localdeveloper()).map(|x| x.to_string()),
+                agerating: data.and_then(|x| x.agerating()),
+                installeduser: installed.userinstalled(
+                    &details.attribute,
+                    &details.pname,
+                    &userpkgtype,
+                ),
+                installedsystem: installed.systeminstalled(&details.attribute),
+                details,
+            };
+            serde_json::to_string_pretty(&item)?
+        }
+        SubCommands::Install {
+            package,
+            system,
+            mode,
+        } => {
+            let pkgtype = installtype(system, &syspkgtype)?;
+            let installer = Installer::new(config, userpkgtype, nixsystem);
+            let canceller = cancelonctrlc(installer.canceller());
+            let result =
+                match installpkg(&installer, &package, PkgAction::Install, &pkgtype, mode).await {
+                    Err(_) if canceller.cancelled() => BuildResult::Failed(None),
+                    result => result?,
+                };
+            success = result == BuildResult::Success;
+            serde_json::to_string_pretty(&ActionResult {
+                package,
+                action: actionname("install", mode),
+                pkgtype: pkgtypename(&pkgtype),
+                success,
+                failed: match result {
+                    BuildResult::Failed(drv) => drv,
+                    BuildResult::Success => None,
+                },
+                cancelled: canceller.cancelled(),
+            })?
+        }
+        SubCommands::Remove {
+            package,
+            system,
+            mode,
+        } => {
+            let pkgtype = installtype(system, &syspkgtype)?;
+            let installer = Installer::new(config, userpkgtype, nixsystem);
+            let canceller = cancelonctrlc(installer.canceller());
+            let result =
+                match installpkg(&installer, &package, PkgAction::Remove, &pkgtype, mode).await {
+                    Err(_) if canceller.cancelled() => BuildResult::Failed(None),
+                    result => result?,
+                };
+            success = result == BuildResult::Success;
+            serde_json::to_string_pretty(&ActionResult {
+                package,
+                action: actionname("remove", mode),
+                pkgtype: pkgtypename(&pkgtype),
+                success,
+                failed: match result {
+                    BuildResult::Failed(drv) => drv,
+                    BuildResult::Success => None,
+                },
+                cancelled: canceller.cancelled(),
+            })?
+        }
+        SubCommands::Update {
+            system,
+            user,
+            input,
+            packages,
+        } => {
+            let system = system || !input.is_empty();
+            let user = user || !packages.is_empty();
+            let both = !system && !user;
+            if system && syspkgtype == SystemPkgs::None {
+                return Err(anyhow!("No system configuration found"));
+            }
+            let updater = Updater::new(config, syspkgtype.clone(), userpkgtype, nixsystem);
+            let canceller = cancelonctrlc(updater.canceller());
+            let system = if (system || both) && syspkgtype != SystemPkgs::None {
+                let result = if input.is_empty() {
+                    updater.updatesystem(None, logger()).await
+                } else {
+                    updater.updatesysteminputs(input, logger()).await
+                };
+                Some(uncancelled(result, &canceller)?)
+            } else {
+                None
+            };
+            let user = if (user || both) && !canceller.cancelled() {
+                let result = if packages.is_empty() {
+                    updater.updateuser(None, logger()).await
+                } else {
+                    updater.updateuserpkgs(packages, logger()).await
+                };
+                Some(uncancelled(result, &canceller)?)
+            } else {
+                None
+            };
+            success = system != Some(false) && user != Some(false);
+            serde_json::to_string_pretty(&UpdateResult {
+                system,
+                user,
+                cancelled: canceller.cancelled(),
+            })?
+        }
+        SubCommands::List { system, user } => {
+            let both = !system && !user;
+            let installed =
+                InstalledState::load(&config.systemconfig, &syspkgtype, &userpkgtype).await;
+            let system = if system || both {
+                let mut pkgs = installed.system.into_iter().collect::<Vec<_>>();
+                pkgs.sort();
+                Some(pkgs)
+            } else {
+                None
+            };
+            let user = if user || both {
+                Some(installed.user)
+            } else {
+                None
+            };
+            serde_json::to_string_pretty(&ListResult { system, user })?
+        }
+        SubCommands::Hold { package } => {
+            if let Some(package) = package {
+                if userpkgtype == UserPkgs::HomeManager {
+                    return Err(anyhow!("home-manager packages can't be held"));
+                }
+                if !InstalledState::load(&config.systemconfig, &syspkgtype, &userpkgtype)
+                    .await
+                    .user
+                    .contains_key(&package)
+                {
+                    return Err(anyhow!("Package {} is not installed", package));
+                }
+                holds::sethold(&package, true)?;
+            }
+            serde_json::to_string_pretty(&helditems(&config, &syspkgtype, &userpkgtype).await)?
+        }
+        SubCommands::Unhold { package } => {
+            if !holds::isheld(&package) {
+                return Err(anyhow!("Package {} is not held", package));
+            }
+            holds::sethold(&package, false)?;
+            serde_json::to_string_pretty(&helditems(&config, &syspkgtype, &userpkgtype).await)?
+        }
+        SubCommands::History { diff } => {
+            let history = History::load()?;
+            let items = history
+                .entries()
+                .iter()
+                .map(|x| {
+                    let mut entry = x.clone();
+                    entry.oldconfig = None;
+                    entry.newconfig = None;
+                    if !diff {
+                        entry.diff = None;
+                    }
+                    entry
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_string_pretty(&items)?
+        }
+        SubCommands::Rollback { id, force } => {
+            if syspkgtype == SystemPkgs::None {
+                return Err(anyhow!("No system configuration found"));
+            }
+            let history = History::load()?;
+            let entry = match id {
+                Some(id) => history
+                    .get(id)
+                    .ok_or_else(|| anyhow!("Transaction {} not found", id))?,
+                None => history
+                    .latestrollback()
+                    .ok_or_else(|| anyhow!("No system transaction to roll back"))?,
+            };
+            let installer = Installer::new(config, userpkgtype, nixsystem);
+            // The whole configuration from before the transaction is written back
+            eprint!("{}", installer.previewrollback(entry)?);
+            if !force {
+                if let Some(conflict) = installer.checkrollback(entry)? {
+                    return Err(anyhow!("{}. Use --force to roll back anyway", conflict));
+                }
+            }
+            let canceller = cancelonctrlc(installer.canceller());
+            success = uncancelled(installer.rollback(entry, force, logger()).await, &canceller)?;
+            serde_json::to_string_pretty(&RollbackResult {
+                id: entry.id,
+                success,
+                cancelled: canceller.cancelled(),
+            })?
+        }
+        SubCommands::Source { action } => {
+            match action {
+                SourceCommands::List => {}
+                SourceCommands::Add { name, url } => {
+                    flakes::add(FlakeSource { name, url }, &nixsystem).await?;
+                }
+                SourceCommands::Remove { name } => flakes::remove(&name)?,
+                SourceCommands::Refresh => flakes::refresh(&nixsystem, true).await,
+            }
+            let items = flakes::sources()
+                .into_iter()
+                .map(|source| SourceItem {
+                    packages: flakes::cached(&source.name).len(),
+                    name: source.name,
+                    url: source.url,
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_string_pretty(&items)?
+        }
+        SubCommands::Appstream { action } => {
+            match action {
+                AppstreamCommands::List => {}
+                AppstreamCommands::Set { path } => {
+                    let path = std::fs::canonicalize(&path)
+                        .map_err(|e| anyhow!("Can't use {}: {}", path, e))?;
+                    appstream::setconfigured(Some(path.to_string_lossy().to_string()))?;
+                }
+                AppstreamCommands::Unset => appstream::setconfigured(None)?,
+            }
+            serde_json::to_string_pretty(&appstream::load(&nixsystem).sources)?
+        }
+        SubCommands::HomeManager { action } => {
+            match action {
+                HomeManagerCommands::Status => {}
+                HomeManagerCommands::Enable => {
+                    if !homemanager::homemanagerinstalled() {
+                        return Err(anyhow!("No home-manager configuration found"));
+                    }
+                    homemanager::setenabled(true)?;
+                }
+                HomeManagerCommands::Disable => homemanager::setenabled(false)?,
+            }
+            serde_json::to_string_pretty(&HomeManagerItem {
+                enabled: homemanager::enabled(),
+                installed: homemanager::homemanagerinstalled(),
+                homeconfig: homemanager::homeconfig(),
+            })?
+        }
+    };
+    Ok((out, success))
+}
+
+async fn helditems(
+    config: &NixDataConfig,
+    syspkgtype: &SystemPkgs,
+    userpkgtype: &UserPkgs,
+) -> Vec<HoldItem> {
+    let installed = InstalledState::load(&config.systemconfig, syspkgtype, userpkgtype).await;
+    let mut held = holds::held().into_iter().collect::<Vec<_>>();
+    held.sort();
+    held.into_iter()
+        .map(|package| HoldItem {
+            version: installed.user.get(&package).cloned(),
+            package,
+        })
+        .collect()
+}
+
+fn installtype(system: bool, syspkgtype: &SystemPkgs) -> Result<InstallType> {
+    if system {
+        if syspkgtype == &SystemPkgs::None {
+            return Err(anyhow!("No system configuration found"));
+        }
+        Ok(InstallType::System)
+    } else {
+        Ok(InstallType::User)
+    }
+}
+
+async fn installpkg(
+    installer: &Installer,
+    pkg: &str,
+    action: PkgAction,
+    pkgtype: &InstallType,
+    mode: Option<RebuildMode>,
+) -> Result<BuildResult> {
+    let pkgdb = PackageDb::open().await?;
+    let pname = pkgdb
+        .pname(pkg)
+        .await
+        .map_err(|_| anyhow!("Package {} not found", pkg))?;
+    let work = [(pkg.to_string(), action.clone())];
+    if pkgtype == &InstallType::System {
+        eprintln!("{}", installer.previewsys(&work)?);
+    }
+    match mode {
+        Some(mode) if mode != RebuildMode::Switch => installer.testsys(&work, mode, logger()).await,
+        _ => {
+            if installer
+                .process(pkg, &pname, &action, pkgtype, logger())
+                .await?
+            {
+                Ok(BuildResult::Success)
+            } else {
+                Ok(BuildResult::Failed(None))
+            }
+        }
+    }
+}
+
+fn actionname(action: &str, mode: Option<RebuildMode>) -> String {
+    match mode {
+        Some(mode) if mode != RebuildMode::Switch => format!("{} ({})", action, mode.arg()),
+        _ => action.to_string(),
+    }
+}
+
+fn pkgtypename(pkgtype: &InstallType) -> String {
+    match pkgtype {
+        InstallType::User => String::from("user"),
+        InstallType::System => String::from("system"),
+    }
+}
+
+// Ctrl-C doesn't reach commands running in their own process group, so cancel them instead
+fn cancelonctrlc(canceller: Canceller) -> Canceller {
+    let c = canceller.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Cancelling...");
+            if let Err(e) = c.cancel().await {
+                eprintln!("{}", e);
+            }
+        }
+    });
+    canceller
+}
+
+// Cancelled commands may stop with an error, report them as unsuccessful instead
+fn uncancelled(result: Result<bool>, canceller: &Canceller) -> Result<bool> {
+    match result {
+        Err(_) if canceller.cancelled() => Ok(false),
+        result => result,
+    }
+}
+
+// Prints nix output as plain text, with the build progress from `--log-format internal-json`
+fn logger() -> impl Fn(String) {
+    let progress = RefCell::new(Progress::default());
+    let status = RefCell::new(None);
+    move |line| {
+        let mut progress = progress.borrow_mut();
+        if let Some(text) = progress.parse(&line) {
+            eprintln!("{}", text);
+        }
+        let newstatus = progress.status();
+        if newstatus.is_some() && *status.borrow() != newstatus {
+            eprintln!("[{}]", newstatus.as_deref().unwrap_or_default());
+            *status.borrow_mut() = newstatus;
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use super::{
+    config::{editnscconfig, getnscconfig},
+    packages::AppData,
+    APPINFO,
+};
+use anyhow::Result;
+use flate2::bufread::GzDecoder;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Lines},
+    path::{Path, PathBuf},
+};
+
+// Bumped whenever AppData or AppStreamSource change, so indexes written by older versions are rebuilt
+static INDEXVERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    // Set in config.json
+    Config,
+    // share/ of a nix profile
+    Profile,
+    // XDG_DATA_HOME and XDG_DATA_DIRS
+    Xdg,
+    // /usr/share/app-info
+    Default,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceState {
+    // used counts the apps that no source before this one had
+    Loaded {
+        apps: usize,
+        used: usize,
+        failed: usize,
+    },
+    // No collection for this system
+    Missing,
+    // The same collection as a source before it, like a profile that is also in XDG_DATA_DIRS
+    Duplicate,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppStreamSource {
+    pub kind: SourceKind,
+    // An app-info or swcatalog directory, or a collection file when configured as one
+    pub path: String,
+    pub file: Option<String>,
+    pub state: SourceState,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct AppStream {
+    pub apps: HashMap<String, AppData>,
+    // Every source that has data or was configured, in order of precedence
+    pub sources: Vec<AppStreamSource>,
+    // Hash of the index key, which changes whenever a source or one of its collections does
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AppStreamIndex<'a> {
+    version: u32,
+    // The sources and hashes of the collections the index was built from
+    key: String,
+    sources: Cow<'a, [AppStreamSource]>,
+    apps: Cow<'a, HashMap<String, AppData>>,
+}
+
+#[derive(Deserialize)]
+struct CollectionHeader {
+    #[serde(rename = "Origin")]
+    origin: Option<String>,
+}
+
+pub fn configured() -> Option<String> {
+    getnscconfig().appstream
+}
+
+pub fn setconfigured(path: Option<String>) -> Result<()> {
+    let mut config = getnscconfig();
+    config.appstream = path.filter(|x| !x.is_empty());
+    editnscconfig(&config)
+}
+
+// Directories that may have AppStream data, in order of precedence
+fn roots() -> Vec<(SourceKind, PathBuf)> {
+    let mut out = vec![];
+    if let Some(path) = configured() {
+        out.push((SourceKind::Config, PathBuf::from(path)));
+    }
+
+    let home = env::var("HOME").ok();
+    let mut profiles = vec![];
+    if let Some(home) = &home {
+        profiles.push(format!("{}/.nix-profile", home));
+        profiles.push(format!("{}/.local/state/nix/profile", home));
+    }
+    if let Ok(user) = env::var("USER") {
+        profiles.push(format!("/etc/profiles/per-user/{}", user));
+    }
+    profiles.push(String::from("/nix/var/nix/profiles/default"));
+    profiles.push(String::from("/run/current-system/sw"));
+
+    let datahome = env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .or_else(|| home.map(|x| format!("{}/.local/share", x)));
+    let datadirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| String::from("/usr/local/share:/usr/share"));
+
+    let shares = profiles
+        .into_iter()
+        .map(|x| (SourceKind::Profile, PathBuf::from(x).join("share")))
+        .chain(
+            datahome
+                .into_iter()
+                .chain(datadirs.split(':').filter(|x| !x.is_empty()).map(String::from))
+                .map(|x| (SourceKind::Xdg, PathBuf::from(x))),
+        );
+    for (kind, share) in shares {
+        // swcatalog replaced app-info, so it is preferred when a directory has both
+        for dir in ["swcatalog", "app-info"] {
+            out.push((kind, share.join(dir)));
+        }
+    }
+    out.push((SourceKind::Default, PathBuf::from(APPINFO)));
+
+    let mut seen = HashSet::new();
+    out.retain(|(_, path)| seen.insert(path.clone()));
+    out
+}
+
+// The DEP-11 collection for the system, named and placed like nixos-appstream-data does
+fn collection(root: &Path, system: &str) -> Option<PathBuf> {
+    if root.is_file() {
+        return Some(root.to_path_buf());
+    }
+    let name = format!("nixos_{}.yml.gz", system.replace('-', "_"));
+    ["xmls", "yaml"]
+        .iter()
+        .map(|x| root.join(x).join(&name))
+        .find(|x| x.is_file())
+}
+
+// Yields the documents of a multi-document YAML stream one at a time, so the file is never read into memory as a whole
+struct Documents<R> {
+    lines: Lines<R>,
+}
+
+impl<R: BufRead> Iterator for Documents<R> {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut doc = String::new();
+        loop {
+            match self.lines.next() {
+                Some(Ok(line)) if line == "---" => {
+                    if !doc.is_empty() {
+                        return Some(Ok(doc));
+                    }
+                }
+                Some(Ok(line)) => {
+                    doc.push_str(&line);
+                    doc.push('\n');
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => return if doc.is_empty() { None } else { Some(Ok(doc)) },
+            }
+        }
+    }
+}
+
+// Returns the apps and how many documents couldn't be parsed
+fn parsecollection(path: &Path) -> Result<(Vec<AppData>, usize)> {
+    let reader = BufReader::new(GzDecoder::new(BufReader::new(File::open(path)?)));
+    // Cached icons are kept in <root>/icons/<origin>, next to the directory of the collection
+    let root = path
+        .parent()
+        .and_then(|x| x.parent())
+        .unwrap_or_else(|| Path::new("/"));
+    let mut origin = String::from("nixos");
+    let mut out = vec![];
+    let mut failed = 0;
+    for doc in (Documents { lines: reader.lines() }) {
+        let doc = doc?;
+        if doc.starts_with("File:") {
+            if let Some(o) = serde_yaml::from_str::<CollectionHeader>(&doc)
+                .ok()
+                .and_then(|x| x.origin)
+            {
+                origin = o;
+            }
+            continue;
+        }
+        match serde_yaml::from_str::<AppData>(&doc) {
+            Ok(mut appstream) => {
+                appstream.icondir = Some(
+                    root.join("icons")
+                        .join(&origin)
+                        .to_string_lossy()
+                        .to_string(),
+                );
+                out.push(appstream);
+            }
+            Err(_) => failed += 1,
+        }
+    }
+    if failed > 0 {
+        warn!(
+            "Failed to parse {} appstream documents in {}",
+            failed,
+            path.display()
+        );
+    }
+    Ok((out, failed))
+}
+
+fn indexpath(system: &str) -> Result<String> {
+    let home = env::var("HOME")?;
+    Ok(format!(
+        "{}/.cache/nix-software-center/appstream-{}.json",
+        home, system
+    ))
+}
+
+fn loadindex(path: &str, key: &str) -> Option<AppStream> {
+    let file = File::open(path).ok()?;
+    let index: AppStreamIndex = serde_json::from_reader(BufReader::new(file)).ok()?;
+    if index.version == INDEXVERSION && index.key == key {
+        Some(AppStream {
+            apps: index.apps.into_owned(),
+            sources: index.sources.into_owned(),
+            key: sha256::digest(key),
+        })
+    } else {
+        None
+    }
+}
+
+fn saveindex(path: &str, key: &str, appstream: &AppStream) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let index = AppStreamIndex {
+        version: INDEXVERSION,
+        key: key.to_string(),
+        sources: Cow::Borrowed(&appstream.sources),
+        apps: Cow::Borrowed(&appstream.apps),
+    };
+    // A unique file renamed into place, so the app and nsc never read a half written index or write the same one
+    let dir = Path::new(path)
+        .parent()
+        .filter(|x| !x.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut writer = BufWriter::new(tempfile::NamedTempFile::new_in(dir)?);
+    serde_json::to_writer(&mut writer, &index)?;
+    writer.into_inner()?.persist(path)?;
+    Ok(())
+}
+
+// Merges the collections of all sources, where an app is taken from the first source that has it.
+// Parsing the YAML takes a while, so the result is indexed on disk until a source changes
+pub fn load(system: &str) -> AppStream {
+    let mut sources = vec![];
+    // Indexes into sources with the collection to read for them
+    let mut collections = vec![];
+    let mut key = vec![];
+    let mut seen = HashSet::new();
+    for (kind, root) in roots() {
+        let path = root.to_string_lossy().to_string();
+        let file = match collection(&root, system) {
+            Some(file) => file,
+            None => {
+                // Directories that aren't there at all would only be noise
+                if kind == SourceKind::Config || root.is_dir() {
+                    key.push(format!("{}:missing", path));
+                    sources.push(AppStreamSource {
+                        kind,
+                        path,
+                        file: None,
+                        state: SourceState::Missing,
+                    });
+                }
+                continue;
+            }
+        };
+        let state = if !seen.insert(fs::canonicalize(&file).unwrap_or_else(|_| file.clone())) {
+            key.push(format!("{}:duplicate", path));
+            SourceState::Duplicate
+        } else {
+            match sha256::try_digest(file.as_path()) {
+                Ok(hash) => {
+                    key.push(format!("{}:{}:{}", path, file.display(), hash));
+                    collections.push((sources.len(), file.clone()));
+                    // Filled in once the collection is parsed
+                    SourceState::Missing
+                }
+                Err(e) => {
+                    key.push(format!("{}:failed", path));
+                    SourceState::Failed(e.to_string())
+                }
+            }
+        };
+        sources.push(AppStreamSource {
+            kind,
+            path,
+            file: Some(file.to_string_lossy().to_string()),
+            state,
+        });
+    }
+    let key = key.join("\n");
+
+    let index = indexpath(system);
+    if let Some(appstream) = index.as_ref().ok().and_then(|x| loadindex(x, &key)) {
+        return appstream;
+    }
+
+    let mut apps = HashMap::new();
+    for (i, file) in collections {
+        info!("Indexing appstream data from {}", file.display());
+        sources[i].state = match parsecollection(&file) {
+            Ok((data, failed)) => {
+                let total = data.len();
+                let mut used = 0;
+                for appstream in data {
+                    if !apps.contains_key(&appstream.package) {
+                        used += 1;
+                        apps.insert(appstream.package.to_string(), appstream);
+                    }
+                }
+                SourceState::Loaded {
+                    apps: total,
+                    used,
+                    failed,
+                }
+            }
+            Err(e) => {
+                warn!("Failed to read appstream data from {}: {}", file.display(), e);
+                SourceState::Failed(e.to_string())
+            }
+        };
+    }
+    if apps.is_empty() {
+        warn!("No appstream data found for {}", system);
+    }
+
+    let appstream = AppStream {
+        apps,
+        sources,
+        key: sha256::digest(key.as_str()),
+    };
+    if let Err(e) = index.and_then(|x| saveindex(&x, &key, &appstream)) {
+        warn!("Failed to save appstream index: {}", e);
+    }
+    appstream
+}
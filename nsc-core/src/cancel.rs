@@ -0,0 +1,108 @@
+use super::helper;
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashSet,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::process::{Child, Command};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum HelperJob {
+    Daemon(u32),
+    Pkexec,
+}
+
+#[derive(Debug, Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    groups: Mutex<HashSet<i32>>,
+    helper: Mutex<Option<HelperJob>>,
+}
+
+// Shared by everything an Installer or Updater runs, so one cancel stops all of it
+#[derive(Debug, Clone, Default)]
+pub struct Canceller(Arc<CancelState>);
+
+impl Canceller {
+    pub fn cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub async fn cancel(&self) -> Result<()> {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        for pgid in self.0.groups.lock().unwrap().iter() {
+            killgroup(*pgid);
+        }
+        let job = *self.0.helper.lock().unwrap();
+        match job {
+            Some(HelperJob::Daemon(id)) => helper::canceljob(id).await,
+            Some(HelperJob::Pkexec) => helper::cancelpkexec().await,
+            None => Ok(()),
+        }
+    }
+
+    // Runs cmd as its own process group, so cancelling also stops everything it starts
+    pub(crate) fn spawn(&self, cmd: &mut Command) -> Result<Process> {
+        if self.cancelled() {
+            return Err(anyhow!("Cancelled"));
+        }
+        let child = cmd.process_group(0).kill_on_drop(true).spawn()?;
+        let pgid = child.id().map(|x| x as i32);
+        if let Some(pgid) = pgid {
+            self.0.groups.lock().unwrap().insert(pgid);
+            if self.cancelled() {
+                killgroup(pgid);
+            }
+        }
+        Ok(Process {
+            child,
+            pgid,
+            canceller: self.clone(),
+        })
+    }
+
+    pub(crate) fn sethelper(&self, job: Option<HelperJob>) {
+        *self.0.helper.lock().unwrap() = job;
+    }
+}
+
+pub(crate) struct Process {
+    child: Child,
+    pgid: Option<i32>,
+    canceller: Canceller,
+}
+
+impl Deref for Process {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.child
+    }
+}
+
+impl DerefMut for Process {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.pgid {
+            self.canceller.0.groups.lock().unwrap().remove(&pgid);
+            if let Ok(None) = self.child.try_wait() {
+                killgroup(pgid);
+            }
+        }
+    }
+}
+
+fn killgroup(pgid: i32) {
+    unsafe {
+        libc::kill(-pgid, libc::SIGTERM);
+    }
+}
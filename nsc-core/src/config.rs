@@ -0,0 +1,64 @@
+use super::writeatomic;
+use anyhow::Result;
+use nix_data::config::configfile::NixDataConfig;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NscConfig {
+    #[serde(default)]
+    pub homeconfig: Option<String>,
+    #[serde(default)]
+    pub homeflake: Option<String>,
+    // User packages go to home.nix only when this is chosen, even if home-manager is set up
+    #[serde(default)]
+    pub homemanager: bool,
+    #[serde(default)]
+    pub flakes: Vec<FlakeSource>,
+    // User packages left out of updates, by attribute or for nix-env by pname
+    #[serde(default)]
+    pub holds: Vec<String>,
+    // AppStream data used before any found on the system, an app-info or swcatalog directory or a collection file
+    #[serde(default)]
+    pub appstream: Option<String>,
+}
+
+// A flake whose packages are offered next to nixpkgs
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlakeSource {
+    pub name: String,
+    pub url: String,
+}
+
+pub fn getconfig() -> Option<NixDataConfig> {
+    nix_data::config::configfile::getconfig().ok()
+}
+
+pub fn editconfig(config: NixDataConfig) -> Result<()> {
+    nix_data::config::configfile::setuserconfig(config)?;
+    Ok(())
+}
+
+fn nscconfigpath() -> Result<PathBuf> {
+    let home = std::env::var("HOME")?;
+    Ok(PathBuf::from(format!(
+        "{}/.config/nix-software-center/config.json",
+        home
+    )))
+}
+
+pub fn getnscconfig() -> NscConfig {
+    nscconfigpath()
+        .ok()
+        .and_then(|x| fs::read_to_string(x).ok())
+        .and_then(|x| serde_json::from_str(&x).ok())
+        .unwrap_or_default()
+}
+
+pub fn editnscconfig(config: &NscConfig) -> Result<()> {
+    let path = nscconfigpath()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    writeatomic(&path, serde_json::to_string_pretty(config)?.as_bytes())
+}
@@ -0,0 +1,139 @@
+use crate::{flakes, packages::AppData};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::{collections::HashMap, path::Path};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub attribute: String,
+    pub pname: String,
+    pub description: String,
+    pub version: String,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PkgDetails {
+    pub attribute: String,
+    pub pname: String,
+    pub version: String,
+    pub system: String,
+    pub description: String,
+    pub longdescription: String,
+    pub homepage: String,
+    pub license: String,
+    pub platforms: String,
+    pub maintainers: String,
+}
+
+pub async fn pkgdb() -> Result<String> {
+    if Path::new("/etc/NIXOS").exists() {
+        Ok(nix_data::cache::nixos::nixospkgs().await?)
+    } else {
+        Ok(nix_data::cache::nonnixos::nixpkgs().await?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageDb {
+    pool: SqlitePool,
+}
+
+impl PackageDb {
+    pub async fn open() -> Result<Self> {
+        Self::connect(&pkgdb().await?).await
+    }
+
+    pub async fn connect(pkgdb: &str) -> Result<Self> {
+        Ok(Self {
+            pool: SqlitePool::connect(&format!("sqlite://{}", pkgdb)).await?,
+        })
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    pub async fn details(&self, pkg: &str) -> Result<PkgDetails> {
+        if flakes::source(pkg).is_some() {
+            return flakedetails(pkg);
+        }
+        let (
+            pname,
+            version,
+            system,
+            description,
+            longdescription,
+            homepage,
+            license,
+            platforms,
+            maintainers,
+        ): (
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+        ) = sqlx::query_as(
+            r#"
+SELECT pname, version, system, description, longdescription, homepage, license, platforms, maintainers
+FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute = $1
+            "#,
+        )
+        .bind(pkg)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(PkgDetails {
+            attribute: pkg.to_string(),
+            pname,
+            version,
+            system,
+            description,
+            longdescription,
+            homepage,
+            license,
+            platforms,
+            maintainers,
+        })
+    }
+
+    pub async fn pname(&self, pkg: &str) -> Result<String> {
+        if flakes::source(pkg).is_some() {
+            return Ok(flakedetails(pkg)?.pname);
+        }
+        let (pname,): (String,) = sqlx::query_as("SELECT pname FROM pkgs WHERE attribute = $1")
+            .bind(pkg)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(pname)
+    }
+}
+
+fn flakedetails(pkg: &str) -> Result<PkgDetails> {
+    let details = flakes::details(pkg).ok_or_else(|| anyhow!("Package {} not found", pkg))?;
+    Ok(PkgDetails {
+        attribute: pkg.to_string(),
+        pname: details.pname,
+        version: details.version,
+        system: String::new(),
+        description: details.description,
+        longdescription: details.longdescription,
+        homepage: details.homepage,
+        license: details.license.to_string(),
+        platforms: details.platforms.to_string(),
+        maintainers: details.maintainers.to_string(),
+    })
+}
+
+pub fn displayname(attribute: &str, pname: &str, appdata: &HashMap<String, AppData>) -> String {
+    if let Some(data) = appdata.get(attribute) {
+        data.localname().unwrap_or(attribute).to_string()
+    } else {
+        pname.to_string()
+    }
+}
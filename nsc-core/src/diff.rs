@@ -0,0 +1,240 @@
+use std::fmt::Write;
+
+const CONTEXT: usize = 3;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+fn diffops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let n = old.len();
+    let m = new.len();
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(Op::Equal(i, j));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(Op::Delete(i));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(j));
+            j += 1;
+        }
+    }
+    ops.extend((i..n).map(Op::Delete));
+    ops.extend((j..m).map(Op::Insert));
+    ops
+}
+
+pub fn unified(old: &str, new: &str, oldname: &str, newname: &str) -> String {
+    // Lines keep their newline, so a missing one at the end of the file shows up as a change
+    let oldlines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let newlines = new.split_inclusive('\n').collect::<Vec<_>>();
+    let ops = diffops(&oldlines, &newlines);
+
+    let changes = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| !matches!(x, Op::Equal(..)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Group changes that are close enough to share context into hunks
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for c in changes {
+        let start = c.saturating_sub(CONTEXT);
+        let end = (c + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", oldname, newname);
+    for (start, end) in hunks {
+        let hunk = &ops[start..end];
+        let (mut oldstart, mut newstart) = (None, None);
+        let (mut oldlen, mut newlen) = (0, 0);
+        for op in hunk {
+            match *op {
+                Op::Equal(i, j) => {
+                    oldstart.get_or_insert(i);
+                    newstart.get_or_insert(j);
+                    oldlen += 1;
+                    newlen += 1;
+                }
+                Op::Delete(i) => {
+                    oldstart.get_or_insert(i);
+                    oldlen += 1;
+                }
+                Op::Insert(j) => {
+                    newstart.get_or_insert(j);
+                    newlen += 1;
+                }
+            }
+        }
+        let oldstart = oldstart
+            .map(|x| x + 1)
+            .unwrap_or_else(|| position(&ops[..start], true));
+        let newstart = newstart
+            .map(|x| x + 1)
+            .unwrap_or_else(|| position(&ops[..start], false));
+        let _ = writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            oldstart, oldlen, newstart, newlen
+        );
+        for op in hunk {
+            let (prefix, line) = match *op {
+                Op::Equal(i, _) => (' ', oldlines[i]),
+                Op::Delete(i) => ('-', oldlines[i]),
+                Op::Insert(j) => ('+', newlines[j]),
+            };
+            match line.strip_suffix('\n') {
+                Some(line) => {
+                    let _ = writeln!(out, "{}{}", prefix, line);
+                }
+                None => {
+                    let _ = writeln!(out, "{}{}\n\\ No newline at end of file", prefix, line);
+                }
+            }
+        }
+    }
+    out
+}
+
+// Line number preceding a hunk with no lines on one side
+fn position(ops: &[Op], old: bool) -> usize {
+    ops.iter()
+        .filter(|x| match x {
+            Op::Equal(..) => true,
+            Op::Delete(_) => old,
+            Op::Insert(_) => !old,
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(range: std::ops::RangeInclusive<usize>) -> String {
+        range.map(|x| format!("{}\n", x)).collect()
+    }
+
+    #[test]
+    fn equal() {
+        assert_eq!(unified("", "", "a", "b"), "");
+        assert_eq!(unified("x\ny\n", "x\ny\n", "a", "b"), "");
+    }
+
+    #[test]
+    fn emptyold() {
+        assert_eq!(
+            unified("", "x\ny\n", "a", "b"),
+            "--- a\n+++ b\n@@ -0,0 +1,2 @@\n+x\n+y\n"
+        );
+    }
+
+    #[test]
+    fn emptynew() {
+        assert_eq!(
+            unified("x\ny\n", "", "a", "b"),
+            "--- a\n+++ b\n@@ -1,2 +0,0 @@\n-x\n-y\n"
+        );
+    }
+
+    #[test]
+    fn change() {
+        let new = lines(1..=10).replace("5\n", "five\n");
+        assert_eq!(
+            unified(&lines(1..=10), &new, "a", "b"),
+            "--- a\n+++ b\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn insertatend() {
+        let new = format!("{}11\n", lines(1..=10));
+        assert_eq!(
+            unified(&lines(1..=10), &new, "a", "b"),
+            "--- a\n+++ b\n@@ -8,3 +8,4 @@\n 8\n 9\n 10\n+11\n"
+        );
+    }
+
+    #[test]
+    fn insertonly() {
+        // A hunk without old lines starts at the line before it
+        assert_eq!(
+            unified("x\n", "x\ny\n", "a", "b"),
+            "--- a\n+++ b\n@@ -1,1 +1,2 @@\n x\n+y\n"
+        );
+        assert_eq!(
+            unified(
+                &lines(1..=8),
+                &lines(1..=8).replace("4\n", "4\nnew\n"),
+                "a",
+                "b"
+            ),
+            "--- a\n+++ b\n@@ -2,6 +2,7 @@\n 2\n 3\n 4\n+new\n 5\n 6\n 7\n"
+        );
+    }
+
+    #[test]
+    fn mergedcontext() {
+        // Changes up to twice the context apart share a hunk
+        let new = lines(1..=20)
+            .replace("\n5\n", "\nfive\n")
+            .replace("\n11\n", "\neleven\n");
+        assert_eq!(
+            unified(&lines(1..=20), &new, "a", "b"),
+            "--- a\n+++ b\n@@ -2,13 +2,13 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n 9\n 10\n-11\n+eleven\n 12\n 13\n 14\n"
+        );
+    }
+
+    #[test]
+    fn separatehunks() {
+        let new = lines(1..=20)
+            .replace("\n4\n", "\nfour\n")
+            .replace("\n12\n", "\ntwelve\n");
+        assert_eq!(
+            unified(&lines(1..=20), &new, "a", "b"),
+            "--- a\n+++ b\n\
+             @@ -1,7 +1,7 @@\n 1\n 2\n 3\n-4\n+four\n 5\n 6\n 7\n\
+             @@ -9,7 +9,7 @@\n 9\n 10\n 11\n-12\n+twelve\n 13\n 14\n 15\n"
+        );
+    }
+
+    #[test]
+    fn trailingnewline() {
+        assert_eq!(
+            unified("x\ny", "x\ny\n", "a", "b"),
+            "--- a\n+++ b\n@@ -1,2 +1,2 @@\n x\n-y\n\\ No newline at end of file\n+y\n"
+        );
+        assert_eq!(
+            unified("x\ny\n", "x\nz", "a", "b"),
+            "--- a\n+++ b\n@@ -1,2 +1,2 @@\n x\n-y\n+z\n\\ No newline at end of file\n"
+        );
+        assert_eq!(unified("x\ny", "x\ny", "a", "b"), "");
+    }
+}
@@ -0,0 +1,173 @@
+use super::config::{editnscconfig, getnscconfig, FlakeSource};
+use anyhow::{anyhow, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::PathBuf, time::UNIX_EPOCH};
+
+// Reduces packages.<system> to the metadata shown in search and on the package page
+static EVALPKGS: &str = r#"pkgs: builtins.mapAttrs (n: p: {
+  pname = p.pname or (builtins.parseDrvName (p.name or n)).name;
+  version = p.version or (builtins.parseDrvName (p.name or n)).version;
+  description = p.meta.description or "";
+  longdescription = p.meta.longDescription or "";
+  homepage = let h = p.meta.homepage or ""; in if builtins.isList h then builtins.head h else h;
+  license = p.meta.license or null;
+  platforms = p.meta.platforms or [];
+  maintainers = p.meta.maintainers or [];
+}) pkgs"#;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlakePkg {
+    pub pname: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub longdescription: String,
+    #[serde(default)]
+    pub homepage: String,
+    #[serde(default)]
+    pub license: serde_json::Value,
+    #[serde(default)]
+    pub platforms: serde_json::Value,
+    #[serde(default)]
+    pub maintainers: serde_json::Value,
+}
+
+pub fn sources() -> Vec<FlakeSource> {
+    getnscconfig().flakes
+}
+
+// Packages from extra flakes are keyed as <source>#<attribute>, which never clashes with nixpkgs attributes
+pub fn attribute(source: &str, attr: &str) -> String {
+    format!("{}#{}", source, attr)
+}
+
+// The source name of a package, None for nixpkgs
+pub fn source(attribute: &str) -> Option<&str> {
+    attribute.split_once('#').map(|(source, _)| source)
+}
+
+pub fn resolve(attribute: &str) -> Option<(FlakeSource, String)> {
+    let (name, attr) = attribute.split_once('#')?;
+    let source = sources().into_iter().find(|x| x.name == name)?;
+    Some((source, attr.to_string()))
+}
+
+// What `nix profile install` needs to install a package
+pub fn installable(attribute: &str) -> Result<String> {
+    if source(attribute).is_none() {
+        return Ok(format!("nixpkgs#{}", attribute));
+    }
+    let (source, attr) =
+        resolve(attribute).ok_or_else(|| anyhow!("Unknown flake source for {}", attribute))?;
+    Ok(format!("{}#{}", source.url, attr))
+}
+
+fn cachepath(name: &str) -> Result<PathBuf> {
+    let home = std::env::var("HOME")?;
+    Ok(PathBuf::from(format!(
+        "{}/.cache/nix-software-center/flakes/{}.json",
+        home, name
+    )))
+}
+
+pub fn cached(name: &str) -> HashMap<String, FlakePkg> {
+    cachepath(name)
+        .ok()
+        .and_then(|x| fs::read_to_string(x).ok())
+        .and_then(|x| serde_json::from_str(&x).ok())
+        .unwrap_or_default()
+}
+
+pub fn details(attribute: &str) -> Option<FlakePkg> {
+    let (name, attr) = attribute.split_once('#')?;
+    cached(name).remove(attr)
+}
+
+// Changes whenever a source is added, removed or refreshed
+pub fn cachekey() -> String {
+    sources()
+        .iter()
+        .map(|source| {
+            let modified = cachepath(&source.name)
+                .ok()
+                .and_then(|x| fs::metadata(x).ok())
+                .and_then(|x| x.modified().ok())
+                .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                .map(|x| x.as_secs())
+                .unwrap_or(0);
+            format!("{}={}", source.name, modified)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub async fn evaluate(source: &FlakeSource, system: &str) -> Result<HashMap<String, FlakePkg>> {
+    info!("Evaluating packages of flake {}", source.url);
+    let output = tokio::process::Command::new("nix")
+        .arg("eval")
+        .arg("--json")
+        .arg(format!("{}#packages.{}", source.url, system))
+        .arg("--apply")
+        .arg(EVALPKGS)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to evaluate {}: {}",
+            source.url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let pkgs: HashMap<String, FlakePkg> = serde_json::from_slice(&output.stdout)?;
+    let path = cachepath(&source.name)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string(&pkgs)?)?;
+    Ok(pkgs)
+}
+
+// Evaluates every source, or only those that were never evaluated
+pub async fn refresh(system: &str, all: bool) {
+    for source in sources() {
+        if !all && cachepath(&source.name).map(|x| x.exists()).unwrap_or(false) {
+            continue;
+        }
+        if let Err(e) = evaluate(&source, system).await {
+            warn!("{}", e);
+        }
+    }
+}
+
+// Evaluates the flake before saving it, so broken sources are never added
+pub async fn add(source: FlakeSource, system: &str) -> Result<()> {
+    if source.name.is_empty()
+        || source
+            .name
+            .chars()
+            .any(|c| c == '#' || c == '/' || c.is_whitespace())
+    {
+        return Err(anyhow!("Invalid source name {}", source.name));
+    }
+    let mut config = getnscconfig();
+    if config.flakes.iter().any(|x| x.name == source.name) {
+        return Err(anyhow!("Source {} already exists", source.name));
+    }
+    evaluate(&source, system).await?;
+    config.flakes.push(source);
+    editnscconfig(&config)
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    let mut config = getnscconfig();
+    let len = config.flakes.len();
+    config.flakes.retain(|x| x.name != name);
+    if config.flakes.len() == len {
+        return Err(anyhow!("Source {} not found", name));
+    }
+    editnscconfig(&config)?;
+    let _ = fs::remove_file(cachepath(name)?);
+    Ok(())
+}
@@ -0,0 +1,446 @@
+use super::{
+    cancel::{Canceller, HelperJob},
+    helperpath, RebuildMode,
+};
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+use log::*;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use zbus::{dbus_proxy, Connection};
+
+#[dbus_proxy(
+    interface = "dev.vlinkz.NixSoftwareCenter.Helper1",
+    default_service = "dev.vlinkz.NixSoftwareCenter.Helper",
+    default_path = "/dev/vlinkz/NixSoftwareCenter/Helper"
+)]
+trait Helper {
+    #[allow(clippy::too_many_arguments)]
+    fn apply_config(
+        &self,
+        output: &str,
+        config: &str,
+        mode: &str,
+        generations: u32,
+        arguments: &[&str],
+    ) -> zbus::Result<u32>;
+
+    fn rebuild(&self, mode: &str, generations: u32, arguments: &[&str]) -> zbus::Result<u32>;
+
+    #[allow(clippy::too_many_arguments)]
+    fn update_channel(
+        &self,
+        output: &str,
+        config: &str,
+        rebuild: bool,
+        mode: &str,
+        generations: u32,
+        arguments: &[&str],
+    ) -> zbus::Result<u32>;
+
+    #[allow(clippy::too_many_arguments)]
+    fn update_flake(
+        &self,
+        flakepath: &str,
+        output: &str,
+        config: &str,
+        rebuild: bool,
+        mode: &str,
+        generations: u32,
+        arguments: &[&str],
+    ) -> zbus::Result<u32>;
+
+    #[allow(clippy::too_many_arguments)]
+    fn update_flake_inputs(
+        &self,
+        flakepath: &str,
+        inputs: &[&str],
+        output: &str,
+        config: &str,
+        rebuild: bool,
+        mode: &str,
+        generations: u32,
+        arguments: &[&str],
+    ) -> zbus::Result<u32>;
+
+    fn rollback(&self, output: &str, config: &str, generation: u32) -> zbus::Result<u32>;
+
+    fn cancel(&self, job: u32) -> zbus::Result<()>;
+
+    fn status(&self) -> zbus::Result<(u32, bool)>;
+
+    #[dbus_proxy(signal)]
+    fn output(&self, job: u32, line: &str) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn finished(&self, job: u32, success: bool) -> zbus::Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub enum HelperCmd {
+    Config {
+        output: String,
+        mode: RebuildMode,
+        generations: u32,
+        arguments: Vec<String>,
+    },
+    Rebuild {
+        mode: RebuildMode,
+        generations: u32,
+        arguments: Vec<String>,
+    },
+    Channel {
+        output: String,
+        update: bool,
+        rebuild: bool,
+        mode: RebuildMode,
+        generations: u32,
+        arguments: Vec<String>,
+    },
+    Flake {
+        flakepath: String,
+        // Empty to update every input
+        inputs: Vec<String>,
+        output: String,
+        update: bool,
+        rebuild: bool,
+        mode: RebuildMode,
+        generations: u32,
+        arguments: Vec<String>,
+    },
+    Rollback {
+        output: String,
+        generation: Option<u32>,
+    },
+}
+
+impl HelperCmd {
+    // Arguments for a one-shot `pkexec nsc-helper` invocation
+    fn args(&self) -> Vec<String> {
+        let rebuildargs = |mode: &RebuildMode, generations: &u32, arguments: &[String]| {
+            let mut args = vec![
+                String::from("--mode"),
+                mode.arg().to_string(),
+                String::from("--generations"),
+                generations.to_string(),
+                String::from("--"),
+            ];
+            args.extend(arguments.iter().cloned());
+            args
+        };
+        match self {
+            HelperCmd::Config {
+                output,
+                mode,
+                generations,
+                arguments,
+            } => {
+                let mut args = vec![
+                    String::from("config"),
+                    String::from("--output"),
+                    output.clone(),
+                ];
+                args.extend(rebuildargs(mode, generations, arguments));
+                args
+            }
+            HelperCmd::Rebuild {
+                mode,
+                generations,
+                arguments,
+            } => {
+                let mut args = vec![String::from("rebuild")];
+                args.extend(rebuildargs(mode, generations, arguments));
+                args
+            }
+            HelperCmd::Channel {
+                output,
+                update,
+                rebuild,
+                mode,
+                generations,
+                arguments,
+            } => {
+                let mut args = vec![
+                    String::from("channel"),
+                    String::from("--output"),
+                    output.clone(),
+                ];
+                if *update {
+                    args.push(String::from("--update"));
+                }
+                if *rebuild {
+                    args.push(String::from("--rebuild"));
+                }
+                args.extend(rebuildargs(mode, generations, arguments));
+                args
+            }
+            HelperCmd::Flake {
+                flakepath,
+                inputs,
+                output,
+                update,
+                rebuild,
+                mode,
+                generations,
+                arguments,
+            } => {
+                let mut args = vec![
+                    String::from("flake"),
+                    String::from("--flakepath"),
+                    flakepath.clone(),
+                    String::from("--output"),
+                    output.clone(),
+                ];
+                for input in inputs {
+                    args.push(String::from("--input"));
+                    args.push(input.clone());
+                }
+                if *update {
+                    args.push(String::from("--update"));
+                }
+                if *rebuild {
+                    args.push(String::from("--rebuild"));
+                }
+                args.extend(rebuildargs(mode, generations, arguments));
+                args
+            }
+            HelperCmd::Rollback { output, generation } => {
+                let mut args = vec![
+                    String::from("rollback"),
+                    String::from("--output"),
+                    output.clone(),
+                ];
+                if let Some(g) = generation {
+                    args.push(String::from("--generation"));
+                    args.push(g.to_string());
+                }
+                args
+            }
+        }
+    }
+}
+
+async fn connect() -> Option<HelperProxy<'static>> {
+    // NSC_HELPER_SESSION talks to a `nsc-helper daemon --session` instead, for testing
+    let conn = if std::env::var("NSC_HELPER_SESSION").is_ok() {
+        Connection::session().await
+    } else {
+        Connection::system().await
+    };
+    let proxy = HelperProxy::new(&conn.ok()?).await.ok()?;
+    // Also starts the service through bus activation if it is installed
+    match proxy.status().await {
+        Ok(_) => Some(proxy),
+        Err(e) => {
+            debug!("nsc-helper daemon unavailable: {}", e);
+            None
+        }
+    }
+}
+
+// Runs a privileged helper command, through the daemon if available and pkexec otherwise
+pub async fn run<F: Fn(String)>(
+    cmd: &HelperCmd,
+    stdin: Option<&str>,
+    cancel: &Canceller,
+    output: F,
+) -> Result<bool> {
+    if cancel.cancelled() {
+        return Err(anyhow!("Cancelled"));
+    }
+    let result = match connect().await {
+        Some(proxy) => rundaemon(&proxy, cmd, stdin.unwrap_or_default(), cancel, output).await,
+        None => runpkexec(cmd, stdin, cancel, output).await,
+    };
+    cancel.sethelper(None);
+    result
+}
+
+async fn rundaemon<F: Fn(String)>(
+    proxy: &HelperProxy<'_>,
+    cmd: &HelperCmd,
+    config: &str,
+    cancel: &Canceller,
+    output: F,
+) -> Result<bool> {
+    // Subscribe first so no lines are missed
+    let mut outputs = proxy.receive_output().await?;
+    let mut finished = proxy.receive_finished().await?;
+    let job = match cmd {
+        HelperCmd::Config {
+            output,
+            mode,
+            generations,
+            arguments,
+        } => {
+            proxy
+                .apply_config(output, config, mode.arg(), *generations, &strs(arguments))
+                .await?
+        }
+        HelperCmd::Rebuild {
+            mode,
+            generations,
+            arguments,
+        } => {
+            proxy
+                .rebuild(mode.arg(), *generations, &strs(arguments))
+                .await?
+        }
+        HelperCmd::Channel {
+            output,
+            update,
+            rebuild,
+            mode,
+            generations,
+            arguments,
+        } => {
+            proxy
+                .update_channel(
+                    output,
+                    if *update { config } else { "" },
+                    *rebuild,
+                    mode.arg(),
+                    *generations,
+                    &strs(arguments),
+                )
+                .await?
+        }
+        HelperCmd::Flake {
+            flakepath,
+            inputs,
+            output,
+            update,
+            rebuild,
+            mode,
+            generations,
+            arguments,
+        } if !inputs.is_empty() => {
+            proxy
+                .update_flake_inputs(
+                    flakepath,
+                    &strs(inputs),
+                    output,
+                    if *update { config } else { "" },
+                    *rebuild,
+                    mode.arg(),
+                    *generations,
+                    &strs(arguments),
+                )
+                .await?
+        }
+        HelperCmd::Flake {
+            flakepath,
+            output,
+            update,
+            rebuild,
+            mode,
+            generations,
+            arguments,
+            ..
+        } => {
+            proxy
+                .update_flake(
+                    flakepath,
+                    output,
+                    if *update { config } else { "" },
+                    *rebuild,
+                    mode.arg(),
+                    *generations,
+                    &strs(arguments),
+                )
+                .await?
+        }
+        HelperCmd::Rollback { output, generation } => {
+            proxy
+                .rollback(output, config, generation.unwrap_or(0))
+                .await?
+        }
+    };
+    cancel.sethelper(Some(HelperJob::Daemon(job)));
+    if cancel.cancelled() {
+        proxy.cancel(job).await?;
+    }
+
+    loop {
+        tokio::select! {
+            // Output signals are always sent before the job finishes
+            biased;
+            Some(signal) = outputs.next() => {
+                let args = signal.args()?;
+                if args.job == job {
+                    output(args.line.to_string());
+                }
+            }
+            Some(signal) = finished.next() => {
+                let args = signal.args()?;
+                if args.job == job {
+                    return Ok(args.success);
+                }
+            }
+            else => return Err(anyhow!("Lost connection to nsc-helper")),
+        }
+    }
+}
+
+async fn runpkexec<F: Fn(String)>(
+    cmd: &HelperCmd,
+    stdin: Option<&str>,
+    cancel: &Canceller,
+    output: F,
+) -> Result<bool> {
+    // The helper runs as root, so it can only be stopped through `nsc-helper cancel`
+    cancel.sethelper(Some(HelperJob::Pkexec));
+    let mut child = tokio::process::Command::new("pkexec")
+        .arg(helperpath())
+        .args(cmd.args())
+        .stderr(Stdio::piped())
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .spawn()?;
+
+    if let Some(stdin) = stdin {
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .await?;
+    }
+    let stderr = child.stderr.take().unwrap();
+    let reader = tokio::io::BufReader::new(stderr);
+
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        trace!("CAUGHT HELPER LINE: {}", line);
+        output(line);
+    }
+    Ok(child.wait().await?.success())
+}
+
+pub(crate) async fn canceljob(job: u32) -> Result<()> {
+    let proxy = connect()
+        .await
+        .ok_or_else(|| anyhow!("nsc-helper daemon is not running"))?;
+    proxy.cancel(job).await?;
+    Ok(())
+}
+
+pub(crate) async fn cancelpkexec() -> Result<()> {
+    let status = tokio::process::Command::new("pkexec")
+        .arg(helperpath())
+        .arg("cancel")
+        .status()
+        .await?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Failed to cancel nsc-helper"))
+    }
+}
+
+fn strs(v: &[String]) -> Vec<&str> {
+    v.iter().map(|x| x.as_str()).collect()
+}
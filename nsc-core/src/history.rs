@@ -0,0 +1,167 @@
+use super::{writeatomic, InstallType, PkgAction};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static SYSTEMPROFILE: &str = "/nix/var/nix/profiles/system";
+const MAXENTRIES: usize = 100;
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct HistoryPkg {
+    pub pkg: String,
+    pub action: PkgAction,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub pkgtype: InstallType,
+    pub packages: Vec<HistoryPkg>,
+    pub success: bool,
+    pub generation: Option<u32>,
+    pub previousgeneration: Option<u32>,
+    pub diff: Option<String>,
+    pub oldconfig: Option<String>,
+    // The configuration the transaction wrote, to tell whether it was changed since
+    pub newconfig: Option<String>,
+    pub rollback: Option<u64>,
+}
+
+impl HistoryEntry {
+    pub fn new(pkgtype: InstallType, packages: Vec<HistoryPkg>, success: bool) -> Self {
+        Self {
+            id: 0,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or_default(),
+            pkgtype,
+            packages,
+            success,
+            generation: None,
+            previousgeneration: None,
+            diff: None,
+            oldconfig: None,
+            newconfig: None,
+            rollback: None,
+        }
+    }
+
+    pub fn canrollback(&self) -> bool {
+        self.success
+            && self.pkgtype == InstallType::System
+            && self.rollback.is_none()
+            && self.oldconfig.is_some()
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn path() -> Result<PathBuf> {
+        let home = std::env::var("HOME")?;
+        Ok(PathBuf::from(format!(
+            "{}/.local/share/nix-software-center/history.json",
+            home
+        )))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let entries = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(Self { entries })
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, id: u64) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|x| x.id == id)
+    }
+
+    // Whether a later transaction already rolled this one back
+    pub fn rolledback(&self, id: u64) -> bool {
+        self.entries
+            .iter()
+            .any(|x| x.success && x.rollback == Some(id))
+    }
+
+    pub fn canrollback(&self, entry: &HistoryEntry) -> bool {
+        entry.canrollback() && !self.rolledback(entry.id)
+    }
+
+    pub fn latestrollback(&self) -> Option<&HistoryEntry> {
+        self.entries.iter().rev().find(|x| self.canrollback(x))
+    }
+
+    // System transactions after this one, whose changes rolling it back would undo as well
+    pub fn newer(&self, entry: &HistoryEntry) -> Vec<&HistoryEntry> {
+        self.entries
+            .iter()
+            .filter(|x| x.id > entry.id && x.success && x.pkgtype == InstallType::System)
+            .collect()
+    }
+
+    pub fn record(mut entry: HistoryEntry) -> Result<u64> {
+        let mut history = Self::load()?;
+        entry.id = history.entries.last().map(|x| x.id + 1).unwrap_or(1);
+        let id = entry.id;
+        history.entries.push(entry);
+        if history.entries.len() > MAXENTRIES {
+            let extra = history.entries.len() - MAXENTRIES;
+            history.entries.drain(..extra);
+        }
+        history.save()?;
+        Ok(id)
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Rollbacks depend on the journal, so it is never left half written
+        writeatomic(
+            &path,
+            serde_json::to_string_pretty(&self.entries)?.as_bytes(),
+        )
+    }
+}
+
+pub fn systemgeneration() -> Option<u32> {
+    let link = fs::read_link(SYSTEMPROFILE).ok()?;
+    let name = link.file_name()?.to_string_lossy().to_string();
+    name.strip_prefix("system-")?
+        .strip_suffix("-link")?
+        .parse()
+        .ok()
+}
+
+pub fn rollbackpkgs(history: &History, entry: &HistoryEntry) -> Result<Vec<HistoryPkg>> {
+    if !history.canrollback(entry) {
+        return Err(anyhow!("Transaction {} cannot be rolled back", entry.id));
+    }
+    Ok(entry
+        .packages
+        .iter()
+        .map(|x| HistoryPkg {
+            pkg: x.pkg.to_string(),
+            action: match x.action {
+                PkgAction::Install => PkgAction::Remove,
+                PkgAction::Remove => PkgAction::Install,
+            },
+        })
+        .collect())
+}
@@ -0,0 +1,21 @@
+use super::config::{editnscconfig, getnscconfig};
+use anyhow::Result;
+use std::collections::HashSet;
+
+pub fn held() -> HashSet<String> {
+    getnscconfig().holds.into_iter().collect()
+}
+
+pub fn isheld(pkg: &str) -> bool {
+    getnscconfig().holds.iter().any(|x| x == pkg)
+}
+
+pub fn sethold(pkg: &str, hold: bool) -> Result<()> {
+    let mut config = getnscconfig();
+    config.holds.retain(|x| x != pkg);
+    if hold {
+        config.holds.push(pkg.to_string());
+        config.holds.sort();
+    }
+    editnscconfig(&config)
+}
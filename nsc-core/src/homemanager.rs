@@ -0,0 +1,143 @@
+use super::{
+    cancel::Canceller,
+    config::{editnscconfig, getnscconfig},
+    writeatomic, PkgAction,
+};
+use anyhow::{anyhow, Result};
+use log::*;
+use std::{collections::HashSet, fs, path::Path, process::Stdio};
+use tokio::io::AsyncBufReadExt;
+
+pub fn homeconfig() -> Option<String> {
+    if let Some(config) = getnscconfig().homeconfig {
+        return Some(config);
+    }
+    let home = std::env::var("HOME").ok()?;
+    [
+        format!("{}/.config/home-manager/home.nix", home),
+        format!("{}/.config/nixpkgs/home.nix", home),
+    ]
+    .into_iter()
+    .find(|x| Path::new(x).exists())
+}
+
+pub fn homeflake() -> Option<String> {
+    if let Some(flake) = getnscconfig().homeflake {
+        return Some(flake);
+    }
+    // Flake based setups keep home.nix next to the flake.nix that imports it
+    let config = homeconfig()?;
+    let dir = Path::new(&config).parent()?;
+    if dir.join("flake.nix").exists() {
+        Some(dir.to_string_lossy().to_string())
+    } else {
+        None
+    }
+}
+
+pub fn enabled() -> bool {
+    getnscconfig().homemanager
+}
+
+pub fn setenabled(enabled: bool) -> Result<()> {
+    let mut config = getnscconfig();
+    config.homemanager = enabled;
+    editnscconfig(&config)
+}
+
+pub fn homemanagerinstalled() -> bool {
+    homeconfig().is_some()
+        && std::env::var("PATH")
+            .map(|x| {
+                x.split(':')
+                    .any(|p| Path::new(p).join("home-manager").exists())
+            })
+            .unwrap_or(false)
+}
+
+pub fn homepkgs() -> HashSet<String> {
+    homeconfig()
+        .and_then(|x| fs::read_to_string(x).ok())
+        .and_then(|f| nix_editor::read::getarrvals(&f, "home.packages").ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| x.strip_prefix("pkgs.").unwrap_or(&x).to_string())
+        .collect()
+}
+
+pub fn edithomeconfig(f: &str, work: &[(String, PkgAction)]) -> Result<String> {
+    let prefix = if let Ok(s) = nix_editor::read::getwithvalue(f, "home.packages") {
+        !s.contains(&"pkgs".to_string())
+    } else {
+        true
+    };
+
+    let mut out = f.to_string();
+    for (pkg, action) in work {
+        let p = if prefix {
+            format!("pkgs.{}", pkg)
+        } else {
+            pkg.to_string()
+        };
+        out = match action {
+            PkgAction::Install => match nix_editor::write::addtoarr(&out, "home.packages", vec![p])
+            {
+                Ok(x) => x,
+                Err(_) => {
+                    return Err(anyhow!("Failed to write home.nix"));
+                }
+            },
+            PkgAction::Remove => match nix_editor::write::rmarr(&out, "home.packages", vec![p]) {
+                Ok(x) => x,
+                Err(_) => {
+                    return Err(anyhow!("Failed to write home.nix"));
+                }
+            },
+        };
+    }
+    Ok(out)
+}
+
+pub async fn switch<F: Fn(String)>(cancel: &Canceller, output: F) -> Result<bool> {
+    let mut cmd = tokio::process::Command::new("home-manager");
+    cmd.arg("switch");
+    if let Some(flake) = homeflake() {
+        cmd.arg("--flake").arg(flake);
+    }
+    let mut child = cancel.spawn(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))?;
+
+    // home-manager writes activation progress to stdout and build logs to stderr
+    let mut stdout = tokio::io::BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut stderr = tokio::io::BufReader::new(child.stderr.take().unwrap()).lines();
+    let (mut outdone, mut errdone) = (false, false);
+    while !(outdone && errdone) {
+        tokio::select! {
+            line = stdout.next_line(), if !outdone => match line {
+                Ok(Some(line)) => output(line),
+                _ => outdone = true,
+            },
+            line = stderr.next_line(), if !errdone => match line {
+                Ok(Some(line)) => output(line),
+                _ => errdone = true,
+            },
+        }
+    }
+    Ok(child.wait().await?.success())
+}
+
+pub async fn apply<F: Fn(String)>(
+    work: &[(String, PkgAction)],
+    cancel: &Canceller,
+    output: F,
+) -> Result<bool> {
+    let path = homeconfig().ok_or_else(|| anyhow!("No home-manager configuration found"))?;
+    let f = fs::read_to_string(&path)?;
+    let out = edithomeconfig(&f, work)?;
+    writeatomic(Path::new(&path), out.as_bytes())?;
+    let result = switch(cancel, output).await;
+    if !matches!(result, Ok(true)) {
+        info!("home-manager switch failed, restoring {}", path);
+        writeatomic(Path::new(&path), f.as_bytes())?;
+    }
+    result
+}
@@ -0,0 +1,408 @@
+use super::{
+    cancel::Canceller,
+    diff, faileddrv, flakes,
+    helper::{self, HelperCmd},
+    history::{self, History, HistoryEntry, HistoryPkg},
+    homemanager, rebuildargs, BuildResult, InstallType, PkgAction, ProfileManifest, Progress,
+    RebuildMode, UserPkgs, LOGFORMAT,
+};
+use anyhow::{anyhow, Result};
+use log::*;
+use nix_data::config::configfile::NixDataConfig;
+use std::{fmt, fs, process::Stdio, sync::Mutex};
+use tokio::io::AsyncBufReadExt;
+
+#[derive(Debug, Clone)]
+pub struct Installer {
+    config: NixDataConfig,
+    userpkgs: UserPkgs,
+    system: String,
+    cancel: Canceller,
+}
+
+impl Installer {
+    pub fn new(config: NixDataConfig, userpkgs: UserPkgs, system: String) -> Self {
+        Self {
+            config,
+            userpkgs,
+            system,
+            cancel: Canceller::default(),
+        }
+    }
+
+    pub fn canceller(&self) -> Canceller {
+        self.cancel.clone()
+    }
+
+    // Cancelled transactions leave the configuration as it was
+    fn record(&self, entry: HistoryEntry) -> Result<()> {
+        if !self.cancel.cancelled() {
+            History::record(entry)?;
+        }
+        Ok(())
+    }
+
+    pub async fn process<F: Fn(String)>(
+        &self,
+        pkg: &str,
+        pname: &str,
+        action: &PkgAction,
+        pkgtype: &InstallType,
+        output: F,
+    ) -> Result<bool> {
+        match pkgtype {
+            InstallType::User => self.installuser(pkg, pname, action, output).await,
+            InstallType::System => self.installsys(pkg, action, output).await,
+        }
+    }
+
+    pub async fn installuser<F: Fn(String)>(
+        &self,
+        pkg: &str,
+        pname: &str,
+        action: &PkgAction,
+        output: F,
+    ) -> Result<bool> {
+        if let Some(source) = flakes::source(pkg) {
+            if self.userpkgs != UserPkgs::Profile {
+                return Err(anyhow!(
+                    "Packages from {} can only be installed with nix profile",
+                    source
+                ));
+            }
+        }
+        let success = match self.userpkgs {
+            UserPkgs::HomeManager => {
+                homemanager::apply(&[(pkg.to_string(), action.clone())], &self.cancel, output)
+                    .await?
+            }
+            _ => self.installnix(pkg, pname, action, output).await?,
+        };
+        let entry = HistoryEntry::new(
+            InstallType::User,
+            vec![HistoryPkg {
+                pkg: pkg.to_string(),
+                action: action.clone(),
+            }],
+            success,
+        );
+        if let Err(e) = self.record(entry) {
+            warn!("Failed to record history: {}", e);
+        }
+        Ok(success)
+    }
+
+    pub fn previewhome(&self, work: &[(String, PkgAction)]) -> Result<String> {
+        if self.userpkgs != UserPkgs::HomeManager {
+            return Ok(String::new());
+        }
+        let homeconfig = homemanager::homeconfig()
+            .ok_or_else(|| anyhow!("No home-manager configuration found"))?;
+        let f = fs::read_to_string(&homeconfig)?;
+        let out = homemanager::edithomeconfig(&f, work)?;
+        Ok(diff::unified(&f, &out, &homeconfig, &homeconfig))
+    }
+
+    async fn installnix<F: Fn(String)>(
+        &self,
+        pkg: &str,
+        pname: &str,
+        action: &PkgAction,
+        output: F,
+    ) -> Result<bool> {
+        let mut cmd = match (action, &self.userpkgs) {
+            (PkgAction::Install, UserPkgs::Env) => {
+                let mut cmd = tokio::process::Command::new("nix-env");
+                cmd.arg("-iA").arg(format!("nixos.{}", pkg));
+                cmd
+            }
+            (PkgAction::Install, UserPkgs::Profile) => {
+                let mut cmd = tokio::process::Command::new("nix");
+                cmd.arg("profile")
+                    .arg("install")
+                    .arg(flakes::installable(pkg)?)
+                    .arg("--impure");
+                cmd
+            }
+            (PkgAction::Remove, UserPkgs::Env) => {
+                let mut cmd = tokio::process::Command::new("nix-env");
+                cmd.arg("-e").arg(pname);
+                cmd
+            }
+            (PkgAction::Remove, UserPkgs::Profile) => {
+                let manifest = ProfileManifest::load()?;
+                let element = manifest
+                    .find(pkg, &self.system, &flakes::sources())
+                    .ok_or_else(|| anyhow!("{} is not installed in the profile", pkg))?;
+                let mut cmd = tokio::process::Command::new("nix");
+                cmd.arg("profile").arg("remove").arg(element.selector());
+                cmd
+            }
+            (_, UserPkgs::HomeManager) => {
+                return Err(anyhow!("home-manager packages are not managed with nix"));
+            }
+        };
+        let mut p = self.cancel.spawn(
+            cmd.args(LOGFORMAT)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )?;
+
+        let stderr = p.stderr.take().unwrap();
+        let reader = tokio::io::BufReader::new(stderr);
+
+        let mut lines = reader.lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            output(line);
+        }
+
+        Ok(p.wait().await?.success())
+    }
+
+    pub async fn installsys<F: Fn(String)>(
+        &self,
+        pkg: &str,
+        action: &PkgAction,
+        output: F,
+    ) -> Result<bool> {
+        self.installsysbatch(&[(pkg.to_string(), action.clone())], output)
+            .await
+    }
+
+    pub fn previewsys(&self, work: &[(String, PkgAction)]) -> Result<String> {
+        let systemconfig = self.config.systemconfig.clone().unwrap_or_default();
+        let f = fs::read_to_string(&systemconfig)?;
+        let out = editsysconfig(&f, work)?;
+        Ok(diff::unified(&f, &out, &systemconfig, &systemconfig))
+    }
+
+    pub fn previewrollback(&self, entry: &HistoryEntry) -> Result<String> {
+        let systemconfig = self.config.systemconfig.clone().unwrap_or_default();
+        let f = fs::read_to_string(&systemconfig)?;
+        let oldconfig = entry.oldconfig.clone().unwrap_or_default();
+        Ok(diff::unified(&f, &oldconfig, &systemconfig, &systemconfig))
+    }
+
+    pub fn checkrollback(&self, entry: &HistoryEntry) -> Result<Option<RollbackConflict>> {
+        let systemconfig = self.config.systemconfig.clone().unwrap_or_default();
+        let current = fs::read_to_string(systemconfig)?;
+        Ok(rollbackconflict(&History::load()?, entry, &current))
+    }
+
+    pub async fn testsys<F: Fn(String)>(
+        &self,
+        work: &[(String, PkgAction)],
+        mode: RebuildMode,
+        output: F,
+    ) -> Result<BuildResult> {
+        let systemconfig = self.config.systemconfig.clone().unwrap_or_default();
+        let f = fs::read_to_string(&systemconfig)?;
+        let out = editsysconfig(&f, work)?;
+        self.rebuildconfig(&out, mode, output).await
+    }
+
+    pub async fn installsysbatch<F: Fn(String)>(
+        &self,
+        work: &[(String, PkgAction)],
+        output: F,
+    ) -> Result<bool> {
+        let systemconfig = self.config.systemconfig.clone().unwrap_or_default();
+        let f = fs::read_to_string(&systemconfig)?;
+        let out = editsysconfig(&f, work)?;
+        let previousgeneration = history::systemgeneration();
+
+        let success = self
+            .rebuildconfig(&out, RebuildMode::Switch, output)
+            .await?
+            == BuildResult::Success;
+        let mut entry = HistoryEntry::new(
+            InstallType::System,
+            work.iter()
+                .map(|(pkg, action)| HistoryPkg {
+                    pkg: pkg.to_string(),
+                    action: action.clone(),
+                })
+                .collect(),
+            success,
+        );
+        entry.previousgeneration = previousgeneration;
+        entry.generation = history::systemgeneration();
+        entry.diff = Some(diff::unified(&f, &out, &systemconfig, &systemconfig));
+        entry.oldconfig = Some(f);
+        entry.newconfig = Some(out);
+        if let Err(e) = self.record(entry) {
+            warn!("Failed to record history: {}", e);
+        }
+        Ok(success)
+    }
+
+    async fn rebuildconfig<F: Fn(String)>(
+        &self,
+        out: &str,
+        mode: RebuildMode,
+        output: F,
+    ) -> Result<BuildResult> {
+        let config = &self.config;
+        let systemconfig = config.systemconfig.clone().unwrap_or_default();
+
+        let mut arguments = vec![String::from("--impure")];
+        arguments.extend(LOGFORMAT.iter().map(|x| x.to_string()));
+        arguments.extend(rebuildargs(config));
+        let cmd = HelperCmd::Config {
+            output: systemconfig,
+            mode,
+            generations: config.generations.unwrap_or(0),
+            arguments,
+        };
+
+        let drv = Mutex::new(None);
+        let progress = Mutex::new(Progress::default());
+        let success = helper::run(&cmd, Some(out), &self.cancel, |line| {
+            if let Some(d) = progress
+                .lock()
+                .unwrap()
+                .parse(&line)
+                .and_then(|x| faileddrv(&x))
+            {
+                *drv.lock().unwrap() = Some(d);
+            }
+            output(line);
+        })
+        .await?;
+
+        if success {
+            Ok(BuildResult::Success)
+        } else {
+            Ok(BuildResult::Failed(drv.into_inner().unwrap()))
+        }
+    }
+
+    // Unless forced, refuses when the configuration was changed after the transaction,
+    // since the whole configuration from before it is written back
+    pub async fn rollback<F: Fn(String)>(
+        &self,
+        entry: &HistoryEntry,
+        force: bool,
+        output: F,
+    ) -> Result<bool> {
+        let packages = history::rollbackpkgs(&History::load()?, entry)?;
+        if !force {
+            if let Some(conflict) = self.checkrollback(entry)? {
+                return Err(anyhow!(conflict));
+            }
+        }
+        let systemconfig = self.config.systemconfig.clone().unwrap_or_default();
+        let current = fs::read_to_string(&systemconfig)?;
+        let oldconfig = entry.oldconfig.clone().unwrap_or_default();
+
+        let cmd = HelperCmd::Rollback {
+            output: systemconfig.clone(),
+            generation: entry.previousgeneration,
+        };
+        let success = helper::run(&cmd, Some(&oldconfig), &self.cancel, output).await?;
+        let mut rollback = HistoryEntry::new(InstallType::System, packages, success);
+        rollback.previousgeneration = entry.generation;
+        rollback.generation = history::systemgeneration();
+        rollback.diff = Some(diff::unified(
+            &current,
+            &oldconfig,
+            &systemconfig,
+            &systemconfig,
+        ));
+        rollback.newconfig = Some(oldconfig);
+        rollback.rollback = Some(entry.id);
+        if let Err(e) = self.record(rollback) {
+            warn!("Failed to record history: {}", e);
+        }
+        Ok(success)
+    }
+}
+
+// Later changes to the configuration that rolling a transaction back would undo as well
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RollbackConflict {
+    // Later transactions, by id
+    Newer(Vec<u64>),
+    // Edited by hand
+    Edited,
+}
+
+impl fmt::Display for RollbackConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RollbackConflict::Newer(ids) => write!(
+                f,
+                "Transactions {} changed the configuration afterwards",
+                ids.iter()
+                    .map(|x| format!("#{}", x))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            RollbackConflict::Edited => write!(f, "The configuration was edited afterwards"),
+        }
+    }
+}
+
+fn rollbackconflict(
+    history: &History,
+    entry: &HistoryEntry,
+    current: &str,
+) -> Option<RollbackConflict> {
+    if entry.newconfig.as_deref() == Some(current) {
+        return None;
+    }
+    let newer = history
+        .newer(entry)
+        .iter()
+        .map(|x| x.id)
+        .collect::<Vec<_>>();
+    if !newer.is_empty() {
+        Some(RollbackConflict::Newer(newer))
+    } else if entry.newconfig.is_some() {
+        Some(RollbackConflict::Edited)
+    } else {
+        None
+    }
+}
+
+pub fn editsysconfig(f: &str, work: &[(String, PkgAction)]) -> Result<String> {
+    if let Some(source) = work.iter().find_map(|(pkg, _)| flakes::source(pkg)) {
+        return Err(anyhow!(
+            "Packages from {} can't be added to configuration.nix",
+            source
+        ));
+    }
+    let prefix = if let Ok(s) = nix_editor::read::getwithvalue(f, "environment.systemPackages") {
+        !s.contains(&"pkgs".to_string())
+    } else {
+        true
+    };
+
+    let mut out = f.to_string();
+    for (pkg, action) in work {
+        let p = if prefix {
+            format!("pkgs.{}", pkg)
+        } else {
+            pkg.to_string()
+        };
+        out = match action {
+            PkgAction::Install => {
+                match nix_editor::write::addtoarr(&out, "environment.systemPackages", vec![p]) {
+                    Ok(x) => x,
+                    Err(_) => {
+                        return Err(anyhow!("Failed to write configuration.nix"));
+                    }
+                }
+            }
+            PkgAction::Remove => {
+                match nix_editor::write::rmarr(&out, "environment.systemPackages", vec![p]) {
+                    Ok(x) => x,
+                    Err(_) => {
+                        return Err(anyhow!("Failed to write configuration.nix"));
+                    }
+                }
+            }
+        };
+    }
+    Ok(out)
+}
@@ -0,0 +1,76 @@
+use super::{currentsystem, flakes, homemanager, ProfileManifest, SystemPkgs, UserPkgs};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InstalledState {
+    pub system: HashSet<String>,
+    pub user: HashMap<String, String>,
+}
+
+impl InstalledState {
+    pub async fn load(
+        systemconfig: &Option<String>,
+        syspkgtype: &SystemPkgs,
+        userpkgtype: &UserPkgs,
+    ) -> Self {
+        Self {
+            system: installedsystempkgs(systemconfig, syspkgtype).await,
+            user: installeduserpkgs(userpkgtype).await,
+        }
+    }
+
+    pub fn systeminstalled(&self, attribute: &str) -> bool {
+        self.system.contains(attribute)
+    }
+
+    pub fn userinstalled(&self, attribute: &str, pname: &str, userpkgtype: &UserPkgs) -> bool {
+        match userpkgtype {
+            UserPkgs::Env => self.user.contains_key(pname),
+            UserPkgs::Profile | UserPkgs::HomeManager => self.user.contains_key(attribute),
+        }
+    }
+}
+
+async fn installedsystempkgs(
+    systemconfig: &Option<String>,
+    syspkgtype: &SystemPkgs,
+) -> HashSet<String> {
+    if let Some(config) = systemconfig {
+        match syspkgtype {
+            SystemPkgs::Flake => {
+                let pkgs = nix_data::cache::flakes::getflakepkgs(&[config]).await;
+                if let Ok(pkgs) = pkgs {
+                    pkgs.keys().cloned().collect::<HashSet<String>>()
+                } else {
+                    HashSet::new()
+                }
+            }
+            SystemPkgs::Legacy => {
+                let pkgs = nix_data::cache::channel::getlegacypkgs(&[config]).await;
+                if let Ok(pkgs) = pkgs {
+                    pkgs.keys().cloned().collect::<HashSet<String>>()
+                } else {
+                    HashSet::new()
+                }
+            }
+            _ => HashSet::new(),
+        }
+    } else {
+        HashSet::new()
+    }
+}
+
+async fn installeduserpkgs(userpkgtype: &UserPkgs) -> HashMap<String, String> {
+    match userpkgtype {
+        UserPkgs::Profile => ProfileManifest::load()
+            .map(|m| m.installed(&currentsystem(), &flakes::sources()))
+            .unwrap_or_default(),
+        UserPkgs::Env => {
+            nix_data::cache::channel::getenvpkgs().unwrap_or_default()
+        }
+        UserPkgs::HomeManager => homemanager::homepkgs()
+            .into_iter()
+            .map(|x| (x, String::new()))
+            .collect(),
+    }
+}
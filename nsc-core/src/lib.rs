@@ -0,0 +1,239 @@
+pub mod appstream;
+pub mod cancel;
+pub mod config;
+pub mod db;
+pub mod diff;
+pub mod flakes;
+pub mod helper;
+pub mod history;
+pub mod holds;
+pub mod homemanager;
+pub mod install;
+pub mod installed;
+pub mod locale;
+pub mod packages;
+pub mod profile;
+pub mod progress;
+pub mod search;
+pub mod update;
+
+pub use cancel::Canceller;
+pub use db::PackageDb;
+pub use history::History;
+pub use install::Installer;
+pub use installed::InstalledState;
+pub use profile::ProfileManifest;
+pub use progress::Progress;
+pub use search::SearchIndex;
+pub use update::Updater;
+
+use log::*;
+use nix_data::config::configfile::NixDataConfig;
+use serde::{Deserialize, Serialize};
+use std::{fs, io::Write, path::Path};
+
+// Where AppStream data is looked for last, see appstream::load
+pub static APPINFO: &str = "/usr/share/app-info";
+pub static LOGFORMAT: [&str; 2] = ["--log-format", "internal-json"];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SystemPkgs {
+    Legacy,
+    Flake,
+    None,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum UserPkgs {
+    Env,
+    Profile,
+    HomeManager,
+}
+
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub enum InstallType {
+    User,
+    System,
+}
+
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub enum PkgAction {
+    Install,
+    Remove,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RebuildMode {
+    DryBuild,
+    Build,
+    Test,
+    Switch,
+}
+
+impl RebuildMode {
+    pub fn arg(&self) -> &'static str {
+        match self {
+            RebuildMode::DryBuild => "dry-build",
+            RebuildMode::Build => "build",
+            RebuildMode::Test => "test",
+            RebuildMode::Switch => "switch",
+        }
+    }
+}
+
+impl std::str::FromStr for RebuildMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "dry-build" => Ok(RebuildMode::DryBuild),
+            "build" => Ok(RebuildMode::Build),
+            "test" => Ok(RebuildMode::Test),
+            "switch" => Ok(RebuildMode::Switch),
+            _ => Err(anyhow::anyhow!("Unknown rebuild mode {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BuildResult {
+    Success,
+    Failed(Option<String>),
+}
+
+// The nix system this machine runs, like x86_64-linux or aarch64-linux
+pub fn currentsystem() -> String {
+    let arch = match std::env::consts::ARCH {
+        "x86" => "i686",
+        "arm" => "armv7l",
+        arch => arch,
+    };
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    format!("{}-{}", arch, os)
+}
+
+pub fn userpkgtype() -> UserPkgs {
+    if homemanager::enabled() && homemanager::homemanagerinstalled() {
+        return UserPkgs::HomeManager;
+    }
+    if let Ok(h) = std::env::var("HOME") {
+        if Path::new(&format!("{}/.nix-profile/manifest.json", h)).exists()
+            || !Path::new("/nix/var/nix/profiles/per-user/root/channels/nixos").exists()
+            || !Path::new(&format!("{}/.nix-profile/manifest.nix", h)).exists()
+            || if let Ok(m) = fs::read_to_string(format!("{}/.nix-profile/manifest.nix", h)) {
+                m == "[ ]"
+            } else {
+                false
+            }
+        {
+            UserPkgs::Profile
+        } else {
+            UserPkgs::Env
+        }
+    } else {
+        UserPkgs::Env
+    }
+}
+
+pub fn syspkgtype(config: &NixDataConfig) -> SystemPkgs {
+    let nixos = Path::new("/etc/NIXOS").exists();
+    if config.systemconfig.is_none() || !nixos {
+        SystemPkgs::None
+    } else {
+        match fs::read_to_string("/run/current-system/nixos-version") {
+            Ok(s) => {
+                if !Path::new("/nix/var/nix/profiles/per-user/root/channels/nixos").exists()
+                    || config.flake.is_some()
+                {
+                    SystemPkgs::Flake
+                } else if let Some(last) = s.split('.').next_back() {
+                    if last.len() == 7 || last == "dirty" || last == "git" {
+                        SystemPkgs::Flake
+                    } else {
+                        SystemPkgs::Legacy
+                    }
+                } else {
+                    SystemPkgs::Legacy
+                }
+            }
+            Err(_) => SystemPkgs::None,
+        }
+    }
+}
+
+// Writes a file through a temporary file renamed over it, so a crash or a full disk never leaves it half written
+pub fn writeatomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    // Configurations are often links into a dotfiles repository, which should stay links
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    if let Ok(m) = fs::metadata(&path) {
+        file.as_file().set_permissions(m.permissions())?;
+    }
+    file.write_all(contents)?;
+    file.as_file().sync_all()?;
+    file.persist(&path)?;
+    Ok(())
+}
+
+pub fn helperpath() -> String {
+    match std::env::current_exe() {
+        Ok(mut e) => {
+            e.pop(); // root/bin
+            e.pop(); // root/
+            e.push("libexec"); // root/libexec
+            e.push("nsc-helper");
+            let x = e.to_string_lossy().to_string();
+            info!("nsc-helper path: {}", x);
+            if Path::new(&x).is_file() {
+                x
+            } else {
+                String::from("nsc-helper")
+            }
+        }
+        Err(_) => String::from("nsc-helper"),
+    }
+}
+
+pub fn flakeargs(config: &NixDataConfig) -> Option<String> {
+    if let Some(flake) = &config.flake {
+        if let Some(flakearg) = &config.flakearg {
+            Some(format!("{}#{}", flake, flakearg))
+        } else {
+            Some(flake.to_string())
+        }
+    } else {
+        None
+    }
+}
+
+// Finds the derivation that broke a build in a line of nix output
+pub fn faileddrv(line: &str) -> Option<String> {
+    let line = line.trim();
+    if !(line.starts_with("error: builder for") || line.starts_with("error: Cannot build")) {
+        return None;
+    }
+    line.split(['\'', '`', ' '])
+        .find(|x| x.starts_with("/nix/store/") && x.ends_with(".drv"))
+        .map(|x| x.to_string())
+}
+
+pub fn rebuildargs(config: &NixDataConfig) -> Vec<String> {
+    if let Some(x) = flakeargs(config) {
+        let mut v = vec![String::from("--flake")];
+        for arg in x.split(' ') {
+            if !arg.is_empty() {
+                v.push(String::from(arg));
+            }
+        }
+        v
+    } else {
+        vec![]
+    }
+}
@@ -0,0 +1,66 @@
+use std::{collections::HashMap, env};
+
+// The locale messages are shown in, like gettext picks it
+fn messageslocale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|x| env::var(x).ok())
+        .find(|x| !x.is_empty())
+}
+
+// Expands ll_CC.codeset@modifier into ll_CC@modifier, ll_CC, ll@modifier and ll
+fn expand(locale: &str) -> Vec<String> {
+    let (rest, modifier) = match locale.split_once('@') {
+        Some((rest, modifier)) => (rest, Some(modifier)),
+        None => (locale, None),
+    };
+    let rest = rest.split('.').next().unwrap_or_default();
+    let mut names = vec![rest];
+    if let Some((lang, _)) = rest.split_once('_') {
+        names.push(lang);
+    }
+    names
+        .into_iter()
+        .filter(|x| !x.is_empty())
+        .flat_map(|x| {
+            let mut v = vec![];
+            if let Some(modifier) = modifier {
+                v.push(format!("{}@{}", x, modifier));
+            }
+            v.push(x.to_string());
+            v
+        })
+        .collect()
+}
+
+// Languages to look translations up in, most specific first and always ending with C
+pub fn languages() -> Vec<String> {
+    let locale = messageslocale().unwrap_or_default();
+    let mut out = vec![];
+    // As with gettext, LANGUAGE is ignored for the C locale
+    if !locale.is_empty() && locale != "C" && locale != "POSIX" {
+        let list = env::var("LANGUAGE")
+            .ok()
+            .filter(|x| !x.is_empty())
+            .unwrap_or(locale);
+        for name in list.split(':').flat_map(expand) {
+            if name != "C" && name != "POSIX" && !out.contains(&name) {
+                out.push(name);
+            }
+        }
+    }
+    out.push(String::from("C"));
+    out
+}
+
+pub fn localized<'a, T>(map: &'a HashMap<String, T>, languages: &[String]) -> Option<&'a T> {
+    languages.iter().find_map(|x| map.get(x))
+}
+
+// Only an actual translation, None if the text is the same as the untranslated one
+pub fn translated<'a, T: PartialEq>(
+    map: &'a HashMap<String, T>,
+    languages: &[String],
+) -> Option<&'a T> {
+    localized(map, languages).filter(|x| map.get("C") != Some(*x))
+}
@@ -0,0 +1,326 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{self, cmp::Reverse, collections::HashMap, path::Path};
+
+use crate::locale::{languages, localized};
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+#[serde(untagged)]
+pub enum StrOrVec {
+    Single(String),
+    List(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+#[serde(untagged)]
+pub enum Platform {
+    Single(String),
+    List(Vec<String>),
+    ListList(Vec<Vec<String>>),
+}
+
+impl Platform {
+    // Packages without any platforms listed are assumed to build everywhere
+    pub fn supports(&self, system: &str) -> bool {
+        match self {
+            Platform::Single(p) => p == system,
+            Platform::List(v) => v.is_empty() || v.iter().any(|p| p == system),
+            Platform::ListList(vv) => {
+                vv.iter().all(|v| v.is_empty()) || vv.iter().flatten().any(|p| p == system)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+#[serde(untagged)]
+pub enum LicenseEnum {
+    Single(License),
+    List(Vec<License>),
+    SingleStr(String),
+    VecStr(Vec<String>),
+    Mixed(Vec<LicenseEnum>)
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct License {
+    pub free: Option<bool>,
+    #[serde(rename = "fullName")]
+    pub fullname: Option<String>,
+    #[serde(rename = "spdxId")]
+    pub spdxid: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct PkgMaintainer {
+    pub email: Option<String>,
+    pub github: Option<String>,
+    pub matrix: Option<String>,
+    pub name: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct AppData {
+    #[serde(rename = "Type")]
+    pub metatype: String,
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Package")]
+    pub package: String,
+    #[serde(rename = "Name")]
+    pub name: Option<HashMap<String, String>>,
+    #[serde(rename = "Description")]
+    pub description: Option<HashMap<String, String>>,
+    #[serde(rename = "Summary")]
+    pub summary: Option<HashMap<String, String>>,
+    #[serde(rename = "Url")]
+    pub url: Option<AppUrl>,
+    #[serde(rename = "Icon")]
+    pub icon: Option<AppIconList>,
+    #[serde(rename = "Launchable")]
+    pub launchable: Option<AppLaunchable>,
+    #[serde(rename = "Provides")]
+    pub provides: Option<AppProvides>,
+    #[serde(rename = "Screenshots")]
+    pub screenshots: Option<Vec<AppScreenshot>>,
+    #[serde(rename = "Categories")]
+    pub categories: Option<Vec<String>>,
+    #[serde(rename = "Keywords")]
+    pub keywords: Option<HashMap<String, Vec<String>>>,
+    #[serde(rename = "DeveloperName")]
+    pub developername: Option<HashMap<String, String>>,
+    #[serde(rename = "Developer")]
+    pub developer: Option<AppDeveloper>,
+    #[serde(rename = "ProjectLicense")]
+    pub projectlicense: Option<String>,
+    #[serde(rename = "Releases")]
+    pub releases: Option<Vec<AppRelease>>,
+    #[serde(rename = "ContentRating")]
+    pub contentrating: Option<HashMap<String, Option<HashMap<String, String>>>>,
+    // Not part of AppStream, set to where the collection keeps its cached icons
+    #[serde(default)]
+    pub icondir: Option<String>,
+}
+
+// Translated in the user's language where available
+impl AppData {
+    pub fn localname(&self) -> Option<&str> {
+        self.name
+            .as_ref()
+            .and_then(|x| localized(x, &languages()))
+            .map(|x| x.as_str())
+    }
+
+    pub fn localsummary(&self) -> Option<&str> {
+        self.summary
+            .as_ref()
+            .and_then(|x| localized(x, &languages()))
+            .map(|x| x.as_str())
+    }
+
+    pub fn localdescription(&self) -> Option<&str> {
+        self.description
+            .as_ref()
+            .and_then(|x| localized(x, &languages()))
+            .map(|x| x.as_str())
+    }
+
+    // Newer data has a Developer block, older data only DeveloperName
+    pub fn localdeveloper(&self) -> Option<&str> {
+        self.developer
+            .as_ref()
+            .and_then(|x| x.name.as_ref())
+            .or(self.developername.as_ref())
+            .and_then(|x| localized(x, &languages()))
+            .map(|x| x.as_str())
+    }
+
+    // The largest cached icon that is on disk
+    pub fn cachedicon(&self) -> Option<String> {
+        let dir = self.icondir.as_ref()?;
+        let mut icons = self
+            .icon
+            .iter()
+            .flat_map(|x| x.cached.iter().flatten())
+            .collect::<Vec<_>>();
+        icons.sort_by_key(|x| Reverse(x.height));
+        icons
+            .into_iter()
+            .map(|i| format!("{}/{}x{}/{}", dir, i.width, i.height, i.name))
+            .find(|x| Path::new(x).is_file())
+    }
+
+    // Newest first
+    pub fn sortedreleases(&self) -> Vec<&AppRelease> {
+        let mut releases = self.releases.iter().flatten().collect::<Vec<_>>();
+        releases.sort_by_key(|x| Reverse(x.timestamp));
+        releases
+    }
+
+    // The minimum age the OARS content rating calls for, None if the app has no rating
+    pub fn agerating(&self) -> Option<u32> {
+        let ratings = self.contentrating.as_ref()?;
+        let ratings = ratings
+            .iter()
+            .filter(|(kind, _)| kind.starts_with("oars-"))
+            .collect::<Vec<_>>();
+        if ratings.is_empty() {
+            return None;
+        }
+        Some(
+            ratings
+                .into_iter()
+                .flat_map(|(_, attrs)| attrs.iter().flatten())
+                .map(|(attr, value)| oarsage(attr, value))
+                .max()
+                .unwrap_or(0),
+        )
+    }
+}
+
+// Ages for the none, mild, moderate and intense values, as the Common Sense Media mapping in libappstream
+static OARSAGES: &[(&str, [u32; 4])] = &[
+    ("violence-cartoon", [0, 3, 4, 6]),
+    ("violence-fantasy", [0, 3, 7, 8]),
+    ("violence-realistic", [0, 4, 9, 14]),
+    ("violence-bloodshed", [0, 9, 11, 18]),
+    ("violence-sexual", [0, 18, 18, 18]),
+    ("violence-desecration", [0, 3, 7, 13]),
+    ("violence-slavery", [0, 7, 13, 18]),
+    ("violence-worship", [0, 3, 7, 15]),
+    ("drugs-alcohol", [0, 11, 13, 16]),
+    ("drugs-narcotics", [0, 12, 14, 17]),
+    ("drugs-tobacco", [0, 10, 13, 13]),
+    ("sex-nudity", [0, 12, 14, 14]),
+    ("sex-themes", [0, 13, 14, 15]),
+    ("sex-homosexuality", [0, 13, 14, 15]),
+    ("sex-prostitution", [0, 12, 14, 18]),
+    ("sex-adultery", [0, 8, 10, 18]),
+    ("sex-appearance", [0, 10, 10, 15]),
+    ("language-profanity", [0, 8, 11, 14]),
+    ("language-humor", [0, 3, 8, 14]),
+    ("language-discrimination", [0, 9, 10, 11]),
+    ("social-chat", [0, 4, 10, 13]),
+    ("social-info", [0, 0, 13, 13]),
+    ("social-audio", [0, 15, 15, 15]),
+    ("social-location", [0, 13, 13, 13]),
+    ("social-contacts", [0, 12, 12, 12]),
+    ("money-purchasing", [0, 15, 15, 15]),
+    ("money-gambling", [0, 18, 18, 18]),
+];
+
+fn oarsage(attr: &str, value: &str) -> u32 {
+    let level = match value {
+        "mild" => 1,
+        "moderate" => 2,
+        "intense" => 3,
+        _ => 0,
+    };
+    OARSAGES
+        .iter()
+        .find(|(x, _)| *x == attr)
+        .map(|(_, ages)| ages[level])
+        .unwrap_or(0)
+}
+
+// Versions are sometimes written as plain YAML numbers
+fn lenientstring<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Option<String>, D::Error> {
+    Ok(match serde_yaml::Value::deserialize(d)? {
+        serde_yaml::Value::String(s) => Some(s),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppDeveloper {
+    pub id: Option<String>,
+    pub name: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppRelease {
+    #[serde(default, deserialize_with = "lenientstring")]
+    pub version: Option<String>,
+    #[serde(rename = "type")]
+    pub releasetype: Option<String>,
+    #[serde(rename = "unix-timestamp")]
+    pub timestamp: Option<i64>,
+    pub description: Option<HashMap<String, String>>,
+}
+
+impl AppRelease {
+    pub fn localdescription(&self) -> Option<&str> {
+        self.description
+            .as_ref()
+            .and_then(|x| localized(x, &languages()))
+            .map(|x| x.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppUrl {
+    pub homepage: Option<String>,
+    pub bugtracker: Option<String>,
+    pub help: Option<String>,
+    pub donation: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppIconList {
+    pub cached: Option<Vec<AppIcon>>,
+    pub stock: Option<String>,
+    pub remote: Option<Vec<AppRemoteIcon>>,
+}
+
+impl AppIconList {
+    // The largest icon that has to be downloaded
+    pub fn remoteurl(&self) -> Option<&str> {
+        self.remote
+            .iter()
+            .flatten()
+            .max_by_key(|x| x.height.unwrap_or(0))
+            .map(|x| x.url.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct AppIcon {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct AppRemoteIcon {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppLaunchable {
+    #[serde(rename = "desktop-id")]
+    pub desktopid: Vec<String>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppProvides {
+    pub binaries: Option<Vec<String>>,
+    pub ids: Option<Vec<String>>,
+    pub mediatypes: Option<Vec<String>>,
+    pub libraries: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppScreenshot {
+    pub default: Option<bool>,
+    pub thumbnails: Option<Vec<String>>,
+    #[serde(rename = "source-image")]
+    pub sourceimage: Option<AppScreenshotImage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppScreenshotImage {
+    pub url: String,
+}
@@ -0,0 +1,492 @@
+use super::{config::FlakeSource, flakes};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileElement {
+    // Elements are named from version 3 on, before that they are addressed by index
+    pub name: Option<String>,
+    pub index: usize,
+    pub attrpath: Option<String>,
+    pub originalurl: Option<String>,
+    pub url: Option<String>,
+    pub storepaths: Vec<String>,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProfileManifest {
+    pub version: u32,
+    pub elements: Vec<ProfileElement>,
+}
+
+#[derive(Deserialize)]
+struct RawManifest {
+    version: u32,
+    #[serde(default)]
+    elements: RawElements,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawElements {
+    List(Vec<RawElement>),
+    Map(HashMap<String, RawElement>),
+}
+
+impl Default for RawElements {
+    fn default() -> Self {
+        RawElements::List(vec![])
+    }
+}
+
+// Version 1 used uri and originalUri, later versions url and originalUrl
+#[derive(Deserialize)]
+struct RawElement {
+    #[serde(rename = "attrPath")]
+    attrpath: Option<String>,
+    #[serde(rename = "originalUrl", alias = "originalUri")]
+    originalurl: Option<String>,
+    #[serde(alias = "uri")]
+    url: Option<String>,
+    #[serde(rename = "storePaths", default)]
+    storepaths: Vec<String>,
+    active: Option<bool>,
+}
+
+pub fn manifestpath() -> Option<String> {
+    let home = std::env::var("HOME").ok()?;
+    Some(format!("{}/.nix-profile/manifest.json", home))
+}
+
+impl ProfileManifest {
+    pub fn load() -> Result<Self> {
+        let path = manifestpath().ok_or_else(|| anyhow!("HOME is not set"))?;
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(json: &str) -> Result<Self> {
+        let raw: RawManifest = serde_json::from_str(json)?;
+        if !(1..=3).contains(&raw.version) {
+            return Err(anyhow!(
+                "Unsupported profile manifest version {}",
+                raw.version
+            ));
+        }
+        let named = match raw.elements {
+            RawElements::List(v) => v.into_iter().map(|e| (None, e)).collect::<Vec<_>>(),
+            RawElements::Map(m) => {
+                // Keep the order nix shows them in
+                let mut v = m.into_iter().map(|(n, e)| (Some(n), e)).collect::<Vec<_>>();
+                v.sort_by(|(a, _), (b, _)| a.cmp(b));
+                v
+            }
+        };
+        let elements = named
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, e))| ProfileElement {
+                name,
+                index,
+                attrpath: e.attrpath,
+                originalurl: e.originalurl,
+                url: e.url,
+                storepaths: e.storepaths,
+                active: e.active.unwrap_or(true),
+            })
+            .collect();
+        Ok(Self {
+            version: raw.version,
+            elements,
+        })
+    }
+
+    // Prefers elements from nixpkgs over other flakes exposing the same attribute
+    pub fn find(
+        &self,
+        attribute: &str,
+        system: &str,
+        sources: &[FlakeSource],
+    ) -> Option<&ProfileElement> {
+        if let Some((name, attr)) = attribute.split_once('#') {
+            let source = sources.iter().find(|x| x.name == name)?;
+            return self.elements.iter().find(|e| {
+                e.originalurl.as_deref() == Some(source.url.as_str())
+                    && e.attribute(system).as_deref() == Some(attr)
+            });
+        }
+        let mut matches = self
+            .elements
+            .iter()
+            .filter(|e| e.attribute(system).as_deref() == Some(attribute));
+        let first = matches.next()?;
+        if first.fromnixpkgs() {
+            Some(first)
+        } else {
+            matches.find(|e| e.fromnixpkgs()).or(Some(first))
+        }
+    }
+
+    // Installed attributes and their versions, from nixpkgs and the configured flake sources
+    pub fn installed(&self, system: &str, sources: &[FlakeSource]) -> HashMap<String, String> {
+        self.elements
+            .iter()
+            .filter_map(|e| Some((e.key(system, sources)?, e.version().unwrap_or_default())))
+            .collect()
+    }
+
+    // Elements installed from a flake, the only ones `nix profile upgrade` can touch
+    pub fn upgradable(&self) -> Vec<&ProfileElement> {
+        self.elements
+            .iter()
+            .filter(|e| e.attrpath.is_some() && e.originalurl.is_some())
+            .collect()
+    }
+
+    // Selectors of the elements to upgrade, leaving out held ones and, if given, those not in only
+    pub fn upgrades(
+        &self,
+        system: &str,
+        sources: &[FlakeSource],
+        held: &HashSet<String>,
+        only: Option<&[String]>,
+    ) -> Vec<String> {
+        self.upgradable()
+            .iter()
+            .filter(|e| match e.key(system, sources) {
+                Some(x) => !held.contains(&x) && only.map(|o| o.contains(&x)).unwrap_or(true),
+                None => only.is_none(),
+            })
+            .map(|e| e.selector())
+            .collect()
+    }
+}
+
+impl ProfileElement {
+    // What `nix profile remove` and `nix profile upgrade` accept for this element
+    pub fn selector(&self) -> String {
+        match &self.name {
+            Some(name) => name.to_string(),
+            None => self.index.to_string(),
+        }
+    }
+
+    pub fn attribute(&self, system: &str) -> Option<String> {
+        let attrpath = self.attrpath.as_ref()?;
+        ["legacyPackages", "packages"].iter().find_map(|output| {
+            attrpath
+                .strip_prefix(&format!("{}.{}.", output, system))
+                .map(|x| x.to_string())
+        })
+    }
+
+    // The attribute the rest of the app knows this element by
+    pub fn key(&self, system: &str, sources: &[FlakeSource]) -> Option<String> {
+        let attr = self.attribute(system)?;
+        if self.fromnixpkgs() {
+            return Some(attr);
+        }
+        let source = sources
+            .iter()
+            .find(|x| self.originalurl.as_deref() == Some(x.url.as_str()))?;
+        Some(flakes::attribute(&source.name, &attr))
+    }
+
+    pub fn fromnixpkgs(&self) -> bool {
+        self.originalurl
+            .as_ref()
+            .map(|u| u.to_lowercase().contains("nixpkgs"))
+            .unwrap_or(false)
+    }
+
+    // Store paths look like /nix/store/<hash>-<name>-<version>
+    pub fn version(&self) -> Option<String> {
+        let path = self.storepaths.first()?;
+        let base = path.rsplit('/').next()?;
+        let (_, name) = base.split_once('-')?;
+        name.char_indices()
+            .find(|(i, c)| {
+                *c == '-' && name[i + 1..].starts_with(|x: char| !x.is_ascii_alphabetic())
+            })
+            .map(|(i, _)| name[i + 1..].to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SYSTEM: &str = "x86_64-linux";
+
+    // Written by nix 2.4, elements are addressed by index and use uri and originalUri
+    static V1: &str = r#"{
+  "version": 1,
+  "elements": [
+    {
+      "active": true,
+      "attrPath": "legacyPackages.x86_64-linux.hello",
+      "originalUri": "flake:nixpkgs",
+      "storePaths": ["/nix/store/aaaa-hello-2.12.1"],
+      "uri": "github:NixOS/nixpkgs/0123456789abcdef"
+    },
+    {
+      "active": true,
+      "storePaths": ["/nix/store/bbbb-local-thing"]
+    },
+    {
+      "active": false,
+      "attrPath": "packages.x86_64-linux.default",
+      "originalUri": "github:me/tool",
+      "storePaths": ["/nix/store/cccc-tool-0.3"],
+      "uri": "github:me/tool/fedcba9876543210"
+    }
+  ]
+}"#;
+
+    // Like version 1 with url and originalUrl, here with hello also installed from a fork before nixpkgs
+    static V2: &str = r#"{
+  "version": 2,
+  "elements": [
+    {
+      "active": true,
+      "attrPath": "packages.x86_64-linux.hello",
+      "originalUrl": "github:other/fork",
+      "storePaths": ["/nix/store/dddd-hello-9.9"],
+      "url": "github:other/fork/0000000000000000"
+    },
+    {
+      "active": true,
+      "attrPath": "legacyPackages.x86_64-linux.hello",
+      "originalUrl": "flake:nixpkgs",
+      "storePaths": ["/nix/store/aaaa-hello-2.12.1"],
+      "url": "github:NixOS/nixpkgs/0123456789abcdef"
+    },
+    {
+      "active": true,
+      "attrPath": "legacyPackages.aarch64-linux.ripgrep",
+      "originalUrl": "flake:nixpkgs",
+      "storePaths": ["/nix/store/eeee-ripgrep-14.1.0"],
+      "url": "github:NixOS/nixpkgs/0123456789abcdef"
+    }
+  ]
+}"#;
+
+    // Written by nix 2.20 and later, elements are named
+    static V3: &str = r#"{
+  "version": 3,
+  "elements": {
+    "tool": {
+      "active": true,
+      "attrPath": "packages.x86_64-linux.default",
+      "originalUrl": "github:me/tool",
+      "storePaths": ["/nix/store/cccc-tool-0.3"],
+      "url": "github:me/tool/fedcba9876543210"
+    },
+    "ripgrep": {
+      "active": true,
+      "attrPath": "legacyPackages.x86_64-linux.ripgrep",
+      "originalUrl": "flake:nixpkgs",
+      "storePaths": ["/nix/store/eeee-ripgrep-14.1.0"],
+      "url": "github:NixOS/nixpkgs/0123456789abcdef"
+    },
+    "hello": {
+      "active": true,
+      "attrPath": "legacyPackages.x86_64-linux.hello",
+      "originalUrl": "flake:nixpkgs",
+      "storePaths": ["/nix/store/aaaa-hello-2.12.1"],
+      "url": "github:NixOS/nixpkgs/0123456789abcdef"
+    },
+    "unknown": {
+      "active": true,
+      "attrPath": "packages.x86_64-linux.default",
+      "originalUrl": "github:someone/else",
+      "storePaths": ["/nix/store/ffff-else-1.0"],
+      "url": "github:someone/else/1111111111111111"
+    },
+    "local": {
+      "active": true,
+      "storePaths": ["/nix/store/bbbb-local-thing"]
+    }
+  }
+}"#;
+
+    fn sources() -> Vec<FlakeSource> {
+        vec![FlakeSource {
+            name: String::from("mine"),
+            url: String::from("github:me/tool"),
+        }]
+    }
+
+    fn selector(manifest: &ProfileManifest, attribute: &str) -> Option<String> {
+        manifest
+            .find(attribute, SYSTEM, &sources())
+            .map(|e| e.selector())
+    }
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn parsev1() {
+        let manifest = ProfileManifest::parse(V1).unwrap();
+        assert_eq!(manifest.version, 1);
+        assert_eq!(manifest.elements.len(), 3);
+        let hello = &manifest.elements[0];
+        assert_eq!(hello.name, None);
+        assert_eq!(hello.index, 0);
+        assert_eq!(hello.originalurl.as_deref(), Some("flake:nixpkgs"));
+        assert_eq!(
+            hello.url.as_deref(),
+            Some("github:NixOS/nixpkgs/0123456789abcdef")
+        );
+        assert_eq!(hello.attribute(SYSTEM).as_deref(), Some("hello"));
+        assert_eq!(hello.version().as_deref(), Some("2.12.1"));
+        assert!(hello.active);
+        assert!(hello.fromnixpkgs());
+
+        let local = &manifest.elements[1];
+        assert_eq!(local.attribute(SYSTEM), None);
+        assert_eq!(local.version(), None);
+        assert!(local.active);
+
+        let tool = &manifest.elements[2];
+        assert_eq!(tool.attribute(SYSTEM).as_deref(), Some("default"));
+        assert_eq!(tool.version().as_deref(), Some("0.3"));
+        assert!(!tool.active);
+        assert!(!tool.fromnixpkgs());
+    }
+
+    #[test]
+    fn parsev3() {
+        let manifest = ProfileManifest::parse(V3).unwrap();
+        assert_eq!(manifest.version, 3);
+        // Named elements are kept in the order nix lists them, by name
+        assert_eq!(
+            manifest
+                .elements
+                .iter()
+                .map(|e| (e.index, e.name.clone().unwrap()))
+                .collect::<Vec<_>>(),
+            [
+                (0, String::from("hello")),
+                (1, String::from("local")),
+                (2, String::from("ripgrep")),
+                (3, String::from("tool")),
+                (4, String::from("unknown")),
+            ]
+        );
+        assert_eq!(
+            manifest.elements[2].attribute(SYSTEM).as_deref(),
+            Some("ripgrep")
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(ProfileManifest::parse(r#"{"version": 4, "elements": {}}"#).is_err());
+        assert!(ProfileManifest::parse(r#"{"version": 0, "elements": []}"#).is_err());
+        assert!(ProfileManifest::parse("not json").is_err());
+        assert!(ProfileManifest::parse(r#"{"version": 2}"#)
+            .unwrap()
+            .elements
+            .is_empty());
+    }
+
+    #[test]
+    fn remove() {
+        // Older manifests are addressed by index
+        let manifest = ProfileManifest::parse(V1).unwrap();
+        assert_eq!(selector(&manifest, "hello").as_deref(), Some("0"));
+        assert_eq!(selector(&manifest, "mine#default").as_deref(), Some("2"));
+        // Without nixpkgs having it, a plain attribute falls back to other flakes
+        assert_eq!(selector(&manifest, "default").as_deref(), Some("2"));
+        assert_eq!(selector(&manifest, "other#default"), None);
+        assert_eq!(selector(&manifest, "missing"), None);
+
+        // The nixpkgs element is removed even when another flake's hello comes first
+        let manifest = ProfileManifest::parse(V2).unwrap();
+        assert_eq!(selector(&manifest, "hello").as_deref(), Some("1"));
+        // Elements for another system aren't installed here
+        assert_eq!(selector(&manifest, "ripgrep"), None);
+        assert_eq!(
+            manifest
+                .find("ripgrep", "aarch64-linux", &[])
+                .map(|e| e.selector())
+                .as_deref(),
+            Some("2")
+        );
+
+        // Newer ones by name
+        let manifest = ProfileManifest::parse(V3).unwrap();
+        assert_eq!(selector(&manifest, "hello").as_deref(), Some("hello"));
+        assert_eq!(selector(&manifest, "mine#default").as_deref(), Some("tool"));
+        assert_eq!(selector(&manifest, "local"), None);
+    }
+
+    #[test]
+    fn upgrade() {
+        let manifest = ProfileManifest::parse(V3).unwrap();
+        let held = HashSet::new();
+        // Elements without a flake can't be upgraded, those from unknown flakes still can
+        assert_eq!(
+            manifest.upgrades(SYSTEM, &sources(), &held, None),
+            strings(&["hello", "ripgrep", "tool", "unknown"])
+        );
+        let held = HashSet::from([String::from("ripgrep"), String::from("mine#default")]);
+        assert_eq!(
+            manifest.upgrades(SYSTEM, &sources(), &held, None),
+            strings(&["hello", "unknown"])
+        );
+        let only = strings(&["ripgrep", "hello"]);
+        assert_eq!(
+            manifest.upgrades(SYSTEM, &sources(), &held, Some(&only)),
+            strings(&["hello"])
+        );
+        // Without the source, its elements can only be upgraded along with everything else
+        assert_eq!(
+            manifest.upgrades(
+                SYSTEM,
+                &[],
+                &HashSet::new(),
+                Some(&strings(&["mine#default"]))
+            ),
+            Vec::<String>::new()
+        );
+
+        let manifest = ProfileManifest::parse(V1).unwrap();
+        assert_eq!(
+            manifest.upgrades(SYSTEM, &sources(), &HashSet::new(), None),
+            strings(&["0", "2"])
+        );
+    }
+
+    #[test]
+    fn installed() {
+        let manifest = ProfileManifest::parse(V3).unwrap();
+        assert_eq!(
+            manifest.installed(SYSTEM, &sources()),
+            HashMap::from([
+                (String::from("hello"), String::from("2.12.1")),
+                (String::from("ripgrep"), String::from("14.1.0")),
+                (String::from("mine#default"), String::from("0.3")),
+            ])
+        );
+        // Packages from flakes that aren't sources can't be shown
+        assert_eq!(manifest.installed(SYSTEM, &[]).len(), 2);
+
+        let manifest = ProfileManifest::parse(V2).unwrap();
+        assert_eq!(
+            manifest.installed(SYSTEM, &sources()),
+            HashMap::from([(String::from("hello"), String::from("2.12.1"))])
+        );
+        assert_eq!(
+            manifest.installed("aarch64-linux", &sources()),
+            HashMap::from([(String::from("ripgrep"), String::from("14.1.0"))])
+        );
+    }
+}
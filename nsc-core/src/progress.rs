@@ -0,0 +1,248 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Activity and result types from nix's `--log-format internal-json`
+const ACT_COPYPATHS: u64 = 103;
+const ACT_FILETRANSFER: u64 = 101;
+const ACT_BUILDS: u64 = 104;
+const ACT_BUILD: u64 = 105;
+
+const RES_BUILDLOGLINE: u64 = 101;
+const RES_SETPHASE: u64 = 104;
+const RES_PROGRESS: u64 = 105;
+const RES_SETEXPECTED: u64 = 106;
+const RES_POSTBUILDLOGLINE: u64 = 107;
+
+// Same as nix's default verbosity
+const LVL_INFO: u64 = 3;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum NixLog {
+    Start {
+        id: u64,
+        #[serde(rename = "type")]
+        acttype: u64,
+        #[serde(default)]
+        level: u64,
+        #[serde(default)]
+        text: String,
+        #[serde(default)]
+        fields: Vec<serde_json::Value>,
+    },
+    Stop {
+        id: u64,
+    },
+    Result {
+        id: u64,
+        #[serde(rename = "type")]
+        restype: u64,
+        #[serde(default)]
+        fields: Vec<serde_json::Value>,
+    },
+    Msg {
+        level: u64,
+        msg: String,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    acttype: u64,
+    done: u64,
+    expected: u64,
+    running: u64,
+    name: Option<String>,
+    phase: Option<String>,
+    setexpected: HashMap<u64, u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Progress {
+    activities: HashMap<u64, Activity>,
+    // Progress of stopped activities, by activity type
+    finished: HashMap<u64, (u64, u64)>,
+    // Running builds in the order they started
+    building: Vec<u64>,
+}
+
+impl Progress {
+    // Returns the text that nix would have printed for this line, if any
+    pub fn parse(&mut self, line: &str) -> Option<String> {
+        let json = match line.strip_prefix("@nix ") {
+            Some(json) => json,
+            None => return Some(line.to_string()),
+        };
+        let log = match serde_json::from_str::<NixLog>(json) {
+            Ok(log) => log,
+            Err(_) => return Some(line.to_string()),
+        };
+        match log {
+            NixLog::Start {
+                id,
+                acttype,
+                level,
+                text,
+                fields,
+            } => {
+                let name = if acttype == ACT_BUILD {
+                    self.building.push(id);
+                    fields.first().and_then(|x| x.as_str()).map(drvname)
+                } else {
+                    None
+                };
+                self.activities.insert(
+                    id,
+                    Activity {
+                        acttype,
+                        name,
+                        ..Default::default()
+                    },
+                );
+                if level <= LVL_INFO && !text.is_empty() {
+                    Some(stripansi(&text))
+                } else {
+                    None
+                }
+            }
+            NixLog::Stop { id } => {
+                if let Some(act) = self.activities.remove(&id) {
+                    let finished = self.finished.entry(act.acttype).or_default();
+                    finished.0 += act.done;
+                    finished.1 += act.expected;
+                }
+                self.building.retain(|x| x != &id);
+                None
+            }
+            NixLog::Result {
+                id,
+                restype,
+                fields,
+            } => {
+                let field = |i: usize| fields.get(i).and_then(|x| x.as_u64()).unwrap_or(0);
+                match restype {
+                    RES_BUILDLOGLINE | RES_POSTBUILDLOGLINE => {
+                        return fields.first().and_then(|x| x.as_str()).map(stripansi);
+                    }
+                    RES_SETPHASE => {
+                        if let Some(act) = self.activities.get_mut(&id) {
+                            act.phase = fields.first().and_then(|x| x.as_str()).map(String::from);
+                        }
+                    }
+                    RES_PROGRESS => {
+                        if let Some(act) = self.activities.get_mut(&id) {
+                            act.done = field(0);
+                            act.expected = field(1);
+                            act.running = field(2);
+                        }
+                    }
+                    RES_SETEXPECTED => {
+                        if let Some(act) = self.activities.get_mut(&id) {
+                            act.setexpected.insert(field(0), field(1));
+                        }
+                    }
+                    _ => {}
+                }
+                None
+            }
+            NixLog::Msg { level, msg } => {
+                if level <= LVL_INFO {
+                    Some(stripansi(&msg))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // Done and expected work for all activities of a type
+    fn total(&self, acttype: u64) -> (u64, u64, u64) {
+        let (mut done, mut expected) = self.finished.get(&acttype).cloned().unwrap_or_default();
+        let mut running = 0;
+        let mut setexpected = 0;
+        for act in self.activities.values() {
+            if act.acttype == acttype {
+                done += act.done;
+                expected += act.expected;
+                running += act.running;
+            }
+            setexpected += act.setexpected.get(&acttype).unwrap_or(&0);
+        }
+        (done, expected.max(setexpected), running)
+    }
+
+    pub fn fraction(&self) -> Option<f64> {
+        let (builtdone, builtexpected, _) = self.total(ACT_BUILDS);
+        let (copydone, copyexpected, _) = self.total(ACT_COPYPATHS);
+        let expected = builtexpected + copyexpected;
+        if expected == 0 {
+            None
+        } else {
+            Some(((builtdone + copydone) as f64 / expected as f64).min(1.0))
+        }
+    }
+
+    pub fn status(&self) -> Option<String> {
+        let mut status = vec![];
+        let (done, expected, running) = self.total(ACT_BUILDS);
+        if expected > 0 {
+            let current = if running > 0 { done + 1 } else { done };
+            let mut s = format!("building {} of {}", current.min(expected), expected);
+            if let Some(act) = self.building.last().and_then(|x| self.activities.get(x)) {
+                match (&act.name, &act.phase) {
+                    (Some(name), Some(phase)) => s.push_str(&format!(" ({}: {})", name, phase)),
+                    (Some(name), None) => s.push_str(&format!(" ({})", name)),
+                    _ => {}
+                }
+            }
+            status.push(s);
+        }
+        let (done, expected, _) = self.total(ACT_FILETRANSFER);
+        if done > 0 {
+            if expected > 0 {
+                status.push(format!(
+                    "downloading {:.1} of {:.1} MiB",
+                    done as f64 / MIB,
+                    expected as f64 / MIB
+                ));
+            } else {
+                status.push(format!("downloading {:.1} MiB", done as f64 / MIB));
+            }
+        }
+        let status = status.join(", ");
+        let mut chars = status.chars();
+        chars
+            .next()
+            .map(|c| c.to_uppercase().chain(chars).collect())
+    }
+}
+
+fn drvname(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let name = name.strip_suffix(".drv").unwrap_or(name);
+    match name.split_once('-') {
+        Some((_, n)) => n.to_string(),
+        None => name.to_string(),
+    }
+}
+
+fn stripansi(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
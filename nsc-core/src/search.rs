@@ -0,0 +1,1079 @@
+use super::{
+    db::SearchResult,
+    flakes,
+    locale::{self, localized, translated},
+    packages::AppData,
+    InstalledState, UserPkgs,
+};
+use anyhow::Result;
+use log::*;
+use serde::Serialize;
+use sqlx::{sqlite::SqliteConnectOptions, Connection, QueryBuilder, Sqlite, SqlitePool};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::UNIX_EPOCH,
+};
+
+// Bump when the index schema changes, so existing indexes get rebuilt
+static INDEXVERSION: u32 = 5;
+static MAXRESULTS: usize = 200;
+static APPSTREAMBOOST: f64 = 1.5;
+static INSTALLEDBOOST: f64 = 1.25;
+static MAXCORRECTIONS: usize = 3;
+static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchFilter {
+    License(String),
+    Maintainer(String),
+    Installed(bool),
+    Category(String),
+    Platform(String),
+    Free(bool),
+    Gui,
+}
+
+impl SearchFilter {
+    fn parse(key: &str, value: &str) -> Option<Self> {
+        let flag = || match value.to_lowercase().as_str() {
+            "" | "true" | "yes" => Some(true),
+            "false" | "no" => Some(false),
+            _ => None,
+        };
+        match key.to_lowercase().as_str() {
+            "license" if !value.is_empty() => Some(SearchFilter::License(value.to_string())),
+            "maintainer" if !value.is_empty() => Some(SearchFilter::Maintainer(value.to_string())),
+            "installed" => flag().map(SearchFilter::Installed),
+            "category" if !value.is_empty() => Some(SearchFilter::Category(value.to_string())),
+            "platform" if !value.is_empty() => Some(SearchFilter::Platform(value.to_string())),
+            "free" => flag().map(SearchFilter::Free),
+            "has" if value.eq_ignore_ascii_case("gui") => Some(SearchFilter::Gui),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SearchFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let quoted = |x: &str| {
+            if x.contains(char::is_whitespace) {
+                format!("\"{}\"", x)
+            } else {
+                x.to_string()
+            }
+        };
+        match self {
+            SearchFilter::License(x) => write!(f, "license:{}", quoted(x)),
+            SearchFilter::Maintainer(x) => write!(f, "maintainer:{}", quoted(x)),
+            SearchFilter::Installed(true) => write!(f, "installed:"),
+            SearchFilter::Installed(false) => write!(f, "installed:false"),
+            SearchFilter::Category(x) => write!(f, "category:{}", quoted(x)),
+            SearchFilter::Platform(x) => write!(f, "platform:{}", quoted(x)),
+            SearchFilter::Free(x) => write!(f, "free:{}", x),
+            SearchFilter::Gui => write!(f, "has:gui"),
+        }
+    }
+}
+
+// Free text plus `key:value` filters, e.g. `editor license:mit has:gui`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub text: String,
+    pub filters: Vec<SearchFilter>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Self {
+        let mut text = vec![];
+        let mut filters = vec![];
+        for word in splitquery(query) {
+            let filter = word
+                .split_once(':')
+                .and_then(|(key, value)| SearchFilter::parse(key, value));
+            match filter {
+                Some(filter) => {
+                    if !filters.contains(&filter) {
+                        filters.push(filter);
+                    }
+                }
+                // Unknown keys are searched for like any other text
+                None => text.push(word),
+            }
+        }
+        Self {
+            text: text.join(" "),
+            filters,
+        }
+    }
+
+    pub fn isempty(&self) -> bool {
+        searchterms(&self.text).is_empty() && self.filters.is_empty()
+    }
+
+    pub fn toggle(&mut self, filter: SearchFilter) {
+        if let Some(i) = self.filters.iter().position(|x| *x == filter) {
+            self.filters.remove(i);
+        } else {
+            self.filters.push(filter);
+        }
+    }
+}
+
+impl fmt::Display for SearchQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = self
+            .filters
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        if !self.text.is_empty() {
+            words.push(self.text.to_string());
+        }
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProvideKind {
+    Binary,
+    Library,
+    MediaType,
+}
+
+impl ProvideKind {
+    fn name(&self) -> &str {
+        match self {
+            ProvideKind::Binary => "binary",
+            ProvideKind::Library => "library",
+            ProvideKind::MediaType => "mediatype",
+        }
+    }
+
+    fn fromname(name: &str) -> Option<Self> {
+        match name {
+            "binary" => Some(ProvideKind::Binary),
+            "library" => Some(ProvideKind::Library),
+            "mediatype" => Some(ProvideKind::MediaType),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProvideSource {
+    AppStream,
+    NixIndex,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Provider {
+    pub attribute: String,
+    pub kind: ProvideKind,
+    pub source: ProvideSource,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchIndex {
+    pool: SqlitePool,
+}
+
+impl SearchIndex {
+    pub fn path() -> Result<PathBuf> {
+        let home = std::env::var("HOME")?;
+        Ok(PathBuf::from(format!(
+            "{}/.cache/nix-software-center/search.db",
+            home
+        )))
+    }
+
+    // Opens the index, rebuilding it first if the package database or appstream data changed
+    // appstreamkey is the key of the AppStream the apps were loaded with
+    pub async fn open(
+        pkgdb: &str,
+        appdata: &HashMap<String, AppData>,
+        appstreamkey: &str,
+    ) -> Result<Self> {
+        let path = Self::path()?;
+        let source = sourcekey(pkgdb, appstreamkey)?;
+        if let Ok(index) = Self::connect(&path).await {
+            if index.source().await.ok().as_deref() == Some(source.as_str()) {
+                return Ok(index);
+            }
+            index.pool.close().await;
+        }
+        Self::build(&path, pkgdb, appdata, &source).await?;
+        Self::connect(&path).await
+    }
+
+    pub async fn connect(path: &Path) -> Result<Self> {
+        let options = SqliteConnectOptions::new().filename(path).read_only(true);
+        Ok(Self {
+            pool: SqlitePool::connect_with(options).await?,
+        })
+    }
+
+    async fn source(&self) -> Result<String> {
+        let (source,): (String,) = sqlx::query_as("SELECT key FROM source")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(source)
+    }
+
+    async fn build(
+        path: &Path,
+        pkgdb: &str,
+        appdata: &HashMap<String, AppData>,
+        source: &str,
+    ) -> Result<()> {
+        info!("Building search index");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Built next to the index and moved into place, so searches never see a partial index
+        let tmp = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            BUILDS.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_file(&tmp);
+        let options = SqliteConnectOptions::new()
+            .filename(&tmp)
+            .create_if_missing(true);
+        let mut conn = sqlx::SqliteConnection::connect_with(&options).await?;
+
+        sqlx::query(
+            "CREATE VIRTUAL TABLE search USING fts5(attribute, pname, name, keywords, description, longdescription, summary, version UNINDEXED, appstream UNINDEXED, gui UNINDEXED, categories UNINDEXED, license UNINDEXED, platforms UNINDEXED, maintainers UNINDEXED)",
+        )
+        .execute(&mut conn)
+        .await?;
+        sqlx::query("CREATE VIRTUAL TABLE vocab USING fts5vocab(search, row)")
+            .execute(&mut conn)
+            .await?;
+        sqlx::query("CREATE TABLE source (key TEXT NOT NULL)")
+            .execute(&mut conn)
+            .await?;
+        sqlx::query(
+            "CREATE TABLE provides (value TEXT NOT NULL COLLATE NOCASE, kind TEXT NOT NULL, attribute TEXT NOT NULL)",
+        )
+        .execute(&mut conn)
+        .await?;
+        sqlx::query("CREATE INDEX provides_value ON provides (value)")
+            .execute(&mut conn)
+            .await?;
+        sqlx::query(
+            "CREATE TEMP TABLE apps (attribute TEXT PRIMARY KEY, name TEXT, keywords TEXT, summary TEXT, gui INTEGER, categories TEXT)",
+        )
+        .execute(&mut conn)
+        .await?;
+        sqlx::query("ATTACH DATABASE $1 AS src")
+            .bind(pkgdb)
+            .execute(&mut conn)
+            .await?;
+
+        // Translations are indexed next to the untranslated text, so both can be searched for
+        let languages = locale::languages();
+        let mut tx = conn.begin().await?;
+        for (attribute, data) in appdata {
+            let c = data.name.as_ref().and_then(|x| x.get("C"));
+            let name = match (
+                data.name.as_ref().and_then(|x| translated(x, &languages)),
+                c,
+            ) {
+                (Some(x), Some(c)) => Some(format!("{} {}", x, c)),
+                (x, c) => x.or(c).cloned(),
+            };
+            let mut keywords = data
+                .keywords
+                .as_ref()
+                .map(|x| {
+                    let mut words = localized(x, &languages).cloned().unwrap_or_default();
+                    for word in x.get("C").into_iter().flatten() {
+                        if !words.contains(word) {
+                            words.push(word.to_string());
+                        }
+                    }
+                    words
+                })
+                .unwrap_or_default();
+            // So apps can be found by who makes them
+            if let Some(developer) = data.localdeveloper() {
+                keywords.push(developer.to_string());
+            }
+            // Package descriptions are only in English, so translated summaries get their own column
+            let summary = data
+                .summary
+                .as_ref()
+                .and_then(|x| translated(x, &languages));
+            let gui = data.metatype == "desktop-application" || data.launchable.is_some();
+            let categories = serde_json::to_string(&data.categories.clone().unwrap_or_default())?;
+            sqlx::query("INSERT OR IGNORE INTO apps VALUES ($1, $2, $3, $4, $5, $6)")
+                .bind(attribute)
+                .bind(name)
+                .bind(keywords.join(" "))
+                .bind(summary)
+                .bind(gui)
+                .bind(categories)
+                .execute(&mut *tx)
+                .await?;
+            if let Some(provides) = &data.provides {
+                for (kind, values) in [
+                    (ProvideKind::Binary, &provides.binaries),
+                    (ProvideKind::Library, &provides.libraries),
+                    (ProvideKind::MediaType, &provides.mediatypes),
+                ] {
+                    for value in values.iter().flatten() {
+                        sqlx::query("INSERT INTO provides VALUES ($1, $2, $3)")
+                            .bind(value)
+                            .bind(kind.name())
+                            .bind(attribute)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                }
+            }
+        }
+        sqlx::query(
+            r#"
+INSERT INTO search (attribute, pname, name, keywords, description, longdescription, summary, version, appstream, gui, categories, license, platforms, maintainers)
+SELECT pkgs.attribute, pkgs.pname, IFNULL(apps.name, ''), IFNULL(apps.keywords, ''), IFNULL(meta.description, ''), IFNULL(meta.longdescription, ''), IFNULL(apps.summary, ''), pkgs.version, apps.attribute IS NOT NULL,
+    IFNULL(apps.gui, 0), IFNULL(apps.categories, '[]'),
+    CASE WHEN json_valid(meta.license) THEN meta.license ELSE 'null' END,
+    CASE WHEN json_valid(meta.platforms) THEN meta.platforms ELSE 'null' END,
+    CASE WHEN json_valid(meta.maintainers) THEN meta.maintainers ELSE 'null' END
+FROM src.pkgs AS pkgs JOIN src.meta AS meta ON (pkgs.attribute = meta.attribute)
+LEFT JOIN temp.apps AS apps ON (pkgs.attribute = apps.attribute)
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        for source in flakes::sources() {
+            for (attr, pkg) in flakes::cached(&source.name) {
+                sqlx::query(
+                    "INSERT INTO search (attribute, pname, name, keywords, description, longdescription, summary, version, appstream, gui, categories, license, platforms, maintainers) VALUES ($1, $2, '', '', $3, $4, '', $5, 0, 0, '[]', $6, $7, $8)",
+                )
+                .bind(flakes::attribute(&source.name, &attr))
+                .bind(pkg.pname)
+                .bind(pkg.description)
+                .bind(pkg.longdescription)
+                .bind(pkg.version)
+                .bind(pkg.license.to_string())
+                .bind(pkg.platforms.to_string())
+                .bind(pkg.maintainers.to_string())
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        sqlx::query("INSERT INTO search (search) VALUES ('optimize')")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO source VALUES ($1)")
+            .bind(source)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        sqlx::query("DETACH DATABASE src")
+            .execute(&mut conn)
+            .await?;
+        conn.close().await?;
+
+        if let Err(e) = fs::rename(&tmp, path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    // Ranks matches with bm25, favouring appstream apps and packages that are already installed
+    pub async fn search(
+        &self,
+        query: &SearchQuery,
+        installed: &InstalledState,
+        userpkgtype: &UserPkgs,
+    ) -> Result<Vec<SearchResult>> {
+        if query.isempty() {
+            return Ok(vec![]);
+        }
+        let mut matchquery = vec![];
+        for term in searchterms(&query.text) {
+            // A single character starts too many words, so it only matches itself, like the R package
+            if term.chars().count() == 1 {
+                matchquery.push(format!("\"{}\"", term));
+                continue;
+            }
+            // Exact tokens match both alternatives, so they score above longer words with the same prefix
+            let mut alternatives = vec![format!("\"{}\"", term), format!("\"{}\"*", term)];
+            if !self.known(&term).await? {
+                for correction in self.corrections(&term).await? {
+                    debug!("Search term {} corrected to {}", term, correction);
+                    alternatives.push(format!("\"{}\"", correction));
+                }
+            }
+            matchquery.push(format!("({})", alternatives.join(" OR ")));
+        }
+
+        // A single word might also be a command, library or file type, whose providers come first
+        let mut results = vec![];
+        if !query.text.is_empty() && !query.text.contains(char::is_whitespace) {
+            let providers = self
+                .provides(&query.text)
+                .await?
+                .into_iter()
+                .map(|x| x.attribute)
+                .collect::<Vec<_>>();
+            if !providers.is_empty() {
+                results = self
+                    .fetch(query, None, Some(&providers), installed, userpkgtype)
+                    .await?;
+            }
+        }
+        let matchquery = if matchquery.is_empty() {
+            None
+        } else {
+            Some(matchquery.join(" AND "))
+        };
+        for result in self
+            .fetch(query, matchquery, None, installed, userpkgtype)
+            .await?
+        {
+            if !results.contains(&result) {
+                results.push(result);
+            }
+        }
+        results.truncate(MAXRESULTS);
+        Ok(results)
+    }
+
+    async fn fetch(
+        &self,
+        query: &SearchQuery,
+        matchquery: Option<String>,
+        attributes: Option<&[String]>,
+        installed: &InstalledState,
+        userpkgtype: &UserPkgs,
+    ) -> Result<Vec<SearchResult>> {
+        let installedsystem = serde_json::to_string(&installed.system)?;
+        let installeduser = serde_json::to_string(&installed.user.keys().collect::<Vec<_>>())?;
+        let usercolumn = match userpkgtype {
+            UserPkgs::Env => "pname",
+            UserPkgs::Profile | UserPkgs::HomeManager => "attribute",
+        };
+        let pushinstalled = |queryb: &mut QueryBuilder<Sqlite>| {
+            queryb
+                .push("(attribute IN (SELECT value FROM json_each(")
+                .push_bind(installedsystem.clone())
+                .push(format!(
+                    ")) OR {} IN (SELECT value FROM json_each(",
+                    usercolumn
+                ))
+                .push_bind(installeduser.clone())
+                .push(")))");
+        };
+
+        let mut queryb: QueryBuilder<Sqlite> =
+            QueryBuilder::new("SELECT attribute, pname, description, version, ");
+        // Without any text there is nothing to rank, so only the boosts order the results
+        if matchquery.is_some() {
+            queryb.push("bm25(search, 8.0, 10.0, 10.0, 5.0, 2.0, 0.5, 2.0)");
+        } else {
+            queryb.push("-1.0");
+        }
+        queryb
+            .push(" * (CASE appstream WHEN 1 THEN ")
+            .push_bind(APPSTREAMBOOST)
+            .push(" ELSE 1.0 END) * (CASE WHEN ");
+        pushinstalled(&mut queryb);
+        queryb
+            .push(" THEN ")
+            .push_bind(INSTALLEDBOOST)
+            .push(" ELSE 1.0 END) AS score FROM search WHERE 1");
+        if let Some(matchquery) = matchquery {
+            queryb.push(" AND search MATCH ").push_bind(matchquery);
+        }
+        if let Some(attributes) = attributes {
+            queryb
+                .push(" AND attribute IN (SELECT value FROM json_each(")
+                .push_bind(serde_json::to_string(attributes)?)
+                .push("))");
+        }
+        for filter in &query.filters {
+            queryb.push(" AND ");
+            match filter {
+                SearchFilter::License(license) => {
+                    queryb
+                        .push("EXISTS (SELECT 1 FROM json_tree(license) WHERE type = 'text' AND (key IS NULL OR typeof(key) = 'integer' OR key IN ('spdxId', 'fullName', 'shortName')) AND value LIKE ")
+                        .push_bind(format!("{}%", license))
+                        .push(")");
+                }
+                SearchFilter::Maintainer(maintainer) => {
+                    queryb
+                        .push("EXISTS (SELECT 1 FROM json_tree(maintainers) WHERE type = 'text' AND key IN ('github', 'name', 'email', 'matrix') AND value LIKE ")
+                        .push_bind(format!("%{}%", maintainer))
+                        .push(")");
+                }
+                SearchFilter::Installed(yes) => {
+                    if !yes {
+                        queryb.push("NOT ");
+                    }
+                    pushinstalled(&mut queryb);
+                }
+                SearchFilter::Category(category) => {
+                    queryb
+                        .push("EXISTS (SELECT 1 FROM json_each(categories) WHERE lower(value) IN (SELECT value FROM json_each(")
+                        .push_bind(serde_json::to_string(&categorynames(category))?)
+                        .push(")))");
+                }
+                SearchFilter::Platform(platform) => {
+                    queryb
+                        .push("EXISTS (SELECT 1 FROM json_tree(platforms) WHERE type = 'text' AND value = ")
+                        .push_bind(platform.to_string())
+                        .push(")");
+                }
+                // Licenses without a free attribute are counted as free
+                SearchFilter::Free(free) => {
+                    if *free {
+                        queryb.push("NOT ");
+                    }
+                    queryb.push(
+                        "EXISTS (SELECT 1 FROM json_tree(license) WHERE key = 'free' AND type = 'false')",
+                    );
+                }
+                SearchFilter::Gui => {
+                    queryb.push("gui = 1");
+                }
+            }
+        }
+        queryb
+            .push(" ORDER BY score, LENGTH(attribute) LIMIT ")
+            .push_bind(MAXRESULTS as i64);
+
+        let rows: Vec<(String, String, String, String, f64)> =
+            queryb.build_query_as().fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|(attribute, pname, description, version, _)| SearchResult {
+                source: flakes::source(&attribute).map(|x| x.to_string()),
+                attribute,
+                pname,
+                description,
+                version,
+            })
+            .collect())
+    }
+
+    // Packages providing a command, library or MIME type, looking in nix-index for commands AppStream doesn't know
+    pub async fn provides(&self, name: &str) -> Result<Vec<Provider>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT DISTINCT attribute, kind FROM provides WHERE value = $1 OR (kind = 'library' AND value LIKE $2) ORDER BY attribute",
+        )
+        .bind(name)
+        .bind(format!("{}.%", name))
+        .fetch_all(&self.pool)
+        .await?;
+        let providers = rows
+            .into_iter()
+            .filter_map(|(attribute, kind)| {
+                Some(Provider {
+                    attribute,
+                    kind: ProvideKind::fromname(&kind)?,
+                    source: ProvideSource::AppStream,
+                })
+            })
+            .collect::<Vec<_>>();
+        if !providers.is_empty() || name.contains('/') {
+            return Ok(providers);
+        }
+        Ok(nixlocate(name)
+            .await
+            .into_iter()
+            .map(|attribute| Provider {
+                attribute,
+                kind: ProvideKind::Binary,
+                source: ProvideSource::NixIndex,
+            })
+            .collect())
+    }
+
+    // Whether any indexed word starts with term
+    async fn known(&self, term: &str) -> Result<bool> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT term FROM vocab WHERE term >= $1 AND term < $2 LIMIT 1")
+                .bind(term)
+                .bind(format!("{}\u{10ffff}", term))
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.is_some())
+    }
+
+    // Indexed words within a small edit distance of term, most common first
+    async fn corrections(&self, term: &str) -> Result<Vec<String>> {
+        let len = term.chars().count();
+        let maxdistance = match len {
+            0..=3 => return Ok(vec![]),
+            4..=7 => 1,
+            _ => 2,
+        };
+        // Only words with the same first letter are considered, which keeps the vocabulary scan small
+        let first = term.chars().next().unwrap_or_default();
+        let candidates: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT term, doc FROM vocab WHERE term >= $1 AND term < $2 AND LENGTH(term) BETWEEN $3 AND $4",
+        )
+        .bind(first.to_string())
+        .bind(format!("{}\u{10ffff}", first))
+        .bind((len - maxdistance) as i64)
+        .bind((len + maxdistance) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut corrections = candidates
+            .into_iter()
+            .filter_map(|(word, docs)| {
+                let distance = editdistance(term, &word);
+                if distance <= maxdistance {
+                    Some((distance, -docs, word))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        corrections.sort();
+        Ok(corrections
+            .into_iter()
+            .take(MAXCORRECTIONS)
+            .map(|(_, _, word)| word)
+            .collect())
+    }
+}
+
+fn sourcekey(pkgdb: &str, appstreamkey: &str) -> Result<String> {
+    let modified = fs::metadata(pkgdb)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs();
+    Ok(format!(
+        "{}:{}:{}:{}:{}:{}",
+        INDEXVERSION,
+        pkgdb,
+        modified,
+        appstreamkey,
+        flakes::cachekey(),
+        locale::languages().join(",")
+    ))
+}
+
+fn nixindexdb() -> Option<PathBuf> {
+    let dir = match std::env::var("NIX_INDEX_DATABASE") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(format!("{}/.cache/nix-index", std::env::var("HOME").ok()?)),
+    };
+    if dir.join("files").exists() {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+// Top level packages with the command in their bin directory, like nix-index's command-not-found
+async fn nixlocate(command: &str) -> Vec<String> {
+    let db = match nixindexdb() {
+        Some(db) => db,
+        None => return vec![],
+    };
+    let output = tokio::process::Command::new("nix-locate")
+        .arg("--db")
+        .arg(db)
+        .args([
+            "--minimal",
+            "--no-group",
+            "--type",
+            "x",
+            "--type",
+            "s",
+            "--top-level",
+            "--whole-name",
+            "--at-root",
+        ])
+        .arg(format!("/bin/{}", command))
+        .stderr(Stdio::null())
+        .output()
+        .await;
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(_) => return vec![],
+        Err(e) => {
+            debug!("Failed to run nix-locate: {}", e);
+            return vec![];
+        }
+    };
+    let mut attributes = vec![];
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // Results are printed as attribute.output
+        let attribute = match line.trim().rsplit_once('.') {
+            Some((attribute, _)) => attribute.to_string(),
+            None => line.trim().to_string(),
+        };
+        if !attribute.is_empty() && !attributes.contains(&attribute) {
+            attributes.push(attribute);
+        }
+    }
+    attributes
+}
+
+// AppStream category names, also accepting the names of the category pages
+fn categorynames(category: &str) -> Vec<String> {
+    let category = category.to_lowercase();
+    let alias = match category.as_str() {
+        "games" => Some("game"),
+        "web" => Some("network"),
+        "dev" => Some("development"),
+        _ => None,
+    };
+    let mut names = vec![category.to_string()];
+    if let Some(alias) = alias {
+        names.push(alias.to_string());
+    }
+    names
+}
+
+// Splits on whitespace, keeping double quoted values like `maintainer:"Jane Doe"` together
+fn splitquery(query: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// Splits a query the same way the index tokenizer splits words
+pub fn searchterms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect()
+}
+
+// Optimal string alignment distance, so a swap of two neighbouring letters counts as one edit
+fn editdistance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    d[0] = (0..=b.len()).collect();
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // attribute, description, longdescription, license, platforms, maintainers
+    static PKGS: &[(&str, &str, &str, &str, &str, &str)] = &[
+        (
+            "greeter",
+            "Login screen",
+            "",
+            r#"{"spdxId":"MIT","free":true}"#,
+            r#"["x86_64-linux"]"#,
+            r#"[{"github":"jdoe","name":"Jane Doe"}]"#,
+        ),
+        (
+            "lightdm",
+            "A cross-desktop display manager with a greeter",
+            "",
+            r#"{"spdxId":"GPL-3.0-or-later","free":true}"#,
+            r#"["x86_64-linux","aarch64-linux"]"#,
+            "[]",
+        ),
+        (
+            "sddm",
+            "QML based display manager",
+            "Comes with a themable greeter",
+            r#"{"spdxId":"GPL-2.0-only","free":true}"#,
+            r#"["aarch64-linux"]"#,
+            "[]",
+        ),
+        ("aplayer", "Music player", "", "null", "null", "null"),
+        ("bplayer", "Music player", "", "null", "null", "null"),
+        ("cplayer", "Music player", "", "null", "null", "null"),
+        (
+            "r",
+            "Language for statistical computing",
+            "",
+            "null",
+            "null",
+            "null",
+        ),
+        (
+            "unrar",
+            "Utility for RAR archives",
+            "",
+            r#"{"fullName":"unRAR license","free":false}"#,
+            "null",
+            "null",
+        ),
+    ];
+
+    static APPS: &str = "
+Type: console-application
+ID: aplayer
+Package: aplayer
+---
+Type: desktop-application
+ID: org.example.Unrar
+Package: unrar
+Name:
+  C: Archive Opener
+Categories:
+  - Utility
+Provides:
+  binaries:
+    - unrar
+";
+
+    async fn index(dir: &Path) -> SearchIndex {
+        let pkgdb = dir.join("pkgs.db");
+        let options = SqliteConnectOptions::new()
+            .filename(&pkgdb)
+            .create_if_missing(true);
+        let mut conn = sqlx::SqliteConnection::connect_with(&options)
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE pkgs (attribute TEXT, system TEXT, pname TEXT, version TEXT)")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE meta (attribute TEXT, description TEXT, longdescription TEXT, homepage TEXT, license TEXT, platforms TEXT, maintainers TEXT)")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        for (attribute, description, longdescription, license, platforms, maintainers) in PKGS {
+            sqlx::query("INSERT INTO pkgs VALUES ($1, 'x86_64-linux', $1, '1.0')")
+                .bind(attribute)
+                .execute(&mut conn)
+                .await
+                .unwrap();
+            sqlx::query("INSERT INTO meta VALUES ($1, $2, $3, '', $4, $5, $6)")
+                .bind(attribute)
+                .bind(description)
+                .bind(longdescription)
+                .bind(license)
+                .bind(platforms)
+                .bind(maintainers)
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        conn.close().await.unwrap();
+
+        let appdata = APPS
+            .split("---")
+            .map(|x| serde_yaml::from_str::<AppData>(x).unwrap())
+            .map(|x| (x.package.to_string(), x))
+            .collect::<HashMap<_, _>>();
+        let path = dir.join("search.db");
+        SearchIndex::build(&path, &pkgdb.to_string_lossy(), &appdata, "test")
+            .await
+            .unwrap();
+        SearchIndex::connect(&path).await.unwrap()
+    }
+
+    async fn search(index: &SearchIndex, query: &str, installed: &[&str]) -> Vec<String> {
+        let installed = InstalledState {
+            system: installed
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<HashSet<_>>(),
+            user: HashMap::new(),
+        };
+        index
+            .search(&SearchQuery::parse(query), &installed, &UserPkgs::Profile)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.attribute)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn ranking() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index(dir.path()).await;
+        assert_eq!(index.source().await.unwrap(), "test");
+        // Package names weigh more than descriptions, which weigh more than long descriptions
+        assert_eq!(
+            search(&index, "greeter", &[]).await,
+            ["greeter", "lightdm", "sddm"]
+        );
+        assert!(search(&index, "nothing", &[]).await.is_empty());
+        assert!(search(&index, "", &[]).await.is_empty());
+        // Single characters are searched for as whole words, not as the start of unrar's RAR
+        assert_eq!(search(&index, "r", &[]).await, ["r"]);
+        assert_eq!(search(&index, "R language", &[]).await, ["r"]);
+    }
+
+    #[tokio::test]
+    async fn boosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index(dir.path()).await;
+        // The players only differ in aplayer having appstream data and what is installed
+        assert_eq!(
+            search(&index, "music", &["bplayer"]).await,
+            ["aplayer", "bplayer", "cplayer"]
+        );
+        assert_eq!(
+            search(&index, "music", &["cplayer"]).await,
+            ["aplayer", "cplayer", "bplayer"]
+        );
+        // Installed packages still rank below appstream apps
+        assert_eq!(
+            search(&index, "music", &["cplayer", "bplayer"]).await[0],
+            "aplayer"
+        );
+    }
+
+    #[tokio::test]
+    async fn prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index(dir.path()).await;
+        assert_eq!(
+            search(&index, "gree", &[]).await,
+            ["greeter", "lightdm", "sddm"]
+        );
+        assert_eq!(search(&index, "displ man", &[]).await, ["lightdm", "sddm"]);
+        // AppStream names and provided commands are searched too
+        assert_eq!(search(&index, "archive opener", &[]).await, ["unrar"]);
+        assert_eq!(search(&index, "unrar", &[]).await, ["unrar"]);
+    }
+
+    #[tokio::test]
+    async fn typos() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index(dir.path()).await;
+        assert_eq!(index.corrections("greter").await.unwrap(), ["greeter"]);
+        assert_eq!(index.corrections("greeetr").await.unwrap(), ["greeter"]);
+        // Short words have too many neighbours to correct
+        assert!(index.corrections("mus").await.unwrap().is_empty());
+        // Longer words may be further off
+        assert_eq!(index.corrections("dispaly").await.unwrap(), ["display"]);
+        assert_eq!(index.corrections("mnaager").await.unwrap(), ["manager"]);
+        assert!(index.corrections("xgreeter").await.unwrap().is_empty());
+        assert_eq!(
+            search(&index, "greter", &[]).await,
+            ["greeter", "lightdm", "sddm"]
+        );
+        // Words that some indexed word starts with are left alone
+        assert!(index.known("manag").await.unwrap());
+        assert!(!index.known("managr").await.unwrap());
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(editdistance("", ""), 0);
+        assert_eq!(editdistance("", "abc"), 3);
+        assert_eq!(editdistance("greeter", "greeter"), 0);
+        assert_eq!(editdistance("greter", "greeter"), 1);
+        assert_eq!(editdistance("greeter", "greetre"), 1);
+        assert_eq!(editdistance("kitten", "sitting"), 3);
+        assert_eq!(editdistance("ärger", "arger"), 1);
+    }
+
+    #[tokio::test]
+    async fn filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index(dir.path()).await;
+        assert_eq!(
+            search(&index, "license:gpl", &[]).await,
+            ["sddm", "lightdm"]
+        );
+        assert_eq!(search(&index, "license:mit", &[]).await, ["greeter"]);
+        assert_eq!(search(&index, "free:no", &[]).await, ["unrar"]);
+        assert_eq!(
+            search(&index, "greeter platform:aarch64-linux", &[]).await,
+            ["lightdm", "sddm"]
+        );
+        assert_eq!(
+            search(&index, r#"maintainer:"jane doe""#, &[]).await,
+            ["greeter"]
+        );
+        assert_eq!(search(&index, "has:gui", &[]).await, ["unrar"]);
+        assert_eq!(search(&index, "category:utility", &[]).await, ["unrar"]);
+        assert_eq!(
+            search(&index, "music installed:", &["bplayer"]).await,
+            ["bplayer"]
+        );
+        assert_eq!(
+            search(&index, "music installed:no", &["bplayer"]).await,
+            ["aplayer", "cplayer"]
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let query = SearchQuery::parse(
+            r#"text license:mit has:gui installed: free:no maintainer:"Jane Doe" foo:bar"#,
+        );
+        assert_eq!(query.text, "text foo:bar");
+        assert_eq!(
+            query.filters,
+            [
+                SearchFilter::License(String::from("mit")),
+                SearchFilter::Gui,
+                SearchFilter::Installed(true),
+                SearchFilter::Free(false),
+                SearchFilter::Maintainer(String::from("Jane Doe")),
+            ]
+        );
+        // Printing a query gives one that parses the same
+        assert_eq!(SearchQuery::parse(&query.to_string()), query);
+
+        // Filters without a valid value are searched for as text
+        let query = SearchQuery::parse("license: installed:maybe has:cli Platform:x86_64-linux");
+        assert_eq!(query.text, "license: installed:maybe has:cli");
+        assert_eq!(
+            query.filters,
+            [SearchFilter::Platform(String::from("x86_64-linux"))]
+        );
+
+        let query = SearchQuery::parse("category:games category:games");
+        assert_eq!(
+            query.filters,
+            [SearchFilter::Category(String::from("games"))]
+        );
+        assert!(query.text.is_empty());
+        assert!(!query.isempty());
+        assert!(SearchQuery::parse(" - ").isempty());
+    }
+}
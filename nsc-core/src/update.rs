@@ -0,0 +1,550 @@
+use super::{
+    cancel::Canceller,
+    diff, flakeargs, flakes,
+    helper::{self, HelperCmd},
+    holds, homemanager, rebuildargs, writeatomic, PkgAction, ProfileManifest, RebuildMode,
+    SystemPkgs, UserPkgs, LOGFORMAT,
+};
+use anyhow::{anyhow, Result};
+use log::*;
+use nix_data::config::configfile::NixDataConfig;
+use std::{collections::HashSet, fs, path::Path, process::Stdio};
+use tokio::io::AsyncBufReadExt;
+
+enum NscCmd {
+    Rebuild,
+    Channel,
+    All,
+    Inputs(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Updater {
+    config: NixDataConfig,
+    syspkgs: SystemPkgs,
+    userpkgs: UserPkgs,
+    system: String,
+    cancel: Canceller,
+}
+
+impl Updater {
+    pub fn new(
+        config: NixDataConfig,
+        syspkgs: SystemPkgs,
+        userpkgs: UserPkgs,
+        system: String,
+    ) -> Self {
+        Self {
+            config,
+            syspkgs,
+            userpkgs,
+            system,
+            cancel: Canceller::default(),
+        }
+    }
+
+    pub fn canceller(&self) -> Canceller {
+        self.cancel.clone()
+    }
+
+    pub async fn updatesystem<F: Fn(String)>(
+        &self,
+        rmpkgs: Option<Vec<String>>,
+        output: F,
+    ) -> Result<bool> {
+        runcmd(
+            NscCmd::All,
+            self.config.clone(),
+            self.syspkgs.clone(),
+            rmpkgs,
+            &self.cancel,
+            output,
+        )
+        .await
+    }
+
+    // Bumps only the given inputs of the system flake before rebuilding
+    pub async fn updatesysteminputs<F: Fn(String)>(
+        &self,
+        inputs: Vec<String>,
+        output: F,
+    ) -> Result<bool> {
+        runcmd(
+            NscCmd::Inputs(inputs),
+            self.config.clone(),
+            self.syspkgs.clone(),
+            None,
+            &self.cancel,
+            output,
+        )
+        .await
+    }
+
+    // The inputs of the system flake that system packages come from
+    pub fn nixpkgsinputs(&self) -> Result<Vec<String>> {
+        let path = flakepath(&self.config);
+        let path = Path::new(&path);
+        // The configured flake may be the flake.nix file or its directory
+        let dir = if path.is_dir() {
+            path
+        } else {
+            path.parent().unwrap_or(path)
+        };
+        nixpkgsinputs(&fs::read_to_string(dir.join("flake.lock"))?)
+    }
+
+    pub fn previewsystem(&self, rmpkgs: &Option<Vec<String>>) -> Result<String> {
+        match (&self.syspkgs, rmpkgs) {
+            (SystemPkgs::None, _) | (_, None) => Ok(String::new()),
+            (_, Some(rmpkgs)) => {
+                let systemconfig = self.config.systemconfig.clone().unwrap_or_default();
+                let f = fs::read_to_string(&systemconfig)?;
+                let out = removesysconfig(&f, rmpkgs.to_vec())?;
+                Ok(diff::unified(&f, &out, &systemconfig, &systemconfig))
+            }
+        }
+    }
+
+    pub async fn updatechannels<F: Fn(String)>(&self, output: F) -> Result<bool> {
+        runcmd(
+            NscCmd::Channel,
+            self.config.clone(),
+            self.syspkgs.clone(),
+            None,
+            &self.cancel,
+            output,
+        )
+        .await
+    }
+
+    pub async fn rebuildsystem<F: Fn(String)>(&self, output: F) -> Result<bool> {
+        match self.syspkgs {
+            SystemPkgs::Legacy => {
+                runcmd(
+                    NscCmd::Rebuild,
+                    self.config.clone(),
+                    self.syspkgs.clone(),
+                    None,
+                    &self.cancel,
+                    output,
+                )
+                .await
+            }
+            SystemPkgs::Flake => self.updatesystem(None, output).await,
+            SystemPkgs::None => Ok(true),
+        }
+    }
+
+    pub async fn updateuser<F: Fn(String)>(
+        &self,
+        rmpkgs: Option<Vec<String>>,
+        output: F,
+    ) -> Result<bool> {
+        match self.userpkgs {
+            UserPkgs::Env => updateenv(None, &self.cancel, output).await,
+            UserPkgs::Profile => {
+                updateprofile(rmpkgs, None, &self.system, &self.cancel, output).await
+            }
+            UserPkgs::HomeManager => updatehome(rmpkgs, &self.cancel, output).await,
+        }
+    }
+
+    // Upgrades only the given packages, by attribute or for nix-env by pname
+    pub async fn updateuserpkgs<F: Fn(String)>(
+        &self,
+        pkgs: Vec<String>,
+        output: F,
+    ) -> Result<bool> {
+        if pkgs.is_empty() {
+            return Ok(true);
+        }
+        match self.userpkgs {
+            UserPkgs::Env => updateenv(Some(pkgs), &self.cancel, output).await,
+            UserPkgs::Profile => {
+                updateprofile(None, Some(pkgs), &self.system, &self.cancel, output).await
+            }
+            UserPkgs::HomeManager => Err(anyhow!(
+                "home-manager packages can only be updated all at once"
+            )),
+        }
+    }
+
+    pub async fn updateall<F: Fn(String)>(
+        &self,
+        userrmpkgs: Option<Vec<String>>,
+        sysrmpkgs: Option<Vec<String>>,
+        output: F,
+    ) -> Result<bool> {
+        if self.updatesystem(sysrmpkgs, &output).await? {
+            self.updateuser(userrmpkgs, &output).await
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+async fn runcmd<F: Fn(String)>(
+    cmd: NscCmd,
+    config: NixDataConfig,
+    syspkgs: SystemPkgs,
+    rmpkgs: Option<Vec<String>>,
+    cancel: &Canceller,
+    output: F,
+) -> Result<bool> {
+    let systemconfig = config.systemconfig.clone().unwrap_or_default();
+    let f = fs::read_to_string(&systemconfig)?;
+    let flakepath = flakepath(&config);
+
+    let mut arguments = vec![];
+    if let SystemPkgs::Flake = syspkgs {
+        arguments.push(String::from("--impure"));
+    }
+    arguments.extend(LOGFORMAT.iter().map(|x| x.to_string()));
+    arguments.extend(rebuildargs(&config));
+    let generations = config.generations.unwrap_or(0);
+    let newconfig = match rmpkgs {
+        Some(rmpkgs) => Some(removesysconfig(&f, rmpkgs)?),
+        None => None,
+    };
+
+    let cmd = match cmd {
+        NscCmd::Rebuild => HelperCmd::Rebuild {
+            mode: RebuildMode::Switch,
+            generations,
+            arguments,
+        },
+        NscCmd::Channel => HelperCmd::Channel {
+            output: systemconfig,
+            update: false,
+            rebuild: false,
+            mode: RebuildMode::Switch,
+            generations,
+            arguments: vec![],
+        },
+        NscCmd::All => match syspkgs {
+            SystemPkgs::Legacy => HelperCmd::Channel {
+                output: systemconfig,
+                update: newconfig.is_some(),
+                rebuild: true,
+                mode: RebuildMode::Switch,
+                generations,
+                arguments,
+            },
+            SystemPkgs::Flake => HelperCmd::Flake {
+                flakepath,
+                inputs: vec![],
+                output: systemconfig,
+                update: newconfig.is_some(),
+                rebuild: true,
+                mode: RebuildMode::Switch,
+                generations,
+                arguments,
+            },
+            SystemPkgs::None => return Ok(true),
+        },
+        NscCmd::Inputs(inputs) => match syspkgs {
+            SystemPkgs::Flake => HelperCmd::Flake {
+                flakepath,
+                inputs,
+                output: systemconfig,
+                update: false,
+                rebuild: true,
+                mode: RebuildMode::Switch,
+                generations,
+                arguments,
+            },
+            _ => return Err(anyhow!("Only flake systems can update single inputs")),
+        },
+    };
+
+    helper::run(&cmd, newconfig.as_deref(), cancel, output).await
+}
+
+// The flake directory, without the configuration name after #
+fn flakepath(config: &NixDataConfig) -> String {
+    flakeargs(config)
+        .unwrap_or_default()
+        .split('#')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Root inputs locked to nixpkgs, only the one named nixpkgs if there are several
+fn nixpkgsinputs(lock: &str) -> Result<Vec<String>> {
+    let lock: serde_json::Value = serde_json::from_str(lock)?;
+    let nodes = &lock["nodes"];
+    let root = lock["root"].as_str().unwrap_or("root");
+    let inputs = nodes[root]["inputs"]
+        .as_object()
+        .ok_or_else(|| anyhow!("flake.lock has no inputs"))?;
+    let mut found = inputs
+        .iter()
+        // Inputs that follow another input are locked by that one
+        .filter_map(|(name, node)| Some((name, &nodes[node.as_str()?])))
+        .filter(|(_, node)| {
+            ["original", "locked"].iter().any(|x| {
+                let n = &node[x];
+                n["repo"].as_str() == Some("nixpkgs")
+                    || n["id"].as_str() == Some("nixpkgs")
+                    || n["url"]
+                        .as_str()
+                        .map(|u| u.contains("nixpkgs") || u.contains("nixexprs"))
+                        .unwrap_or(false)
+            })
+        })
+        .map(|(name, _)| name.to_string())
+        .collect::<Vec<_>>();
+    if found.iter().any(|x| x == "nixpkgs") {
+        found.retain(|x| x == "nixpkgs");
+    }
+    if found.is_empty() {
+        return Err(anyhow!("No nixpkgs input found in flake.lock"));
+    }
+    Ok(found)
+}
+
+// Every root input of a flake.lock
+fn lockinputs(lock: &str) -> Result<Vec<String>> {
+    let lock: serde_json::Value = serde_json::from_str(lock)?;
+    let root = lock["root"].as_str().unwrap_or("root");
+    Ok(lock["nodes"][root]["inputs"]
+        .as_object()
+        .map(|x| x.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+fn removesysconfig(f: &str, rmpkgs: Vec<String>) -> Result<String> {
+    match nix_editor::write::rmarr(f, "environment.systemPackages", rmpkgs) {
+        Ok(x) => Ok(x),
+        Err(_) => Err(anyhow!("Failed to write configuration.nix")),
+    }
+}
+
+async fn updatehome<F: Fn(String)>(
+    rmpkgs: Option<Vec<String>>,
+    cancel: &Canceller,
+    output: F,
+) -> Result<bool> {
+    let mut restore = None;
+    if let Some(rmpkgs) = rmpkgs {
+        let work = rmpkgs
+            .into_iter()
+            .map(|x| (x, PkgAction::Remove))
+            .collect::<Vec<_>>();
+        let homeconfig = homemanager::homeconfig()
+            .ok_or_else(|| anyhow!("No home-manager configuration found"))?;
+        let f = fs::read_to_string(&homeconfig)?;
+        writeatomic(
+            Path::new(&homeconfig),
+            homemanager::edithomeconfig(&f, &work)?.as_bytes(),
+        )?;
+        restore = Some((homeconfig, f));
+    }
+
+    let result = updatehomeinputs(cancel, output).await;
+    if let Some((homeconfig, f)) = restore {
+        if !matches!(result, Ok(true)) {
+            info!("home-manager update failed, restoring {}", homeconfig);
+            writeatomic(Path::new(&homeconfig), f.as_bytes())?;
+        }
+    }
+    result
+}
+
+async fn updatehomeinputs<F: Fn(String)>(cancel: &Canceller, output: F) -> Result<bool> {
+    let mut cmd = if let Some(flake) = homemanager::homeflake() {
+        // Positional arguments to `nix flake update` are input names since nix 2.19, so inputs are named instead
+        let flake = flake.split('#').next().unwrap_or_default().to_string();
+        let path = Path::new(&flake);
+        let dir = if path.is_file() {
+            path.parent().unwrap_or(path)
+        } else {
+            path
+        };
+        let inputs = match fs::read_to_string(dir.join("flake.lock")) {
+            Ok(lock) => lockinputs(&lock)?,
+            // Locking creates the file with the latest inputs
+            Err(_) => vec![],
+        };
+        let mut cmd = tokio::process::Command::new("nix");
+        cmd.arg("flake").arg("lock").arg(dir);
+        for input in inputs {
+            cmd.arg("--update-input").arg(input);
+        }
+        cmd
+    } else {
+        let mut cmd = tokio::process::Command::new("nix-channel");
+        cmd.arg("--update");
+        cmd
+    };
+    let mut cmd = cancel.spawn(cmd.stderr(Stdio::piped()))?;
+
+    let stderr = cmd.stderr.take().unwrap();
+    let reader = tokio::io::BufReader::new(stderr);
+
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        output(line);
+    }
+    if !cmd.wait().await?.success() {
+        return Ok(false);
+    }
+    homemanager::switch(cancel, output).await
+}
+
+async fn updateenv<F: Fn(String)>(
+    only: Option<Vec<String>>,
+    cancel: &Canceller,
+    output: F,
+) -> Result<bool> {
+    let pkgs = match envupgrades(only, &holds::held(), || {
+        Ok(nix_data::cache::channel::getenvpkgs()?
+            .into_keys()
+            .collect())
+    })? {
+        Some(pkgs) => pkgs,
+        None => return Ok(true),
+    };
+    let mut cmd = cancel.spawn(
+        tokio::process::Command::new("nix-env")
+            .arg("-u")
+            .args(pkgs)
+            .args(LOGFORMAT)
+            .stderr(Stdio::piped()),
+    )?;
+
+    let stderr = cmd.stderr.take().unwrap();
+    let reader = tokio::io::BufReader::new(stderr);
+
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        trace!("CAUGHT NIXENV LINE: {}", line);
+        output(line);
+    }
+    Ok(cmd.wait().await?.success())
+}
+
+// The names to pass to `nix-env -u`, None when all of them are held.
+// Held packages are left out by naming the others, since a bare `nix-env -u` upgrades everything
+fn envupgrades<I: FnOnce() -> Result<Vec<String>>>(
+    only: Option<Vec<String>>,
+    held: &HashSet<String>,
+    installed: I,
+) -> Result<Option<Vec<String>>> {
+    let mut pkgs = match only {
+        Some(only) => only,
+        None if held.is_empty() => return Ok(Some(vec![])),
+        None => installed()?,
+    };
+    pkgs.retain(|x| !held.contains(x));
+    if pkgs.is_empty() {
+        return Ok(None);
+    }
+    pkgs.sort();
+    Ok(Some(pkgs))
+}
+
+async fn updateprofile<F: Fn(String)>(
+    rmpkgs: Option<Vec<String>>,
+    only: Option<Vec<String>>,
+    system: &str,
+    cancel: &Canceller,
+    output: F,
+) -> Result<bool> {
+    let manifest = ProfileManifest::load()?;
+    let sources = flakes::sources();
+    if let Some(rmpkgs) = rmpkgs {
+        let selectors = rmpkgs
+            .iter()
+            .filter_map(|x| manifest.find(x, system, &sources).map(|e| e.selector()))
+            .collect::<Vec<String>>();
+        if !selectors.is_empty() {
+            let mut cmd = cancel.spawn(
+                tokio::process::Command::new("nix")
+                    .arg("profile")
+                    .arg("remove")
+                    .args(selectors)
+                    // Allow updating potential unfree packages
+                    .arg("--impure")
+                    .args(LOGFORMAT)
+                    .stderr(Stdio::piped()),
+            )?;
+
+            let stderr = cmd.stderr.take().unwrap();
+            let reader = tokio::io::BufReader::new(stderr);
+
+            let mut lines = reader.lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                trace!("CAUGHT NIX PROFILE LINE: {}", line);
+                output(line);
+            }
+            cmd.wait().await?;
+        }
+    }
+
+    // Removed elements shift the indices of older manifests, so read it again
+    let manifest = ProfileManifest::load()?;
+    let upgrade = manifest.upgrades(system, &sources, &holds::held(), only.as_deref());
+    if upgrade.is_empty() {
+        return Ok(true);
+    }
+    let mut cmd = cancel.spawn(
+        tokio::process::Command::new("nix")
+            .arg("profile")
+            .arg("upgrade")
+            .args(upgrade)
+            // Allow updating potential unfree packages
+            .arg("--impure")
+            .args(LOGFORMAT)
+            .stderr(Stdio::piped()),
+    )?;
+
+    let stderr = cmd.stderr.take().unwrap();
+    let reader = tokio::io::BufReader::new(stderr);
+
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        trace!("CAUGHT NIX PROFILE LINE: {}", line);
+        output(line);
+    }
+    Ok(cmd.wait().await?.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn installed() -> Result<Vec<String>> {
+        Ok(strings(&["ripgrep", "firefox", "hello"]))
+    }
+
+    #[test]
+    fn envholds() {
+        // Without holds a bare `nix-env -u` upgrades everything, so installed packages aren't needed
+        assert_eq!(
+            envupgrades(None, &HashSet::new(), || panic!()).unwrap(),
+            Some(vec![])
+        );
+        let held = HashSet::from([String::from("firefox")]);
+        assert_eq!(
+            envupgrades(None, &held, installed).unwrap(),
+            Some(strings(&["hello", "ripgrep"]))
+        );
+        // Held packages stay held when they are named
+        assert_eq!(
+            envupgrades(Some(strings(&["firefox", "hello"])), &held, installed).unwrap(),
+            Some(strings(&["hello"]))
+        );
+        assert_eq!(
+            envupgrades(Some(strings(&["firefox"])), &held, installed).unwrap(),
+            None
+        );
+        let held = HashSet::from_iter(installed().unwrap());
+        assert_eq!(envupgrades(None, &held, installed).unwrap(), None);
+    }
+}
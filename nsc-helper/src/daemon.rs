@@ -0,0 +1,587 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    process::Stdio,
+    sync::{Arc, Mutex},
+};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use zbus::{
+    dbus_interface, dbus_proxy, fdo, zvariant::Value, Connection, ConnectionBuilder, MessageHeader,
+    SignalContext,
+};
+
+static BUSNAME: &str = "dev.vlinkz.NixSoftwareCenter.Helper";
+static OBJECTPATH: &str = "/dev/vlinkz/NixSoftwareCenter/Helper";
+static POLKITACTION: &str = "dev.vlinkz.NixSoftwareCenter";
+
+#[dbus_proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait Authority {
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value<'_>>),
+        action_id: &str,
+        details: HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+}
+
+struct Job {
+    id: u32,
+    pgid: i32,
+}
+
+#[derive(Default)]
+struct State {
+    lastid: u32,
+    job: Option<Job>,
+}
+
+struct Helper {
+    // Session bus instances run as the calling user and skip polkit, for testing
+    session: bool,
+    // What operations run, nsc-helper itself unless a session instance was given another program
+    program: PathBuf,
+    state: Arc<Mutex<State>>,
+}
+
+impl Helper {
+    async fn authorize(&self, conn: &Connection, hdr: &MessageHeader<'_>) -> fdo::Result<()> {
+        if self.session {
+            return Ok(());
+        }
+        let sender = hdr
+            .sender()?
+            .ok_or_else(|| fdo::Error::AccessDenied(String::from("Unknown sender")))?;
+        let mut subject = HashMap::new();
+        subject.insert("name", Value::from(sender.as_str()));
+        let authority = AuthorityProxy::new(conn).await?;
+        // Flag 1 lets polkit ask the user for their password
+        let (authorized, _, _) = authority
+            .check_authorization(
+                &("system-bus-name", subject),
+                POLKITACTION,
+                HashMap::new(),
+                1,
+                "",
+            )
+            .await?;
+        if authorized {
+            Ok(())
+        } else {
+            Err(fdo::Error::AccessDenied(String::from(
+                "Not authorized to change the system configuration",
+            )))
+        }
+    }
+
+    // Runs the one-shot helper program with the given arguments as a new process group
+    async fn start(
+        &self,
+        hdr: &MessageHeader<'_>,
+        args: Vec<String>,
+        stdin: Option<String>,
+        ctxt: SignalContext<'_>,
+    ) -> fdo::Result<u32> {
+        // Build logs show paths and configurations, so they only go to the caller instead of everyone on the bus
+        let sender = hdr
+            .sender()?
+            .ok_or_else(|| fdo::Error::AccessDenied(String::from("Unknown sender")))?
+            .to_owned();
+        let ctxt = ctxt.to_owned().set_destination(sender.into());
+        let mut state = self.state.lock().unwrap();
+        if state.job.is_some() {
+            return Err(fdo::Error::Failed(String::from(
+                "Another operation is already running",
+            )));
+        }
+        let mut child = tokio::process::Command::new(&self.program)
+            .args(args)
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .map_err(|e| fdo::Error::SpawnFailed(e.to_string()))?;
+
+        // Without a pid, cancelling would signal the daemon's own process group
+        let pgid = child.id().ok_or_else(|| {
+            fdo::Error::SpawnFailed(String::from("The operation exited before it started"))
+        })? as i32;
+        state.lastid += 1;
+        let id = state.lastid;
+        state.job = Some(Job { id, pgid });
+        drop(state);
+
+        let jobs = self.state.clone();
+        let mut input = child.stdin.take();
+        tokio::spawn(async move {
+            if let (Some(input), Some(stdin)) = (&mut input, stdin) {
+                match input.write_all(stdin.as_bytes()).await {
+                    // The program may exit without reading it
+                    Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                        let _ = Helper::output(&ctxt, id, &e.to_string()).await;
+                    }
+                    _ => {}
+                }
+            }
+            drop(input);
+
+            let mut stdout = tokio::io::BufReader::new(child.stdout.take().unwrap()).lines();
+            let mut stderr = tokio::io::BufReader::new(child.stderr.take().unwrap()).lines();
+            let (mut outdone, mut errdone) = (false, false);
+            while !(outdone && errdone) {
+                tokio::select! {
+                    line = stdout.next_line(), if !outdone => match line {
+                        Ok(Some(line)) => { let _ = Helper::output(&ctxt, id, &line).await; }
+                        _ => outdone = true,
+                    },
+                    line = stderr.next_line(), if !errdone => match line {
+                        Ok(Some(line)) => { let _ = Helper::output(&ctxt, id, &line).await; }
+                        _ => errdone = true,
+                    },
+                }
+            }
+            let success = child.wait().await.map(|x| x.success()).unwrap_or(false);
+            jobs.lock().unwrap().job = None;
+            let _ = Helper::finished(&ctxt, id, success).await;
+        });
+        Ok(id)
+    }
+}
+
+#[dbus_interface(name = "dev.vlinkz.NixSoftwareCenter.Helper1")]
+impl Helper {
+    #[allow(clippy::too_many_arguments)]
+    async fn apply_config(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        output: String,
+        config: String,
+        mode: String,
+        generations: u32,
+        arguments: Vec<String>,
+    ) -> fdo::Result<u32> {
+        self.authorize(conn, &hdr).await?;
+        let mut args = vec![
+            String::from("config"),
+            String::from("--output"),
+            output,
+            String::from("--mode"),
+            mode,
+            String::from("--generations"),
+            generations.to_string(),
+            String::from("--"),
+        ];
+        args.extend(arguments);
+        self.start(&hdr, args, Some(config), ctxt).await
+    }
+
+    async fn rebuild(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        mode: String,
+        generations: u32,
+        arguments: Vec<String>,
+    ) -> fdo::Result<u32> {
+        self.authorize(conn, &hdr).await?;
+        let mut args = vec![
+            String::from("rebuild"),
+            String::from("--mode"),
+            mode,
+            String::from("--generations"),
+            generations.to_string(),
+            String::from("--"),
+        ];
+        args.extend(arguments);
+        self.start(&hdr, args, None, ctxt).await
+    }
+
+    // An empty config only updates the channels, without writing the configuration
+    #[allow(clippy::too_many_arguments)]
+    async fn update_channel(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        output: String,
+        config: String,
+        rebuild: bool,
+        mode: String,
+        generations: u32,
+        arguments: Vec<String>,
+    ) -> fdo::Result<u32> {
+        self.authorize(conn, &hdr).await?;
+        let mut args = vec![String::from("channel"), String::from("--output"), output];
+        args.extend(updateargs(&config, rebuild, mode, generations, arguments));
+        self.start(&hdr, args, Some(config), ctxt).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn update_flake(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        flakepath: String,
+        output: String,
+        config: String,
+        rebuild: bool,
+        mode: String,
+        generations: u32,
+        arguments: Vec<String>,
+    ) -> fdo::Result<u32> {
+        self.authorize(conn, &hdr).await?;
+        let mut args = vec![
+            String::from("flake"),
+            String::from("--flakepath"),
+            flakepath,
+            String::from("--output"),
+            output,
+        ];
+        args.extend(updateargs(&config, rebuild, mode, generations, arguments));
+        self.start(&hdr, args, Some(config), ctxt).await
+    }
+
+    // Like update_flake, but only bumps the given inputs
+    #[allow(clippy::too_many_arguments)]
+    async fn update_flake_inputs(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        flakepath: String,
+        inputs: Vec<String>,
+        output: String,
+        config: String,
+        rebuild: bool,
+        mode: String,
+        generations: u32,
+        arguments: Vec<String>,
+    ) -> fdo::Result<u32> {
+        self.authorize(conn, &hdr).await?;
+        let mut args = vec![
+            String::from("flake"),
+            String::from("--flakepath"),
+            flakepath,
+            String::from("--output"),
+            output,
+        ];
+        for input in inputs {
+            args.push(String::from("--input"));
+            args.push(input);
+        }
+        args.extend(updateargs(&config, rebuild, mode, generations, arguments));
+        self.start(&hdr, args, Some(config), ctxt).await
+    }
+
+    // Generation 0 switches to the previous generation
+    async fn rollback(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        output: String,
+        config: String,
+        generation: u32,
+    ) -> fdo::Result<u32> {
+        self.authorize(conn, &hdr).await?;
+        let mut args = vec![String::from("rollback")];
+        if !output.is_empty() {
+            args.push(String::from("--output"));
+            args.push(output);
+        }
+        if generation > 0 {
+            args.push(String::from("--generation"));
+            args.push(generation.to_string());
+        }
+        self.start(&hdr, args, Some(config), ctxt).await
+    }
+
+    async fn cancel(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] hdr: MessageHeader<'_>,
+        job: u32,
+    ) -> fdo::Result<()> {
+        self.authorize(conn, &hdr).await?;
+        match &self.state.lock().unwrap().job {
+            Some(j) if j.id == job => {
+                // Stops nixos-rebuild and everything it started
+                unsafe {
+                    libc::kill(-j.pgid, libc::SIGTERM);
+                }
+                Ok(())
+            }
+            _ => Err(fdo::Error::Failed(format!(
+                "Operation {} is not running",
+                job
+            ))),
+        }
+    }
+
+    async fn status(&self) -> (u32, bool) {
+        match &self.state.lock().unwrap().job {
+            Some(j) => (j.id, true),
+            None => (0, false),
+        }
+    }
+
+    #[dbus_interface(signal)]
+    async fn output(ctxt: &SignalContext<'_>, job: u32, line: &str) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn finished(ctxt: &SignalContext<'_>, job: u32, success: bool) -> zbus::Result<()>;
+}
+
+fn updateargs(
+    config: &str,
+    rebuild: bool,
+    mode: String,
+    generations: u32,
+    arguments: Vec<String>,
+) -> Vec<String> {
+    let mut args = vec![];
+    if !config.is_empty() {
+        args.push(String::from("--update"));
+    }
+    if rebuild {
+        args.push(String::from("--rebuild"));
+    }
+    args.extend([
+        String::from("--mode"),
+        mode,
+        String::from("--generations"),
+        generations.to_string(),
+        String::from("--"),
+    ]);
+    args.extend(arguments);
+    args
+}
+
+async fn serve(builder: ConnectionBuilder<'_>, helper: Helper) -> zbus::Result<Connection> {
+    builder
+        .name(BUSNAME)?
+        .serve_at(OBJECTPATH, helper)?
+        .build()
+        .await
+}
+
+pub async fn run(session: bool, exec: Option<PathBuf>) -> zbus::Result<()> {
+    let program = match exec {
+        Some(program) => program,
+        None => std::env::current_exe()?,
+    };
+    let helper = Helper {
+        session,
+        program,
+        state: Arc::default(),
+    };
+    let builder = if session {
+        ConnectionBuilder::session()?
+    } else {
+        ConnectionBuilder::system()?
+    };
+    let _conn = serve(builder, helper).await?;
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::{
+        fs,
+        io::{BufRead, BufReader},
+        os::unix::fs::PermissionsExt,
+        process::{Child, Command},
+        time::Duration,
+    };
+
+    #[dbus_proxy(
+        interface = "dev.vlinkz.NixSoftwareCenter.Helper1",
+        default_service = "dev.vlinkz.NixSoftwareCenter.Helper",
+        default_path = "/dev/vlinkz/NixSoftwareCenter/Helper"
+    )]
+    trait Client {
+        fn apply_config(
+            &self,
+            output: &str,
+            config: &str,
+            mode: &str,
+            generations: u32,
+            arguments: &[&str],
+        ) -> zbus::Result<u32>;
+        fn rebuild(&self, mode: &str, generations: u32, arguments: &[&str]) -> zbus::Result<u32>;
+        fn cancel(&self, job: u32) -> zbus::Result<()>;
+        fn status(&self) -> zbus::Result<(u32, bool)>;
+        #[dbus_proxy(signal)]
+        fn output(&self, job: u32, line: &str) -> zbus::Result<()>;
+        #[dbus_proxy(signal)]
+        fn finished(&self, job: u32, success: bool) -> zbus::Result<()>;
+    }
+
+    // A private session bus, stopped when dropped
+    struct Bus(Child, String);
+
+    impl Bus {
+        fn start() -> Option<Bus> {
+            let mut child = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(child.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some(Bus(child, address.trim().to_string()))
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    // Stands in for nsc-helper: echoes what it was asked to do, and keeps running for rebuilds
+    static PROGRAM: &str = "#!/bin/sh
+echo \"args: $*\"
+cat
+echo \"error output\" >&2
+if [ \"$1\" = rebuild ]; then
+    exec sleep 60
+fi
+";
+
+    // Collects the output of job until it finishes
+    async fn wait(
+        outputs: &mut OutputStream<'_>,
+        finished: &mut FinishedStream<'_>,
+        job: u32,
+    ) -> (Vec<String>, bool) {
+        let mut lines = vec![];
+        let success = loop {
+            tokio::select! {
+                Some(x) = outputs.next() => lines.extend(line(x, job)),
+                Some(x) = finished.next() => {
+                    let args = x.args().unwrap();
+                    if args.job == job {
+                        break args.success;
+                    }
+                }
+            }
+        };
+        // Output sent before the job finished may still be queued
+        while let Ok(Some(x)) =
+            tokio::time::timeout(Duration::from_millis(100), outputs.next()).await
+        {
+            lines.extend(line(x, job));
+        }
+        (lines, success)
+    }
+
+    fn line(x: Output, job: u32) -> Option<String> {
+        let args = x.args().unwrap();
+        (args.job == job).then(|| args.line.to_string())
+    }
+
+    #[tokio::test]
+    async fn session() {
+        let bus = match Bus::start() {
+            Some(bus) => bus,
+            None => {
+                eprintln!("dbus-daemon is not available, skipping");
+                return;
+            }
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("helper");
+        fs::write(&program, PROGRAM).unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let helper = Helper {
+            session: true,
+            program,
+            state: Arc::default(),
+        };
+        let _server = serve(ConnectionBuilder::address(bus.1.as_str()).unwrap(), helper)
+            .await
+            .unwrap();
+        let conn = ConnectionBuilder::address(bus.1.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let proxy = ClientProxy::new(&conn).await.unwrap();
+        let mut outputs = proxy.receive_output().await.unwrap();
+        let mut finished = proxy.receive_finished().await.unwrap();
+        let other = ConnectionBuilder::address(bus.1.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let otherproxy = ClientProxy::new(&other).await.unwrap();
+        let mut otheroutputs = otherproxy.receive_output().await.unwrap();
+        let mut otherfinished = otherproxy.receive_finished().await.unwrap();
+
+        let run = async {
+            assert_eq!(proxy.status().await.unwrap(), (0, false));
+            let job = proxy
+                .apply_config(
+                    "/etc/nixos/configuration.nix",
+                    "{ }",
+                    "build",
+                    3,
+                    &["--flake", "/etc/nixos#host"],
+                )
+                .await
+                .unwrap();
+            let (lines, success) = wait(&mut outputs, &mut finished, job).await;
+            assert!(success);
+            assert!(lines.contains(&String::from(
+                "args: config --output /etc/nixos/configuration.nix --mode build --generations 3 -- --flake /etc/nixos#host"
+            )));
+            assert!(lines.contains(&String::from("{ }")));
+            assert!(lines.contains(&String::from("error output")));
+            assert_eq!(proxy.status().await.unwrap(), (0, false));
+            // Other clients don't see the output of jobs they didn't start
+            let timeout = Duration::from_millis(100);
+            assert!(tokio::time::timeout(timeout, otheroutputs.next())
+                .await
+                .is_err());
+            assert!(tokio::time::timeout(timeout, otherfinished.next())
+                .await
+                .is_err());
+
+            let job = proxy.rebuild("switch", 0, &[]).await.unwrap();
+            assert_eq!(proxy.status().await.unwrap(), (job, true));
+            // Only one operation runs at a time
+            assert!(proxy.rebuild("switch", 0, &[]).await.is_err());
+            assert!(proxy.cancel(job + 1).await.is_err());
+            proxy.cancel(job).await.unwrap();
+            let (_, success) = wait(&mut outputs, &mut finished, job).await;
+            assert!(!success);
+            assert_eq!(proxy.status().await.unwrap(), (0, false));
+        };
+        tokio::time::timeout(Duration::from_secs(30), run)
+            .await
+            .expect("the helper did not finish in time");
+    }
+}
@@ -1,11 +1,30 @@
-use clap::{self, FromArgMatches, Subcommand};
+mod daemon;
+
+use clap::{self, FromArgMatches, Subcommand, ValueEnum};
 use std::{
     error::Error,
-    fs::{self, File},
+    fs::{self, File, OpenOptions},
     io::{self, Read, Write},
-    process::Command,
+    os::unix::fs::{fchown, MetadataExt},
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::{Component, Path, PathBuf},
+    process::{Command, ExitStatus},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+static BACKUPDIR: &str = "/var/lib/nix-software-center/backups";
+const MAXBACKUPS: usize = 20;
+// Paths the helper may write to, one per line. Only /etc/nixos is allowed without it.
+static POLICYFILE: &str = "/etc/nix-software-center/helper.conf";
+static DEFAULTALLOWED: &str = "/etc/nixos";
+static LOGFILE: &str = "/var/log/nsc-helper.log";
+// Pid of the running helper, for `nsc-helper cancel`
+static PIDFILE: &str = "/run/nsc-helper.pid";
+const EXIT_CANCELLED: i32 = 130;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
 #[derive(Subcommand, Debug)]
 enum SubCommands {
     Config {
@@ -15,6 +34,9 @@ enum SubCommands {
         /// How many generations to keep
         #[arg(short, long)]
         generations: Option<u32>,
+        /// nixos-rebuild action, only `switch` keeps the written file
+        #[arg(short, long, value_enum, default_value_t = Mode::Switch)]
+        mode: Mode,
         /// Run `nixos-rebuild` with the given arguments
         arguments: Vec<String>,
     },
@@ -24,6 +46,9 @@ enum SubCommands {
         /// How many generations to keep
         #[arg(short, long)]
         generations: Option<u32>,
+        /// nixos-rebuild action
+        #[arg(short, long, value_enum, default_value_t = Mode::Switch)]
+        mode: Mode,
     },
     Channel {
         /// Whether to rebuild the system after updating channels
@@ -38,6 +63,9 @@ enum SubCommands {
         /// How many generations to keep
         #[arg(short, long)]
         generations: Option<u32>,
+        /// nixos-rebuild action, only `switch` keeps the written file
+        #[arg(short, long, value_enum, default_value_t = Mode::Switch)]
+        mode: Mode,
         /// Run `nixos-rebuild` with the given arguments
         arguments: Vec<String>,
     },
@@ -51,15 +79,64 @@ enum SubCommands {
         /// Update file
         #[arg(short, long)]
         update: bool,
+        /// Only update these flake inputs instead of all of them
+        #[arg(short, long)]
+        input: Vec<String>,
         /// Write stdin to file in path output
         #[arg(short, long)]
         output: String,
         /// How many generations to keep
         #[arg(short, long)]
         generations: Option<u32>,
+        /// nixos-rebuild action, only `switch` keeps the written file
+        #[arg(short, long, value_enum, default_value_t = Mode::Switch)]
+        mode: Mode,
         /// Run `nixos-rebuild` with the given arguments
         arguments: Vec<String>,
     },
+    Rollback {
+        /// Write stdin to file in path output
+        #[arg(short, long)]
+        output: Option<String>,
+        /// System generation to switch to, defaults to the previous generation
+        #[arg(short, long)]
+        generation: Option<u32>,
+    },
+    Restore {
+        /// Backup to restore, defaults to the latest backup
+        backup: Option<String>,
+        /// List available backups instead
+        #[arg(short, long)]
+        list: bool,
+    },
+    Cancel,
+    Daemon {
+        /// Serve on the session bus without polkit checks, for testing
+        #[arg(long, requires = "exec")]
+        session: bool,
+        /// Run this program for operations instead of nsc-helper, with the same arguments and input
+        #[arg(long, requires = "session")]
+        exec: Option<PathBuf>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    DryBuild,
+    Build,
+    Test,
+    Switch,
+}
+
+impl Mode {
+    fn arg(&self) -> &'static str {
+        match self {
+            Mode::DryBuild => "dry-build",
+            Mode::Build => "build",
+            Mode::Test => "test",
+            Mode::Switch => "switch",
+        }
+    }
 }
 
 fn main() {
@@ -71,45 +148,59 @@ fn main() {
         .map_err(|err| err.exit())
         .unwrap();
 
-    if users::get_effective_uid() != 0 {
+    let session = matches!(
+        derived_subcommands,
+        SubCommands::Daemon { session: true, .. }
+    );
+    if users::get_effective_uid() != 0 && !session {
         eprintln!("nsc-helper must be run as root");
         std::process::exit(1);
     }
 
+    if !matches!(
+        derived_subcommands,
+        SubCommands::Restore { .. } | SubCommands::Cancel | SubCommands::Daemon { .. }
+    ) {
+        oncancel();
+    }
+
     match derived_subcommands {
         SubCommands::Config {
             output,
             generations,
+            mode,
             arguments,
         } => {
             let old = fs::read_to_string(&output);
             match write_file(&output) {
-                Ok(_) => match rebuild(arguments, generations) {
-                    Ok(_) => {}
+                Ok(_) => match rebuild(mode, arguments, generations) {
+                    Ok(_) => {
+                        // Test builds only check the new file, the running system keeps the old one
+                        if mode != Mode::Switch {
+                            restore(&output, old);
+                        }
+                    }
                     Err(err) => {
                         eprintln!("{}", err);
-                        if let Ok(o) = old {
-                            if fs::write(&output, o).is_err() {
-                                eprintln!("Could not restore old file");
-                            }
-                        }
-                        std::process::exit(1);
+                        restore(&output, old);
+                        exit(1);
                     }
                 },
                 Err(err) => {
                     eprintln!("{}", err);
-                    std::process::exit(1);
+                    exit(1);
                 }
             };
         }
         SubCommands::Rebuild {
             generations,
+            mode,
             arguments,
-        } => match rebuild(arguments, generations) {
+        } => match rebuild(mode, arguments, generations) {
             Ok(_) => (),
             Err(err) => {
                 eprintln!("{}", err);
-                std::process::exit(1);
+                exit(1);
             }
         },
         SubCommands::Channel {
@@ -117,138 +208,683 @@ fn main() {
             update,
             output,
             generations,
+            mode,
             arguments,
         } => {
+            let old = fs::read_to_string(&output);
             if update {
                 if let Err(e) = write_file(&output) {
                     eprintln!("{}", e);
-                    std::process::exit(1);
+                    exit(1);
                 }
             }
-            match channel() {
-                Ok(_) => {
-                    if dorebuild {
-                        match rebuild(arguments, generations) {
-                            Ok(_) => (),
-                            Err(err) => {
-                                eprintln!("{}", err);
-                                std::process::exit(1);
-                            }
-                        }
-                    }
-                }
-                Err(err) => {
-                    eprintln!("{}", err);
-                    std::process::exit(1);
+            let result = channel().and_then(|_| {
+                if dorebuild {
+                    rebuild(mode, arguments, generations)
+                } else {
+                    Ok(())
                 }
+            });
+            // Only a successful switch keeps the new file
+            if update && (result.is_err() || mode != Mode::Switch) {
+                restore(&output, old);
+            }
+            if let Err(err) = result {
+                eprintln!("{}", err);
+                exit(1);
             }
         }
         SubCommands::Flake {
             rebuild: dorebuild,
             flakepath,
             update,
+            input,
             output,
             generations,
+            mode,
             arguments,
         } => {
+            if let Err(e) = check_path(&flakepath) {
+                log_write(&format!("refused flake update of {}: {}", flakepath, e));
+                eprintln!("{}", e);
+                exit(1);
+            }
+            if let Err(e) = check_inputs(&input) {
+                eprintln!("{}", e);
+                exit(1);
+            }
+            let old = fs::read_to_string(&output);
             if update {
                 if let Err(e) = write_file(&output) {
                     eprintln!("{}", e);
-                    std::process::exit(1);
+                    exit(1);
                 }
             }
-            match flake(&flakepath) {
-                Ok(_) => {
-                    if dorebuild {
-                        match rebuild(arguments, generations) {
-                            Ok(_) => (),
-                            Err(err) => {
-                                eprintln!("{}", err);
-                                std::process::exit(1);
-                            }
-                        }
-                    }
+            let result = flake(&flakepath, &input).and_then(|_| {
+                if dorebuild {
+                    rebuild(mode, arguments, generations)
+                } else {
+                    Ok(())
+                }
+            });
+            if update && (result.is_err() || mode != Mode::Switch) {
+                restore(&output, old);
+            }
+            if let Err(err) = result {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        }
+        SubCommands::Rollback { output, generation } => {
+            let old = output.as_ref().map(fs::read_to_string);
+            if let Some(output) = &output {
+                if let Err(e) = write_file(output) {
+                    eprintln!("{}", e);
+                    exit(1);
+                }
+            }
+            if let Err(err) = rollback(generation) {
+                eprintln!("{}", err);
+                if let (Some(output), Some(old)) = (&output, old) {
+                    restore(output, old);
+                }
+                exit(1);
+            }
+        }
+        SubCommands::Restore { backup, list } => {
+            if list {
+                for (id, path) in backups() {
+                    println!("{}\t{}", id, path.to_string_lossy());
                 }
+            } else if let Err(err) = restore_backup(backup) {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        }
+        SubCommands::Cancel => {
+            if let Err(err) = cancel() {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        }
+        SubCommands::Daemon { session, exec } => {
+            let runtime = match tokio::runtime::Runtime::new() {
+                Ok(r) => r,
                 Err(err) => {
                     eprintln!("{}", err);
-                    std::process::exit(1);
+                    exit(1);
                 }
+            };
+            if let Err(err) = runtime.block_on(daemon::run(session, exec)) {
+                eprintln!("{}", err);
+                exit(1);
             }
         }
     }
+    exit(0);
+}
+
+fn exit(code: i32) -> ! {
+    if fs::read_to_string(PIDFILE)
+        .map(|x| x.trim() == std::process::id().to_string())
+        .unwrap_or(false)
+    {
+        let _ = fs::remove_file(PIDFILE);
+    }
+    if code != 0 && CANCELLED.load(Ordering::SeqCst) {
+        std::process::exit(EXIT_CANCELLED);
+    }
+    std::process::exit(code)
+}
+
+extern "C" fn setcancelled(_: libc::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+// Stop running commands instead of exiting, so the configuration can be restored
+fn oncancel() {
+    let handler = setcancelled as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGHUP, handler);
+    }
+    if let Err(e) = fs::write(PIDFILE, std::process::id().to_string()) {
+        eprintln!("Could not write {}: {}", PIDFILE, e);
+    }
+}
+
+fn cancel() -> Result<(), Box<dyn Error>> {
+    let pid: i32 = fs::read_to_string(PIDFILE)?.trim().parse()?;
+    // The pid may have been reused since the helper exited
+    if fs::read_link(format!("/proc/{}/exe", pid))? != std::env::current_exe()? {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No running nsc-helper to cancel",
+        )));
+    }
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+    log_write(&format!("cancelled helper {}", pid));
+    Ok(())
+}
+
+// Runs cmd in its own process group, which is stopped as a whole when cancelled
+fn run(cmd: &mut Command) -> Result<ExitStatus, Box<dyn Error>> {
+    let cancelled = || io::Error::new(io::ErrorKind::Interrupted, "Cancelled");
+    if CANCELLED.load(Ordering::SeqCst) {
+        return Err(Box::new(cancelled()));
+    }
+    let mut child = cmd.process_group(0).spawn()?;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if CANCELLED.load(Ordering::SeqCst) {
+            unsafe {
+                libc::kill(-(child.id() as i32), libc::SIGTERM);
+            }
+            child.wait()?;
+            return Err(Box::new(cancelled()));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// Reports a command that failed, which has no exit code when a signal killed it
+fn failed(name: &str, x: ExitStatus) -> Box<dyn Error> {
+    match (x.code(), x.signal()) {
+        (Some(code), _) => eprintln!("{} failed with exit code {}", name, code),
+        (None, Some(signal)) => eprintln!("{} was killed by signal {}", name, signal),
+        (None, None) => eprintln!("{} failed", name),
+    }
+    Box::new(io::Error::other(format!("{} failed", name)))
 }
 
 fn write_file(path: &str) -> Result<(), Box<dyn Error>> {
+    let path = match check_output(path) {
+        Ok(p) => p,
+        Err(e) => {
+            log_write(&format!("refused write to {}: {}", path, e));
+            return Err(e);
+        }
+    };
     let stdin = io::stdin();
     let mut buf = String::new();
     stdin.lock().read_to_string(&mut buf)?;
-    let mut file = File::create(path)?;
-    write!(file, "{}", &buf)?;
+    backup(&path)?;
+    write_atomic(&path, &buf)?;
+    log_write(&format!("wrote {} ({} bytes)", path.display(), buf.len()));
+    Ok(())
+}
+
+// Reads the allowed paths from policy, which only owner may be able to write
+fn allowed_paths(policy: &Path, owner: u32) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let meta = match fs::metadata(policy) {
+        Ok(m) => m,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(vec![PathBuf::from(DEFAULTALLOWED)])
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+    // Anyone able to edit the policy could make the helper write anywhere
+    if meta.uid() != owner || meta.mode() & 0o022 != 0 {
+        return Err(format!(
+            "{} must be owned and only writable by root",
+            policy.display()
+        )
+        .into());
+    }
+    Ok(fs::read_to_string(policy)?
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .map(PathBuf::from)
+        .filter(|x| x.is_absolute())
+        .collect())
+}
+
+fn check_path(path: &str) -> Result<PathBuf, Box<dyn Error>> {
+    check_path_in(path, &allowed_paths(Path::new(POLICYFILE), 0)?)
+}
+
+fn check_path_in(path: &str, allowed: &[PathBuf]) -> Result<PathBuf, Box<dyn Error>> {
+    let path = Path::new(path);
+    if !path.is_absolute()
+        || path
+            .components()
+            .any(|c| matches!(c, Component::ParentDir | Component::CurDir))
+    {
+        return Err(format!("{} must be an absolute path without '..'", path.display()).into());
+    }
+    // Symlinks anywhere in the path could point outside of the allowed directories
+    let mut current = PathBuf::new();
+    for c in path.components() {
+        current.push(c);
+        match fs::symlink_metadata(&current) {
+            Ok(m) if m.file_type().is_symlink() => {
+                return Err(format!("{} is a symlink", current.display()).into());
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    if !allowed.iter().any(|x| path.starts_with(x)) {
+        return Err(format!("{} is not in an allowed directory", path.display()).into());
+    }
+    Ok(path.to_path_buf())
+}
+
+// Input names are passed on to nix, so only allow what flake.nix can name
+fn check_inputs(inputs: &[String]) -> Result<(), Box<dyn Error>> {
+    for input in inputs {
+        if input.is_empty()
+            || input.starts_with('-')
+            || !input
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '/')
+        {
+            return Err(format!("{} is not a valid flake input", input).into());
+        }
+    }
+    Ok(())
+}
+
+fn check_output(path: &str) -> Result<PathBuf, Box<dyn Error>> {
+    check_output_in(path, &allowed_paths(Path::new(POLICYFILE), 0)?)
+}
+
+fn check_output_in(path: &str, allowed: &[PathBuf]) -> Result<PathBuf, Box<dyn Error>> {
+    let path = check_path_in(path, allowed)?;
+    if path.extension().map(|x| x != "nix").unwrap_or(true) {
+        return Err(format!("{} is not a nix file", path.display()).into());
+    }
+    if let Ok(m) = fs::symlink_metadata(&path) {
+        if !m.is_file() {
+            return Err(format!("{} is not a regular file", path.display()).into());
+        }
+    }
+    Ok(path)
+}
+
+fn log_write(msg: &str) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default();
+    // pkexec and sudo both record who asked for the write
+    let uid = std::env::var("PKEXEC_UID")
+        .or_else(|_| std::env::var("SUDO_UID"))
+        .unwrap_or_else(|_| String::from("0"));
+    let line = format!("{} uid={} {}\n", time, uid, msg);
+    if let Err(e) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(LOGFILE)
+        .and_then(|mut f| f.write_all(line.as_bytes()))
+    {
+        eprintln!("Could not write to {}: {}", LOGFILE, e);
+    }
+}
+
+fn write_atomic(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    let dir = path.parent().ok_or("Invalid output path")?;
+    let name = path.file_name().ok_or("Invalid output path")?;
+    let tmp = dir.join(format!(".{}.nsc-tmp", name.to_string_lossy()));
+
+    // Never follow a link someone placed at the temporary path
+    let _ = fs::remove_file(&tmp);
+    let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+    if let Ok(m) = fs::metadata(path) {
+        file.set_permissions(m.permissions())?;
+        fchown(&file, Some(m.uid()), Some(m.gid()))?;
+    }
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(Box::new(e));
+    }
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn restore(path: &str, old: io::Result<String>) {
+    if let Ok(o) = old {
+        if write_atomic(Path::new(path), &o).is_err() {
+            eprintln!("Could not restore old file");
+        } else {
+            log_write(&format!("restored previous {}", path));
+        }
+    }
+}
+
+fn backup(path: &Path) -> Result<(), Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut dir = Path::new(BACKUPDIR).join(time.to_string());
+    let mut i = 1;
+    while dir.exists() {
+        dir = Path::new(BACKUPDIR).join(format!("{}-{}", time, i));
+        i += 1;
+    }
+    // Backups mirror the original location, e.g. <id>/etc/nixos/configuration.nix
+    let target = dir.join(path.strip_prefix("/")?);
+    fs::create_dir_all(target.parent().ok_or("Invalid backup path")?)?;
+    fs::copy(path, &target)?;
+
+    let backups = backups();
+    if backups.len() > MAXBACKUPS {
+        for (id, _) in &backups[..backups.len() - MAXBACKUPS] {
+            fs::remove_dir_all(Path::new(BACKUPDIR).join(id))?;
+        }
+    }
+    Ok(())
+}
+
+// Backup ids and the files they hold, oldest first
+fn backups() -> Vec<(String, PathBuf)> {
+    let mut backups = fs::read_dir(BACKUPDIR)
+        .map(|dir| {
+            dir.flatten()
+                .filter_map(|entry| {
+                    let id = entry.file_name().to_string_lossy().to_string();
+                    let file = find_file(&entry.path())?;
+                    let original = Path::new("/").join(file.strip_prefix(entry.path()).ok()?);
+                    Some((id, original))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    backups.sort_by_key(|(id, _)| {
+        let (time, i) = id.split_once('-').unwrap_or((id, "0"));
+        (
+            time.parse::<u64>().unwrap_or_default(),
+            i.parse::<u64>().unwrap_or_default(),
+        )
+    });
+    backups
+}
+
+fn find_file(dir: &Path) -> Option<PathBuf> {
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(file) = find_file(&path) {
+                return Some(file);
+            }
+        } else {
+            return Some(path);
+        }
+    }
+    None
+}
+
+fn restore_backup(id: Option<String>) -> Result<(), Box<dyn Error>> {
+    let backups = backups();
+    let (id, original) = match id {
+        Some(b) => backups.into_iter().find(|(id, _)| id == &b),
+        None => backups.into_iter().last(),
+    }
+    .ok_or("Backup not found")?;
+    let file = Path::new(BACKUPDIR)
+        .join(&id)
+        .join(original.strip_prefix("/")?);
+    let contents = fs::read_to_string(file)?;
+    let original = match check_output(&original.to_string_lossy()) {
+        Ok(p) => p,
+        Err(e) => {
+            log_write(&format!("refused restore of {}: {}", original.display(), e));
+            return Err(e);
+        }
+    };
+    backup(&original)?;
+    write_atomic(&original, &contents)?;
+    log_write(&format!(
+        "restored {} from backup {}",
+        original.display(),
+        id
+    ));
+    println!("Restored {} from backup {}", original.to_string_lossy(), id);
     Ok(())
 }
 
-fn rebuild(args: Vec<String>, generations: Option<u32>) -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::new("nixos-rebuild").args(args).spawn()?;
-    let x = cmd.wait()?;
+fn rebuild(mode: Mode, args: Vec<String>, generations: Option<u32>) -> Result<(), Box<dyn Error>> {
+    // `nixos-rebuild build` leaves a result link in the working directory
+    let x = run(Command::new("nixos-rebuild")
+        .current_dir(std::env::temp_dir())
+        .arg(mode.arg())
+        .args(args))?;
     if !x.success() {
-        eprintln!("nixos-rebuild failed with exit code {}", x.code().unwrap());
-        return Err(Box::new(io::Error::new(
-            io::ErrorKind::Other,
-            "nixos-rebuild failed",
-        )));
+        return Err(failed("nixos-rebuild", x));
+    }
+    if mode != Mode::Switch {
+        return Ok(());
     }
     if let Some(g) = generations {
         if g > 0 {
-            let mut cmd = Command::new("nix-env")
+            let x = run(Command::new("nix-env")
                 .arg("--delete-generations")
                 .arg("-p")
                 .arg("/nix/var/nix/profiles/system")
-                .arg(&format!("+{}", g))
-                .spawn()?;
-            let x = cmd.wait()?;
+                .arg(format!("+{}", g)))?;
             if !x.success() {
-                eprintln!(
-                    "nix-env --delete-generations failed with exit code {}",
-                    x.code().unwrap()
-                );
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::Other,
-                    "nix-env failed",
-                )));
+                return Err(failed("nix-env --delete-generations", x));
             }
         }
     }
     Ok(())
 }
 
+fn rollback(generation: Option<u32>) -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::new("nix-env");
+    cmd.arg("-p").arg("/nix/var/nix/profiles/system");
+    if let Some(g) = generation {
+        cmd.arg("--switch-generation").arg(g.to_string());
+    } else {
+        cmd.arg("--rollback");
+    }
+    let x = run(&mut cmd)?;
+    if !x.success() {
+        return Err(failed("nix-env", x));
+    }
+    let x = run(
+        Command::new("/nix/var/nix/profiles/system/bin/switch-to-configuration").arg("switch"),
+    )?;
+    if x.success() {
+        Ok(())
+    } else {
+        Err(failed("switch-to-configuration", x))
+    }
+}
+
 fn channel() -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::new("nix-channel").arg("--update").spawn()?;
-    let x = cmd.wait()?;
+    let x = run(Command::new("nix-channel").arg("--update"))?;
     if x.success() {
         Ok(())
     } else {
-        eprintln!("nix-channel failed with exit code {}", x.code().unwrap());
-        Err(Box::new(io::Error::new(
-            io::ErrorKind::Other,
-            "nix-channel failed",
-        )))
+        Err(failed("nix-channel", x))
     }
 }
 
-fn flake(path: &str) -> Result<(), Box<dyn Error>> {
-    let mut cmd = Command::new("nix")
-        .arg("flake")
-        .arg("update")
-        .arg(path)
-        .spawn()?;
-    let x = cmd.wait()?;
+fn flake(path: &str, inputs: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::new("nix");
+    if inputs.is_empty() {
+        cmd.arg("flake").arg("update").arg(path);
+    } else {
+        cmd.arg("flake").arg("lock").arg(path);
+        for input in inputs {
+            cmd.arg("--update-input").arg(input);
+        }
+    }
+    let x = run(&mut cmd)?;
     if x.success() {
         Ok(())
     } else {
-        eprintln!("nix flake failed with exit code {}", x.code().unwrap());
-        Err(Box::new(io::Error::new(
-            io::ErrorKind::Other,
-            "nix flake failed",
-        )))
+        Err(failed("nix flake", x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    // A temporary directory with nixos/configuration.nix, resolved so /tmp itself is no symlink
+    fn tree() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("nixos")).unwrap();
+        fs::write(root.join("nixos/configuration.nix"), "{ }").unwrap();
+        (dir, root)
+    }
+
+    fn policy(root: &Path, contents: &str, mode: u32) -> PathBuf {
+        let path = root.join("helper.conf");
+        fs::write(&path, contents).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    fn check(path: &Path, allowed: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        check_output_in(&path.to_string_lossy(), &[allowed.to_path_buf()])
+    }
+
+    #[test]
+    fn allowed() {
+        let (_dir, root) = tree();
+        let allowed = root.join("nixos");
+        let file = allowed.join("configuration.nix");
+        assert_eq!(check(&file, &allowed).unwrap(), file);
+        // Files that don't exist yet can be created
+        assert!(check(&allowed.join("new.nix"), &allowed).is_ok());
+    }
+
+    #[test]
+    fn parentdir() {
+        let (_dir, root) = tree();
+        let allowed = root.join("nixos");
+        assert!(check(&allowed.join("../nixos/configuration.nix"), &allowed).is_err());
+        assert!(check(&allowed.join("../escape.nix"), &allowed).is_err());
+    }
+
+    #[test]
+    fn relative() {
+        assert!(check_output_in("nixos/configuration.nix", &[PathBuf::from("nixos")]).is_err());
+        assert!(check_path_in("configuration.nix", &[PathBuf::from("/")]).is_err());
+    }
+
+    #[test]
+    fn outside() {
+        let (_dir, root) = tree();
+        let allowed = root.join("nixos");
+        fs::create_dir(root.join("nixosevil")).unwrap();
+        assert!(check(&root.join("other.nix"), &allowed).is_err());
+        // Only whole components match
+        assert!(check(&root.join("nixosevil/configuration.nix"), &allowed).is_err());
+        assert!(
+            check_output_in(&allowed.join("configuration.nix").to_string_lossy(), &[]).is_err()
+        );
+    }
+
+    #[test]
+    fn symlinks() {
+        let (_dir, root) = tree();
+        let allowed = root.join("nixos");
+        // A link in the allowed directory to somewhere else
+        fs::create_dir(root.join("elsewhere")).unwrap();
+        symlink(root.join("elsewhere"), allowed.join("linked")).unwrap();
+        assert!(check(&allowed.join("linked/configuration.nix"), &allowed).is_err());
+        // A link as the target itself
+        symlink(
+            root.join("elsewhere/target.nix"),
+            allowed.join("target.nix"),
+        )
+        .unwrap();
+        assert!(check(&allowed.join("target.nix"), &allowed).is_err());
+        // A link as the allowed directory
+        symlink(&allowed, root.join("nixoslink")).unwrap();
+        assert!(check(
+            &root.join("nixoslink/configuration.nix"),
+            &root.join("nixoslink")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn notnix() {
+        let (_dir, root) = tree();
+        let allowed = root.join("nixos");
+        fs::write(allowed.join("hardware.conf"), "").unwrap();
+        assert!(check(&allowed.join("hardware.conf"), &allowed).is_err());
+        assert!(check(&allowed.join("configuration"), &allowed).is_err());
+        assert!(check(&allowed.join("configuration.nix.bak"), &allowed).is_err());
+        // check_path is also used for flake directories, which have no extension
+        assert!(check_path_in(&allowed.to_string_lossy(), &[root.join("nixos")]).is_ok());
+        fs::create_dir(allowed.join("dir.nix")).unwrap();
+        assert!(check(&allowed.join("dir.nix"), &allowed).is_err());
+    }
+
+    #[test]
+    fn policymissing() {
+        let (_dir, root) = tree();
+        assert_eq!(
+            allowed_paths(&root.join("missing.conf"), users::get_current_uid()).unwrap(),
+            vec![PathBuf::from(DEFAULTALLOWED)]
+        );
+    }
+
+    #[test]
+    fn policyparse() {
+        let (_dir, root) = tree();
+        let path = policy(
+            &root,
+            "# comment\n\n  /etc/nixos  \nrelative/path\n/home/user/flake\nnot a path\n",
+            0o644,
+        );
+        assert_eq!(
+            allowed_paths(&path, users::get_current_uid()).unwrap(),
+            vec![
+                PathBuf::from("/etc/nixos"),
+                PathBuf::from("/home/user/flake")
+            ]
+        );
+        // Nothing but comments allows nothing, instead of falling back to /etc/nixos
+        let path = policy(&root, "# nothing\n", 0o644);
+        assert!(allowed_paths(&path, users::get_current_uid())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn policyinsecure() {
+        let (_dir, root) = tree();
+        let uid = users::get_current_uid();
+        let path = policy(&root, "/etc/nixos\n", 0o664);
+        assert!(allowed_paths(&path, uid).is_err());
+        let path = policy(&root, "/etc/nixos\n", 0o646);
+        assert!(allowed_paths(&path, uid).is_err());
+        let path = policy(&root, "/etc/nixos\n", 0o644);
+        assert!(allowed_paths(&path, uid + 1).is_err());
+    }
+
+    #[test]
+    fn policyunreadable() {
+        let (_dir, root) = tree();
+        // A directory where the policy should be can't be read
+        fs::create_dir(root.join("helper.conf")).unwrap();
+        fs::set_permissions(root.join("helper.conf"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(allowed_paths(&root.join("helper.conf"), users::get_current_uid()).is_err());
+        fs::remove_dir(root.join("helper.conf")).unwrap();
+        // Neither can a file that isn't UTF-8
+        let path = root.join("helper.conf");
+        fs::write(&path, [0xff, 0xfe, b'/']).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(allowed_paths(&path, users::get_current_uid()).is_err());
     }
 }
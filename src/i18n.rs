@@ -0,0 +1,47 @@
+use crate::config::{GETTEXT_PACKAGE, LOCALEDIR};
+use gettextrs::{bind_textdomain_codeset, bindtextdomain, setlocale, textdomain, LocaleCategory};
+use log::*;
+
+// Loads the installed translations for the user's locale
+pub fn init() {
+    setlocale(LocaleCategory::LcAll, "");
+    if let Err(e) = bindtextdomain(GETTEXT_PACKAGE, LOCALEDIR) {
+        warn!("Failed to bind text domain: {}", e);
+    }
+    if let Err(e) = bind_textdomain_codeset(GETTEXT_PACKAGE, "UTF-8") {
+        warn!("Failed to set text domain codeset: {}", e);
+    }
+    if let Err(e) = textdomain(GETTEXT_PACKAGE) {
+        warn!("Failed to set text domain: {}", e);
+    }
+}
+
+// Fills in the placeholders of a translated string, since format! only takes literals.
+// {} takes the next argument, while {0}, {1}, ... let translations reorder them
+pub fn fill(template: &str, args: &[&str]) -> String {
+    let mut out = String::new();
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        let index = match &rest[1..end] {
+            "" => {
+                next += 1;
+                Some(next - 1)
+            }
+            x => x.parse::<usize>().ok(),
+        };
+        match index.and_then(|i| args.get(i)) {
+            Some(arg) => out.push_str(arg),
+            None => out.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
@@ -1,4 +1,4 @@
 pub mod ui;
 pub mod parse;
 pub mod config;
-static APPINFO: &str = "/usr/share/app-info";
\ No newline at end of file
+pub mod i18n;
@@ -1,12 +1,15 @@
 use adw::gio;
 use gtk::{prelude::ApplicationExt, glib};
+use gettextrs::gettext;
 use log::{error, info};
-use nix_software_center::{ui::window::AppModel, config::RESOURCES_FILE};
+use nix_software_center::{ui::window::AppModel, config::RESOURCES_FILE, i18n};
 use relm4::*;
 fn main() {
-    gtk::init().unwrap();
     pretty_env_logger::init();
-	glib::set_application_name("Software Center");
+    // Before GTK, so its own strings are translated too
+    i18n::init();
+    gtk::init().unwrap();
+	glib::set_application_name(&gettext("Software Center"));
     if let Ok(res) = gio::Resource::load(RESOURCES_FILE) {
         info!("Resource loaded: {}", RESOURCES_FILE);
         gio::resources_register(&res);
@@ -1,3 +1 @@
-pub mod packages;
-pub mod config;
-pub mod util;
\ No newline at end of file
+pub mod util;
@@ -1,3 +1,40 @@
+use adw::{gio, prelude::*};
+use nsc_core::packages::AppData;
+use sha256::digest;
+use std::{env, path::Path};
+
 pub fn checkonline() -> bool {
     reqwest::blocking::get("https://nmcheck.gnome.org/check_network_status.txt").is_ok()
-}
\ No newline at end of file
+}
+
+// Icons that are only online are downloaded next to the screenshots
+pub fn remoteiconpath(url: &str) -> Option<String> {
+    let home = env::var("HOME").ok()?;
+    Some(format!(
+        "{}/.cache/nix-software-center/screenshots/icon-{}",
+        home,
+        digest(url.to_string())
+    ))
+}
+
+// A file to show as the app's icon, or else a name to look up in the icon theme
+pub fn appicon(data: &AppData) -> Option<String> {
+    let icons = data.icon.as_ref()?;
+    data.cachedicon()
+        .or_else(|| {
+            icons
+                .remoteurl()
+                .and_then(remoteiconpath)
+                .filter(|x| Path::new(x).is_file())
+        })
+        .or_else(|| icons.stock.clone())
+}
+
+// Falls back to the generic package icon, also when the icon theme doesn't have the icon
+pub fn appgicon(icon: Option<&str>) -> gio::Icon {
+    match icon {
+        Some(i) if i.starts_with('/') => gio::FileIcon::new(&gio::File::for_path(i)).upcast(),
+        Some(i) => gio::ThemedIcon::from_names(&[i, "package-x-generic"]).upcast(),
+        None => gio::ThemedIcon::new("package-x-generic").upcast(),
+    }
+}
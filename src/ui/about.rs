@@ -1,4 +1,5 @@
 use adw::prelude::*;
+use gettextrs::gettext;
 use relm4::*;
 
 use crate::config;
@@ -40,11 +41,13 @@ impl SimpleComponent for AboutPageModel {
     fn update_view(&self, dialog: &mut Self::Widgets, _sender: ComponentSender<Self>) {
         let dialog = adw::AboutWindow::builder()
             .application_icon(config::APP_ID)
-            .application_name("Nix Software Center")
+            .application_name(gettext("Nix Software Center"))
             .developer_name("Victor Fuentes")
             .developers(vec!["Victor Fuentes https://github.com/vlinkz"])
             .issue_url("https://github.com/vlinkz/nix-software-center/issues")
             .license_type(gtk::License::Gpl30)
+            // Translators: your names, one per line
+            .translator_credits(gettext("translator-credits"))
             .modal(true)
             .transient_for(&dialog.parent_window)
             .version(config::VERSION)
@@ -0,0 +1,86 @@
+use crate::i18n;
+use gettextrs::{gettext, ngettext};
+use nsc_core::appstream::{AppStreamSource, SourceKind, SourceState};
+use relm4::adw::prelude::*;
+use relm4::{factory::*, *};
+
+use super::preferencespage::PreferencesPageMsg;
+
+#[derive(Debug)]
+pub struct AppStreamSourceItem {
+    pub source: AppStreamSource,
+}
+
+#[derive(Debug)]
+pub enum AppStreamSourceItemMsg {}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for AppStreamSourceItem {
+    type CommandOutput = ();
+    type Init = AppStreamSource;
+    type Input = ();
+    type Output = AppStreamSourceItemMsg;
+    type ParentWidget = gtk::ListBox;
+    type ParentInput = PreferencesPageMsg;
+
+    view! {
+        adw::ActionRow {
+            set_use_markup: false,
+            set_title: &self.source.path,
+            set_title_lines: 1,
+            set_subtitle: &self.subtitle(),
+            set_subtitle_lines: 2,
+            set_tooltip_text: self.source.file.as_deref(),
+            add_suffix = &gtk::Image {
+                set_icon_name: match self.source.state {
+                    SourceState::Loaded { used, .. } if used > 0 => Some("emblem-ok-symbolic"),
+                    SourceState::Failed(_) => Some("dialog-warning-symbolic"),
+                    _ => None,
+                },
+            }
+        }
+    }
+
+    fn init_model(
+        parent: Self::Init,
+        _index: &DynamicIndex,
+        _sender: FactorySender<Self>,
+    ) -> Self {
+        Self { source: parent }
+    }
+}
+
+impl AppStreamSourceItem {
+    fn subtitle(&self) -> String {
+        let kind = match self.source.kind {
+            SourceKind::Config => gettext("Configured"),
+            SourceKind::Profile => gettext("Nix profile"),
+            SourceKind::Xdg => gettext("XDG data directory"),
+            SourceKind::Default => gettext("Default location"),
+        };
+        let state = match &self.source.state {
+            SourceState::Loaded { apps, used, failed } => {
+                let mut out = i18n::fill(
+                    &ngettext("{0} app, {1} used", "{0} apps, {1} used", *apps as u32),
+                    &[&apps.to_string(), &used.to_string()],
+                );
+                if *failed > 0 {
+                    out.push_str(", ");
+                    out.push_str(&i18n::fill(
+                        &ngettext(
+                            "{} entry could not be read",
+                            "{} entries could not be read",
+                            *failed as u32,
+                        ),
+                        &[&failed.to_string()],
+                    ));
+                }
+                out
+            }
+            SourceState::Missing => gettext("No data for this system"),
+            SourceState::Duplicate => gettext("Same data as a source above"),
+            SourceState::Failed(e) => i18n::fill(&gettext("Could not be read: {}"), &[e]),
+        };
+        format!("{} · {}", kind, state)
+    }
+}
@@ -1,3 +1,4 @@
+use gettextrs::gettext;
 use relm4::adw::prelude::*;
 use relm4::gtk::pango;
 use relm4::{factory::*, *};
@@ -19,6 +20,19 @@ pub enum PkgCategory {
     Video,
 }
 
+impl PkgCategory {
+    pub fn name(&self) -> String {
+        match self {
+            PkgCategory::Audio => gettext("Audio"),
+            PkgCategory::Development => gettext("Development"),
+            PkgCategory::Games => gettext("Games"),
+            PkgCategory::Graphics => gettext("Graphics"),
+            PkgCategory::Web => gettext("Web"),
+            PkgCategory::Video => gettext("Video"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum PkgCategoryMsg {
     Open(PkgCategory),
@@ -62,14 +76,7 @@ impl FactoryComponent for PkgGroup {
                         add_css_class: "title-2",
                         set_valign: gtk::Align::Center,
                         set_hexpand: true,
-                        set_label: match self.category {
-                            PkgCategory::Audio => "Audio",
-                            PkgCategory::Development => "Development",
-                            PkgCategory::Games => "Games",
-                            PkgCategory::Graphics => "Graphics",
-                            PkgCategory::Web => "Web",
-                            PkgCategory::Video => "Video",
-                        },
+                        set_label: &self.category.name(),
                         set_ellipsize: pango::EllipsizeMode::End,
                         set_lines: 1,
                         set_wrap: true,
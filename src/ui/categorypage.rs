@@ -1,5 +1,6 @@
 use super::{categories::PkgCategory, categorytile::CategoryTile, window::*};
 use adw::prelude::*;
+use gettextrs::gettext;
 use log::*;
 use relm4::{factory::*, *};
 
@@ -52,14 +53,7 @@ impl Component for CategoryPageModel {
                 #[wrap(Some)]
                 set_title_widget = &gtk::Label {
                     #[watch]
-                    set_label: match model.category {
-                        PkgCategory::Audio => "Audio",
-                        PkgCategory::Development => "Development",
-                        PkgCategory::Games => "Games",
-                        PkgCategory::Graphics => "Graphics",
-                        PkgCategory::Web => "Web",
-                        PkgCategory::Video => "Video",
-                    },
+                    set_label: &model.category.name(),
                 },
             },
             gtk::ScrolledWindow {
@@ -91,7 +85,7 @@ impl Component for CategoryPageModel {
                             gtk::Label {
                                 set_halign: gtk::Align::Start,
                                 add_css_class: "title-4",
-                                set_label: "Recommended",
+                                set_label: &gettext("Recommended"),
                             },
                             #[local_ref]
                             recbox -> gtk::FlowBox {
@@ -109,7 +103,7 @@ impl Component for CategoryPageModel {
                             gtk::Label {
                                 set_halign: gtk::Align::Start,
                                 add_css_class: "title-4",
-                                set_label: "Other",
+                                set_label: &gettext("Other"),
                             },
                             #[local_ref]
                             allbox -> gtk::FlowBox {
@@ -1,6 +1,4 @@
-use std::path::Path;
-
-use crate::APPINFO;
+use crate::parse::util;
 
 use super::categorypage::CategoryPageMsg;
 use relm4::adw::prelude::*;
@@ -75,34 +73,11 @@ impl FactoryComponent for CategoryTile {
                         set_margin_top: 10,
                         set_margin_bottom: 10,
                         set_spacing: 20,
-                        append = if self.icon.is_some() {
-                            gtk::Image {
-                                add_css_class: "icon-dropshadow",
-                                set_halign: gtk::Align::Start,
-                                set_from_file: {
-                                    if let Some(i) = &self.icon {
-                                        let iconpath = format!("{}/icons/nixos/128x128/{}", APPINFO, i);
-                                        let iconpath64 = format!("{}/icons/nixos/64x64/{}", APPINFO, i);
-                                        if Path::new(&iconpath).is_file() {
-                                            Some(iconpath)
-                                        } else if Path::new(&iconpath64).is_file() {
-                                            Some(iconpath64)
-                                        } else {
-                                            None
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                },
-                                set_pixel_size: 64,
-                            }
-                        } else {
-                            gtk::Image {
-                                add_css_class: "icon-dropshadow",
-                                set_halign: gtk::Align::Start,
-                                set_icon_name: Some("package-x-generic"),
-                                set_pixel_size: 64,
-                            }
+                        gtk::Image {
+                            add_css_class: "icon-dropshadow",
+                            set_halign: gtk::Align::Start,
+                            set_from_gicon: &util::appgicon(self.icon.as_deref()),
+                            set_pixel_size: 64,
                         },
                         gtk::Box {
                             set_orientation: gtk::Orientation::Vertical,
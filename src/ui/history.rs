@@ -0,0 +1,370 @@
+use adw::prelude::*;
+use gettextrs::gettext;
+use gtk::pango;
+use log::*;
+use nix_data::config::configfile::NixDataConfig;
+use nsc_core::{
+    history::HistoryEntry, install::RollbackConflict, History, InstallType, Installer, PkgAction,
+};
+use relm4::{factory::*, prelude::*, *};
+
+use crate::i18n;
+
+use super::{
+    rebuild::{review, RebuildMsg},
+    window::{AppMsg, UserPkgs, REBUILD_BROKER},
+};
+
+#[tracker::track]
+#[derive(Debug)]
+pub struct HistoryModel {
+    hidden: bool,
+    busy: bool,
+    config: NixDataConfig,
+    userpkgs: UserPkgs,
+    system: String,
+    #[tracker::no_eq]
+    items: FactoryVecDeque<HistoryItemModel>,
+}
+
+#[derive(Debug)]
+pub enum HistoryMsg {
+    Show,
+    Close,
+    Reload,
+    UpdateConfig(NixDataConfig),
+    Rollback(u64),
+}
+
+#[derive(Debug)]
+pub enum HistoryAsyncMsg {
+    RollbackFinished(bool),
+    RollbackRefused(RollbackConflict),
+    RollbackCancelled,
+    RollbackStopped,
+}
+
+pub struct HistoryInit {
+    pub window: gtk::Window,
+    pub config: NixDataConfig,
+    pub userpkgs: UserPkgs,
+    pub system: String,
+}
+
+#[relm4::component(pub)]
+impl Component for HistoryModel {
+    type Init = HistoryInit;
+    type Input = HistoryMsg;
+    type Output = AppMsg;
+    type CommandOutput = HistoryAsyncMsg;
+
+    view! {
+        dialog = adw::Window {
+            set_transient_for: Some(&init.window),
+            set_modal: true,
+            set_default_width: 600,
+            set_default_height: 500,
+            #[watch]
+            set_visible: !model.hidden,
+            connect_close_request[sender] => move |_| {
+                sender.input(HistoryMsg::Close);
+                gtk::Inhibit(true)
+            },
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+                adw::HeaderBar {
+                    add_css_class: "flat",
+                    #[wrap(Some)]
+                    set_title_widget = &gtk::Label {
+                        add_css_class: "heading",
+                        set_label: &gettext("History"),
+                    },
+                },
+                gtk::ScrolledWindow {
+                    set_vexpand: true,
+                    set_hscrollbar_policy: gtk::PolicyType::Never,
+                    adw::Clamp {
+                        set_margin_all: 15,
+                        if model.items.is_empty() {
+                            adw::StatusPage {
+                                set_icon_name: Some("document-open-recent-symbolic"),
+                                set_title: &gettext("No History"),
+                                set_description: Some(&gettext("Packages you install or remove will be listed here")),
+                            }
+                        } else {
+                            #[local_ref]
+                            historylist -> gtk::ListBox {
+                                set_valign: gtk::Align::Start,
+                                add_css_class: "boxed-list",
+                                set_selection_mode: gtk::SelectionMode::None,
+                                #[watch]
+                                set_sensitive: !model.busy,
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = HistoryModel {
+            hidden: true,
+            busy: false,
+            config: init.config.clone(),
+            userpkgs: init.userpkgs.clone(),
+            system: init.system.clone(),
+            items: FactoryVecDeque::new(gtk::ListBox::new(), sender.input_sender()),
+            tracker: 0,
+        };
+
+        let historylist = model.items.widget();
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
+        self.reset();
+        match msg {
+            HistoryMsg::Show => {
+                sender.input(HistoryMsg::Reload);
+                self.set_hidden(false);
+            }
+            HistoryMsg::Close => {
+                self.set_hidden(true);
+            }
+            HistoryMsg::Reload => {
+                let history = match History::load() {
+                    Ok(h) => h,
+                    Err(e) => {
+                        warn!("Failed to load history: {}", e);
+                        History::default()
+                    }
+                };
+                let mut items_guard = self.items.guard();
+                items_guard.clear();
+                for entry in history.entries().iter().rev() {
+                    items_guard.push_back((entry.clone(), history.canrollback(entry)));
+                }
+            }
+            HistoryMsg::UpdateConfig(config) => {
+                self.set_config(config);
+            }
+            HistoryMsg::Rollback(id) => {
+                let entry = match History::load().map(|x| x.get(id).cloned()) {
+                    Ok(Some(entry)) => entry,
+                    _ => {
+                        warn!("Transaction {} not found", id);
+                        return;
+                    }
+                };
+                info!("Rolling back transaction {}", id);
+                self.set_busy(true);
+                self.set_hidden(true);
+                REBUILD_BROKER.send(RebuildMsg::Show);
+                let installer = Installer::new(self.config.clone(), self.userpkgs.clone(), self.system.clone());
+                let canceller = installer.canceller();
+                sender.oneshot_command(async move {
+                    // The whole configuration from before the transaction is written back
+                    match installer.checkrollback(&entry) {
+                        Ok(Some(conflict)) => return HistoryAsyncMsg::RollbackRefused(conflict),
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!("Error reading system configuration: {}", e);
+                            return HistoryAsyncMsg::RollbackFinished(false);
+                        }
+                    }
+                    match installer.previewrollback(&entry) {
+                        Ok(diff) => {
+                            if !review(diff).await {
+                                return HistoryAsyncMsg::RollbackCancelled;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Error reading system configuration: {}", e);
+                            return HistoryAsyncMsg::RollbackFinished(false);
+                        }
+                    }
+                    REBUILD_BROKER.send(RebuildMsg::SetCanceller(Some(canceller.clone())));
+                    let result = installer
+                        .rollback(&entry, false, |line| {
+                            REBUILD_BROKER.send(RebuildMsg::UpdateText(line));
+                        })
+                        .await;
+                    if canceller.cancelled() {
+                        return HistoryAsyncMsg::RollbackStopped;
+                    }
+                    match result {
+                        Ok(success) => HistoryAsyncMsg::RollbackFinished(success),
+                        Err(e) => {
+                            warn!("Error rolling back transaction: {}", e);
+                            HistoryAsyncMsg::RollbackFinished(false)
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    fn update_cmd(
+        &mut self,
+        msg: Self::CommandOutput,
+        sender: ComponentSender<Self>,
+        _root: &Self::Root,
+    ) {
+        self.reset();
+        match msg {
+            HistoryAsyncMsg::RollbackFinished(success) => {
+                self.set_busy(false);
+                if success {
+                    REBUILD_BROKER.send(RebuildMsg::FinishSuccess);
+                } else {
+                    REBUILD_BROKER.send(RebuildMsg::FinishError(None));
+                }
+                sender.output(AppMsg::UpdateInstalledPkgs);
+                sender.input(HistoryMsg::Reload);
+            }
+            HistoryAsyncMsg::RollbackRefused(conflict) => {
+                self.set_busy(false);
+                let reason = match conflict {
+                    RollbackConflict::Newer(ids) => i18n::fill(
+                        &gettext("Transactions {} changed the configuration afterwards and would be undone as well. Roll them back first."),
+                        &[&ids
+                            .iter()
+                            .map(|x| format!("#{}", x))
+                            .collect::<Vec<_>>()
+                            .join(", ")],
+                    ),
+                    RollbackConflict::Edited => gettext("The configuration was edited afterwards, and rolling back would undo those edits."),
+                };
+                REBUILD_BROKER.send(RebuildMsg::FinishError(Some(reason)));
+            }
+            HistoryAsyncMsg::RollbackCancelled => {
+                self.set_busy(false);
+                self.set_hidden(false);
+            }
+            HistoryAsyncMsg::RollbackStopped => {
+                self.set_busy(false);
+                REBUILD_BROKER.send(RebuildMsg::FinishCancelled);
+                sender.input(HistoryMsg::Reload);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryItemModel {
+    entry: HistoryEntry,
+    // Not already rolled back by a later transaction
+    canrollback: bool,
+}
+
+#[derive(Debug)]
+pub enum HistoryItemMsg {
+    Rollback(u64),
+}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for HistoryItemModel {
+    type CommandOutput = ();
+    type Init = (HistoryEntry, bool);
+    type Input = ();
+    type Output = HistoryItemMsg;
+    type ParentWidget = adw::gtk::ListBox;
+    type ParentInput = HistoryMsg;
+
+    view! {
+        adw::ExpanderRow {
+            set_title: &self.title(),
+            set_subtitle: &self.subtitle(),
+            set_enable_expansion: self.entry.diff.as_ref().map(|x| !x.is_empty()).unwrap_or(false),
+            set_show_enable_switch: false,
+            add_prefix = &gtk::Image {
+                set_valign: gtk::Align::Center,
+                set_icon_name: Some(if !self.entry.success {
+                    "dialog-error-symbolic"
+                } else if self.entry.rollback.is_some() {
+                    "edit-undo-symbolic"
+                } else {
+                    "object-select-symbolic"
+                }),
+                add_css_class: if self.entry.success { "success" } else { "error" },
+            },
+            add_action = &gtk::Button {
+                set_valign: gtk::Align::Center,
+                set_label: &gettext("Rollback"),
+                set_visible: self.canrollback,
+                connect_clicked[sender, id = self.entry.id] => move |_| {
+                    sender.output(HistoryItemMsg::Rollback(id));
+                }
+            },
+            add_row = &gtk::Label {
+                set_margin_all: 10,
+                set_halign: gtk::Align::Start,
+                set_xalign: 0.0,
+                set_selectable: true,
+                add_css_class: "monospace",
+                set_wrap: true,
+                set_wrap_mode: pango::WrapMode::WordChar,
+                set_label: self.entry.diff.as_deref().unwrap_or_default(),
+            }
+        }
+    }
+
+    fn init_model(
+        (entry, canrollback): Self::Init,
+        _index: &DynamicIndex,
+        _sender: FactorySender<Self>,
+    ) -> Self {
+        Self { entry, canrollback }
+    }
+
+    fn forward_to_parent(output: Self::Output) -> Option<HistoryMsg> {
+        Some(match output {
+            HistoryItemMsg::Rollback(id) => HistoryMsg::Rollback(id),
+        })
+    }
+}
+
+impl HistoryItemModel {
+    fn title(&self) -> String {
+        let pkgs = self
+            .entry
+            .packages
+            .iter()
+            .map(|x| match x.action {
+                PkgAction::Install => i18n::fill(&gettext("Install {}"), &[&x.pkg]),
+                PkgAction::Remove => i18n::fill(&gettext("Remove {}"), &[&x.pkg]),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Some(id) = self.entry.rollback {
+            i18n::fill(&gettext("Rollback of #{}: {}"), &[&id.to_string(), &pkgs])
+        } else {
+            pkgs
+        }
+    }
+
+    fn subtitle(&self) -> String {
+        let mut subtitle = vec![match self.entry.pkgtype {
+            InstallType::User => gettext("User"),
+            InstallType::System => gettext("System"),
+        }];
+        if let Ok(time) = gtk::glib::DateTime::from_unix_local(self.entry.timestamp as i64)
+            .and_then(|x| x.format("%x %X"))
+        {
+            subtitle.push(time.to_string());
+        }
+        if let Some(g) = self.entry.generation {
+            subtitle.push(i18n::fill(&gettext("Generation {}"), &[&g.to_string()]));
+        }
+        if !self.entry.success {
+            subtitle.push(gettext("Failed"));
+        }
+        subtitle.join(" · ")
+    }
+}
@@ -1,5 +1,4 @@
-use std::path::Path;
-use crate::APPINFO;
+use crate::parse::util;
 
 use super::{window::*, pkgpage::{InstallType, WorkPkg, PkgAction, NotifyPage}};
 use adw::prelude::*;
@@ -49,10 +48,7 @@ impl SimpleComponent for InstalledPageModel {
                         set_visible: !model.installeduserlist.is_empty(),
                         set_halign: gtk::Align::Start,
                         add_css_class: "title-4",
-                        set_label: match model.userpkgtype {
-                            UserPkgs::Env => "User (nix-env)",
-                            UserPkgs::Profile => "User (nix profile)",
-                        },
+                        set_label: &userpkgslabel(&model.userpkgtype),
                     },
                     #[local_ref]
                     installeduserlist -> gtk::ListBox {
@@ -72,7 +68,7 @@ impl SimpleComponent for InstalledPageModel {
                         set_visible: !model.installedsystemlist.is_empty(),
                         set_halign: gtk::Align::Start,
                         add_css_class: "title-4",
-                        set_label: "System (configuration.nix)",
+                        set_label: &systempkgslabel(),
                     },
                     #[local_ref]
                     installedsystemlist -> gtk::ListBox {
@@ -244,34 +240,11 @@ impl FactoryComponent for InstalledItemModel {
                 adw::Bin {
                     set_valign: gtk::Align::Center,
                     #[wrap(Some)]
-                    set_child = if self.item.icon.is_some() {
-                        gtk::Image {
-                            add_css_class: "icon-dropshadow",
-                            set_halign: gtk::Align::Start,
-                            set_from_file: {
-                                if let Some(i) = &self.item.icon {
-                                    let iconpath = format!("{}/icons/nixos/128x128/{}", APPINFO, i);
-                                    let iconpath64 = format!("{}/icons/nixos/64x64/{}", APPINFO, i);
-                                    if Path::new(&iconpath).is_file() {
-                                        Some(iconpath)
-                                    } else if Path::new(&iconpath64).is_file() {
-                                        Some(iconpath64)
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                }
-                            },
-                            set_pixel_size: 64,
-                        }
-                    } else {
-                        gtk::Image {
-                            add_css_class: "icon-dropshadow",
-                            set_halign: gtk::Align::Start,
-                            set_icon_name: Some("package-x-generic"),
-                            set_pixel_size: 64,
-                        }
+                    set_child = &gtk::Image {
+                        add_css_class: "icon-dropshadow",
+                        set_halign: gtk::Align::Start,
+                        set_from_gicon: &util::appgicon(self.item.icon.as_deref()),
+                        set_pixel_size: 64,
                     }
                 },
                 gtk::Box {
@@ -1,25 +1,27 @@
 use super::pkgpage::{InstallType, PkgAction, PkgMsg, WorkPkg};
-use super::rebuild::RebuildMsg;
+use super::rebuild::{review, reviewbuild, RebuildMsg};
 use super::window::{SystemPkgs, UserPkgs, REBUILD_BROKER};
+use gettextrs::gettext;
 use log::*;
 use nix_data::config::configfile::NixDataConfig;
+use nsc_core::{BuildResult, Canceller, Installer, Progress};
 use relm4::*;
-use anyhow::{Result, anyhow};
-use std::path::Path;
-use std::process::Stdio;
-use std::fs;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use std::sync::Mutex;
 
 #[tracker::track]
 #[derive(Debug)]
 pub struct InstallAsyncHandler {
     #[tracker::no_eq]
     process: Option<JoinHandle<()>>,
+    #[tracker::no_eq]
+    batch: Option<JoinHandle<()>>,
     work: Option<WorkPkg>,
     config: NixDataConfig,
-    pid: Option<u32>,
+    #[tracker::no_eq]
+    canceller: Option<Canceller>,
     syspkgs: SystemPkgs,
     userpkgs: UserPkgs,
+    system: String,
 }
 
 #[derive(Debug)]
@@ -27,14 +29,15 @@ pub enum InstallAsyncHandlerMsg {
     SetConfig(NixDataConfig),
     SetPkgTypes(SystemPkgs, UserPkgs),
     Process(WorkPkg),
+    ProcessBatch(Vec<WorkPkg>),
     CancelProcess,
-    SetPid(Option<u32>),
 }
 
 #[derive(Debug)]
 pub struct InstallAsyncHandlerInit {
     pub syspkgs: SystemPkgs,
     pub userpkgs: UserPkgs,
+    pub system: String,
 }
 
 impl Worker for InstallAsyncHandler {
@@ -45,6 +48,7 @@ impl Worker for InstallAsyncHandler {
     fn init(params: Self::Init, _sender: relm4::ComponentSender<Self>) -> Self {
         Self {
             process: None,
+            batch: None,
             work: None,
             config: NixDataConfig {
                 systemconfig: None,
@@ -52,9 +56,10 @@ impl Worker for InstallAsyncHandler {
                 flakearg: None,
                 generations: None
             },
-            pid: None,
+            canceller: None,
             syspkgs: params.syspkgs,
             userpkgs: params.userpkgs,
+            system: params.system,
             tracker: 0,
         }
     }
@@ -76,369 +81,215 @@ impl Worker for InstallAsyncHandler {
                 }
                 let config = self.config.clone();
                 match work.pkgtype {
-                    InstallType::User => match work.action {
-                        PkgAction::Install => {
-                            info!("Installing user package: {}", work.pkg);
-                            match self.userpkgs {
-                                UserPkgs::Env => {
-                                    self.process = Some(relm4::spawn(async move {
-                                        let mut p = tokio::process::Command::new("nix-env")
-                                            .arg("-iA")
-                                            .arg(format!("nixos.{}", work.pkg))
-                                            .kill_on_drop(true)
-                                            .stdout(Stdio::piped())
-                                            .stderr(Stdio::piped())
-                                            .spawn()
-                                            .expect("Failed to run nix-env");
-
-                                        let stderr = p.stderr.take().unwrap();
-                                        let reader = tokio::io::BufReader::new(stderr);
-
-                                        let mut lines = reader.lines();
-                                        while let Ok(Some(line)) = lines.next_line().await {
-                                            trace!("CAUGHT LINE: {}", line);
-                                        }
-
-                                        match p.wait().await {
-                                            Ok(o) => {
-                                                if o.success() {
-                                                    info!(
-                                                        "Removed user package: {} success",
-                                                        work.pkg
-                                                    );
-                                                    sender.output(PkgMsg::FinishedProcess(work));
-                                                } else {
-                                                    warn!(
-                                                        "Removed user package: {} failed",
-                                                        work.pkg
-                                                    );
-                                                    sender.output(PkgMsg::FailedProcess(work));
-                                                }
-                                            }
-                                            Err(e) => {
-                                                warn!("Error removing user package: {}", e);
-                                                sender.output(PkgMsg::FailedProcess(work));
-                                            }
-                                        }
-                                    }));
-                                }
-                                UserPkgs::Profile => {
-                                    self.process = Some(relm4::spawn(async move {
-                                        let mut p = tokio::process::Command::new("nix")
-                                            .arg("profile")
-                                            .arg("install")
-                                            .arg(format!("nixpkgs#{}", work.pkg))
-                                            .arg("--impure")
-                                            .kill_on_drop(true)
-                                            .stdout(Stdio::piped())
-                                            .stderr(Stdio::piped())
-                                            .spawn()
-                                            .expect("Failed to run nix profile");
-
-                                        let stderr = p.stderr.take().unwrap();
-                                        let reader = tokio::io::BufReader::new(stderr);
-
-                                        let mut lines = reader.lines();
-                                        while let Ok(Some(line)) = lines.next_line().await {
-                                            trace!("CAUGHT LINE: {}", line);
-                                        }
-
-                                        match p.wait().await {
-                                            Ok(o) => {
-                                                if o.success() {
-                                                    info!(
-                                                        "Removed user package: {} success",
-                                                        work.pkg
-                                                    );
-                                                    sender.output(PkgMsg::FinishedProcess(work));
-                                                } else {
-                                                    warn!(
-                                                        "Removed user package: {} failed",
-                                                        work.pkg
-                                                    );
-                                                    sender.output(PkgMsg::FailedProcess(work));
-                                                }
-                                            }
-                                            Err(e) => {
-                                                warn!("Error removing user package: {}", e);
-                                                sender.output(PkgMsg::FailedProcess(work));
-                                            }
-                                        }
-                                    }));
+                    InstallType::User => {
+                        match work.action {
+                            PkgAction::Install => info!("Installing user package: {}", work.pkg),
+                            PkgAction::Remove => info!("Removing user package: {}", work.pkg),
+                        }
+                        let installer = Installer::new(config, self.userpkgs.clone(), self.system.clone());
+                        let canceller = installer.canceller();
+                        self.canceller = Some(canceller.clone());
+                        // home-manager rebuilds the whole home environment, so treat it like a system rebuild
+                        let home = self.userpkgs == UserPkgs::HomeManager;
+                        if home {
+                            REBUILD_BROKER.send(RebuildMsg::Show);
+                        }
+                        self.process = Some(relm4::spawn(async move {
+                            if home {
+                                let diff = match installer.previewhome(&[(work.pkg.to_string(), work.action.clone())]) {
+                                    Ok(diff) => diff,
+                                    Err(e) => {
+                                        warn!("Error editing home configuration: {}", e);
+                                        REBUILD_BROKER.send(RebuildMsg::FinishError(Some(e.to_string())));
+                                        sender.output(PkgMsg::FailedProcess(work));
+                                        return;
+                                    }
+                                };
+                                if !review(diff).await {
+                                    info!("Home changes cancelled");
+                                    sender.output(PkgMsg::CancelledProcess(work));
+                                    return;
                                 }
+                                REBUILD_BROKER.send(RebuildMsg::SetCanceller(Some(canceller.clone())));
                             }
-                        }
-                        PkgAction::Remove => {
-                            info!("Removing user package: {}", work.pkg);
-                            match self.userpkgs {
-                                UserPkgs::Env => {
-                                    self.process = Some(relm4::spawn(async move {
-                                        let mut p = tokio::process::Command::new("nix-env")
-                                            .arg("-e")
-                                            .arg(&work.pname)
-                                            .kill_on_drop(true)
-                                            .stdout(Stdio::piped())
-                                            .stderr(Stdio::piped())
-                                            .spawn()
-                                            .expect("Failed to run nix-env");
-                                        let stderr = p.stderr.take().unwrap();
-                                        let reader = tokio::io::BufReader::new(stderr);
-
-                                        let mut lines = reader.lines();
-                                        while let Ok(Some(line)) = lines.next_line().await {
-                                            trace!("CAUGHT LINE: {}", line);
-                                        }
-                                        match p.wait().await {
-                                            Ok(o) => {
-                                                if o.success() {
-                                                    info!(
-                                                        "Removed user package: {} success",
-                                                        work.pkg
-                                                    );
-                                                    sender.output(PkgMsg::FinishedProcess(work));
-                                                } else {
-                                                    warn!(
-                                                        "Removed user package: {} failed",
-                                                        work.pkg
-                                                    );
-                                                    sender.output(PkgMsg::FailedProcess(work));
-                                                }
-                                            }
-                                            Err(e) => {
-                                                warn!("Error removing user package: {}", e);
-                                                sender.output(PkgMsg::FailedProcess(work));
-                                            }
-                                        }
-                                    }));
+                            let progress = Mutex::new(ProgressTracker::default());
+                            let result = installer.installuser(&work.pkg, &work.pname, &work.action, |line| {
+                                trace!("CAUGHT LINE: {}", line);
+                                if let Some((fraction, status)) = progress.lock().unwrap().update(&line) {
+                                    sender.output(PkgMsg::Progress(InstallType::User, fraction, status));
                                 }
-                                UserPkgs::Profile => {
-                                    self.process = Some(relm4::spawn(async move {
-                                        let mut p = tokio::process::Command::new("nix")
-                                            .arg("profile")
-                                            .arg("remove")
-                                            .arg(&format!(
-                                                "legacyPackages.x86_64-linux.{}",
-                                                work.pkg
-                                            ))
-                                            .kill_on_drop(true)
-                                            .stdout(Stdio::piped())
-                                            .stderr(Stdio::piped())
-                                            .spawn()
-                                            .expect("Failed to run nix profile");
-                                        let stderr = p.stderr.take().unwrap();
-                                        let reader = tokio::io::BufReader::new(stderr);
-
-                                        let mut lines = reader.lines();
-                                        while let Ok(Some(line)) = lines.next_line().await {
-                                            trace!("CAUGHT LINE: {}", line);
-                                        }
-                                        match p.wait().await {
-                                            Ok(o) => {
-                                                if o.success() {
-                                                    info!(
-                                                        "Removed user package: {} success",
-                                                        work.pkg
-                                                    );
-                                                    sender.output(PkgMsg::FinishedProcess(work));
-                                                } else {
-                                                    warn!(
-                                                        "Removed user package: {} failed",
-                                                        work.pkg
-                                                    );
-                                                    sender.output(PkgMsg::FailedProcess(work));
-                                                }
-                                            }
-
-                                            Err(e) => {
-                                                warn!("Error removing user package: {}", e);
-                                                sender.output(PkgMsg::FailedProcess(work));
-                                            }
-                                        }
-                                    }));
+                                if home {
+                                    REBUILD_BROKER.send(RebuildMsg::UpdateText(line));
                                 }
+                            })
+                            .await;
+                            if canceller.cancelled() {
+                                info!("User package: {} cancelled", work.pkg);
+                                if home {
+                                    REBUILD_BROKER.send(RebuildMsg::FinishCancelled);
+                                }
+                                sender.output(PkgMsg::CancelledProcess(work));
+                                return;
                             }
-                        }
-                    },
-                    InstallType::System => {
-                        REBUILD_BROKER.send(RebuildMsg::Show);
-                        if let Some(systemconfig) = &config.systemconfig {
-                            match work.action {
-                                PkgAction::Install => {
-                                    info!("Installing system package: {}", work.pkg);
-                                    self.process = Some(relm4::spawn(async move {
-                                        match installsys(
-                                            work.pkg.to_string(),
-                                            work.action.clone(),
-                                            config,
-                                            sender.clone(),
-                                        )
-                                        .await
-                                        {
-                                            Ok(b) => {
-                                                if b {
-                                                    REBUILD_BROKER.send(RebuildMsg::FinishSuccess);
-                                                    sender.output(PkgMsg::FinishedProcess(work));
-                                                } else {
-                                                    REBUILD_BROKER.send(RebuildMsg::FinishError(None));
-                                                    sender.output(PkgMsg::FailedProcess(work));
-                                                }
-                                            }
-                                            Err(e) => {
-                                                REBUILD_BROKER.send(RebuildMsg::FinishError(None));
-                                                sender.output(PkgMsg::FailedProcess(work));
-                                                warn!("Error installing system package: {}", e);
-                                            }
-                                        }
-                                    }));
+                            match result {
+                                Ok(true) => {
+                                    info!("User package: {} success", work.pkg);
+                                    if home {
+                                        REBUILD_BROKER.send(RebuildMsg::FinishSuccess);
+                                    }
+                                    sender.output(PkgMsg::FinishedProcess(work));
+                                }
+                                Ok(false) => {
+                                    warn!("User package: {} failed", work.pkg);
+                                    if home {
+                                        REBUILD_BROKER.send(RebuildMsg::FinishError(None));
+                                    }
+                                    sender.output(PkgMsg::FailedProcess(work));
                                 }
-                                PkgAction::Remove => {
-                                    info!("Removing system package: {}", work.pkg);
-                                    self.process = Some(relm4::spawn(async move {
-                                        match installsys(
-                                            work.pkg.to_string(),
-                                            work.action.clone(),
-                                            config,
-                                            sender.clone(),
-                                        )
-                                        .await
-                                        {
-                                            Ok(b) => {
-                                                if b {
-                                                    REBUILD_BROKER.send(RebuildMsg::FinishSuccess);
-                                                    sender.output(PkgMsg::FinishedProcess(work));
-                                                } else {
-                                                    REBUILD_BROKER.send(RebuildMsg::FinishError(None));
-                                                    sender.output(PkgMsg::FailedProcess(work));
-                                                }
-                                            }
-                                            Err(e) => {
-                                                REBUILD_BROKER.send(RebuildMsg::FinishError(None));
-                                                sender.output(PkgMsg::FailedProcess(work));
-                                                warn!("Error removing system package: {}", e);
-                                            }
-                                        }
-                                    }));
+                                Err(e) => {
+                                    warn!("Error processing user package: {}", e);
+                                    if home {
+                                        REBUILD_BROKER.send(RebuildMsg::FinishError(None));
+                                    }
+                                    sender.output(PkgMsg::FailedProcess(work));
                                 }
                             }
+                        }));
+                    }
+                    InstallType::System => {
+                        sender.input(InstallAsyncHandlerMsg::ProcessBatch(vec![work]));
+                    }
+                }
+            }
+            InstallAsyncHandlerMsg::ProcessBatch(work) => {
+                let config = self.config.clone();
+                if config.systemconfig.is_some() {
+                    REBUILD_BROKER.send(RebuildMsg::Show);
+                    let userpkgs = self.userpkgs.clone();
+                    let system = self.system.clone();
+                    let installer = Installer::new(config.clone(), userpkgs.clone(), system.clone());
+                    let canceller = installer.canceller();
+                    self.canceller = Some(canceller.clone());
+                    for w in &work {
+                        match w.action {
+                            PkgAction::Install => info!("Installing system package: {}", w.pkg),
+                            PkgAction::Remove => info!("Removing system package: {}", w.pkg),
                         }
                     }
+                    self.batch = Some(relm4::spawn(async move {
+                        let edits = work
+                            .iter()
+                            .map(|w| (w.pkg.to_string(), w.action.clone()))
+                            .collect::<Vec<_>>();
+                        let diff = match installer.previewsys(&edits) {
+                            Ok(diff) => diff,
+                            Err(e) => {
+                                warn!("Error editing system configuration: {}", e);
+                                REBUILD_BROKER.send(RebuildMsg::FinishError(Some(e.to_string())));
+                                sender.output(PkgMsg::FailedBatch(work));
+                                return;
+                            }
+                        };
+                        let test = |mode| {
+                            // Test builds get their own canceller, so they can't cancel the switch
+                            let installer = Installer::new(config.clone(), userpkgs.clone(), system.clone());
+                            let canceller = installer.canceller();
+                            REBUILD_BROKER.send(RebuildMsg::SetCanceller(Some(canceller.clone())));
+                            let edits = edits.clone();
+                            async move {
+                                let result = match installer
+                                    .testsys(&edits, mode, |line| {
+                                        trace!("CAUGHT LINE: {}", line);
+                                        REBUILD_BROKER.send(RebuildMsg::UpdateText(line));
+                                    })
+                                    .await
+                                {
+                                    Ok(result) => result,
+                                    Err(e) => {
+                                        warn!("Error test building system packages: {}", e);
+                                        BuildResult::Failed(None)
+                                    }
+                                };
+                                (!canceller.cancelled()).then_some(result)
+                            }
+                        };
+                        if !reviewbuild(diff, test).await {
+                            info!("System changes cancelled");
+                            sender.output(PkgMsg::CancelledBatch(work));
+                            return;
+                        }
+                        REBUILD_BROKER.send(RebuildMsg::SetCanceller(Some(canceller.clone())));
+                        let progress = Mutex::new(ProgressTracker::default());
+                        let result = installer
+                            .installsysbatch(&edits, |line| {
+                                trace!("CAUGHT LINE: {}", line);
+                                if let Some((fraction, status)) = progress.lock().unwrap().update(&line) {
+                                    sender.output(PkgMsg::Progress(InstallType::System, fraction, status));
+                                }
+                                REBUILD_BROKER.send(RebuildMsg::UpdateText(line));
+                            })
+                            .await;
+                        if canceller.cancelled() {
+                            info!("System changes cancelled during rebuild");
+                            REBUILD_BROKER.send(RebuildMsg::FinishCancelled);
+                            sender.output(PkgMsg::CancelledBatch(work));
+                            return;
+                        }
+                        match result {
+                            Ok(true) => {
+                                REBUILD_BROKER.send(RebuildMsg::FinishSuccess);
+                                sender.output(PkgMsg::FinishedBatch(work));
+                            }
+                            Ok(false) => {
+                                REBUILD_BROKER.send(RebuildMsg::FinishError(None));
+                                sender.output(PkgMsg::FailedBatch(work));
+                            }
+                            Err(e) => {
+                                REBUILD_BROKER.send(RebuildMsg::FinishError(None));
+                                sender.output(PkgMsg::FailedBatch(work));
+                                warn!("Error processing system packages: {}", e);
+                            }
+                        }
+                    }));
+                } else {
+                    warn!("No system configuration file set");
+                    REBUILD_BROKER.send(RebuildMsg::FinishError(Some(gettext(
+                        "No system configuration file is set. Choose one in the preferences.",
+                    ))));
+                    sender.output(PkgMsg::FailedBatch(work));
                 }
             }
             InstallAsyncHandlerMsg::CancelProcess => {
                 info!("CANCELING PROCESS");
-                if let Some(p) = &mut self.process {
-                    p.abort()
+                // The running process reports back once it has stopped and restored its changes
+                if let Some(canceller) = self.canceller.clone() {
+                    relm4::spawn(async move {
+                        if let Err(e) = canceller.cancel().await {
+                            warn!("Failed to cancel process: {}", e);
+                        }
+                    });
                 }
-                self.process = None;
-                self.pid = None;
-                sender.output(PkgMsg::CancelFinished);
             }
-            InstallAsyncHandlerMsg::SetPid(p) => self.pid = p,
         }
     }
 }
 
-async fn installsys(
-    pkg: String,
-    action: PkgAction,
-    config: NixDataConfig,
-    _sender: ComponentSender<InstallAsyncHandler>,
-) -> Result<bool> {
-    let systemconfig = config.systemconfig.unwrap_or_default();
-    let flakeargs = if let Some(flake) = config.flake {
-        if let Some(flakearg) = config.flakearg {
-            Some(format!("{}#{}", flake, flakearg))
-        } else {
-            Some(flake)
-        }
-    } else {
-        None
-    };
-    let mut p = pkg;
-    let f = fs::read_to_string(&systemconfig)?;
-    if let Ok(s) = nix_editor::read::getwithvalue(&f, "environment.systemPackages") {
-        if !s.contains(&"pkgs".to_string()) {
-            p = format!("pkgs.{}", p);
-        }
-    } else {
-        p = format!("pkgs.{}", p);
-    }
-
-    let out = match action {
-        PkgAction::Install => {
-            match nix_editor::write::addtoarr(&f, "environment.systemPackages", vec![p]) {
-                Ok(x) => x,
-                Err(_) => {
-                    return Err(anyhow!("Failed to write configuration.nix"));
-                }
-            }
-        }
-        PkgAction::Remove => {
-            match nix_editor::write::rmarr(&f, "environment.systemPackages", vec![p]) {
-                Ok(x) => x,
-                Err(_) => {
-                    return Err(anyhow!("Failed to write configuration.nix"));
-                }
-            }
-        }
-    };
-
-    let exe = match std::env::current_exe() {
-        Ok(mut e) => {
-            e.pop(); // root/bin
-            e.pop(); // root/
-            e.push("libexec"); // root/libexec
-            e.push("nsc-helper");
-            let x = e.to_string_lossy().to_string();
-            info!("nsc-helper path: {}", x);
-            if Path::new(&x).is_file() {
-                x
-            } else {
-                String::from("nsc-helper")
-            }
-        }
-        Err(_) => String::from("nsc-helper"),
-    };
+// Turns nix output into package page progress, only reporting changes
+#[derive(Default)]
+struct ProgressTracker {
+    progress: Progress,
+    last: (Option<f64>, Option<String>),
+}
 
-    let rebuildargs = if let Some(x) = flakeargs {
-        let mut v = vec![String::from("--flake")];
-        for arg in x.split(' ') {
-            if !arg.is_empty() {
-                v.push(String::from(arg));
-            }
+impl ProgressTracker {
+    fn update(&mut self, line: &str) -> Option<(Option<f64>, Option<String>)> {
+        self.progress.parse(line);
+        let current = (self.progress.fraction(), self.progress.status());
+        if current != self.last {
+            self.last = current.clone();
+            Some(current)
+        } else {
+            None
         }
-        v
-    } else {
-        vec![]
-    };
-
-    let mut cmd = tokio::process::Command::new("pkexec")
-        .arg(&exe)
-        .arg("config")
-        .arg("--generations")
-        .arg(config.generations.unwrap_or(0).to_string())
-        .arg("--output")
-        .arg(&systemconfig)
-        .arg("--")
-        .arg("switch")
-        .arg("--impure")
-        .args(&rebuildargs)
-        .stderr(Stdio::piped())
-        .stdin(Stdio::piped())
-        .spawn()?;
-
-    cmd.stdin.take().unwrap().write_all(out.as_bytes()).await?;
-    let stderr = cmd.stderr.take().unwrap();
-    let reader = tokio::io::BufReader::new(stderr);
-
-    let mut lines = reader.lines();
-    while let Ok(Some(line)) = lines.next_line().await {
-        trace!("CAUGHT LINE: {}", line);
-        REBUILD_BROKER.send(RebuildMsg::UpdateText(line));
-    }
-    if cmd.wait().await?.success() {
-        Ok(true)
-    } else {
-        Ok(false)
     }
 }
@@ -1,15 +1,19 @@
 pub mod about;
+pub mod appstreamfactory;
 pub mod categories;
 pub mod categorypage;
 pub mod categorytile;
+pub mod history;
 pub mod installedpage;
 pub mod installworker;
 pub mod pkgpage;
 pub mod pkgtile;
 pub mod preferencespage;
 pub mod rebuild;
+pub mod releasefactory;
 pub mod screenshotfactory;
 pub mod searchpage;
+pub mod transaction;
 pub mod unavailabledialog;
 pub mod updatepage;
 pub mod updateworker;
@@ -8,7 +8,7 @@ use relm4::actions::RelmActionGroup;
 use relm4::gtk::pango;
 use relm4::{factory::FactoryVecDeque, *};
 use sha256::digest;
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::convert::identity;
 use std::io::Cursor;
 use std::process::Command;
@@ -22,15 +22,20 @@ use std::{
     time::Duration,
 };
 use log::*;
+use gettextrs::{gettext, ngettext};
 
-use crate::parse::packages::PkgMaintainer;
+use nsc_core::packages::PkgMaintainer;
+use crate::i18n;
 use crate::parse::util;
 use crate::ui::installworker::InstallAsyncHandlerMsg;
 
 use super::installworker::InstallAsyncHandler;
 use super::installworker::InstallAsyncHandlerInit;
+use super::transaction::{TransactionModel, TransactionMsg};
 use super::window::SystemPkgs;
 use super::window::UserPkgs;
+use super::window::{systempkgslabel, userpkgslabel};
+use super::releasefactory::ReleaseItem;
 use super::{screenshotfactory::ScreenshotItem, window::AppMsg};
 
 #[tracker::track]
@@ -48,22 +53,34 @@ pub struct PkgModel {
     homepage: Option<String>,
     licenses: Vec<License>,
     platforms: Vec<String>,
+    unsupported: bool,
+    source: Option<String>,
     maintainers: Vec<PkgMaintainer>,
     launchable: Option<Launch>,
+    developer: Option<String>,
+    agerating: Option<u32>,
 
     syspkgtype: SystemPkgs,
     userpkgtype: UserPkgs,
+    system: String,
 
     #[tracker::no_eq]
     screenshots: FactoryVecDeque<ScreenshotItem>,
     #[tracker::no_eq]
+    releases: FactoryVecDeque<ReleaseItem>,
+    #[tracker::no_eq]
     installworker: WorkerController<InstallAsyncHandler>,
     carpage: CarouselPage,
     installtype: InstallType,
     installeduserpkgs: HashSet<String>,
     installedsystempkgs: HashSet<String>,
 
-    workqueue: HashSet<WorkPkg>,
+    workqueue: VecDeque<WorkPkg>,
+    #[tracker::no_eq]
+    transaction: Controller<TransactionModel>,
+    transactionpkgs: Vec<WorkPkg>,
+    applying: bool,
+    progress: Option<(InstallType, Option<f64>, Option<String>)>,
     visible: bool,
     online: bool,
 }
@@ -83,12 +100,7 @@ pub enum NotifyPage {
     Installed,
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Clone)]
-pub enum PkgAction {
-    Install,
-    Remove
-}
-
+pub use nsc_core::PkgAction;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Launch {
@@ -104,11 +116,7 @@ pub enum CarouselPage {
     Single,
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Clone)]
-pub enum InstallType {
-    User,
-    System,
-}
+pub use nsc_core::InstallType;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct License {
@@ -118,6 +126,13 @@ pub struct License {
     pub url: Option<String>,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct Release {
+    pub version: String,
+    pub timestamp: Option<i64>,
+    pub description: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct PkgInitModel {
     pub name: String,
@@ -133,8 +148,15 @@ pub struct PkgInitModel {
     pub homepage: Option<String>,
     pub licenses: Vec<License>,
     pub platforms: Vec<String>,
+    pub unsupported: bool,
+    pub source: Option<String>,
     pub maintainers: Vec<PkgMaintainer>,
     pub launchable: Option<String>,
+    pub developer: Option<String>,
+    pub agerating: Option<u32>,
+    pub releases: Vec<Release>,
+    // Only set when the icon still has to be downloaded
+    pub remoteicon: Option<String>,
 }
 
 #[derive(Debug)]
@@ -144,6 +166,7 @@ pub enum PkgMsg {
     Open(Box<PkgInitModel>),
     LoadScreenshot(String, usize, String),
     SetError(String, usize),
+    LoadIcon(String, String),
     SetCarouselPage(CarouselPage),
     OpenHomepage,
     Close,
@@ -152,27 +175,38 @@ pub enum PkgMsg {
     InstallSystem,
     RemoveSystem,
     Cancel,
-    CancelFinished,
     FinishedProcess(WorkPkg),
     FailedProcess(WorkPkg),
+    CancelledProcess(WorkPkg),
     Launch,
     NixRun,
     NixShell,
     SetInstallType(InstallType),
     AddToQueue(WorkPkg),
-    UpdateOnline(bool)
+    UpdateOnline(bool),
+    ShowTransaction,
+    SetTransaction(Vec<WorkPkg>),
+    TransactionDropped(WorkPkg),
+    ApplyTransaction(Vec<WorkPkg>),
+    FinishedBatch(Vec<WorkPkg>),
+    FailedBatch(Vec<WorkPkg>),
+    CancelledBatch(Vec<WorkPkg>),
+    Progress(InstallType, Option<f64>, Option<String>),
 }
 
 #[derive(Debug)]
 pub enum PkgAsyncMsg {
     LoadScreenshot(String, usize, String),
     SetError(String, usize),
+    LoadIcon(String, String),
 }
 
 #[derive(Debug)]
 pub struct PkgPageInit {
+    pub window: gtk::Window,
     pub syspkgs: SystemPkgs,
     pub userpkgs: UserPkgs,
+    pub system: String,
     pub config: NixDataConfig,
     pub online: bool
 }
@@ -210,25 +244,16 @@ impl Component for PkgModel {
                     set_visible: model.syspkgtype != SystemPkgs::None,
 
                     #[watch]
-                    set_label: match model.userpkgtype {
-                        UserPkgs::Env => {
-                            match model.installtype {
-                                InstallType::User => "User (nix-env)",
-                                InstallType::System => "System (configuration.nix)",
-                            }
-                        }
-                        UserPkgs::Profile => {
-                            match model.installtype {
-                                InstallType::User => "User (nix profile)",
-                                InstallType::System => "System (configuration.nix)",
-                            }
-                        }
+                    set_label: &match model.installtype {
+                        InstallType::User => userpkgslabel(&model.userpkgtype),
+                        InstallType::System => systempkgslabel(),
                     },
                         
                     #[wrap(Some)]
                     set_popover = &gtk::PopoverMenu::from_model(Some(&match model.userpkgtype {
                         UserPkgs::Env => installtype,
                         UserPkgs::Profile => installprofiletype,
+                        UserPkgs::HomeManager => installhometype,
                     })) {}
                 }
             },
@@ -250,21 +275,12 @@ impl Component for PkgModel {
                             set_orientation: gtk::Orientation::Horizontal,
                             set_spacing: 10,
                             set_margin_all: 15,
-                            append = if model.icon.is_some() {
-                                gtk::Image {
-                                    add_css_class: "icon-dropshadow",
-                                    set_halign: gtk::Align::Start,
-                                    #[watch]
-                                    set_from_file: model.icon.clone(),
-                                    set_pixel_size: 128,
-                                }
-                            } else {
-                                gtk::Image {
-                                    add_css_class: "icon-dropshadow",
-                                    set_halign: gtk::Align::Start,
-                                    set_icon_name: Some("package-x-generic"),
-                                    set_pixel_size: 128,
-                                }
+                            gtk::Image {
+                                add_css_class: "icon-dropshadow",
+                                set_halign: gtk::Align::Start,
+                                #[watch]
+                                set_from_gicon: &util::appgicon(model.icon.as_deref()),
+                                set_pixel_size: 128,
                             },
                             gtk::FlowBox {
                                 set_halign: gtk::Align::Fill,
@@ -299,6 +315,26 @@ impl Component for PkgModel {
                                             #[watch]
                                             set_label: &model.pkg,
                                         },
+                                        gtk::Label {
+                                            add_css_class: "heading",
+                                            set_halign: gtk::Align::Start,
+                                            set_wrap: true,
+                                            set_wrap_mode: pango::WrapMode::WordChar,
+                                            set_natural_wrap_mode: gtk::NaturalWrapMode::Word,
+                                            #[watch]
+                                            set_label: model.developer.as_deref().unwrap_or_default(),
+                                            #[watch]
+                                            set_visible: model.developer.is_some(),
+                                        },
+                                        gtk::Label {
+                                            add_css_class: "accent",
+                                            add_css_class: "heading",
+                                            set_halign: gtk::Align::Start,
+                                            #[watch]
+                                            set_label: &i18n::fill(&gettext("From {}"), &[model.source.as_deref().unwrap_or_default()]),
+                                            #[watch]
+                                            set_visible: model.source.is_some(),
+                                        },
                                         gtk::Label {
                                             add_css_class: "dim-label",
                                             set_halign: gtk::Align::Start,
@@ -306,7 +342,40 @@ impl Component for PkgModel {
                                             set_wrap_mode: pango::WrapMode::WordChar,
                                             set_natural_wrap_mode: gtk::NaturalWrapMode::Word,
                                             #[watch]
-                                            set_label: &model.version.clone().unwrap_or_else(|| "Unknown".to_string()),
+                                            set_label: &model.version.clone().unwrap_or_else(|| gettext("Unknown")),
+                                        },
+                                        gtk::Box {
+                                            set_halign: gtk::Align::Start,
+                                            set_spacing: 5,
+                                            add_css_class: "dim-label",
+                                            #[watch]
+                                            set_visible: model.agerating.is_some(),
+                                            gtk::Image {
+                                                set_icon_name: Some("system-users-symbolic"),
+                                            },
+                                            gtk::Label {
+                                                add_css_class: "caption-heading",
+                                                #[watch]
+                                                set_label: &match model.agerating {
+                                                    Some(0) | None => gettext("All ages"),
+                                                    Some(age) => i18n::fill(&gettext("Ages {}+"), &[&age.to_string()]),
+                                                },
+                                            },
+                                        },
+                                        gtk::Box {
+                                            set_halign: gtk::Align::Start,
+                                            set_spacing: 5,
+                                            add_css_class: "warning",
+                                            #[watch]
+                                            set_visible: model.unsupported,
+                                            gtk::Image {
+                                                set_icon_name: Some("dialog-warning-symbolic"),
+                                            },
+                                            gtk::Label {
+                                                set_wrap: true,
+                                                #[watch]
+                                                set_label: &i18n::fill(&gettext("Not available for {}"), &[&model.system]),
+                                            },
                                         },
                                     },
                                 },
@@ -323,25 +392,42 @@ impl Component for PkgModel {
                                                     #[name(userinstallstack)]
                                                     if model.workqueue.iter().any(|x| x.pkg == model.pkg && x.pkgtype == InstallType::User) /*model.installinguserpkgs.contains(&model.pkg)*/ {
                                                         gtk::Box {
+                                                            set_spacing: 10,
                                                             gtk::Spinner {
                                                                 set_halign: gtk::Align::End,
                                                                 #[watch]
+                                                                set_visible: model.fraction(InstallType::User).is_none(),
+                                                                #[watch]
                                                                 set_spinning: true, //model.installinguserpkgs.contains(&model.pkg),
                                                                 set_size_request: (32, 32),
                                                                 set_can_focus: false,
                                                             },
+                                                            gtk::ProgressBar {
+                                                                set_valign: gtk::Align::Center,
+                                                                set_width_request: 150,
+                                                                set_show_text: true,
+                                                                set_ellipsize: pango::EllipsizeMode::End,
+                                                                #[watch]
+                                                                set_visible: model.fraction(InstallType::User).is_some(),
+                                                                #[watch]
+                                                                set_fraction: model.fraction(InstallType::User).unwrap_or_default(),
+                                                                #[watch]
+                                                                set_text: model.progresstext(InstallType::User).as_deref(),
+                                                                #[watch]
+                                                                set_tooltip_text: model.progresstext(InstallType::User).as_deref(),
+                                                            },
                                                             gtk::Button {
                                                                 set_halign: gtk::Align::End,
                                                                 set_valign: gtk::Align::Center,
                                                                 set_can_focus: false,
                                                                 set_width_request: 105,
-                                                                set_label: "Cancel",
+                                                                set_label: &gettext("Cancel"),
                                                                 connect_clicked[sender] => move |_| {
                                                                     sender.input(PkgMsg::Cancel)
                                                                 },
                                                             }
                                                         }                                                   
-                                                    } else if model.installeduserpkgs.contains(match model.userpkgtype { UserPkgs::Env => &model.pname, UserPkgs::Profile => &model.pkg }) {
+                                                    } else if model.installeduserpkgs.contains(match model.userpkgtype { UserPkgs::Env => &model.pname, UserPkgs::Profile | UserPkgs::HomeManager => &model.pkg }) {
                                                         gtk::Box {
                                                             set_halign: gtk::Align::End,
                                                             set_valign: gtk::Align::Center,
@@ -354,7 +440,7 @@ impl Component for PkgModel {
                                                                 set_can_focus: false,
                                                                 set_width_request: 105,
                                                                 #[watch]
-                                                                set_label: if model.launchable.is_some() { "Open" } else { "Installed" },
+                                                                set_label: &if model.launchable.is_some() { gettext("Open") } else { gettext("Installed") },
                                                                 #[watch]
                                                                 set_sensitive: model.launchable.is_some(),
                                                                 connect_clicked[sender] => move |_| {
@@ -391,7 +477,7 @@ impl Component for PkgModel {
                                                                 set_halign: gtk::Align::End,
                                                                 set_valign: gtk::Align::Center,
                                                                 add_css_class: "error",
-                                                                set_label: "Offline",
+                                                                set_label: &gettext("Offline"),
                                                                 set_can_target: false,
                                                             },
                                                             gtk::Button {
@@ -409,7 +495,7 @@ impl Component for PkgModel {
                                                             set_halign: gtk::Align::End,
                                                             set_valign: gtk::Align::Center,
                                                             set_can_focus: false,
-                                                            set_label: "Install",
+                                                            set_label: &gettext("Install"),
                                                             set_width_request: 105,
                                                             connect_clicked[sender] => move |_| {
                                                                 sender.input(PkgMsg::InstallUser);
@@ -425,25 +511,52 @@ impl Component for PkgModel {
                                             InstallType::System => {
                                                 gtk::Box {
                                                     #[name(systeminstallstack)]
-                                                    if model.workqueue.iter().any(|x| x.pkg == model.pkg && x.pkgtype == InstallType::System) {
+                                                    if model.transactionpkgs.iter().any(|x| x.pkg == model.pkg) {
                                                         gtk::Box {
+                                                            set_spacing: 10,
                                                             gtk::Spinner {
                                                                 set_halign: gtk::Align::End,
                                                                 #[watch]
-                                                                set_spinning: true, //model.installingsystempkgs.contains(&model.pkg),
+                                                                set_visible: model.applying && model.fraction(InstallType::System).is_none(),
+                                                                #[watch]
+                                                                set_spinning: model.applying,
                                                                 set_size_request: (32, 32),
                                                                 set_can_focus: false,
                                                             },
+                                                            gtk::ProgressBar {
+                                                                set_valign: gtk::Align::Center,
+                                                                set_width_request: 150,
+                                                                set_show_text: true,
+                                                                set_ellipsize: pango::EllipsizeMode::End,
+                                                                #[watch]
+                                                                set_visible: model.applying && model.fraction(InstallType::System).is_some(),
+                                                                #[watch]
+                                                                set_fraction: model.fraction(InstallType::System).unwrap_or_default(),
+                                                                #[watch]
+                                                                set_text: model.progresstext(InstallType::System).as_deref(),
+                                                                #[watch]
+                                                                set_tooltip_text: model.progresstext(InstallType::System).as_deref(),
+                                                            },
                                                             gtk::Button {
                                                                 set_halign: gtk::Align::End,
                                                                 set_valign: gtk::Align::Center,
                                                                 set_can_focus: false,
                                                                 set_width_request: 105,
-                                                                set_label: "Cancel",
+                                                                set_label: &gettext("Queued"),
                                                                 #[watch]
-                                                                set_sensitive: if let Some(w) = model.workqueue.iter().next() { w.pkg != model.pkg } else {
-                                                                    false
+                                                                set_visible: !model.applying,
+                                                                connect_clicked[sender] => move |_| {
+                                                                    sender.input(PkgMsg::ShowTransaction)
                                                                 },
+                                                            },
+                                                            gtk::Button {
+                                                                set_halign: gtk::Align::End,
+                                                                set_valign: gtk::Align::Center,
+                                                                set_can_focus: false,
+                                                                set_width_request: 105,
+                                                                set_label: &gettext("Cancel"),
+                                                                #[watch]
+                                                                set_sensitive: !model.applying,
                                                                 connect_clicked[sender] => move |_| {
                                                                     sender.input(PkgMsg::Cancel)
                                                                 },
@@ -462,7 +575,7 @@ impl Component for PkgModel {
                                                                 set_can_focus: false,
                                                                 set_width_request: 105,
                                                                 #[watch]
-                                                                set_label: if model.launchable.is_some() { "Open" } else { "Installed" },
+                                                                set_label: &if model.launchable.is_some() { gettext("Open") } else { gettext("Installed") },
                                                                 #[watch]
                                                                 set_sensitive: model.launchable.is_some(),
                                                                 connect_clicked[sender] => move |_| {
@@ -499,7 +612,7 @@ impl Component for PkgModel {
                                                                 set_halign: gtk::Align::End,
                                                                 set_valign: gtk::Align::Center,
                                                                 add_css_class: "error",
-                                                                set_label: "Offline",
+                                                                set_label: &gettext("Offline"),
                                                                 set_can_target: false,
                                                             },
                                                             gtk::Button {
@@ -517,7 +630,7 @@ impl Component for PkgModel {
                                                             set_halign: gtk::Align::End,
                                                             set_valign: gtk::Align::Center,
                                                             set_can_focus: false,
-                                                            set_label: "Install",
+                                                            set_label: &gettext("Install"),
                                                             set_width_request: 105,
                                                             connect_clicked[sender] => move |_| {
                                                                 sender.input(PkgMsg::InstallSystem);
@@ -677,6 +790,28 @@ impl Component for PkgModel {
                             },
                         },
                     },
+                    adw::Clamp {
+                        set_halign: gtk::Align::Fill,
+                        set_valign: gtk::Align::Start,
+                        set_maximum_size: 1000,
+                        #[watch]
+                        set_visible: !model.releases.is_empty(),
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_margin_all: 15,
+                            set_spacing: 10,
+                            gtk::Label {
+                                add_css_class: "title-4",
+                                set_halign: gtk::Align::Start,
+                                set_label: &gettext("Release Notes"),
+                            },
+                            #[local_ref]
+                            releasebox -> gtk::ListBox {
+                                add_css_class: "boxed-list",
+                                set_selection_mode: gtk::SelectionMode::None,
+                            },
+                        },
+                    },
                     adw::Clamp {
                         set_vexpand: true,
                         set_halign: gtk::Align::Fill,
@@ -729,7 +864,7 @@ impl Component for PkgModel {
                                                     set_halign: gtk::Align::Center,
                                                     set_valign: gtk::Align::Center,
                                                     add_css_class: "heading",
-                                                    set_label: "Homepage"
+                                                    set_label: &gettext("Homepage")
                                                 },
                                                 gtk::Label {
                                                     set_halign: gtk::Align::Fill,
@@ -782,7 +917,7 @@ impl Component for PkgModel {
                                                     set_halign: gtk::Align::Center,
                                                     add_css_class: "heading",
                                                     #[watch]
-                                                    set_label: if model.licenses.len() > 1 { "Licenses" } else { "License" }
+                                                    set_label: &ngettext("License", "Licenses", model.licenses.len() as u32)
                                                 },
                                                 gtk::Label {
                                                     set_halign: gtk::Align::Fill,
@@ -812,9 +947,9 @@ impl Component for PkgModel {
                                                                 }
                                                             } else if Some(license) == model.licenses.iter().last() {
                                                                 if let Some(id) = &license.spdxid {
-                                                                    let _ = write!(s, "and {}", id);
+                                                                    s.push_str(&i18n::fill(&gettext("and {}"), &[id]));
                                                                 } else {
-                                                                    let _ = write!(s, "and {}", license.fullname);
+                                                                    s.push_str(&i18n::fill(&gettext("and {}"), &[&license.fullname]));
                                                                 }
                                                             } else if let Some(id) = &license.spdxid {
                                                                 let _ = write!(s, "{}, ", id);
@@ -823,7 +958,7 @@ impl Component for PkgModel {
                                                             }
                                                         }
                                                         if model.licenses.is_empty() {
-                                                            s.push_str("Unknown");
+                                                            s.push_str(&gettext("Unknown"));
                                                         }
                                                         &s.to_string()
                                                     },
@@ -863,7 +998,7 @@ impl Component for PkgModel {
                                                 gtk::Label {
                                                     set_halign: gtk::Align::Center,
                                                     add_css_class: "heading",
-                                                    set_label: "Platforms"
+                                                    set_label: &gettext("Platforms")
                                                 },
                                                 gtk::Label {
                                                     set_halign: gtk::Align::Fill,
@@ -884,13 +1019,13 @@ impl Component for PkgModel {
                                                             } else if model.platforms.iter().len() == 2 && model.platforms.get(0) == Some(p) {
                                                                 let _ = write!(s, "{} ", p);
                                                             } else if Some(p) == model.platforms.iter().last() {
-                                                                let _ = write!(s, "and {}", p);
+                                                                s.push_str(&i18n::fill(&gettext("and {}"), &[p]));
                                                             } else {
                                                                 let _ = write!(s, "{}, ", p);
                                                             }
                                                         }
                                                         if model.platforms.is_empty() {
-                                                            s.push_str("Unknown");
+                                                            s.push_str(&gettext("Unknown"));
                                                         }
                                                         &s.to_string()
                                                     },
@@ -927,7 +1062,7 @@ impl Component for PkgModel {
                                                     set_halign: gtk::Align::Center,
                                                     add_css_class: "heading",
                                                     #[watch]
-                                                    set_label: if model.maintainers.len() > 1 { "Maintainers" } else { "Maintainer" }
+                                                    set_label: &ngettext("Maintainer", "Maintainers", model.maintainers.len() as u32)
                                                 },
                                                 gtk::Label {
                                                     set_halign: gtk::Align::Fill,
@@ -958,9 +1093,9 @@ impl Component for PkgModel {
                                                                 }
                                                             } else if Some(p) == maintainerlist.last() {
                                                                 if let Some(n) = &p.name {
-                                                                    let _ = write!(s, "and {}", n.as_str());
+                                                                    s.push_str(&i18n::fill(&gettext("and {}"), &[n]));
                                                                 } else if let Some(g) = &p.github {
-                                                                    let _ = write!(s, "and {}", g.as_str());
+                                                                    s.push_str(&i18n::fill(&gettext("and {}"), &[g]));
                                                                 }
                                                             } else if let Some(n) = &p.name {
                                                                 let _ = write!(s, "{}, ", n.as_str());
@@ -969,7 +1104,7 @@ impl Component for PkgModel {
                                                             }
                                                         }
                                                         if model.maintainers.is_empty() {
-                                                            s.push_str("Unknown");
+                                                            s.push_str(&gettext("Unknown"));
                                                         }
                                                         &s.to_string()
                                                     }
@@ -990,31 +1125,19 @@ impl Component for PkgModel {
         }
     }
 
-    menu! {
-        installtype: {
-            "User (nix-env)" => NixEnvAction,
-            "System (configuration.nix)" => NixSystemAction,
-        },
-        installprofiletype: {
-            "User (nix-profile)" => NixProfileAction,
-            "System (configuration.nix)" => NixSystemAction,
-        },
-        runaction: {
-            "Run without installing" => LaunchAction,
-            "Open interactive shell" => TermShellAction,
-        }
-    }
-
     fn init(
         initparams: Self::Init,
         root: &Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
         let installworker = InstallAsyncHandler::builder()
-            .detach_worker(InstallAsyncHandlerInit { syspkgs: initparams.syspkgs.clone(), userpkgs: initparams.userpkgs.clone() })
+            .detach_worker(InstallAsyncHandlerInit { syspkgs: initparams.syspkgs.clone(), userpkgs: initparams.userpkgs.clone(), system: initparams.system.clone() })
             .forward(sender.input_sender(), identity);
         let config = initparams.config;
         installworker.emit(InstallAsyncHandlerMsg::SetConfig(config.clone()));
+        let transaction = TransactionModel::builder()
+            .launch(initparams.window)
+            .forward(sender.input_sender(), identity);
         let model = PkgModel {
             config,
             name: String::default(),
@@ -1027,8 +1150,11 @@ impl Component for PkgModel {
             homepage: None,
             licenses: vec![],
             screenshots: FactoryVecDeque::new(adw::Carousel::new(), sender.input_sender()),
+            releases: FactoryVecDeque::new(gtk::ListBox::new(), sender.input_sender()),
             installworker,
             platforms: vec![],
+            unsupported: false,
+            source: None,
             carpage: CarouselPage::Single,
             installtype: InstallType::User,
             maintainers: vec![],
@@ -1036,14 +1162,22 @@ impl Component for PkgModel {
             installedsystempkgs: HashSet::new(),
             syspkgtype: initparams.syspkgs,
             userpkgtype: initparams.userpkgs,
-            workqueue: HashSet::new(),
+            system: initparams.system,
+            workqueue: VecDeque::new(),
+            transaction,
+            transactionpkgs: vec![],
+            applying: false,
+            progress: None,
             launchable: None,
+            developer: None,
+            agerating: None,
             visible: false,
             online: initparams.online,
             tracker: 0,
         };
 
         let scrnfactory = model.screenshots.widget();
+        let releasebox = model.releases.widget();
         relm4::set_global_css(
             ".scrnbox {
             border-left-width: 0;
@@ -1052,6 +1186,21 @@ impl Component for PkgModel {
             border-bottom-width: 1px;
         }",
         );
+
+        // Built by hand since menu! only takes literal labels
+        let installtype = gio::Menu::new();
+        installtype.append_item(&RelmAction::<NixEnvAction>::to_menu_item(&userpkgslabel(&UserPkgs::Env)));
+        installtype.append_item(&RelmAction::<NixSystemAction>::to_menu_item(&systempkgslabel()));
+        let installprofiletype = gio::Menu::new();
+        installprofiletype.append_item(&RelmAction::<NixProfileAction>::to_menu_item(&userpkgslabel(&UserPkgs::Profile)));
+        installprofiletype.append_item(&RelmAction::<NixSystemAction>::to_menu_item(&systempkgslabel()));
+        let installhometype = gio::Menu::new();
+        installhometype.append_item(&RelmAction::<HomeManagerAction>::to_menu_item(&userpkgslabel(&UserPkgs::HomeManager)));
+        installhometype.append_item(&RelmAction::<NixSystemAction>::to_menu_item(&systempkgslabel()));
+        let runaction = gio::Menu::new();
+        runaction.append_item(&RelmAction::<LaunchAction>::to_menu_item(&gettext("Run without installing")));
+        runaction.append_item(&RelmAction::<TermShellAction>::to_menu_item(&gettext("Open interactive shell")));
+
         let widgets = view_output!();
         widgets.userinstallstack.set_hhomogeneous(false);
         widgets.systeminstallstack.set_hhomogeneous(false);
@@ -1071,6 +1220,13 @@ impl Component for PkgModel {
             })
         };
 
+        let homemanager: RelmAction<HomeManagerAction> = {
+            let sender = sender.clone();
+            RelmAction::new_stateless(move |_| {
+                sender.input(PkgMsg::SetInstallType(InstallType::User));
+            })
+        };
+
         let nixsystem: RelmAction<NixSystemAction> = {
             let sender = sender.clone();
             RelmAction::new_stateless(move |_| {
@@ -1080,6 +1236,7 @@ impl Component for PkgModel {
 
         group.add_action(nixenv);
         group.add_action(nixprofile);
+        group.add_action(homemanager);
         group.add_action(nixsystem);
 
         let actions = group.into_action_group();
@@ -1141,13 +1298,17 @@ impl Component for PkgModel {
                 self.set_icon(pkgmodel.icon);
                 self.set_version(pkgmodel.version);
                 self.set_platforms(pkgmodel.platforms);
+                self.set_unsupported(pkgmodel.unsupported);
+                self.set_source(pkgmodel.source);
                 self.set_maintainers(pkgmodel.maintainers);
                 self.set_licenses(pkgmodel.licenses);
+                self.set_developer(pkgmodel.developer);
+                self.set_agerating(pkgmodel.agerating);
                 self.set_pname(pkgmodel.pname);
                 self.set_installeduserpkgs(pkgmodel.installeduserpkgs);
                 self.set_installedsystempkgs(pkgmodel.installedsystempkgs);
 
-                if self.installedsystempkgs.contains(&self.pkg) && !self.installeduserpkgs.contains(match self.userpkgtype { UserPkgs::Env => &self.pname, UserPkgs::Profile => &self.pkg }) {
+                if self.source.is_none() && self.installedsystempkgs.contains(&self.pkg) && !self.installeduserpkgs.contains(match self.userpkgtype { UserPkgs::Env => &self.pname, UserPkgs::Profile | UserPkgs::HomeManager => &self.pkg }) {
                     self.set_installtype(InstallType::System)
                 } else {
                     self.set_installtype(InstallType::User)
@@ -1155,7 +1316,7 @@ impl Component for PkgModel {
 
                 self.launchable = if let Some(l) = pkgmodel.launchable {
                     Some(Launch::GtkApp(l))
-                } else if self.installeduserpkgs.contains(match self.userpkgtype { UserPkgs::Env => &self.pname, UserPkgs::Profile => &self.pkg }) {
+                } else if self.installeduserpkgs.contains(match self.userpkgtype { UserPkgs::Env => &self.pname, UserPkgs::Profile | UserPkgs::HomeManager => &self.pkg }) {
                     if let Ok(o) = Command::new("command").arg("-v").arg(&self.pname).output() {
                         if o.status.success() {
                             Some(Launch::TerminalApp(self.pname.to_string()))
@@ -1182,31 +1343,41 @@ impl Component for PkgModel {
                 };
 
                 if let Some(d) = pkgmodel.description {
-                    let mut input = d;
-                    // Fix formatting
-                    while input.contains('\n') {
-                        input = input.replace('\n', " ");
-                    }
-                    while input.contains('\t') {
-                        input = input.replace('\t', " ");
-                    }
-                    while input.contains("  ") {
-                        input = input.replace("  ", " ");
-                    }
-                    let mut pango = html2pango::markup_html(&input)
-                        .unwrap_or_else(|_| {
-                            warn!("Pango failed to parse description");
-                            input.to_string()
-                        })
-                        .trim()
-                        .to_string();
-                    while pango.contains("\n ") {
-                        pango = pango.replace("\n ", "\n");
+                    self.description = Some(descriptionmarkup(d));
+                }
+
+                {
+                    let mut release_guard = self.releases.guard();
+                    release_guard.clear();
+                    for release in pkgmodel.releases {
+                        release_guard.push_back(ReleaseItem {
+                            version: release.version,
+                            date: release
+                                .timestamp
+                                .and_then(|x| gtk::glib::DateTime::from_unix_local(x).ok())
+                                .and_then(|x| x.format("%x").ok())
+                                .map(|x| x.to_string()),
+                            description: release.description.map(descriptionmarkup),
+                        });
                     }
-                    while pango.ends_with('\n') {
-                        pango.pop();
+                }
+
+                if let Some(url) = pkgmodel.remoteicon {
+                    if let Some(iconpath) = util::remoteiconpath(&url) {
+                        let pkg = self.pkg.clone();
+                        sender.command(move |out, shutdown| {
+                            shutdown
+                                .register(async move {
+                                    match downloadicon(&url, &iconpath).await {
+                                        Ok(_) => {
+                                            out.send(PkgAsyncMsg::LoadIcon(pkg, iconpath));
+                                        }
+                                        Err(e) => warn!("Failed to download icon {}: {}", url, e),
+                                    }
+                                })
+                                .drop_on_shutdown()
+                        });
                     }
-                    self.description = Some(pango.strip_prefix('\n').unwrap_or(&pango).to_string());
                 }
 
                 self.homepage = pkgmodel.homepage;
@@ -1346,6 +1517,11 @@ impl Component for PkgModel {
                     }
                 }
             }
+            PkgMsg::LoadIcon(pkg, path) => {
+                if pkg == self.pkg {
+                    self.set_icon(Some(path));
+                }
+            }
             PkgMsg::SetCarouselPage(page) => {
                 self.carpage = page;
             }
@@ -1377,10 +1553,7 @@ impl Component for PkgModel {
                     block: false,
                     notify: None,
                 };
-                self.workqueue.insert(w.clone());
-                if self.workqueue.len() == 1 {
-                    self.installworker.emit(InstallAsyncHandlerMsg::Process(w));
-                }
+                sender.input(PkgMsg::AddToQueue(w));
             }
             PkgMsg::RemoveUser => {
                 let w = WorkPkg {
@@ -1391,10 +1564,7 @@ impl Component for PkgModel {
                     block: false,
                     notify: None,
                 };
-                self.workqueue.insert(w.clone());
-                if self.workqueue.len() == 1 {
-                    self.installworker.emit(InstallAsyncHandlerMsg::Process(w));
-                }
+                sender.input(PkgMsg::AddToQueue(w));
             }
             PkgMsg::InstallSystem => {
                 let online = util::checkonline();
@@ -1411,10 +1581,7 @@ impl Component for PkgModel {
                     block: false,
                     notify: None,
                 };
-                self.workqueue.insert(w.clone());
-                if self.workqueue.len() == 1 {
-                    self.installworker.emit(InstallAsyncHandlerMsg::Process(w));
-                }
+                sender.input(PkgMsg::AddToQueue(w));
             }
             PkgMsg::RemoveSystem => {
                 let w = WorkPkg {
@@ -1425,14 +1592,12 @@ impl Component for PkgModel {
                     block: false,
                     notify: None,
                 };
-                self.workqueue.insert(w.clone());
-                if self.workqueue.len() == 1 {
-                    self.installworker.emit(InstallAsyncHandlerMsg::Process(w));
-                }
+                sender.input(PkgMsg::AddToQueue(w));
             }
             PkgMsg::FinishedProcess(work) => {
+                self.set_progress(None);
                 let _ = nix_data::utils::refreshicons();
-                self.workqueue.remove(&work);
+                self.workqueue.retain(|x| x != &work);
                 trace!("WORK QUEUE: {}", self.workqueue.len());
                 match work.pkgtype {
                     InstallType::User => {
@@ -1440,7 +1605,7 @@ impl Component for PkgModel {
                             PkgAction::Install => {
                                 match self.userpkgtype {
                                     UserPkgs::Env => self.installeduserpkgs.insert(work.pname.to_string()),
-                                    UserPkgs::Profile => self.installeduserpkgs.insert(work.pkg.to_string()),
+                                    UserPkgs::Profile | UserPkgs::HomeManager => self.installeduserpkgs.insert(work.pkg.to_string()),
                                 };
                                 if self.launchable.is_none() {
                                     if let Ok(o) = Command::new("command").arg("-v").arg(&self.pname).output() {
@@ -1453,7 +1618,7 @@ impl Component for PkgModel {
                             PkgAction::Remove => {
                                 match self.userpkgtype {
                                     UserPkgs::Env => self.installeduserpkgs.remove(&work.pname),
-                                    UserPkgs::Profile => self.installeduserpkgs.remove(&work.pkg),
+                                    UserPkgs::Profile | UserPkgs::HomeManager => self.installeduserpkgs.remove(&work.pkg),
                                 };
                             }
                         }
@@ -1485,14 +1650,13 @@ impl Component for PkgModel {
                     }
                 }
                 
-                if !self.workqueue.is_empty() {
-                    if let Some(w) = self.workqueue.clone().iter().next() {
-                        self.installworker.emit(InstallAsyncHandlerMsg::Process(w.clone()));
-                    }
+                if let Some(w) = self.workqueue.front() {
+                    self.installworker.emit(InstallAsyncHandlerMsg::Process(w.clone()));
                 }
             }
             PkgMsg::FailedProcess(work) => {
-                self.workqueue.remove(&work);
+                self.set_progress(None);
+                self.workqueue.retain(|x| x != &work);
                 if let Some(n) = &work.notify {
                     match n {
                         NotifyPage::Installed => {
@@ -1500,15 +1664,19 @@ impl Component for PkgModel {
                         }
                     }
                 }
-                if !self.workqueue.is_empty() {
-                    if let Some(w) = self.workqueue.clone().iter().next() {
-                        self.installworker.emit(InstallAsyncHandlerMsg::Process(w.clone()));
-                    }
+                if let Some(w) = self.workqueue.front() {
+                    self.installworker.emit(InstallAsyncHandlerMsg::Process(w.clone()));
                 }
             }
             PkgMsg::Cancel => {
+                // System changes are only staged, drop them from the transaction
+                if let Some(w) = self.transactionpkgs.iter().find(|x| x.pkg == self.pkg) {
+                    self.transaction.emit(TransactionMsg::Drop(w.clone()));
+                    return
+                }
+
                 // If running, cancel the current process
-                if let Some(h) = self.workqueue.iter().next() {
+                if let Some(h) = self.workqueue.front() {
                     if h.pkg == self.pkg {
                         self.installworker.
                         emit(InstallAsyncHandlerMsg::CancelProcess);
@@ -1517,26 +1685,21 @@ impl Component for PkgModel {
                 }
 
                 // If not running, remove from queue
-                for w in self.workqueue.clone() {
-                    if w.pkg == self.pkg {
-                        self.workqueue.remove(&w);
-                    }
-                }
+                let pkg = self.pkg.to_string();
+                self.workqueue.retain(|x| x.pkg != pkg);
             }
-            PkgMsg::CancelFinished => {
-                // If running, cancel the current process
-                if let Some(h) = self.workqueue.clone().iter().next() {
-                    if h.pkg == self.pkg {
-                        self.workqueue.remove(h);
-                        return
+            PkgMsg::CancelledProcess(work) => {
+                self.set_progress(None);
+                self.workqueue.retain(|x| x != &work);
+                if let Some(n) = &work.notify {
+                    match n {
+                        NotifyPage::Installed => {
+                            sender.output(AppMsg::RemoveInstalledBusy(work));
+                        }
                     }
                 }
-
-                // If not running, remove from queue
-                for w in self.workqueue.clone() {
-                    if w.pkg == self.pkg {
-                        self.workqueue.remove(&w);
-                    }
+                if let Some(w) = self.workqueue.front() {
+                    self.installworker.emit(InstallAsyncHandlerMsg::Process(w.clone()));
                 }
             }
             PkgMsg::Launch => {
@@ -1556,7 +1719,7 @@ impl Component for PkgModel {
                     match l {
                         Launch::GtkApp(x) => {
                             match self.userpkgtype {
-                                UserPkgs::Env => {
+                                UserPkgs::Env | UserPkgs::HomeManager => {
                                     debug!("Launching {} with nix-shell", x);
                                     let _ = Command::new("nix-shell")
                                         .arg("-p")
@@ -1580,7 +1743,7 @@ impl Component for PkgModel {
                         }
                         Launch::TerminalApp(x) => {
                             let cmd = match self.userpkgtype {
-                                UserPkgs::Env => {
+                                UserPkgs::Env | UserPkgs::HomeManager => {
                                     format!("nix-shell -p {} --command \"{}; $SHELL\"", self.pkg, x)
                                 }
                                 UserPkgs::Profile => {
@@ -1592,7 +1755,7 @@ impl Component for PkgModel {
                     }
                 } else {
                     let cmd = match self.userpkgtype {
-                        UserPkgs::Env => {
+                        UserPkgs::Env | UserPkgs::HomeManager => {
                             format!("nix-shell -p {} --command \"{}; $SHELL\"", self.pkg, self.pname)
                         }
                         UserPkgs::Profile => {
@@ -1604,7 +1767,7 @@ impl Component for PkgModel {
             }
             PkgMsg::NixShell => {
                 let cmd = match self.userpkgtype {
-                    UserPkgs::Env => {
+                    UserPkgs::Env | UserPkgs::HomeManager => {
                         format!("nix-shell -p {}", self.pkg)
                     }
                     UserPkgs::Profile => {
@@ -1614,17 +1777,111 @@ impl Component for PkgModel {
                 launchterm(&cmd);
             }
             PkgMsg::SetInstallType(t) => {
-                self.set_installtype(t);
+                // Packages from other flakes can't be added to configuration.nix
+                if self.source.is_none() || t == InstallType::User {
+                    self.set_installtype(t);
+                }
             }
             PkgMsg::AddToQueue(work) => {
-                self.workqueue.insert(work.clone());
-                if self.workqueue.len() == 1 {
-                    self.installworker.emit(InstallAsyncHandlerMsg::Process(work));
+                match work.pkgtype {
+                    InstallType::User => {
+                        if self.workqueue.contains(&work) {
+                            return;
+                        }
+                        self.workqueue.push_back(work.clone());
+                        if self.workqueue.len() == 1 {
+                            self.installworker.emit(InstallAsyncHandlerMsg::Process(work));
+                        }
+                    }
+                    InstallType::System => {
+                        self.transaction.emit(TransactionMsg::Add(work));
+                    }
                 }
             }
             PkgMsg::UpdateOnline(online) => {
                 self.set_online(online);
             }
+            PkgMsg::ShowTransaction => {
+                self.transaction.emit(TransactionMsg::Show);
+            }
+            PkgMsg::SetTransaction(work) => {
+                sender.output(AppMsg::SetTransactionCount(work.len()));
+                self.set_transactionpkgs(work);
+            }
+            PkgMsg::TransactionDropped(work) => {
+                if let Some(n) = &work.notify {
+                    match n {
+                        NotifyPage::Installed => {
+                            sender.output(AppMsg::RemoveInstalledBusy(work));
+                        }
+                    }
+                }
+            }
+            PkgMsg::ApplyTransaction(work) => {
+                let online = util::checkonline();
+                if !online {
+                    sender.output(AppMsg::CheckNetwork);
+                    self.online = false;
+                    self.transaction.emit(TransactionMsg::Finished(false));
+                    return;
+                }
+                self.set_applying(true);
+                self.installworker.emit(InstallAsyncHandlerMsg::ProcessBatch(work));
+            }
+            PkgMsg::FinishedBatch(work) => {
+                let _ = nix_data::utils::refreshicons();
+                self.set_applying(false);
+                self.set_progress(None);
+                for w in work {
+                    match w.action {
+                        PkgAction::Install => {
+                            self.installedsystempkgs.insert(w.pkg.clone());
+                            if w.pkg == self.pkg && self.launchable.is_none() {
+                                if let Ok(o) = Command::new("command").arg("-v").arg(&self.pname).output() {
+                                    if o.status.success() {
+                                        self.set_launchable(Some(Launch::TerminalApp(self.pname.to_string())))
+                                    }
+                                }
+                            }
+                        }
+                        PkgAction::Remove => {
+                            self.installedsystempkgs.remove(&w.pkg);
+                        }
+                    }
+                    if let Some(n) = &w.notify {
+                        match n {
+                            NotifyPage::Installed => {
+                                sender.output(AppMsg::RemoveInstalledBusy(w));
+                            }
+                        }
+                    }
+                }
+                sender.output(AppMsg::UpdateInstalledPkgs);
+                self.transaction.emit(TransactionMsg::Finished(true));
+            }
+            PkgMsg::FailedBatch(work) => {
+                self.set_applying(false);
+                self.set_progress(None);
+                for w in work {
+                    if let Some(n) = &w.notify {
+                        match n {
+                            NotifyPage::Installed => {
+                                sender.output(AppMsg::RemoveInstalledBusy(w));
+                            }
+                        }
+                    }
+                }
+                self.transaction.emit(TransactionMsg::Finished(false));
+            }
+            PkgMsg::CancelledBatch(_) => {
+                self.set_applying(false);
+                self.set_progress(None);
+                self.transaction.emit(TransactionMsg::Cancelled);
+                self.transaction.emit(TransactionMsg::Show);
+            }
+            PkgMsg::Progress(pkgtype, fraction, status) => {
+                self.set_progress(Some((pkgtype, fraction, status)));
+            }
         }
     }
 
@@ -1636,8 +1893,70 @@ impl Component for PkgModel {
             PkgAsyncMsg::SetError(pkg, i) => {
                 sender.input(PkgMsg::SetError(pkg, i));
             }
+            PkgAsyncMsg::LoadIcon(pkg, path) => {
+                sender.input(PkgMsg::LoadIcon(pkg, path));
+            }
+        }
+    }
+}
+
+// AppStream descriptions are HTML-like, with whitespace that is not meant to be shown
+fn descriptionmarkup(mut input: String) -> String {
+    while input.contains('\n') {
+        input = input.replace('\n', " ");
+    }
+    while input.contains('\t') {
+        input = input.replace('\t', " ");
+    }
+    while input.contains("  ") {
+        input = input.replace("  ", " ");
+    }
+    let mut pango = html2pango::markup_html(&input)
+        .unwrap_or_else(|_| {
+            warn!("Pango failed to parse description");
+            input.to_string()
+        })
+        .trim()
+        .to_string();
+    while pango.contains("\n ") {
+        pango = pango.replace("\n ", "\n");
+    }
+    while pango.ends_with('\n') {
+        pango.pop();
+    }
+    pango.strip_prefix('\n').unwrap_or(&pango).to_string()
+}
+
+async fn downloadicon(url: &str, iconpath: &str) -> anyhow::Result<()> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    let bytes = response.bytes().await?;
+    if let Some(parent) = Path::new(iconpath).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(iconpath, bytes)?;
+    Ok(())
+}
+
+impl PkgModel {
+    // Progress of the package shown on the page, if it is the one being built
+    fn currentprogress(&self, pkgtype: InstallType) -> Option<(Option<f64>, Option<String>)> {
+        let current = match pkgtype {
+            InstallType::User => self.workqueue.front().map(|x| x.pkg == self.pkg).unwrap_or(false),
+            InstallType::System => self.applying,
+        };
+        match &self.progress {
+            Some((t, fraction, status)) if current && t == &pkgtype => Some((*fraction, status.clone())),
+            _ => None,
         }
     }
+
+    fn fraction(&self, pkgtype: InstallType) -> Option<f64> {
+        self.currentprogress(pkgtype).and_then(|x| x.0)
+    }
+
+    fn progresstext(&self, pkgtype: InstallType) -> Option<String> {
+        self.currentprogress(pkgtype).and_then(|x| x.1)
+    }
 }
 
 fn launchterm(cmd: &str) {
@@ -1647,6 +1966,7 @@ fn launchterm(cmd: &str) {
 relm4::new_action_group!(ModeActionGroup, "mode");
 relm4::new_stateless_action!(NixEnvAction, ModeActionGroup, "env");
 relm4::new_stateless_action!(NixProfileAction, ModeActionGroup, "profile");
+relm4::new_stateless_action!(HomeManagerAction, ModeActionGroup, "homemanager");
 relm4::new_stateless_action!(NixSystemAction, ModeActionGroup, "system");
 
 relm4::new_action_group!(RunActionGroup, "run");
@@ -1,8 +1,15 @@
 use std::path::{PathBuf, Path};
+use super::appstreamfactory::AppStreamSourceItem;
 use super::window::AppMsg;
+use gettextrs::gettext;
 use adw::prelude::*;
+use log::*;
 use nix_data::config::configfile::NixDataConfig;
-use relm4::*;
+use nsc_core::{
+    appstream::{self, AppStreamSource},
+    homemanager,
+};
+use relm4::{factory::FactoryVecDeque, *};
 use relm4_components::open_dialog::*;
 
 #[tracker::track]
@@ -11,10 +18,16 @@ pub struct PreferencesPageModel {
     configpath: Option<PathBuf>,
     flake: Option<PathBuf>,
     flakearg: Option<String>,
+    homemanager: bool,
     #[tracker::no_eq]
     open_dialog: Controller<OpenDialog>,
     #[tracker::no_eq]
     flake_file_dialog: Controller<OpenDialog>,
+    appstreampath: Option<PathBuf>,
+    #[tracker::no_eq]
+    appstream_dialog: Controller<OpenDialog>,
+    #[tracker::no_eq]
+    appstreamsources: FactoryVecDeque<AppStreamSourceItem>,
 }
 
 #[derive(Debug)]
@@ -26,6 +39,10 @@ pub enum PreferencesPageMsg {
     SetFlakePath(Option<PathBuf>),
     SetFlakeArg(Option<String>),
     ModifyFlake,
+    SetHomeManager(bool),
+    OpenAppStream,
+    SetAppStreamPath(Option<PathBuf>),
+    SetAppStreamSources(Vec<AppStreamSource>),
     Ignore,
 }
 
@@ -47,7 +64,7 @@ impl SimpleComponent for PreferencesPageModel {
                     // set_title: "Preferences",
                     set_visible: Path::new("/etc/NIXOS").exists(),
                     add = &adw::ActionRow {
-                        set_title: "Configuration file",
+                        set_title: &gettext("Configuration file"),
                         add_suffix = &gtk::Box {
                             set_orientation: gtk::Orientation::Horizontal,
                             set_halign: gtk::Align::End,
@@ -62,12 +79,12 @@ impl SimpleComponent for PreferencesPageModel {
                                     },
                                     gtk::Label {
                                         #[watch]
-                                        set_label: {
-                                            let x = if let Some(configpath)  = &model.configpath { configpath.file_name().unwrap_or_default().to_str().unwrap_or_default() } else { "(None)" };
+                                        set_label: &{
+                                            let x = if let Some(configpath)  = &model.configpath { configpath.file_name().unwrap_or_default().to_str().unwrap_or_default() } else { "" };
                                             if x.is_empty() {
-                                                "(None)"
+                                                gettext("(None)")
                                             } else {
-                                                x
+                                                x.to_string()
                                             }
                                         }
                                     }
@@ -85,7 +102,7 @@ impl SimpleComponent for PreferencesPageModel {
                         }
                     },
                     add = &adw::ActionRow {
-                        set_title: "Use nix flakes",
+                        set_title: &gettext("Use nix flakes"),
                         add_suffix = &gtk::Switch {
                             set_valign: gtk::Align::Center,
                             connect_state_set[sender] => move |_, b| {
@@ -103,7 +120,7 @@ impl SimpleComponent for PreferencesPageModel {
                         }
                     },
                     add = &adw::ActionRow {
-                        set_title: "Flake file",
+                        set_title: &gettext("Flake file"),
                         #[watch]
                         set_visible: model.flake.is_some(),
                         add_suffix = &gtk::Box {
@@ -120,16 +137,16 @@ impl SimpleComponent for PreferencesPageModel {
                                     },
                                     gtk::Label {
                                         #[watch]
-                                        set_label: {
+                                        set_label: &{
                                             let x = if let Some(f) = &model.flake {
                                                 f.file_name().unwrap_or_default().to_str().unwrap_or_default()
                                             } else {
                                                 ""
                                             };
                                             if x.is_empty() {
-                                                "(None)"
+                                                gettext("(None)")
                                             } else {
-                                                x
+                                                x.to_string()
                                             }
                                         }
                                     }
@@ -150,7 +167,7 @@ impl SimpleComponent for PreferencesPageModel {
                     add = &adw::EntryRow {
                         #[watch]
                         set_visible: model.flake.is_some(),
-                        set_title: "Flake arguments (--flake path/to/flake.nix#<THIS ENTRY>)",
+                        set_title: &gettext("Flake arguments (--flake path/to/flake.nix#<THIS ENTRY>)"),
                         set_use_markup: false,
                         set_use_markup: false,
                         connect_changed[sender] => move |x| {
@@ -167,6 +184,80 @@ impl SimpleComponent for PreferencesPageModel {
                         set_text: model.flakearg.as_ref().unwrap_or(&String::new())
                     }
 
+                },
+                add = &adw::PreferencesGroup {
+                    set_title: &gettext("User Packages"),
+                    set_visible: homemanager::homemanagerinstalled(),
+                    add = &adw::ActionRow {
+                        set_title: &gettext("Use home-manager"),
+                        set_subtitle: &gettext("Install user packages to home.nix instead of the user profile, after a restart"),
+                        add_suffix = &gtk::Switch {
+                            set_valign: gtk::Align::Center,
+                            connect_state_set[sender] => move |_, b| {
+                                sender.input(PreferencesPageMsg::SetHomeManager(b));
+                                gtk::Inhibit(false)
+                            } @homemanagerswitched,
+                            #[track(model.changed(PreferencesPageModel::homemanager()))]
+                            #[block_signal(homemanagerswitched)]
+                            set_state: model.homemanager
+                        }
+                    },
+                },
+                add = &adw::PreferencesGroup {
+                    set_title: &gettext("AppStream Data"),
+                    add = &adw::ActionRow {
+                        set_title: &gettext("Extra AppStream data"),
+                        set_subtitle: &gettext("Used before any data found on the system, after a restart"),
+                        add_suffix = &gtk::Box {
+                            set_orientation: gtk::Orientation::Horizontal,
+                            set_halign: gtk::Align::End,
+                            set_valign: gtk::Align::Center,
+                            set_spacing: 10,
+                            gtk::Button {
+                                gtk::Box {
+                                    set_orientation: gtk::Orientation::Horizontal,
+                                    set_spacing: 5,
+                                    gtk::Image {
+                                        set_icon_name: Some("folder-open-symbolic"),
+                                    },
+                                    gtk::Label {
+                                        #[watch]
+                                        set_label: &{
+                                            let x = if let Some(p) = &model.appstreampath { p.file_name().unwrap_or_default().to_str().unwrap_or_default() } else { "" };
+                                            if x.is_empty() {
+                                                gettext("(None)")
+                                            } else {
+                                                x.to_string()
+                                            }
+                                        }
+                                    }
+                                },
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(PreferencesPageMsg::OpenAppStream);
+                                }
+                            },
+                            gtk::Button {
+                                set_icon_name: "user-trash-symbolic",
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(PreferencesPageMsg::SetAppStreamPath(None));
+                                }
+                            }
+                        }
+                    },
+                },
+                add = &adw::PreferencesGroup {
+                    set_title: &gettext("Sources"),
+                    #[watch]
+                    set_description: Some(if model.appstreamsources.is_empty() {
+                        gettext("No AppStream data was found, so apps are shown without icons and screenshots")
+                    } else {
+                        gettext("Where app details come from. An app is taken from the first source that has it")
+                    }.as_str()),
+                    #[local_ref]
+                    appstreambox -> gtk::ListBox {
+                        add_css_class: "boxed-list",
+                        set_selection_mode: gtk::SelectionMode::None,
+                    },
                 }
             }
         }
@@ -191,15 +282,30 @@ impl SimpleComponent for PreferencesPageModel {
                 OpenDialogResponse::Accept(path) => PreferencesPageMsg::SetFlakePath(Some(path)),
                 OpenDialogResponse::Cancel => PreferencesPageMsg::Ignore,
             });
+        let appstream_dialog = OpenDialog::builder()
+            .transient_for_native(root)
+            .launch(OpenDialogSettings {
+                folder_mode: true,
+                ..Default::default()
+            })
+            .forward(sender.input_sender(), |response| match response {
+                OpenDialogResponse::Accept(path) => PreferencesPageMsg::SetAppStreamPath(Some(path)),
+                OpenDialogResponse::Cancel => PreferencesPageMsg::Ignore,
+            });
         let model = PreferencesPageModel {
             configpath: None,
             flake: None,
             flakearg: None,
+            homemanager: false,
             open_dialog,
             flake_file_dialog,
+            appstreampath: None,
+            appstream_dialog,
+            appstreamsources: FactoryVecDeque::new(gtk::ListBox::new(), sender.input_sender()),
             tracker: 0,
         };
 
+        let appstreambox = model.appstreamsources.widget();
         let widgets = view_output!();
 
         ComponentParts { model, widgets }
@@ -212,6 +318,8 @@ impl SimpleComponent for PreferencesPageModel {
                 self.configpath = config.systemconfig.as_ref().map(PathBuf::from);
                 self.set_flake(config.flake.as_ref().map(PathBuf::from));
                 self.set_flakearg(config.flakearg);
+                self.set_homemanager(homemanager::enabled());
+                self.appstreampath = appstream::configured().map(PathBuf::from);
             }
             PreferencesPageMsg::Open => self.open_dialog.emit(OpenDialogMsg::Open),
             PreferencesPageMsg::OpenFlake => self.flake_file_dialog.emit(OpenDialogMsg::Open),
@@ -227,6 +335,26 @@ impl SimpleComponent for PreferencesPageModel {
                 self.flakearg = arg;
                 sender.input(PreferencesPageMsg::ModifyFlake)
             }
+            PreferencesPageMsg::SetHomeManager(enabled) => {
+                self.homemanager = enabled;
+                if let Err(e) = homemanager::setenabled(enabled) {
+                    warn!("Failed to set home-manager: {}", e);
+                }
+            }
+            PreferencesPageMsg::OpenAppStream => self.appstream_dialog.emit(OpenDialogMsg::Open),
+            PreferencesPageMsg::SetAppStreamPath(path) => {
+                self.appstreampath = path.clone();
+                if let Err(e) = appstream::setconfigured(path.map(|x| x.to_string_lossy().to_string())) {
+                    warn!("Failed to set AppStream path: {}", e);
+                }
+            }
+            PreferencesPageMsg::SetAppStreamSources(sources) => {
+                let mut guard = self.appstreamsources.guard();
+                guard.clear();
+                for source in sources {
+                    guard.push_back(source);
+                }
+            }
             PreferencesPageMsg::ModifyFlake => {
                 sender.output(AppMsg::UpdateFlake(self.flake.as_ref().map(|x| x.to_string_lossy().to_string()), self.flakearg.clone()));
             }
@@ -1,8 +1,16 @@
-use super::window::AppMsg;
+use super::window::{AppMsg, REBUILD_BROKER};
+use crate::i18n;
 use adw::prelude::*;
-use log::{info, trace};
+use gettextrs::gettext;
+use log::{info, trace, warn};
+use nsc_core::{BuildResult, Canceller, Progress, RebuildMode};
 use relm4::*;
 use sourceview5::prelude::*;
+use std::future::Future;
+use tokio::sync::oneshot;
+
+// The modes offered for test builds, in the order of the mode dropdown
+const TESTMODES: [RebuildMode; 3] = [RebuildMode::DryBuild, RebuildMode::Build, RebuildMode::Test];
 
 #[tracker::track]
 pub struct RebuildModel {
@@ -13,6 +21,18 @@ pub struct RebuildModel {
     path: String,
     flake: Option<String>,
     scheme: Option<sourceview5::StyleScheme>,
+    #[tracker::no_eq]
+    review: Option<oneshot::Sender<ReviewAction>>,
+    testable: bool,
+    testmode: RebuildMode,
+    tested: Option<BuildResult>,
+    #[tracker::no_eq]
+    progress: Progress,
+    fraction: Option<f64>,
+    progresstext: Option<String>,
+    #[tracker::no_eq]
+    canceller: Option<Canceller>,
+    cancelling: bool,
 }
 
 #[derive(Debug)]
@@ -24,13 +44,31 @@ pub enum RebuildMsg {
     Close,
     SetScheme(String),
     Quit,
+    Review(String, bool, oneshot::Sender<ReviewAction>),
+    Tested(BuildResult, oneshot::Sender<ReviewAction>),
+    Test,
+    SetTestMode(u32),
+    Apply,
+    Cancel,
+    SetCanceller(Option<Canceller>),
+    CancelBuild,
+    FinishCancelled,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReviewAction {
+    Cancel,
+    Test(RebuildMode),
+    Apply,
 }
 
 #[derive(PartialEq)]
 enum RebuildStatus {
+    Review,
     Building,
     Success,
     Error,
+    Cancelled,
 }
 
 #[relm4::component(pub)]
@@ -60,6 +98,58 @@ impl SimpleComponent for RebuildModel {
                     set_margin_top: 20,
                     set_transition_type: gtk::StackTransitionType::Crossfade,
                     set_vhomogeneous: false,
+                    #[name(review)]
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+                        set_spacing: 10,
+                        gtk::Label {
+                            #[track(model.changed(RebuildModel::tested()))]
+                            set_label: &match model.tested {
+                                Some(BuildResult::Success) => gettext("Test Build Succeeded"),
+                                Some(BuildResult::Failed(_)) => gettext("Test Build Failed"),
+                                None => gettext("Review Changes"),
+                            },
+                            add_css_class: "title-1",
+                        },
+                        gtk::Label {
+                            set_wrap: true,
+                            set_justify: gtk::Justification::Center,
+                            #[track(model.changed(RebuildModel::tested()))]
+                            set_label: &match &model.tested {
+                                Some(BuildResult::Success) => match model.testmode {
+                                    RebuildMode::DryBuild => gettext("The new configuration evaluated successfully and can now be applied"),
+                                    RebuildMode::Test => gettext("The new configuration is active until the next reboot and can now be applied"),
+                                    _ => gettext("The new configuration built successfully and can now be applied"),
+                                },
+                                Some(BuildResult::Failed(Some(drv))) => i18n::fill(&gettext("Failed to build {}"), &[drv]),
+                                Some(BuildResult::Failed(None)) => gettext("See below for error message."),
+                                None => gettext("The following changes will be made to your configuration"),
+                            },
+                            add_css_class: "dim-label",
+                        },
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Horizontal,
+                            set_halign: gtk::Align::Center,
+                            set_spacing: 10,
+                            #[track(model.changed(RebuildModel::testable()))]
+                            set_visible: model.testable,
+                            gtk::Label {
+                                set_label: &gettext("Test build mode"),
+                            },
+                            gtk::DropDown {
+                                set_model: Some(&gtk::StringList::new(&[
+                                    &gettext("Dry build"),
+                                    &gettext("Build"),
+                                    &gettext("Activate until reboot"),
+                                ])),
+                                set_selected: TESTMODES.iter().position(|x| *x == model.testmode).unwrap_or_default() as u32,
+                                set_tooltip_text: Some(&gettext("A dry build only evaluates the configuration, a build also builds it and activating it switches to it until the next reboot")),
+                                connect_selected_notify[sender] => move |x| {
+                                    sender.input(RebuildMsg::SetTestMode(x.selected()))
+                                }
+                            }
+                        }
+                    },
                     #[name(building)]
                     gtk::Box {
                         set_orientation: gtk::Orientation::Vertical,
@@ -70,9 +160,38 @@ impl SimpleComponent for RebuildModel {
                             set_height_request: 60,
                         },
                         gtk::Label {
-                            set_label: "Building...",
+                            set_label: &gettext("Building..."),
                             add_css_class: "title-1",
                         },
+                        gtk::ProgressBar {
+                            set_margin_start: 20,
+                            set_margin_end: 20,
+                            #[track(model.changed(RebuildModel::fraction()))]
+                            set_visible: model.fraction.is_some(),
+                            #[track(model.changed(RebuildModel::fraction()))]
+                            set_fraction: model.fraction.unwrap_or_default(),
+                        },
+                        gtk::Label {
+                            add_css_class: "dim-label",
+                            set_ellipsize: gtk::pango::EllipsizeMode::End,
+                            #[track(model.changed(RebuildModel::progresstext()))]
+                            set_visible: model.progresstext.is_some(),
+                            #[track(model.changed(RebuildModel::progresstext()))]
+                            set_label: model.progresstext.as_deref().unwrap_or_default(),
+                        },
+                        gtk::Button {
+                            set_halign: gtk::Align::Center,
+                            add_css_class: "pill",
+                            #[track(model.changed(RebuildModel::cancelling()))]
+                            set_label: &if model.cancelling { gettext("Cancelling...") } else { gettext("Cancel") },
+                            #[track(model.changed(RebuildModel::canceller()))]
+                            set_visible: model.canceller.is_some(),
+                            #[track(model.changed(RebuildModel::cancelling()))]
+                            set_sensitive: !model.cancelling,
+                            connect_clicked[sender] => move |_| {
+                                sender.input(RebuildMsg::CancelBuild)
+                            }
+                        },
                     },
                     #[name(success)]
                     gtk::Box {
@@ -84,11 +203,11 @@ impl SimpleComponent for RebuildModel {
                             set_pixel_size: 128,
                         },
                         gtk::Label {
-                            set_label: "Done!",
+                            set_label: &gettext("Done!"),
                             add_css_class: "title-1",
                         },
                         gtk::Label {
-                            set_label: "Rebuild successful!",
+                            set_label: &gettext("Rebuild successful!"),
                             add_css_class: "dim-label",
                         }
                     },
@@ -102,11 +221,31 @@ impl SimpleComponent for RebuildModel {
                             set_pixel_size: 128,
                         },
                         gtk::Label {
-                            set_label: "Error!",
+                            set_label: &gettext("Error!"),
+                            add_css_class: "title-1",
+                        },
+                        gtk::Label {
+                            set_label: &gettext("Rebuild failed! See below for error message."),
+                            add_css_class: "dim-label",
+                        }
+                    },
+                    #[name(cancelled)]
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+                        set_spacing: 10,
+                        gtk::Image {
+                            add_css_class: "dim-label",
+                            set_icon_name: Some("process-stop-symbolic"),
+                            set_pixel_size: 128,
+                        },
+                        gtk::Label {
+                            set_label: &gettext("Cancelled"),
                             add_css_class: "title-1",
                         },
                         gtk::Label {
-                            set_label: "Rebuild failed! See below for error message.",
+                            set_label: &gettext("The rebuild was stopped and your previous configuration was kept."),
+                            set_wrap: true,
+                            set_justify: gtk::Justification::Center,
                             add_css_class: "dim-label",
                         }
                     }
@@ -145,12 +284,41 @@ impl SimpleComponent for RebuildModel {
                     #[track(model.changed(RebuildModel::status()))]
                     set_visible: model.status != RebuildStatus::Building,
                     gtk::Button {
-                        set_label: "Close",
+                        set_label: &gettext("Close"),
                         #[track(model.changed(RebuildModel::status()))]
-                        set_visible: model.status != RebuildStatus::Building,
+                        set_visible: model.status != RebuildStatus::Building && model.status != RebuildStatus::Review,
                         connect_clicked[sender] => move |_| {
                             sender.input(RebuildMsg::Close)
                         }
+                    },
+                    gtk::Button {
+                        set_label: &gettext("Cancel"),
+                        #[track(model.changed(RebuildModel::status()))]
+                        set_visible: model.status == RebuildStatus::Review,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(RebuildMsg::Cancel)
+                        }
+                    },
+                    gtk::Button {
+                        set_label: &gettext("Test Build"),
+                        #[track(model.changed(RebuildModel::status()) || model.changed(RebuildModel::testable()))]
+                        set_visible: model.status == RebuildStatus::Review && model.testable,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(RebuildMsg::Test)
+                        }
+                    },
+                    gtk::Button {
+                        #[track(model.changed(RebuildModel::testable()))]
+                        set_label: &if model.testable { gettext("Switch") } else { gettext("Apply") },
+                        add_css_class: "suggested-action",
+                        #[track(model.changed(RebuildModel::status()))]
+                        set_visible: model.status == RebuildStatus::Review,
+                        // Configurations that can be test built are only switched to once they build
+                        #[track(model.changed(RebuildModel::testable()) || model.changed(RebuildModel::tested()))]
+                        set_sensitive: !model.testable || model.tested == Some(BuildResult::Success),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(RebuildMsg::Apply)
+                        }
                     }
                 }
             }
@@ -158,24 +326,41 @@ impl SimpleComponent for RebuildModel {
     }
 
     fn pre_view() {
+        if model.changed(RebuildModel::status()) {
+            if model.status == RebuildStatus::Review && model.tested.is_none() {
+                outbuf.set_language(
+                    sourceview5::LanguageManager::default()
+                        .language("diff")
+                        .as_ref(),
+                );
+            } else {
+                outbuf.set_language(None);
+            }
+        }
         match model.status {
+            RebuildStatus::Review => statusstack.set_visible_child(review),
             RebuildStatus::Building => {
                 statusstack.set_visible_child(building);
             }
             RebuildStatus::Success => statusstack.set_visible_child(success),
             RebuildStatus::Error => statusstack.set_visible_child(error),
+            RebuildStatus::Cancelled => statusstack.set_visible_child(cancelled),
         }
     }
 
     fn post_view() {
         let adj = scrollwindow.vadjustment();
-        if model.status == RebuildStatus::Building {
-            adj.set_upper(adj.upper() + 20.0);
-        }
-        adj.set_value(adj.upper());
-        if model.status != RebuildStatus::Building {
-            outview.scroll_to_mark(&outview.buffer().get_insert(), 0.0, true, 0.0, 0.0);
-            scrollwindow.hadjustment().set_value(0.0);
+        if model.status == RebuildStatus::Review && model.tested.is_none() {
+            adj.set_value(0.0);
+        } else {
+            if model.status == RebuildStatus::Building {
+                adj.set_upper(adj.upper() + 20.0);
+            }
+            adj.set_value(adj.upper());
+            if model.status != RebuildStatus::Building {
+                outview.scroll_to_mark(&outview.buffer().get_insert(), 0.0, true, 0.0, 0.0);
+                scrollwindow.hadjustment().set_value(0.0);
+            }
         }
     }
 
@@ -193,6 +378,15 @@ impl SimpleComponent for RebuildModel {
             path: String::new(),
             flake: None,
             scheme: None,
+            review: None,
+            testable: false,
+            testmode: RebuildMode::Build,
+            tested: None,
+            progress: Progress::default(),
+            fraction: None,
+            progresstext: None,
+            canceller: None,
+            cancelling: false,
             tracker: 0,
         };
 
@@ -207,17 +401,40 @@ impl SimpleComponent for RebuildModel {
             RebuildMsg::Show => {
                 self.update_hidden(|x| *x = false);
                 self.update_text(|x| x.clear());
+                self.resetprogress();
                 self.set_status(RebuildStatus::Building);
             }
+            RebuildMsg::SetCanceller(canceller) => {
+                self.set_canceller(canceller);
+                self.set_cancelling(false);
+            }
+            RebuildMsg::CancelBuild => {
+                if let Some(canceller) = self.canceller.clone() {
+                    self.set_cancelling(true);
+                    relm4::spawn(async move {
+                        if let Err(e) = canceller.cancel().await {
+                            warn!("Failed to cancel rebuild: {}", e);
+                        }
+                    });
+                }
+            }
+            RebuildMsg::FinishCancelled => {
+                self.update_hidden(|x| *x = false);
+                self.set_status(RebuildStatus::Cancelled);
+            }
             RebuildMsg::UpdateText(s) => {
                 info!("RebuildMsg::UpdateText({})", s);
-                let newtext = if self.text.is_empty() {
-                    s
-                } else {
-                    format!("{}\n{}", self.text, s)
-                };
-                self.set_text(newtext);
-                trace!("NEWTEXT: {}", self.text);
+                if let Some(s) = self.progress.parse(&s) {
+                    let newtext = if self.text.is_empty() {
+                        s
+                    } else {
+                        format!("{}\n{}", self.text, s)
+                    };
+                    self.set_text(newtext);
+                    trace!("NEWTEXT: {}", self.text);
+                }
+                self.set_fraction(self.progress.fraction());
+                self.set_progresstext(self.progress.status());
             }
             RebuildMsg::FinishSuccess => {
                 self.set_status(RebuildStatus::Success);
@@ -239,6 +456,97 @@ impl SimpleComponent for RebuildModel {
             RebuildMsg::Quit => {
                 sender.output(AppMsg::Close);
             }
+            RebuildMsg::Review(diff, testable, review) => {
+                self.update_hidden(|x| *x = false);
+                if diff.is_empty() {
+                    self.set_text(gettext("No changes"));
+                } else {
+                    self.set_text(diff);
+                }
+                self.set_testable(testable);
+                self.set_tested(None);
+                self.set_status(RebuildStatus::Review);
+                self.review = Some(review);
+            }
+            RebuildMsg::Tested(result, review) => {
+                self.update_hidden(|x| *x = false);
+                self.set_tested(Some(result));
+                self.set_status(RebuildStatus::Review);
+                self.review = Some(review);
+            }
+            RebuildMsg::Test => {
+                if let Some(review) = self.review.take() {
+                    let _ = review.send(ReviewAction::Test(self.testmode));
+                }
+                self.update_text(|x| x.clear());
+                self.resetprogress();
+                self.set_status(RebuildStatus::Building);
+            }
+            RebuildMsg::SetTestMode(i) => {
+                if let Some(mode) = TESTMODES.get(i as usize) {
+                    self.set_testmode(*mode);
+                }
+            }
+            RebuildMsg::Apply => {
+                if let Some(review) = self.review.take() {
+                    let _ = review.send(ReviewAction::Apply);
+                }
+                self.update_text(|x| x.clear());
+                self.resetprogress();
+                self.set_status(RebuildStatus::Building);
+            }
+            RebuildMsg::Cancel => {
+                if let Some(review) = self.review.take() {
+                    let _ = review.send(ReviewAction::Cancel);
+                }
+                self.update_hidden(|x| *x = true);
+                self.update_text(|x| x.clear());
+                self.set_status(RebuildStatus::Building);
+            }
+        }
+    }
+}
+
+impl RebuildModel {
+    fn resetprogress(&mut self) {
+        self.set_canceller(None);
+        self.set_cancelling(false);
+        self.progress = Progress::default();
+        self.set_fraction(None);
+        self.set_progresstext(None);
+    }
+}
+
+pub async fn review(diff: String) -> bool {
+    let (tx, rx) = oneshot::channel();
+    REBUILD_BROKER.send(RebuildMsg::Review(diff, false, tx));
+    rx.await.map(|x| x == ReviewAction::Apply).unwrap_or(false)
+}
+
+// test returns None when the test build was cancelled, which cancels the whole change
+pub async fn reviewbuild<F, Fut>(diff: String, test: F) -> bool
+where
+    F: Fn(RebuildMode) -> Fut,
+    Fut: Future<Output = Option<BuildResult>>,
+{
+    let (tx, mut rx) = oneshot::channel();
+    REBUILD_BROKER.send(RebuildMsg::Review(diff, true, tx));
+    loop {
+        match rx.await.unwrap_or(ReviewAction::Cancel) {
+            ReviewAction::Cancel => return false,
+            ReviewAction::Apply => return true,
+            ReviewAction::Test(mode) => {
+                let result = match test(mode).await {
+                    Some(result) => result,
+                    None => {
+                        REBUILD_BROKER.send(RebuildMsg::FinishCancelled);
+                        return false;
+                    }
+                };
+                let (tx, newrx) = oneshot::channel();
+                REBUILD_BROKER.send(RebuildMsg::Tested(result, tx));
+                rx = newrx;
+            }
         }
     }
 }
@@ -0,0 +1,50 @@
+use crate::i18n;
+use gettextrs::gettext;
+use relm4::adw::prelude::*;
+use relm4::{factory::*, *};
+
+use super::pkgpage::PkgMsg;
+
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct ReleaseItem {
+    pub version: String,
+    pub date: Option<String>,
+    // Pango markup
+    pub description: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ReleaseItemMsg {}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for ReleaseItem {
+    type CommandOutput = ();
+    type Init = ReleaseItem;
+    type Input = ();
+    type Output = ReleaseItemMsg;
+    type ParentWidget = gtk::ListBox;
+    type ParentInput = PkgMsg;
+
+    view! {
+        adw::ExpanderRow {
+            set_title: &i18n::fill(&gettext("Version {}"), &[&self.version]),
+            set_subtitle: self.date.as_deref().unwrap_or_default(),
+            set_enable_expansion: self.description.is_some(),
+            add_row = &gtk::Label {
+                set_halign: gtk::Align::Start,
+                set_margin_all: 15,
+                set_wrap: true,
+                set_xalign: 0.0,
+                set_markup: self.description.as_deref().unwrap_or_default(),
+            }
+        }
+    }
+
+    fn init_model(
+        parent: Self::Init,
+        _index: &DynamicIndex,
+        _sender: FactorySender<Self>,
+    ) -> Self {
+        parent
+    }
+}
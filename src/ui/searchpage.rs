@@ -1,8 +1,9 @@
-use std::{path::Path, collections::HashSet};
-use crate::APPINFO;
+use std::collections::HashSet;
+use crate::parse::util;
 
 use super::window::*;
 use adw::prelude::*;
+use nsc_core::search::SearchFilter;
 use relm4::{factory::*, *, gtk::pango};
 use log::*;
 
@@ -12,11 +13,15 @@ pub struct SearchPageModel {
     #[tracker::no_eq]
     searchitems: FactoryVecDeque<SearchItemModel>,
     searchitemtracker: u8,
+    #[tracker::no_eq]
+    filterchips: FactoryVecDeque<SearchFilterChip>,
 }
 
 #[derive(Debug)]
 pub enum SearchPageMsg {
     Search(Vec<SearchItem>),
+    SetFilters(Vec<SearchFilter>),
+    ToggleFilter(SearchFilter),
     UpdateInstalled(HashSet<String>, HashSet<String>),
     OpenRow(gtk::ListBoxRow)
 }
@@ -33,9 +38,18 @@ impl SimpleComponent for SearchPageModel {
             #[track(model.changed(SearchPageModel::searchitemtracker()))]
             set_vadjustment: gtk::Adjustment::NONE,
             adw::Clamp {
-                gtk::Stack {
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 15,
                     set_margin_all: 20,
                     #[local_ref]
+                    filterbox -> gtk::FlowBox {
+                        set_selection_mode: gtk::SelectionMode::None,
+                        set_column_spacing: 6,
+                        set_row_spacing: 6,
+                        set_max_children_per_line: 20,
+                    },
+                    #[local_ref]
                     searchlist -> gtk::ListBox {
                         set_valign: gtk::Align::Start,
                         add_css_class: "boxed-list",
@@ -57,10 +71,13 @@ impl SimpleComponent for SearchPageModel {
         let model = SearchPageModel {
             searchitems: FactoryVecDeque::new(gtk::ListBox::new(), sender.input_sender()),
             searchitemtracker: 0,
+            filterchips: FactoryVecDeque::new(gtk::FlowBox::new(), sender.input_sender()),
             tracker: 0,
         };
 
         let searchlist = model.searchitems.widget();
+        let filterbox = model.filterchips.widget();
+        sender.input(SearchPageMsg::SetFilters(vec![]));
 
         let widgets = view_output!();
 
@@ -79,6 +96,27 @@ impl SimpleComponent for SearchPageModel {
                 searchitem_guard.drop();
                 self.update_searchitemtracker(|_| ());
             }
+            SearchPageMsg::SetFilters(filters) => {
+                // Active filters first, followed by common ones that can be added with a click
+                let mut chips_guard = self.filterchips.guard();
+                chips_guard.clear();
+                for filter in &filters {
+                    chips_guard.push_back((filter.clone(), true));
+                }
+                for filter in [
+                    SearchFilter::Installed(true),
+                    SearchFilter::Gui,
+                    SearchFilter::Free(true),
+                ] {
+                    if !filters.contains(&filter) {
+                        chips_guard.push_back((filter, false));
+                    }
+                }
+                chips_guard.drop();
+            }
+            SearchPageMsg::ToggleFilter(filter) => {
+                sender.output(AppMsg::ToggleSearchFilter(filter));
+            }
             SearchPageMsg::OpenRow(row) => {
                 let searchitem_guard = self.searchitems.guard();
                 for (i, child) in searchitem_guard.widget().iter_children().enumerate() {
@@ -114,6 +152,7 @@ pub struct SearchItem {
     pub icon: Option<String>,
     pub installeduser: bool,
     pub installedsystem: bool,
+    pub source: Option<String>,
 }
 
 #[tracker::track]
@@ -145,34 +184,11 @@ impl FactoryComponent for SearchItemModel {
                 adw::Bin {
                     set_valign: gtk::Align::Center,
                     #[wrap(Some)]
-                    set_child = if self.item.icon.is_some() {
-                        gtk::Image {
-                            add_css_class: "icon-dropshadow",
-                            set_halign: gtk::Align::Start,
-                            set_from_file: {
-                                if let Some(i) = &self.item.icon {
-                                    let iconpath = format!("{}/icons/nixos/128x128/{}", APPINFO, i);
-                                    let iconpath64 = format!("{}/icons/nixos/64x64/{}", APPINFO, i);
-                                    if Path::new(&iconpath).is_file() {
-                                        Some(iconpath)
-                                    } else if Path::new(&iconpath64).is_file() {
-                                        Some(iconpath64)
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                }
-                            },
-                            set_pixel_size: 64,
-                        }
-                    } else {
-                        gtk::Image {
-                            add_css_class: "icon-dropshadow",
-                            set_halign: gtk::Align::Start,
-                            set_icon_name: Some("package-x-generic"),
-                            set_pixel_size: 64,
-                        }
+                    set_child = &gtk::Image {
+                        add_css_class: "icon-dropshadow",
+                        set_halign: gtk::Align::Start,
+                        set_from_gicon: &util::appgicon(self.item.icon.as_deref()),
+                        set_pixel_size: 64,
                     }
                 },
                 gtk::Overlay {
@@ -211,15 +227,25 @@ impl FactoryComponent for SearchItemModel {
                             set_wrap: true,
                             set_max_width_chars: 0,
                         },
-                        gtk::Label {
-                            set_halign: gtk::Align::Start,
-                            add_css_class: "dim-label",
-                            add_css_class: "caption",
-                            set_label: self.item.pkg.as_str(),
-                            set_ellipsize: pango::EllipsizeMode::End,
-                            set_lines: 1,
-                            set_wrap: true,
-                            set_max_width_chars: 0,
+                        gtk::Box {
+                            set_spacing: 5,
+                            gtk::Label {
+                                set_halign: gtk::Align::Start,
+                                add_css_class: "dim-label",
+                                add_css_class: "caption",
+                                set_label: self.item.pkg.as_str(),
+                                set_ellipsize: pango::EllipsizeMode::End,
+                                set_lines: 1,
+                                set_wrap: true,
+                                set_max_width_chars: 0,
+                            },
+                            gtk::Label {
+                                set_halign: gtk::Align::Start,
+                                add_css_class: "accent",
+                                add_css_class: "caption-heading",
+                                set_label: self.item.source.as_deref().unwrap_or(""),
+                                set_visible: self.item.source.is_some(),
+                            },
                         },
                         gtk::Label {
                             set_halign: gtk::Align::Start,
@@ -262,8 +288,64 @@ impl FactoryComponent for SearchItemModel {
             icon: parent.icon,
             installeduser: parent.installeduser,
             installedsystem: parent.installedsystem,
+            source: parent.source,
         };
 
         Self { item, tracker: 0 }
     }
 }
+
+#[derive(Debug)]
+pub struct SearchFilterChip {
+    filter: SearchFilter,
+    active: bool,
+}
+
+#[derive(Debug)]
+pub enum SearchFilterChipMsg {
+    Toggle(SearchFilter),
+}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for SearchFilterChip {
+    type CommandOutput = ();
+    type Init = (SearchFilter, bool);
+    type Input = ();
+    type Output = SearchFilterChipMsg;
+    type ParentWidget = gtk::FlowBox;
+    type ParentInput = SearchPageMsg;
+
+    view! {
+        gtk::FlowBoxChild {
+            gtk::Button {
+                set_css_classes: if self.active { &["pill", "suggested-action"] } else { &["pill"] },
+                gtk::Box {
+                    set_spacing: 6,
+                    gtk::Label {
+                        set_label: &self.filter.to_string(),
+                    },
+                    gtk::Image {
+                        set_icon_name: Some(if self.active { "window-close-symbolic" } else { "list-add-symbolic" }),
+                    }
+                },
+                connect_clicked[sender, filter = self.filter.clone()] => move |_| {
+                    sender.output(SearchFilterChipMsg::Toggle(filter.clone()));
+                }
+            }
+        }
+    }
+
+    fn init_model(
+        (filter, active): Self::Init,
+        _index: &DynamicIndex,
+        _sender: FactorySender<Self>,
+    ) -> Self {
+        Self { filter, active }
+    }
+
+    fn forward_to_parent(output: Self::Output) -> Option<SearchPageMsg> {
+        Some(match output {
+            SearchFilterChipMsg::Toggle(x) => SearchPageMsg::ToggleFilter(x),
+        })
+    }
+}
@@ -0,0 +1,435 @@
+use adw::prelude::*;
+use gettextrs::gettext;
+use gtk::pango;
+use log::*;
+use relm4::{factory::*, prelude::*, *};
+
+use super::pkgpage::{InstallType, PkgAction, PkgMsg, WorkPkg};
+
+#[tracker::track]
+#[derive(Debug)]
+pub struct TransactionModel {
+    hidden: bool,
+    applying: bool,
+    #[tracker::no_eq]
+    items: FactoryVecDeque<TransactionItemModel>,
+}
+
+#[derive(Debug)]
+pub enum TransactionMsg {
+    Show,
+    Close,
+    Add(WorkPkg),
+    Drop(WorkPkg),
+    Remove(DynamicIndex),
+    MoveUp(DynamicIndex),
+    MoveDown(DynamicIndex),
+    Clear,
+    Apply,
+    Finished(bool),
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionStatus {
+    Queued,
+    Applying,
+    Done,
+    Failed,
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for TransactionModel {
+    type Init = gtk::Window;
+    type Input = TransactionMsg;
+    type Output = PkgMsg;
+    type Widgets = TransactionWidgets;
+
+    view! {
+        dialog = adw::Window {
+            set_transient_for: Some(&parent_window),
+            set_modal: true,
+            set_default_width: 500,
+            set_default_height: 450,
+            #[watch]
+            set_visible: !model.hidden,
+            connect_close_request[sender] => move |_| {
+                sender.input(TransactionMsg::Close);
+                gtk::Inhibit(true)
+            },
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+                adw::HeaderBar {
+                    add_css_class: "flat",
+                    #[wrap(Some)]
+                    set_title_widget = &gtk::Label {
+                        add_css_class: "heading",
+                        set_label: &gettext("Pending System Changes"),
+                    },
+                },
+                gtk::ScrolledWindow {
+                    set_vexpand: true,
+                    set_hscrollbar_policy: gtk::PolicyType::Never,
+                    adw::Clamp {
+                        set_margin_all: 15,
+                        if model.items.is_empty() {
+                            adw::StatusPage {
+                                set_icon_name: Some("emblem-ok-symbolic"),
+                                set_title: &gettext("No Pending Changes"),
+                                set_description: Some(&gettext("System packages you install or remove are collected here and applied together with a single rebuild")),
+                            }
+                        } else {
+                            #[local_ref]
+                            transactionlist -> gtk::ListBox {
+                                set_valign: gtk::Align::Start,
+                                add_css_class: "boxed-list",
+                                set_selection_mode: gtk::SelectionMode::None,
+                            }
+                        }
+                    }
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Horizontal,
+                    set_halign: gtk::Align::End,
+                    set_spacing: 10,
+                    set_margin_all: 15,
+                    gtk::Button {
+                        set_label: &gettext("Clear"),
+                        #[watch]
+                        set_sensitive: !model.applying && !model.items.is_empty(),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(TransactionMsg::Clear);
+                        }
+                    },
+                    gtk::Button {
+                        add_css_class: "suggested-action",
+                        set_label: &gettext("Apply Changes"),
+                        #[watch]
+                        set_sensitive: !model.applying && model.items.iter().any(|x| x.status != TransactionStatus::Done),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(TransactionMsg::Apply);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        parent_window: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = TransactionModel {
+            hidden: true,
+            applying: false,
+            items: FactoryVecDeque::new(gtk::ListBox::new(), sender.input_sender()),
+            tracker: 0,
+        };
+
+        let transactionlist = model.items.widget();
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+        self.reset();
+        match msg {
+            TransactionMsg::Show => {
+                self.set_hidden(false);
+            }
+            TransactionMsg::Close => {
+                if !self.applying {
+                    self.removedone();
+                }
+                self.set_hidden(true);
+            }
+            TransactionMsg::Add(work) => {
+                self.removedone();
+                let mut items_guard = self.items.guard();
+                if items_guard
+                    .iter()
+                    .any(|x| x.work.pkg == work.pkg && x.work.action == work.action)
+                {
+                    return;
+                }
+                // Queuing the opposite action cancels out the pending one
+                if let Some(i) = items_guard
+                    .iter()
+                    .position(|x| x.work.pkg == work.pkg && x.status != TransactionStatus::Applying)
+                {
+                    if let Some(old) = items_guard.remove(i) {
+                        sender.output(PkgMsg::TransactionDropped(old.work));
+                    }
+                    items_guard.drop();
+                    self.sendpending(&sender);
+                    return;
+                }
+                info!("Adding {:?} {} to transaction", work.action, work.pkg);
+                items_guard.push_back(work);
+                items_guard.drop();
+                self.sendpending(&sender);
+            }
+            TransactionMsg::Drop(work) => {
+                let mut items_guard = self.items.guard();
+                if let Some(i) = items_guard
+                    .iter()
+                    .position(|x| x.work.pkg == work.pkg && x.status != TransactionStatus::Applying)
+                {
+                    if let Some(old) = items_guard.remove(i) {
+                        sender.output(PkgMsg::TransactionDropped(old.work));
+                    }
+                }
+                items_guard.drop();
+                self.sendpending(&sender);
+            }
+            TransactionMsg::Remove(index) => {
+                if self.applying {
+                    return;
+                }
+                let mut items_guard = self.items.guard();
+                if let Some(old) = items_guard.remove(index.current_index()) {
+                    sender.output(PkgMsg::TransactionDropped(old.work));
+                }
+                items_guard.drop();
+                self.sendpending(&sender);
+            }
+            TransactionMsg::MoveUp(index) => {
+                let i = index.current_index();
+                if !self.applying && i > 0 {
+                    self.items.guard().move_to(i, i - 1);
+                    self.sendpending(&sender);
+                }
+            }
+            TransactionMsg::MoveDown(index) => {
+                let i = index.current_index();
+                if !self.applying && i + 1 < self.items.len() {
+                    self.items.guard().move_to(i, i + 1);
+                    self.sendpending(&sender);
+                }
+            }
+            TransactionMsg::Clear => {
+                let mut items_guard = self.items.guard();
+                while let Some(old) = items_guard.pop_front() {
+                    if old.status != TransactionStatus::Done {
+                        sender.output(PkgMsg::TransactionDropped(old.work));
+                    }
+                }
+                items_guard.drop();
+                self.sendpending(&sender);
+            }
+            TransactionMsg::Apply => {
+                let mut work = vec![];
+                let mut items_guard = self.items.guard();
+                for i in 0..items_guard.len() {
+                    if let Some(item) = items_guard.get_mut(i) {
+                        if item.status != TransactionStatus::Done {
+                            item.status = TransactionStatus::Applying;
+                            work.push(item.work.clone());
+                        }
+                    }
+                }
+                items_guard.drop();
+                if work.is_empty() {
+                    return;
+                }
+                self.set_applying(true);
+                self.set_hidden(true);
+                sender.output(PkgMsg::ApplyTransaction(work));
+            }
+            TransactionMsg::Finished(success) => {
+                let mut items_guard = self.items.guard();
+                for i in 0..items_guard.len() {
+                    if let Some(item) = items_guard.get_mut(i) {
+                        if item.status == TransactionStatus::Applying {
+                            item.status = if success {
+                                TransactionStatus::Done
+                            } else {
+                                TransactionStatus::Failed
+                            };
+                        }
+                    }
+                }
+                items_guard.drop();
+                self.set_applying(false);
+                self.sendpending(&sender);
+            }
+            TransactionMsg::Cancelled => {
+                let mut items_guard = self.items.guard();
+                for i in 0..items_guard.len() {
+                    if let Some(item) = items_guard.get_mut(i) {
+                        if item.status == TransactionStatus::Applying {
+                            item.status = TransactionStatus::Queued;
+                        }
+                    }
+                }
+                items_guard.drop();
+                self.set_applying(false);
+            }
+        }
+    }
+}
+
+impl TransactionModel {
+    fn removedone(&mut self) {
+        let mut items_guard = self.items.guard();
+        let mut i = 0;
+        while i < items_guard.len() {
+            if items_guard
+                .get(i)
+                .map(|x| x.status == TransactionStatus::Done)
+                == Some(true)
+            {
+                items_guard.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn sendpending(&self, sender: &ComponentSender<Self>) {
+        sender.output(PkgMsg::SetTransaction(
+            self.items
+                .iter()
+                .filter(|x| x.status != TransactionStatus::Done)
+                .map(|x| x.work.clone())
+                .collect(),
+        ));
+    }
+}
+
+#[derive(Debug)]
+pub struct TransactionItemModel {
+    pub work: WorkPkg,
+    pub status: TransactionStatus,
+}
+
+#[derive(Debug)]
+pub enum TransactionItemMsg {
+    Remove(DynamicIndex),
+    MoveUp(DynamicIndex),
+    MoveDown(DynamicIndex),
+}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for TransactionItemModel {
+    type CommandOutput = ();
+    type Init = WorkPkg;
+    type Input = ();
+    type Output = TransactionItemMsg;
+    type ParentWidget = adw::gtk::ListBox;
+    type ParentInput = TransactionMsg;
+
+    view! {
+        adw::PreferencesRow {
+            set_activatable: false,
+            #[wrap(Some)]
+            set_child = &gtk::Box {
+                set_orientation: gtk::Orientation::Horizontal,
+                set_hexpand: true,
+                set_spacing: 10,
+                set_margin_all: 10,
+                gtk::Image {
+                    set_valign: gtk::Align::Center,
+                    set_icon_name: Some(match self.work.action {
+                        PkgAction::Install => "list-add-symbolic",
+                        PkgAction::Remove => "user-trash-symbolic",
+                    }),
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_halign: gtk::Align::Fill,
+                    set_valign: gtk::Align::Center,
+                    set_hexpand: true,
+                    set_spacing: 2,
+                    gtk::Label {
+                        set_halign: gtk::Align::Start,
+                        set_label: self.work.pkg.as_str(),
+                        set_ellipsize: pango::EllipsizeMode::End,
+                        set_lines: 1,
+                        set_wrap: true,
+                        set_max_width_chars: 0,
+                    },
+                    gtk::Label {
+                        set_halign: gtk::Align::Start,
+                        add_css_class: "dim-label",
+                        add_css_class: "caption",
+                        #[watch]
+                        set_label: &format!("{} · {}", match self.work.action {
+                            PkgAction::Install => gettext("Install"),
+                            PkgAction::Remove => gettext("Remove"),
+                        }, match self.status {
+                            TransactionStatus::Queued => gettext("Queued"),
+                            TransactionStatus::Applying => gettext("Applying"),
+                            TransactionStatus::Done => gettext("Done"),
+                            TransactionStatus::Failed => gettext("Failed"),
+                        }),
+                        set_ellipsize: pango::EllipsizeMode::End,
+                        set_lines: 1,
+                        set_wrap: true,
+                        set_max_width_chars: 0,
+                    },
+                },
+                gtk::Spinner {
+                    set_valign: gtk::Align::Center,
+                    #[watch]
+                    set_visible: self.status == TransactionStatus::Applying,
+                    #[watch]
+                    set_spinning: self.status == TransactionStatus::Applying,
+                },
+                gtk::Image {
+                    set_valign: gtk::Align::Center,
+                    add_css_class: "error",
+                    set_icon_name: Some("dialog-error-symbolic"),
+                    #[watch]
+                    set_visible: self.status == TransactionStatus::Failed,
+                },
+                gtk::Box {
+                    set_valign: gtk::Align::Center,
+                    add_css_class: "linked",
+                    #[watch]
+                    set_visible: self.status == TransactionStatus::Queued || self.status == TransactionStatus::Failed,
+                    gtk::Button {
+                        set_icon_name: "go-up-symbolic",
+                        set_can_focus: false,
+                        connect_clicked[sender, index] => move |_| {
+                            sender.output(TransactionItemMsg::MoveUp(index.clone()));
+                        }
+                    },
+                    gtk::Button {
+                        set_icon_name: "go-down-symbolic",
+                        set_can_focus: false,
+                        connect_clicked[sender, index] => move |_| {
+                            sender.output(TransactionItemMsg::MoveDown(index.clone()));
+                        }
+                    },
+                    gtk::Button {
+                        set_icon_name: "edit-delete-symbolic",
+                        set_can_focus: false,
+                        connect_clicked[sender, index] => move |_| {
+                            sender.output(TransactionItemMsg::Remove(index.clone()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn init_model(work: Self::Init, _index: &DynamicIndex, _sender: FactorySender<Self>) -> Self {
+        if work.pkgtype != InstallType::System {
+            warn!("Non-system package {} added to transaction", work.pkg);
+        }
+        Self {
+            work,
+            status: TransactionStatus::Queued,
+        }
+    }
+
+    fn forward_to_parent(output: Self::Output) -> Option<TransactionMsg> {
+        Some(match output {
+            TransactionItemMsg::Remove(x) => TransactionMsg::Remove(x),
+            TransactionItemMsg::MoveUp(x) => TransactionMsg::MoveUp(x),
+            TransactionItemMsg::MoveDown(x) => TransactionMsg::MoveDown(x),
+        })
+    }
+}
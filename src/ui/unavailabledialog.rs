@@ -1,10 +1,9 @@
-use std::path::Path;
-
+use gettextrs::{gettext, ngettext};
 use gtk::pango;
 use log::*;
 use relm4::{*, prelude::*, factory::*};
 use adw::prelude::*;
-use crate::{APPINFO, ui::{window::REBUILD_BROKER, rebuild::RebuildMsg}};
+use crate::{i18n, parse::util, ui::{window::REBUILD_BROKER, rebuild::RebuildMsg}};
 
 use super::updatepage::{UpdatePageMsg, UpdateType};
 
@@ -35,8 +34,16 @@ impl SimpleComponent for UnavailableDialogModel {
             set_visible: !model.hidden,
             set_transient_for: Some(&parent_window),
             set_modal: true,
-            set_heading: Some("Some packages are unavailable!"),
-            set_body: "If you continue this update, some packages will be removed",
+            set_heading: Some(&gettext("Some packages are unavailable!")),
+            #[watch]
+            set_body: &{
+                let n = model.unavailableuseritems.len() + model.unavailablesysitems.len();
+                i18n::fill(&ngettext(
+                    "If you continue this update, {} package will be removed",
+                    "If you continue this update, {} packages will be removed",
+                    n as u32,
+                ), &[&n.to_string()])
+            },
             #[wrap(Some)]
             set_extra_child = &gtk::Box {
                 set_orientation: gtk::Orientation::Vertical,
@@ -44,7 +51,7 @@ impl SimpleComponent for UnavailableDialogModel {
                 adw::PreferencesGroup {
                     #[watch]
                     set_visible: !model.unavailableuseritems.is_empty(),
-                    set_title: "User Packages",
+                    set_title: &gettext("User Packages"),
                     #[local_ref]
                     unavailableuserlist -> gtk::ListBox {
                         add_css_class: "boxed-list",
@@ -54,7 +61,7 @@ impl SimpleComponent for UnavailableDialogModel {
                 adw::PreferencesGroup {
                     #[watch]
                     set_visible: !model.unavailablesysitems.is_empty(),
-                    set_title: "System Packages",
+                    set_title: &gettext("System Packages"),
                     #[local_ref]
                     unavailablesyslist -> gtk::ListBox {
                         add_css_class: "boxed-list",
@@ -62,8 +69,8 @@ impl SimpleComponent for UnavailableDialogModel {
                     },
                 }
             },
-            add_response: ("cancel", "Cancel"),
-            add_response: ("continue", "Continue"),
+            add_response: ("cancel", &gettext("Cancel")),
+            add_response: ("continue", &gettext("Continue")),
             set_response_appearance: ("continue", adw::ResponseAppearance::Destructive),
             connect_close_request => |_| {
                 gtk::Inhibit(true)
@@ -180,34 +187,11 @@ impl FactoryComponent for UnavailableItemModel {
                 adw::Bin {
                     set_valign: gtk::Align::Center,
                     #[wrap(Some)]
-                    set_child = if self.icon.is_some() {
-                        gtk::Image {
-                            add_css_class: "icon-dropshadow",
-                            set_halign: gtk::Align::Start,
-                            set_from_file: {
-                                if let Some(i) = &self.icon {
-                                    let iconpath = format!("{}/icons/nixos/128x128/{}", APPINFO, i);
-                                    let iconpath64 = format!("{}/icons/nixos/64x64/{}", APPINFO, i);
-                                    if Path::new(&iconpath).is_file() {
-                                        Some(iconpath)
-                                    } else if Path::new(&iconpath64).is_file() {
-                                        Some(iconpath64)
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                }
-                            },
-                            set_pixel_size: 64,
-                        }
-                    } else {
-                        gtk::Image {
-                            add_css_class: "icon-dropshadow",
-                            set_halign: gtk::Align::Start,
-                            set_icon_name: Some("package-x-generic"),
-                            set_pixel_size: 64,
-                        }
+                    set_child = &gtk::Image {
+                        add_css_class: "icon-dropshadow",
+                        set_halign: gtk::Align::Start,
+                        set_from_gicon: &util::appgicon(self.icon.as_deref()),
+                        set_pixel_size: 64,
                     }
                 },
                 gtk::Box {
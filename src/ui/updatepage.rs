@@ -1,10 +1,12 @@
-use crate::{APPINFO, ui::unavailabledialog::UnavailableDialogModel, parse::util};
+use crate::{i18n, ui::unavailabledialog::UnavailableDialogModel, parse::util};
 
 use super::{pkgpage::InstallType, window::*, updateworker::{UpdateAsyncHandler, UpdateAsyncHandlerMsg, UpdateAsyncHandlerInit}, rebuild::RebuildMsg, unavailabledialog::UnavailableDialogMsg};
 use adw::prelude::*;
+use gettextrs::gettext;
 use nix_data::config::configfile::NixDataConfig;
+use nsc_core::holds;
 use relm4::{factory::*, gtk::pango, *};
-use std::{path::Path, convert::identity, collections::HashMap};
+use std::{convert::identity, collections::{HashMap, HashSet}};
 use log::*;
 
 pub static UNAVAILABLE_BROKER: MessageBroker<UnavailableDialogMsg> = MessageBroker::new();
@@ -26,6 +28,7 @@ pub struct UpdatePageModel {
     #[tracker::no_eq]
     unavailabledialog: Controller<UnavailableDialogModel>,
     online: bool,
+    selected: HashSet<String>,
 }
 
 #[derive(Debug)]
@@ -39,12 +42,17 @@ pub enum UpdatePageMsg {
     UpdateAllUser,
     UpdateAllUserRm(Vec<String>),
     UpdateUser(String),
+    UpdateSelectedUser,
+    SetSelected(String, bool),
+    UpdateSystemInputs,
+    SetHold(String, bool),
     // UpdateChannels,
     // UpdateSystemAndChannels,
     UpdateAll,
     UpdateAllRm(Vec<String>, Vec<String>),
     DoneWorking,
     FailedWorking,
+    CancelledWorking,
     UpdateOnline(bool),
 }
 
@@ -59,6 +67,7 @@ pub struct UpdatePageInit {
     pub window: gtk::Window,
     pub systype: SystemPkgs,
     pub usertype: UserPkgs,
+    pub system: String,
     pub config: NixDataConfig,
     pub online: bool,
 }
@@ -79,14 +88,14 @@ impl SimpleComponent for UpdatePageModel {
                 if !model.online {
                     adw::StatusPage {
                         set_icon_name: Some("nsc-network-offline-symbolic"),
-                        set_title: "No internet connection",
-                        set_description: Some("Please connect to the internet to update your system"),
+                        set_title: &gettext("No internet connection"),
+                        set_description: Some(&gettext("Please connect to the internet to update your system")),
                         gtk::Button {
                             add_css_class: "pill",
                             set_halign: gtk::Align::Center,
                             adw::ButtonContent {
                                 set_icon_name: "nsc-refresh-symbolic",
-                                set_label: "Refresh",
+                                set_label: &gettext("Refresh"),
                             },
                             connect_clicked[sender] => move |_| {
                                 sender.output(AppMsg::CheckNetwork);
@@ -105,14 +114,14 @@ impl SimpleComponent for UpdatePageModel {
                             gtk::Label {
                                 set_halign: gtk::Align::Start,
                                 add_css_class: "title-2",
-                                set_label: "Updates",
+                                set_label: &gettext("Updates"),
                             },
                             gtk::Button {
                                 add_css_class: "suggested-action",
                                 set_halign: gtk::Align::End,
                                 set_valign: gtk::Align::Center,
                                 set_hexpand: true,
-                                set_label: "Update Everything",
+                                set_label: &gettext("Update Everything"),
                                 connect_clicked[sender] => move |_| {
                                     sender.input(UpdatePageMsg::UpdateAll);
                                 }
@@ -126,19 +135,29 @@ impl SimpleComponent for UpdatePageModel {
                             gtk::Label {
                                 set_halign: gtk::Align::Start,
                                 add_css_class: "title-4",
-                                set_label: match model.usertype {
-                                    UserPkgs::Env => "User (nix-env)",
-                                    UserPkgs::Profile => "User (nix profile)",
-                                }
+                                set_label: &userpkgslabel(&model.usertype),
                             },
-                            gtk::Button {
-                                add_css_class: "suggested-action",
+                            gtk::Box {
+                                set_orientation: gtk::Orientation::Horizontal,
                                 set_halign: gtk::Align::End,
                                 set_valign: gtk::Align::Center,
                                 set_hexpand: true,
-                                set_label: "Update All",
-                                connect_clicked[sender] => move |_| {
-                                    sender.input(UpdatePageMsg::UpdateAllUser);
+                                set_spacing: 10,
+                                gtk::Button {
+                                    #[watch]
+                                    set_visible: !model.selected.is_empty(),
+                                    #[watch]
+                                    set_label: &i18n::fill(&gettext("Update Selected ({})"), &[&model.selected.len().to_string()]),
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(UpdatePageMsg::UpdateSelectedUser);
+                                    }
+                                },
+                                gtk::Button {
+                                    add_css_class: "suggested-action",
+                                    set_label: &gettext("Update All"),
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(UpdatePageMsg::UpdateAllUser);
+                                    }
                                 }
                             }
                         },
@@ -163,17 +182,30 @@ impl SimpleComponent for UpdatePageModel {
                             gtk::Label {
                                 set_halign: gtk::Align::Start,
                                 add_css_class: "title-4",
-                                set_label: "System (configuration.nix)",
+                                set_label: &systempkgslabel(),
                             },
-                            gtk::Button {
-                                add_css_class: "suggested-action",
+                            gtk::Box {
+                                set_orientation: gtk::Orientation::Horizontal,
                                 set_halign: gtk::Align::End,
-                                set_hexpand: true,
                                 set_valign: gtk::Align::Center,
-                                set_label: "Update",
-                                connect_clicked[sender] => move |_|{
-                                    sender.input(UpdatePageMsg::UpdateSystem);
+                                set_hexpand: true,
+                                set_spacing: 10,
+                                gtk::Button {
+                                    #[watch]
+                                    set_visible: model.systype == SystemPkgs::Flake,
+                                    set_label: &gettext("Update Nixpkgs Only"),
+                                    set_tooltip_text: Some(&gettext("Leave the other flake inputs at their locked versions")),
+                                    connect_clicked[sender] => move |_|{
+                                        sender.input(UpdatePageMsg::UpdateSystemInputs);
+                                    },
                                 },
+                                gtk::Button {
+                                    add_css_class: "suggested-action",
+                                    set_label: &gettext("Update"),
+                                    connect_clicked[sender] => move |_|{
+                                        sender.input(UpdatePageMsg::UpdateSystem);
+                                    },
+                                }
                             }
                         },
                         #[local_ref]
@@ -205,7 +237,7 @@ impl SimpleComponent for UpdatePageModel {
                         },
                         gtk::Label {
                             add_css_class: "title-1",
-                            set_label: "Everything is up to date!"
+                            set_label: &gettext("Everything is up to date!")
                         }
                     }
                 }
@@ -219,7 +251,7 @@ impl SimpleComponent for UpdatePageModel {
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
         let updateworker = UpdateAsyncHandler::builder()
-            .detach_worker(UpdateAsyncHandlerInit { syspkgs: initparams.systype.clone(), userpkgs: initparams.usertype.clone() })
+            .detach_worker(UpdateAsyncHandlerInit { syspkgs: initparams.systype.clone(), userpkgs: initparams.usertype.clone(), system: initparams.system.clone() })
             .forward(sender.input_sender(), identity);
 
         let unavailabledialog = UnavailableDialogModel::builder()
@@ -240,6 +272,7 @@ impl SimpleComponent for UpdatePageModel {
             usertype: initparams.usertype,
             unavailabledialog,
             online: initparams.online,
+            selected: HashSet::new(),
             tracker: 0,
         };
 
@@ -270,6 +303,8 @@ impl SimpleComponent for UpdatePageModel {
                 debug!("UPDATEUSERLIST: {:?}", updateuserlist);
                 debug!("UPDATESYSTEMLIST: {:?}", updatesystemlist);
                 self.update_updatetracker(|_| ());
+                // The rows are rebuilt unchecked
+                self.selected.clear();
                 let mut updateuserlist_guard = self.updateuserlist.guard();
                 updateuserlist_guard.clear();
                 for updateuser in updateuserlist {
@@ -334,8 +369,58 @@ impl SimpleComponent for UpdatePageModel {
                 self.updateworker.emit(UpdateAsyncHandlerMsg::UpdateSystemRemove(pkgs));
             }
             UpdatePageMsg::UpdateUser(pkg) => {
-                info!("UPDATE USER PKG: {}", pkg);
-                warn!("unimplemented");
+                info!("UpdatePageMsg::UpdateUser({})", pkg);
+                let online = util::checkonline();
+                if !online {
+                    sender.output(AppMsg::CheckNetwork);
+                    self.online = false;
+                    return;
+                }
+                REBUILD_BROKER.send(RebuildMsg::Show);
+                self.updateworker.emit(UpdateAsyncHandlerMsg::UpdateUserPkgsOnly(vec![pkg]));
+            }
+            UpdatePageMsg::UpdateSelectedUser => {
+                let mut pkgs = self.selected.iter().cloned().collect::<Vec<_>>();
+                pkgs.sort();
+                info!("UpdatePageMsg::UpdateSelectedUser({:?})", pkgs);
+                let online = util::checkonline();
+                if !online {
+                    sender.output(AppMsg::CheckNetwork);
+                    self.online = false;
+                    return;
+                }
+                REBUILD_BROKER.send(RebuildMsg::Show);
+                self.updateworker.emit(UpdateAsyncHandlerMsg::UpdateUserPkgsOnly(pkgs));
+            }
+            UpdatePageMsg::SetSelected(pkg, selected) => {
+                if selected {
+                    self.selected.insert(pkg);
+                } else {
+                    self.selected.remove(&pkg);
+                }
+            }
+            UpdatePageMsg::UpdateSystemInputs => {
+                info!("UpdatePageMsg::UpdateSystemInputs");
+                let online = util::checkonline();
+                if !online {
+                    sender.output(AppMsg::CheckNetwork);
+                    self.online = false;
+                    return;
+                }
+                REBUILD_BROKER.send(RebuildMsg::Show);
+                // Channel systems can only update everything at once
+                if self.systype == SystemPkgs::Flake {
+                    self.updateworker.emit(UpdateAsyncHandlerMsg::UpdateSystemInputs);
+                } else {
+                    self.updateworker.emit(UpdateAsyncHandlerMsg::UpdateSystem);
+                }
+            }
+            UpdatePageMsg::SetHold(pkg, hold) => {
+                info!("UpdatePageMsg::SetHold({}, {})", pkg, hold);
+                if let Err(e) = holds::sethold(&pkg, hold) {
+                    warn!("Failed to change hold on {}: {}", pkg, e);
+                }
+                sender.output(AppMsg::UpdateInstalledPage);
             }
             UpdatePageMsg::UpdateAllUser => {
                 let online = util::checkonline();
@@ -416,6 +501,9 @@ impl SimpleComponent for UpdatePageModel {
             UpdatePageMsg::FailedWorking => {
                 REBUILD_BROKER.send(RebuildMsg::FinishError(None));
             }
+            UpdatePageMsg::CancelledWorking => {
+                REBUILD_BROKER.send(RebuildMsg::FinishCancelled);
+            }
             UpdatePageMsg::UpdateOnline(online) => {
                 self.set_online(online);
             }
@@ -433,6 +521,9 @@ pub struct UpdateItem {
     pub pkgtype: InstallType,
     pub verfrom: Option<String>,
     pub verto: Option<String>,
+    // What a user package is held and updated by on its own
+    pub key: Option<String>,
+    pub held: bool,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -441,7 +532,12 @@ pub struct UpdateItemModel {
 }
 
 #[derive(Debug)]
-pub enum UpdateItemMsg {}
+pub enum UpdateItemMsg {
+    SetHold(String, bool),
+    SetSelected(String, bool),
+    UpdateUser(String),
+    UpdateSystem,
+}
 
 #[relm4::factory(pub)]
 impl FactoryComponent for UpdateItemModel {
@@ -462,37 +558,25 @@ impl FactoryComponent for UpdateItemModel {
                 set_hexpand: true,
                 set_spacing: 10,
                 set_margin_all: 10,
+                gtk::CheckButton {
+                    set_valign: gtk::Align::Center,
+                    set_can_focus: false,
+                    set_visible: self.item.key.is_some() && !self.item.held,
+                    set_tooltip_text: Some(&gettext("Select for updating")),
+                    connect_toggled[sender, key = self.item.key.clone()] => move |x| {
+                        if let Some(pkg) = &key {
+                            sender.output(UpdateItemMsg::SetSelected(pkg.to_string(), x.is_active()));
+                        }
+                    }
+                },
                 adw::Bin {
                     set_valign: gtk::Align::Center,
                     #[wrap(Some)]
-                    set_child = if self.item.icon.is_some() {
-                        gtk::Image {
-                            add_css_class: "icon-dropshadow",
-                            set_halign: gtk::Align::Start,
-                            set_from_file: {
-                                if let Some(i) = &self.item.icon {
-                                    let iconpath = format!("{}/icons/nixos/128x128/{}", APPINFO, i);
-                                    let iconpath64 = format!("{}/icons/nixos/64x64/{}", APPINFO, i);
-                                    if Path::new(&iconpath).is_file() {
-                                        Some(iconpath)
-                                    } else if Path::new(&iconpath64).is_file() {
-                                        Some(iconpath64)
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                }
-                            },
-                            set_pixel_size: 64,
-                        }
-                    } else {
-                        gtk::Image {
-                            add_css_class: "icon-dropshadow",
-                            set_halign: gtk::Align::Start,
-                            set_icon_name: Some("package-x-generic"),
-                            set_pixel_size: 64,
-                        }
+                    set_child = &gtk::Image {
+                        add_css_class: "icon-dropshadow",
+                        set_halign: gtk::Align::Start,
+                        set_from_gicon: &util::appgicon(self.item.icon.as_deref()),
+                        set_pixel_size: 64,
                     }
                 },
                 gtk::Box {
@@ -514,17 +598,14 @@ impl FactoryComponent for UpdateItemModel {
                         add_css_class: "dim-label",
                         add_css_class: "caption",
                         set_label: {
-                            &(if let Some(old) = &self.item.verfrom {
-                                if let Some(new) = &self.item.verto {
-                                    format!("{} → {}", old, new)
-                                } else {
-                                    String::default()
-                                }
-                            } else {
-                                String::default()
+                            &(match (&self.item.verfrom, &self.item.verto) {
+                                (Some(old), Some(new)) if self.item.held && old != new => i18n::fill(&gettext("Held at {}, {} available"), &[old.as_str(), new.as_str()]),
+                                (Some(old), _) if self.item.held => i18n::fill(&gettext("Held at {}"), &[old.as_str()]),
+                                (Some(old), Some(new)) => format!("{} → {}", old, new),
+                                _ => String::default(),
                             })
                         },
-                        set_visible: self.item.verfrom.is_some() && self.item.verto.is_some(),
+                        set_visible: self.item.verfrom.is_some() && (self.item.held || self.item.verto.is_some()),
                         set_ellipsize: pango::EllipsizeMode::End,
                         set_lines: 1,
                         set_wrap: true,
@@ -540,13 +621,33 @@ impl FactoryComponent for UpdateItemModel {
                         set_max_width_chars: 0,
                     },
                 },
-                // gtk::Button {
-                //     set_visible: self.item.pkgtype == InstallType::User,
-                //     set_valign: gtk::Align::Center,
-                //     set_halign: gtk::Align::End,
-                //     set_label: "Update",
-                //     set_can_focus: false,
-                // }
+                gtk::Button {
+                    set_valign: gtk::Align::Center,
+                    set_halign: gtk::Align::End,
+                    set_can_focus: false,
+                    set_visible: self.item.key.is_some(),
+                    set_label: &if self.item.held { gettext("Release") } else { gettext("Hold") },
+                    set_tooltip_text: Some(&if self.item.held { gettext("Include in updates again") } else { gettext("Keep the installed version when updating") }),
+                    connect_clicked[sender, key = self.item.key.clone(), held = self.item.held] => move |_| {
+                        if let Some(pkg) = &key {
+                            sender.output(UpdateItemMsg::SetHold(pkg.to_string(), !held));
+                        }
+                    }
+                },
+                gtk::Button {
+                    set_visible: !self.item.held && (self.item.key.is_some() || self.item.pkgtype == InstallType::System),
+                    set_valign: gtk::Align::Center,
+                    set_halign: gtk::Align::End,
+                    set_label: &gettext("Update"),
+                    set_can_focus: false,
+                    set_tooltip_text: Some(&if self.item.pkgtype == InstallType::System { gettext("Update the packages this comes from and rebuild") } else { gettext("Update only this package") }),
+                    connect_clicked[sender, key = self.item.key.clone()] => move |_| {
+                        match &key {
+                            Some(pkg) => sender.output(UpdateItemMsg::UpdateUser(pkg.to_string())),
+                            None => sender.output(UpdateItemMsg::UpdateSystem),
+                        }
+                    }
+                }
             }
         }
     }
@@ -578,8 +679,19 @@ impl FactoryComponent for UpdateItemModel {
             pkgtype: parent.pkgtype,
             verfrom: parent.verfrom,
             verto: parent.verto,
+            key: parent.key,
+            held: parent.held,
         };
 
         Self { item }
     }
+
+    fn forward_to_parent(output: Self::Output) -> Option<UpdatePageMsg> {
+        Some(match output {
+            UpdateItemMsg::SetHold(pkg, hold) => UpdatePageMsg::SetHold(pkg, hold),
+            UpdateItemMsg::SetSelected(pkg, selected) => UpdatePageMsg::SetSelected(pkg, selected),
+            UpdateItemMsg::UpdateUser(pkg) => UpdatePageMsg::UpdateUser(pkg),
+            UpdateItemMsg::UpdateSystem => UpdatePageMsg::UpdateSystemInputs,
+        })
+    }
 }
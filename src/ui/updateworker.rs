@@ -1,11 +1,12 @@
-use anyhow::{anyhow, Result};
 use log::*;
 use nix_data::config::configfile::NixDataConfig;
+use nsc_core::Updater;
 use relm4::*;
-use std::{fs, path::Path, process::Stdio};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
 
-use crate::ui::{rebuild::RebuildMsg, window::REBUILD_BROKER};
+use crate::ui::{
+    rebuild::{review, RebuildMsg},
+    window::REBUILD_BROKER,
+};
 
 use super::{
     updatepage::UpdatePageMsg,
@@ -20,6 +21,7 @@ pub struct UpdateAsyncHandler {
     config: NixDataConfig,
     syspkgs: SystemPkgs,
     userpkgs: UserPkgs,
+    system: String,
 }
 
 #[derive(Debug)]
@@ -31,24 +33,21 @@ pub enum UpdateAsyncHandlerMsg {
     // UpdateChannelsAndSystem,
     UpdateSystem,
     UpdateSystemRemove(Vec<String>),
+    UpdateSystemInputs,
 
     RebuildSystem,
     UpdateUserPkgs,
     UpdateUserPkgsRemove(Vec<String>),
+    UpdateUserPkgsOnly(Vec<String>),
 
     UpdateAll,
     UpdateAllRemove(Vec<String>, Vec<String>),
 }
 
-enum NscCmd {
-    Rebuild,
-    Channel,
-    All,
-}
-
 pub struct UpdateAsyncHandlerInit {
     pub syspkgs: SystemPkgs,
     pub userpkgs: UserPkgs,
+    pub system: String,
 }
 
 impl Worker for UpdateAsyncHandler {
@@ -67,6 +66,7 @@ impl Worker for UpdateAsyncHandler {
             },
             syspkgs: params.syspkgs,
             userpkgs: params.userpkgs,
+            system: params.system,
             tracker: 0,
         }
     }
@@ -81,14 +81,16 @@ impl Worker for UpdateAsyncHandler {
                 self.userpkgs = userpkgs;
             }
             UpdateAsyncHandlerMsg::UpdateSystem => {
-                let config = self.config.clone();
-                let syspkgs = self.syspkgs.clone();
+                let updater = self.updater();
                 relm4::spawn(async move {
-                    let result = runcmd(NscCmd::All, config, syspkgs, None).await;
-                    match result {
+                    cancellable(&updater);
+                    match updater.updatesystem(None, rebuildoutput).await {
                         Ok(true) => {
                             sender.output(UpdatePageMsg::DoneWorking);
                         }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
+                        }
                         _ => {
                             warn!("UPDATE SYSTEM FAILED");
                             sender.output(UpdatePageMsg::FailedWorking);
@@ -97,15 +99,25 @@ impl Worker for UpdateAsyncHandler {
                 });
             }
             UpdateAsyncHandlerMsg::UpdateSystemRemove(pkgs) => {
-                let config = self.config.clone();
-                let syspkgs = self.syspkgs.clone();
+                let updater = self.updater();
                 relm4::spawn(async move {
-                    let result =
-                        runcmd(NscCmd::All, config, syspkgs, Some(pkgs)).await;
-                    match result {
+                    let pkgs = Some(pkgs);
+                    match reviewsystem(&updater, &pkgs).await {
+                        Ok(true) => {}
+                        Ok(false) => return,
+                        Err(_) => {
+                            sender.output(UpdatePageMsg::FailedWorking);
+                            return;
+                        }
+                    }
+                    cancellable(&updater);
+                    match updater.updatesystem(pkgs, rebuildoutput).await {
                         Ok(true) => {
                             sender.output(UpdatePageMsg::DoneWorking);
                         }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
+                        }
                         _ => {
                             warn!("UPDATE SYSTEM FAILED");
                             sender.output(UpdatePageMsg::FailedWorking);
@@ -113,23 +125,43 @@ impl Worker for UpdateAsyncHandler {
                     }
                 });
             }
-            UpdateAsyncHandlerMsg::RebuildSystem => {
-                let config = self.config.clone();
-                let syspkgs = self.syspkgs.clone();
+            UpdateAsyncHandlerMsg::UpdateSystemInputs => {
+                let updater = self.updater();
                 relm4::spawn(async move {
-                    let result = match syspkgs {
-                        SystemPkgs::Legacy => {
-                            runcmd(NscCmd::Rebuild, config, syspkgs, None).await
-                        }
-                        SystemPkgs::Flake => {
-                            runcmd(NscCmd::All, config, syspkgs, None).await
+                    let inputs = match updater.nixpkgsinputs() {
+                        Ok(inputs) => inputs,
+                        Err(e) => {
+                            warn!("Failed to find the nixpkgs input: {}", e);
+                            sender.output(UpdatePageMsg::FailedWorking);
+                            return;
                         }
-                        SystemPkgs::None => Ok(true),
                     };
-                    match result {
+                    cancellable(&updater);
+                    match updater.updatesysteminputs(inputs, rebuildoutput).await {
                         Ok(true) => {
                             sender.output(UpdatePageMsg::DoneWorking);
                         }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
+                        }
+                        _ => {
+                            warn!("UPDATE SYSTEM INPUTS FAILED");
+                            sender.output(UpdatePageMsg::FailedWorking);
+                        }
+                    }
+                });
+            }
+            UpdateAsyncHandlerMsg::RebuildSystem => {
+                let updater = self.updater();
+                relm4::spawn(async move {
+                    cancellable(&updater);
+                    match updater.rebuildsystem(rebuildoutput).await {
+                        Ok(true) => {
+                            sender.output(UpdatePageMsg::DoneWorking);
+                        }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
+                        }
                         _ => {
                             warn!("REBUILD FAILED");
                             sender.output(UpdatePageMsg::FailedWorking);
@@ -138,16 +170,16 @@ impl Worker for UpdateAsyncHandler {
                 });
             }
             UpdateAsyncHandlerMsg::UpdateUserPkgs => {
-                let userpkgs = self.userpkgs.clone();
+                let updater = self.updater();
                 relm4::spawn(async move {
-                    let result = match userpkgs {
-                        UserPkgs::Env => updateenv().await,
-                        UserPkgs::Profile => updateprofile(None).await,
-                    };
-                    match result {
+                    cancellable(&updater);
+                    match updater.updateuser(None, rebuildoutput).await {
                         Ok(true) => {
                             sender.output(UpdatePageMsg::DoneWorking);
                         }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
+                        }
                         _ => {
                             warn!("UPDATE USER FAILED");
                             sender.output(UpdatePageMsg::FailedWorking);
@@ -156,16 +188,16 @@ impl Worker for UpdateAsyncHandler {
                 });
             }
             UpdateAsyncHandlerMsg::UpdateUserPkgsRemove(pkgs) => {
-                let userpkgs = self.userpkgs.clone();
+                let updater = self.updater();
                 relm4::spawn(async move {
-                    let result = match userpkgs {
-                        UserPkgs::Env => updateenv().await,
-                        UserPkgs::Profile => updateprofile(Some(pkgs)).await,
-                    };
-                    match result {
+                    cancellable(&updater);
+                    match updater.updateuser(Some(pkgs), rebuildoutput).await {
                         Ok(true) => {
                             sender.output(UpdatePageMsg::DoneWorking);
                         }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
+                        }
                         _ => {
                             warn!("UPDATE USER FAILED");
                             sender.output(UpdatePageMsg::FailedWorking);
@@ -173,26 +205,34 @@ impl Worker for UpdateAsyncHandler {
                     }
                 });
             }
+            UpdateAsyncHandlerMsg::UpdateUserPkgsOnly(pkgs) => {
+                let updater = self.updater();
+                relm4::spawn(async move {
+                    cancellable(&updater);
+                    match updater.updateuserpkgs(pkgs, rebuildoutput).await {
+                        Ok(true) => {
+                            sender.output(UpdatePageMsg::DoneWorking);
+                        }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
+                        }
+                        _ => {
+                            warn!("UPDATE USER PACKAGES FAILED");
+                            sender.output(UpdatePageMsg::FailedWorking);
+                        }
+                    }
+                });
+            }
             UpdateAsyncHandlerMsg::UpdateAll => {
-                let config = self.config.clone();
-                let syspkgs = self.syspkgs.clone();
-                let userpkgs = self.userpkgs.clone();
+                let updater = self.updater();
                 relm4::spawn(async move {
-                    let result = runcmd(NscCmd::All, config, syspkgs, None).await;
-                    match result {
+                    cancellable(&updater);
+                    match updater.updateall(None, None, rebuildoutput).await {
                         Ok(true) => {
-                            match match userpkgs {
-                                UserPkgs::Env => updateenv().await,
-                                UserPkgs::Profile => updateprofile(None).await,
-                            } {
-                                Ok(true) => {
-                                    sender.output(UpdatePageMsg::DoneWorking);
-                                }
-                                _ => {
-                                    warn!("UPDATE ALL FAILED");
-                                    sender.output(UpdatePageMsg::FailedWorking);
-                                }
-                            }
+                            sender.output(UpdatePageMsg::DoneWorking);
+                        }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
                         }
                         _ => {
                             warn!("UPDATE ALL FAILED");
@@ -202,31 +242,24 @@ impl Worker for UpdateAsyncHandler {
                 });
             }
             UpdateAsyncHandlerMsg::UpdateAllRemove(userrmpkgs, sysrmpkgs) => {
-                let config = self.config.clone();
-                let syspkgs = self.syspkgs.clone();
-                let userpkgs = self.userpkgs.clone();
+                let updater = self.updater();
                 relm4::spawn(async move {
-                    let result = runcmd(
-                        NscCmd::All,
-                        config,
-                        syspkgs,
-                        Some(sysrmpkgs),
-                    )
-                    .await;
-                    match result {
+                    let sysrmpkgs = Some(sysrmpkgs);
+                    match reviewsystem(&updater, &sysrmpkgs).await {
+                        Ok(true) => {}
+                        Ok(false) => return,
+                        Err(_) => {
+                            sender.output(UpdatePageMsg::FailedWorking);
+                            return;
+                        }
+                    }
+                    cancellable(&updater);
+                    match updater.updateall(Some(userrmpkgs), sysrmpkgs, rebuildoutput).await {
                         Ok(true) => {
-                            match match userpkgs {
-                                UserPkgs::Env => updateenv().await,
-                                UserPkgs::Profile => updateprofile(Some(userrmpkgs)).await,
-                            } {
-                                Ok(true) => {
-                                    sender.output(UpdatePageMsg::DoneWorking);
-                                }
-                                _ => {
-                                    warn!("UPDATE ALL FAILED");
-                                    sender.output(UpdatePageMsg::FailedWorking);
-                                }
-                            }
+                            sender.output(UpdatePageMsg::DoneWorking);
+                        }
+                        _ if updater.canceller().cancelled() => {
+                            sender.output(UpdatePageMsg::CancelledWorking);
                         }
                         _ => {
                             warn!("UPDATE ALL FAILED");
@@ -239,264 +272,38 @@ impl Worker for UpdateAsyncHandler {
     }
 }
 
-async fn runcmd(
-    cmd: NscCmd,
-    config: NixDataConfig,
-    syspkgs: SystemPkgs,
-    rmpkgs: Option<Vec<String>>,
-) -> Result<bool> {
-    let systemconfig = config.systemconfig.unwrap_or_default();
-    let flakeargs = if let Some(flake) = config.flake {
-        if let Some(flakearg) = config.flakearg {
-            Some(format!("{}#{}", flake, flakearg))
-        } else {
-            Some(flake)
-        }
-    } else {
-        None
-    };
-    let f = fs::read_to_string(&systemconfig)?;
-    let exe = match std::env::current_exe() {
-        Ok(mut e) => {
-            e.pop(); // root/bin
-            e.pop(); // root/
-            e.push("libexec"); // root/libexec
-            e.push("nsc-helper");
-            let x = e.to_string_lossy().to_string();
-            info!("nsc-helper path: {}", x);
-            if Path::new(&x).is_file() {
-                x
-            } else {
-                String::from("nsc-helper")
-            }
-        }
-        Err(_) => String::from("nsc-helper"),
-    };
-
-    let flakepathsplit = flakeargs.clone().unwrap_or_default().to_string();
-    let flakepath = flakepathsplit
-        .split('#')
-        .collect::<Vec<&str>>()
-        .first()
-        .cloned()
-        .unwrap_or_default();
-
-    let rebuildargs = if let Some(x) = flakeargs {
-        let mut v = vec![String::from("--flake")];
-        for arg in x.split(' ') {
-            if !arg.is_empty() {
-                v.push(String::from(arg));
-            }
-        }
-        v
-    } else {
-        vec![]
-    };
-
-    let mut cmd = match cmd {
-        NscCmd::Rebuild => tokio::process::Command::new("pkexec")
-            .arg(&exe)
-            .arg("rebuild")
-            .arg("--generations")
-            .arg(config.generations.unwrap_or(0).to_string())
-            .arg("--")
-            .arg("switch")
-            .args(&rebuildargs)
-            .stderr(Stdio::piped())
-            .spawn()?,
-        NscCmd::Channel => tokio::process::Command::new("pkexec")
-            .arg(&exe)
-            .arg("channel")
-            .arg("--output")
-            .arg(&systemconfig)
-            .stderr(Stdio::piped())
-            .spawn()?,
-        NscCmd::All => match syspkgs {
-            SystemPkgs::Legacy => {
-                if let Some(rmpkgs) = rmpkgs {
-                    let newconfig =
-                        match nix_editor::write::rmarr(&f, "environment.systemPackages", rmpkgs) {
-                            Ok(x) => x,
-                            Err(_) => {
-                                return Err(anyhow!("Failed to write configuration.nix"));
-                            }
-                        };
-                    let mut cmd = tokio::process::Command::new("pkexec")
-                        .arg(&exe)
-                        .arg("channel")
-                        .arg("--rebuild")
-                        .arg("--update")
-                        .arg("--generations")
-                        .arg(config.generations.unwrap_or(0).to_string())
-                        .arg("--output")
-                        .arg(&systemconfig)
-                        .arg("--")
-                        .arg("switch")
-                        .args(&rebuildargs)
-                        .stderr(Stdio::piped())
-                        .stdin(Stdio::piped())
-                        .spawn()?;
-                    cmd.stdin
-                        .take()
-                        .unwrap()
-                        .write_all(newconfig.as_bytes())
-                        .await?;
-                    cmd
-                } else {
-                    tokio::process::Command::new("pkexec")
-                        .arg(&exe)
-                        .arg("channel")
-                        .arg("--rebuild")
-                        .arg("--generations")
-                        .arg(config.generations.unwrap_or(0).to_string())
-                        .arg("--")
-                        .arg("switch")
-                        .args(&rebuildargs)
-                        .stderr(Stdio::piped())
-                        .spawn()?
-                }
-            }
-            SystemPkgs::Flake => {
-                if let Some(rmpkgs) = rmpkgs {
-                    let newconfig =
-                        match nix_editor::write::rmarr(&f, "environment.systemPackages", rmpkgs) {
-                            Ok(x) => x,
-                            Err(_) => {
-                                return Err(anyhow!("Failed to write configuration.nix"));
-                            }
-                        };
-                    let mut cmd = tokio::process::Command::new("pkexec")
-                        .arg(&exe)
-                        .arg("flake")
-                        .arg("--rebuild")
-                        .arg("--flakepath")
-                        .arg(&flakepath)
-                        .arg("--update")
-                        .arg("--generations")
-                        .arg(config.generations.unwrap_or(0).to_string())
-                        .arg("--output")
-                        .arg(&systemconfig)
-                        .arg("--")
-                        .arg("switch")
-                        .arg("--impure")
-                        .args(&rebuildargs)
-                        .stderr(Stdio::piped())
-                        .stdin(Stdio::piped())
-                        .spawn()?;
-                    cmd.stdin
-                        .take()
-                        .unwrap()
-                        .write_all(newconfig.as_bytes())
-                        .await?;
-                    cmd
-                } else {
-                    tokio::process::Command::new("pkexec")
-                        .arg(&exe)
-                        .arg("flake")
-                        .arg("--rebuild")
-                        .arg("--flakepath")
-                        .arg(&flakepath)
-                        .arg("--generations")
-                        .arg(config.generations.unwrap_or(0).to_string())
-                        .arg("--output")
-                        .arg(&systemconfig)
-                        .arg("--")
-                        .arg("switch")
-                        .arg("--impure")
-                        .args(&rebuildargs)
-                        .stderr(Stdio::piped())
-                        .spawn()?
-                }
-            }
-            SystemPkgs::None => return Ok(true),
-        },
-    };
-
-    let stderr = cmd.stderr.take().unwrap();
-    let reader = tokio::io::BufReader::new(stderr);
-
-    let mut lines = reader.lines();
-    while let Ok(Some(line)) = lines.next_line().await {
-        REBUILD_BROKER.send(RebuildMsg::UpdateText(line.to_string()));
-        trace!("CAUGHT REBUILD LINE: {}", line);
-    }
-    if cmd.wait().await?.success() {
-        Ok(true)
-    } else {
-        Ok(false)
+impl UpdateAsyncHandler {
+    fn updater(&self) -> Updater {
+        Updater::new(
+            self.config.clone(),
+            self.syspkgs.clone(),
+            self.userpkgs.clone(),
+            self.system.clone(),
+        )
     }
 }
 
-async fn updateenv() -> Result<bool> {
-    let mut cmd = tokio::process::Command::new("nix-env")
-        .arg("-u")
-        .stderr(Stdio::piped())
-        .spawn()?;
-
-    let stderr = cmd.stderr.take().unwrap();
-    let reader = tokio::io::BufReader::new(stderr);
-
-    let mut lines = reader.lines();
-    while let Ok(Some(line)) = lines.next_line().await {
-        REBUILD_BROKER.send(RebuildMsg::UpdateText(line.to_string()));
-        trace!("CAUGHT NIXENV LINE: {}", line);
+async fn reviewsystem(updater: &Updater, rmpkgs: &Option<Vec<String>>) -> anyhow::Result<bool> {
+    let diff = updater.previewsystem(rmpkgs).map_err(|e| {
+        warn!("Error editing system configuration: {}", e);
+        e
+    })?;
+    // Only configuration edits need to be reviewed
+    if diff.is_empty() {
+        return Ok(true);
     }
-    if cmd.wait().await?.success() {
-        Ok(true)
-    } else {
-        Ok(false)
+    let apply = review(diff).await;
+    if !apply {
+        info!("System update cancelled");
     }
+    Ok(apply)
 }
 
-async fn updateprofile(rmpkgs: Option<Vec<String>>) -> Result<bool> {
-    if let Some(rmpkgs) = rmpkgs {
-        if !rmpkgs.is_empty() {
-            let mut cmd = tokio::process::Command::new("nix")
-                .arg("profile")
-                .arg("remove")
-                .args(
-                    &rmpkgs
-                        .iter()
-                        .map(|x| format!("legacyPackages.x86_64-linux.{}", x))
-                        .collect::<Vec<String>>(),
-                )
-                // Allow updating potential unfree packages
-                .arg("--impure")
-                .stderr(Stdio::piped())
-                .spawn()?;
-
-            let stderr = cmd.stderr.take().unwrap();
-            let reader = tokio::io::BufReader::new(stderr);
-
-            let mut lines = reader.lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                REBUILD_BROKER.send(RebuildMsg::UpdateText(line.to_string()));
-                trace!("CAUGHT NIX PROFILE LINE: {}", line);
-            }
-            cmd.wait().await?;
-        }
-    }
-
-    let mut cmd = tokio::process::Command::new("nix")
-        .arg("profile")
-        .arg("upgrade")
-        .arg(".*")
-        // Allow updating potential unfree packages
-        .arg("--impure")
-        .stderr(Stdio::piped())
-        .spawn()?;
-
-    let stderr = cmd.stderr.take().unwrap();
-    let reader = tokio::io::BufReader::new(stderr);
+// Lets the rebuild dialog stop the update once it is running
+fn cancellable(updater: &Updater) {
+    REBUILD_BROKER.send(RebuildMsg::SetCanceller(Some(updater.canceller())));
+}
 
-    let mut lines = reader.lines();
-    while let Ok(Some(line)) = lines.next_line().await {
-        REBUILD_BROKER.send(RebuildMsg::UpdateText(line.to_string()));
-        trace!("CAUGHT NIX PROFILE LINE: {}", line);
-    }
-    if cmd.wait().await?.success() {
-        Ok(true)
-    } else {
-        Ok(false)
-    }
+fn rebuildoutput(line: String) {
+    REBUILD_BROKER.send(RebuildMsg::UpdateText(line));
 }
@@ -1,6 +1,7 @@
 use std::path::{PathBuf, Path};
 
 use adw::prelude::*;
+use gettextrs::gettext;
 use log::info;
 use nix_data::config::configfile::NixDataConfig;
 use relm4::*;
@@ -58,12 +59,12 @@ impl SimpleComponent for WelcomeModel {
                         set_spacing: 10,
                         gtk::Label {
                             add_css_class: "title-1",
-                            set_text: "Welcome the Nix Software Center!",
+                            set_text: &gettext("Welcome the Nix Software Center!"),
                             set_justify: gtk::Justification::Center,
                         },
                         gtk::Label {
                             add_css_class: "dim-label",
-                            set_text: "If your configuration file is not in the default location, you can change it here.",
+                            set_text: &gettext("If your configuration file is not in the default location, you can change it here."),
                         },
                     },
                     gtk::ListBox {
@@ -71,7 +72,7 @@ impl SimpleComponent for WelcomeModel {
                         set_halign: gtk::Align::Fill,
                         set_selection_mode: gtk::SelectionMode::None,
                         adw::ActionRow {
-                            set_title: "Configuration file",
+                            set_title: &gettext("Configuration file"),
                             add_suffix = &gtk::Button {
                                 set_halign: gtk::Align::Center,
                                 set_valign: gtk::Align::Center,
@@ -83,16 +84,16 @@ impl SimpleComponent for WelcomeModel {
                                     },
                                     gtk::Label {
                                         #[watch]
-                                        set_label: {
+                                        set_label: &{
                                             if let Some(path) = &model.confpath {
                                                 let x = path.file_name().unwrap_or_default().to_str().unwrap_or_default();
                                                 if x.is_empty() {
-                                                    "(None)"
+                                                    gettext("(None)")
                                                 } else {
-                                                    x
+                                                    x.to_string()
                                                 }
                                             } else {
-                                                "(None)"
+                                                gettext("(None)")
                                             }
                                         }
                                     }
@@ -116,8 +117,8 @@ impl SimpleComponent for WelcomeModel {
                         set_halign: gtk::Align::Fill,
                         set_selection_mode: gtk::SelectionMode::None,
                         adw::ActionRow {
-                            set_title: "Flake file",
-                            set_subtitle: "If you are using flakes, you can specify the path to your flake.nix file here.",
+                            set_title: &gettext("Flake file"),
+                            set_subtitle: &gettext("If you are using flakes, you can specify the path to your flake.nix file here."),
                             add_suffix = &gtk::Button {
                                 set_halign: gtk::Align::Center,
                                 set_valign: gtk::Align::Center,
@@ -129,16 +130,16 @@ impl SimpleComponent for WelcomeModel {
                                     },
                                     gtk::Label {
                                         #[watch]
-                                        set_label: {
+                                        set_label: &{
                                             if let Some(path) = &model.flakepath {
                                                 let x = path.file_name().unwrap_or_default().to_str().unwrap_or_default();
                                                 if x.is_empty() {
-                                                    "(None)"
+                                                    gettext("(None)")
                                                 } else {
-                                                    x
+                                                    x.to_string()
                                                 }
                                             } else {
-                                                "(None)"
+                                                gettext("(None)")
                                             }
                                         }
                                     }
@@ -161,7 +162,7 @@ impl SimpleComponent for WelcomeModel {
                     gtk::Button {
                         add_css_class: "pill",
                         add_css_class: "suggested-action",
-                        set_label: "Continue",
+                        set_label: &gettext("Continue"),
                         set_hexpand: false,
                         set_halign: gtk::Align::Center,
                         connect_clicked[sender] => move |_| {
@@ -1,20 +1,25 @@
 use crate::{
-    config,
-    parse::{
-        config::{editconfig, getconfig},
-        packages::{AppData, LicenseEnum, PkgMaintainer, Platform},
-        util,
-    },
+    config, i18n,
+    parse::util,
     ui::{
         installedpage::InstalledItem, pkgpage::PkgPageInit, rebuild::RebuildMsg,
         unavailabledialog::UnavailableDialogMsg, updatepage::UNAVAILABLE_BROKER,
         welcome::WelcomeMsg,
     },
-    APPINFO,
 };
 use adw::prelude::*;
+use gettextrs::gettext;
 use log::*;
 use nix_data::config::configfile::NixDataConfig;
+use nsc_core::{
+    appstream::AppStreamSource,
+    config::{editconfig, getconfig},
+    db::{self, PackageDb, PkgDetails},
+    flakes, holds,
+    packages::{AppData, LicenseEnum, PkgMaintainer, Platform},
+    search::{SearchFilter, SearchQuery},
+    InstalledState, SearchIndex,
+};
 use relm4::{
     self,
     actions::{RelmAction, RelmActionGroup},
@@ -23,12 +28,12 @@ use relm4::{
     RelmWidgetExt, WorkerController,
 };
 use spdx::Expression;
-use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use sqlx::SqlitePool;
 use std::{
     collections::{HashMap, HashSet},
     convert::identity,
-    fs,
     path::Path,
+    sync::Arc,
 };
 
 use super::{
@@ -36,6 +41,7 @@ use super::{
     categories::{PkgCategory, PkgGroup},
     categorypage::{CategoryPageModel, CategoryPageMsg},
     categorytile::CategoryTile,
+    history::{HistoryInit, HistoryModel, HistoryMsg},
     installedpage::{InstalledPageModel, InstalledPageMsg},
     pkgpage::{self, InstallType, PkgInitModel, PkgModel, PkgMsg, WorkPkg},
     pkgtile::PkgTile,
@@ -62,17 +68,19 @@ enum MainPage {
     CategoryPage,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum SystemPkgs {
-    Legacy,
-    Flake,
-    None,
+pub use nsc_core::{SystemPkgs, UserPkgs};
+
+// Headings for the packages of each install type
+pub fn userpkgslabel(userpkgs: &UserPkgs) -> String {
+    match userpkgs {
+        UserPkgs::Env => gettext("User (nix-env)"),
+        UserPkgs::Profile => gettext("User (nix profile)"),
+        UserPkgs::HomeManager => gettext("User (home-manager)"),
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum UserPkgs {
-    Env,
-    Profile,
+pub fn systempkgslabel() -> String {
+    gettext("System (configuration.nix)")
 }
 
 #[tracker::track]
@@ -97,11 +105,14 @@ pub struct AppModel {
     nixpkgsdb: Option<String>,
     #[tracker::no_eq]
     systemdb: Option<String>,
-    appdata: HashMap<String, AppData>,
+    appdata: Arc<HashMap<String, AppData>>,
+    #[tracker::no_eq]
+    appstreamkey: String,
     installeduserpkgs: HashMap<String, String>,
     installedsystempkgs: HashSet<String>,
     syspkgtype: SystemPkgs,
     userpkgtype: UserPkgs,
+    system: String,
     categoryrec: HashMap<PkgCategory, Vec<String>>,
     categoryall: HashMap<PkgCategory, Vec<String>>,
     #[tracker::no_eq]
@@ -116,6 +127,7 @@ pub struct AppModel {
     categorypage: Controller<CategoryPageModel>,
     searching: bool,
     searchquery: String,
+    searchtext: String,
     vschild: String,
     showvsbar: bool,
     #[tracker::no_eq]
@@ -132,7 +144,10 @@ pub struct AppModel {
     rebuild: Controller<RebuildModel>,
     #[tracker::no_eq]
     welcomepage: Controller<WelcomeModel>,
+    #[tracker::no_eq]
+    historypage: Controller<HistoryModel>,
     online: bool,
+    transactioncount: usize,
 }
 
 #[derive(Debug)]
@@ -148,7 +163,9 @@ pub enum AppMsg {
         String,
         Option<String>,
         Option<String>,
-        HashMap<String, AppData>,
+        Arc<HashMap<String, AppData>>,
+        String,
+        Vec<AppStreamSource>,
         Vec<String>,
         HashMap<PkgCategory, Vec<String>>,
         HashMap<PkgCategory, Vec<String>>,
@@ -165,6 +182,7 @@ pub enum AppMsg {
     SetVsBar(bool),
     SetVsChild(String),
     Search(String),
+    ToggleSearchFilter(SearchFilter),
     AddInstalledToWorkQueue(WorkPkg),
     RemoveInstalledBusy(WorkPkg),
     OpenCategoryPage(PkgCategory),
@@ -173,6 +191,8 @@ pub enum AppMsg {
     SetDarkMode(bool),
     GetUnavailableItems(HashMap<String, String>, HashMap<String, String>, UpdateType),
     CheckNetwork,
+    ShowTransaction,
+    SetTransactionCount(usize),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -217,7 +237,7 @@ impl Component for AppModel {
                         add_css_class: "flat",
                         #[wrap(Some)]
                         set_title_widget = &gtk::Label {
-                            set_label: "Nix Software Center"
+                            set_label: &gettext("Nix Software Center")
                         }
                     },
                     gtk::Box {
@@ -233,7 +253,7 @@ impl Component for AppModel {
                         },
                         gtk::Label {
                             add_css_class: "title-1",
-                            set_label: "Loading...",
+                            set_label: &gettext("Loading..."),
                         },
                     }
                 }
@@ -269,7 +289,7 @@ impl Component for AppModel {
                                 #[name(viewswitchertitle)]
                                 #[wrap(Some)]
                                 set_title_widget = &adw::ViewSwitcherTitle {
-                                    set_title: "Nix Software Center",
+                                    set_title: &gettext("Nix Software Center"),
                                     set_stack: Some(viewstack),
                                     connect_title_visible_notify[sender] => move |x| {
                                         sender.input(AppMsg::SetVsBar(x.is_title_visible()))
@@ -282,6 +302,16 @@ impl Component for AppModel {
                                     set_popover = &gtk::PopoverMenu::from_model(Some(&mainmenu)) {
                                         add_css_class: "menu"
                                     }
+                                },
+                                pack_end = &gtk::Button {
+                                    add_css_class: "suggested-action",
+                                    #[watch]
+                                    set_visible: model.transactioncount > 0,
+                                    #[watch]
+                                    set_label: &i18n::fill(&gettext("Pending Changes ({})"), &[&model.transactioncount.to_string()]),
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(AppMsg::ShowTransaction)
+                                    }
                                 }
                             },
                             gtk::SearchBar {
@@ -293,8 +323,10 @@ impl Component for AppModel {
                                     gtk::SearchEntry {
                                         #[track(model.changed(AppModel::searching()) && model.searching)]
                                         grab_focus: (),
-                                        #[track(model.changed(AppModel::searching()) && !model.searching)]
-                                        set_text: "",
+                                        #[track(model.changed(AppModel::searchtext()))]
+                                        set_text: &model.searchtext,
+                                        #[track(model.changed(AppModel::searchtext()))]
+                                        set_position: -1,
                                         connect_search_changed[sender] => move |x| {
                                             if x.text().len() > 1 {
                                                 sender.input(AppMsg::Search(x.text().to_string()))
@@ -326,7 +358,7 @@ impl Component for AppModel {
                                             gtk::Label {
                                                 set_halign: gtk::Align::Start,
                                                 add_css_class: "title-4",
-                                                set_label: "Categories",
+                                                set_label: &gettext("Categories"),
                                             },
                                             #[local_ref]
                                             categorybox -> gtk::FlowBox {
@@ -344,7 +376,7 @@ impl Component for AppModel {
                                             gtk::Label {
                                                 set_halign: gtk::Align::Start,
                                                 add_css_class: "title-4",
-                                                set_label: "Recommended",
+                                                set_label: &gettext("Recommended"),
                                             },
                                             #[local_ref]
                                             recbox -> gtk::FlowBox {
@@ -380,13 +412,6 @@ impl Component for AppModel {
         }
     }
 
-    menu! {
-        mainmenu: {
-            "Preferences" => PreferencesAction,
-            "About" => AboutAction,
-        }
-    }
-
     fn pre_view() {
         match model.page {
             Page::FrontPage => {
@@ -442,62 +467,29 @@ impl Component for AppModel {
             )
         };
 
-        let userpkgtype = if let Ok(h) = std::env::var("HOME") {
-            if Path::new(&format!("{}/.nix-profile/manifest.json", h)).exists()
-                || !Path::new("/nix/var/nix/profiles/per-user/root/channels/nixos").exists()
-                || !Path::new(&format!("{}/.nix-profile/manifest.nix", h)).exists()
-                || if let Ok(m) = fs::read_to_string(&format!("{}/.nix-profile/manifest.nix", h)) {
-                    m == "[ ]"
-                } else {
-                    false
-                }
-            {
-                UserPkgs::Profile
-            } else {
-                UserPkgs::Env
-            }
-        } else {
-            UserPkgs::Env
-        };
+        let userpkgtype = nsc_core::userpkgtype();
         let nixos = Path::new("/etc/NIXOS").exists();
-        let syspkgtype = if config.systemconfig.is_none() || !nixos {
-            SystemPkgs::None
-        } else {
-            match fs::read_to_string("/run/current-system/nixos-version") {
-                Ok(s) => {
-                    if !Path::new("/nix/var/nix/profiles/per-user/root/channels/nixos").exists()
-                        || config.flake.is_some()
-                    {
-                        SystemPkgs::Flake
-                    } else if let Some(last) = s.split('.').last() {
-                        if last.len() == 7 || last == "dirty" || last == "git" {
-                            SystemPkgs::Flake
-                        } else {
-                            SystemPkgs::Legacy
-                        }
-                    } else {
-                        SystemPkgs::Legacy
-                    }
-                }
-                Err(_) => SystemPkgs::None,
-            }
-        };
+        let syspkgtype = nsc_core::syspkgtype(&config);
+        let system = nsc_core::currentsystem();
 
         debug!("userpkgtype: {:?}", userpkgtype);
         debug!("syspkgtype: {:?}", syspkgtype);
+        debug!("system: {}", system);
 
         let online = util::checkonline();
 
         let windowloading = WindowAsyncHandler::builder()
-            .detach_worker(())
+            .detach_worker(system.clone())
             .forward(sender.input_sender(), identity);
         let loaderrordialog = LoadErrorModel::builder()
             .launch(root.clone().upcast())
             .forward(sender.input_sender(), identity);
         let pkgpage = PkgModel::builder()
             .launch(PkgPageInit {
+                window: root.clone().upcast(),
                 userpkgs: userpkgtype.clone(),
                 syspkgs: syspkgtype.clone(),
+                system: system.clone(),
                 config: config.clone(),
                 online,
             })
@@ -516,6 +508,7 @@ impl Component for AppModel {
                 window: root.clone().upcast(),
                 systype: syspkgtype.clone(),
                 usertype: userpkgtype.clone(),
+                system: system.clone(),
                 config: config.clone(),
                 online,
             })
@@ -533,6 +526,14 @@ impl Component for AppModel {
         let preferencespage = PreferencesPageModel::builder()
             .launch(root.clone().upcast())
             .forward(sender.input_sender(), identity);
+        let historypage = HistoryModel::builder()
+            .launch(HistoryInit {
+                window: root.clone().upcast(),
+                config: config.clone(),
+                userpkgs: userpkgtype.clone(),
+                system: system.clone(),
+            })
+            .forward(sender.input_sender(), identity);
 
         let model = AppModel {
             mainwindow: root.clone(),
@@ -545,11 +546,13 @@ impl Component for AppModel {
             pkgdb: String::new(),
             nixpkgsdb: None,
             systemdb: None,
-            appdata: HashMap::new(),
+            appdata: Arc::new(HashMap::new()),
+            appstreamkey: String::new(),
             installeduserpkgs: HashMap::new(),
             installedsystempkgs: HashSet::new(),
             syspkgtype,
             userpkgtype,
+            system,
             categoryrec: HashMap::new(),
             categoryall: HashMap::new(),
             recommendedapps: FactoryVecDeque::new(gtk::FlowBox::new(), sender.input_sender()),
@@ -559,6 +562,7 @@ impl Component for AppModel {
             categorypage,
             searching: false,
             searchquery: String::default(),
+            searchtext: String::default(),
             vschild: String::default(),
             showvsbar: false,
             installedpage,
@@ -569,7 +573,9 @@ impl Component for AppModel {
             welcomepage,
             aboutpage,
             preferencespage,
+            historypage,
             online,
+            transactioncount: 0,
             tracker: 0,
         };
 
@@ -594,6 +600,12 @@ impl Component for AppModel {
         let categorybox = model.categories.widget();
         let viewstack = &model.viewstack;
 
+        // Built by hand since menu! only takes literal labels
+        let mainmenu = gtk::gio::Menu::new();
+        mainmenu.append_item(&RelmAction::<HistoryAction>::to_menu_item(&gettext("History")));
+        mainmenu.append_item(&RelmAction::<PreferencesAction>::to_menu_item(&gettext("Preferences")));
+        mainmenu.append_item(&RelmAction::<AboutAction>::to_menu_item(&gettext("About")));
+
         let widgets = view_output!();
 
         let mut group = RelmActionGroup::<MenuActionGroup>::new();
@@ -614,8 +626,16 @@ impl Component for AppModel {
             })
         };
 
+        let historypage: RelmAction<HistoryAction> = {
+            let sender = model.historypage.sender().clone();
+            RelmAction::new_stateless(move |_| {
+                sender.send(HistoryMsg::Show).unwrap();
+            })
+        };
+
         group.add_action(aboutpage);
         group.add_action(prefernecespage);
+        group.add_action(historypage);
         let actions = group.into_action_group();
         widgets
             .main_window
@@ -627,9 +647,9 @@ impl Component for AppModel {
         let installedvs = widgets.viewstack.page(model.installedpage.widget());
         let updatesvs = widgets.viewstack.page(model.updatepage.widget());
         let searchvs = widgets.viewstack.page(model.searchpage.widget());
-        frontvs.set_title(Some("Explore"));
-        installedvs.set_title(Some("Installed"));
-        updatesvs.set_title(Some("Updates"));
+        frontvs.set_title(Some(&gettext("Explore")));
+        installedvs.set_title(Some(&gettext("Installed")));
+        updatesvs.set_title(Some(&gettext("Updates")));
         frontvs.set_name(Some("explore"));
         installedvs.set_name(Some("installed"));
         searchvs.set_name(Some("search"));
@@ -670,30 +690,7 @@ impl Component for AppModel {
                 if let Err(e) = editconfig(self.config.clone()) {
                     warn!("Error editing config: {}", e);
                 }
-                let nixos = Path::new("/etc/NIXOS").exists();
-                self.syspkgtype = if self.config.systemconfig.is_none() || !nixos {
-                    SystemPkgs::None
-                } else {
-                    match fs::read_to_string("/run/current-system/nixos-version") {
-                        Ok(s) => {
-                            if !Path::new("/nix/var/nix/profiles/per-user/root/channels/nixos")
-                                .exists()
-                                || self.config.flake.is_some()
-                            {
-                                SystemPkgs::Flake
-                            } else if let Some(last) = s.split('.').last() {
-                                if last.len() == 7 || last == "dirty" || last == "git" {
-                                    SystemPkgs::Flake
-                                } else {
-                                    SystemPkgs::Legacy
-                                }
-                            } else {
-                                SystemPkgs::Legacy
-                            }
-                        }
-                        Err(_) => SystemPkgs::None,
-                    }
-                };
+                self.syspkgtype = nsc_core::syspkgtype(&self.config);
                 self.pkgpage.emit(PkgMsg::UpdatePkgTypes(
                     self.syspkgtype.clone(),
                     self.userpkgtype.clone(),
@@ -705,6 +702,8 @@ impl Component for AppModel {
                 ));
                 self.updatepage
                     .emit(UpdatePageMsg::UpdateConfig(self.config.clone()));
+                self.historypage
+                    .emit(HistoryMsg::UpdateConfig(self.config.clone()));
                 self.windowloading.emit(WindowAsyncHandlerMsg::CheckCache(
                     self.syspkgtype.clone(),
                     self.userpkgtype.clone(),
@@ -744,6 +743,8 @@ impl Component for AppModel {
                 self.pkgpage.emit(PkgMsg::UpdateConfig(self.config.clone()));
                 self.updatepage
                     .emit(UpdatePageMsg::UpdateConfig(self.config.clone()));
+                self.historypage
+                    .emit(HistoryMsg::UpdateConfig(self.config.clone()));
                 self.pkgpage.emit(PkgMsg::UpdatePkgTypes(
                     self.syspkgtype.clone(),
                     self.userpkgtype.clone(),
@@ -780,6 +781,8 @@ impl Component for AppModel {
                 self.pkgpage.emit(PkgMsg::UpdateConfig(self.config.clone()));
                 self.updatepage
                     .emit(UpdatePageMsg::UpdateConfig(self.config.clone()));
+                self.historypage
+                    .emit(HistoryMsg::UpdateConfig(self.config.clone()));
                 self.pkgpage.emit(PkgMsg::UpdatePkgTypes(
                     self.syspkgtype.clone(),
                     self.userpkgtype.clone(),
@@ -798,6 +801,8 @@ impl Component for AppModel {
                 nixpkgsdb,
                 systemdb,
                 appdata,
+                appstreamkey,
+                appstreamsources,
                 recommendedapps,
                 categoryrec,
                 categoryall,
@@ -807,12 +812,29 @@ impl Component for AppModel {
                 self.nixpkgsdb = nixpkgsdb;
                 self.systemdb = systemdb;
                 self.appdata = appdata;
+                self.appstreamkey = appstreamkey;
                 self.categoryrec = categoryrec;
                 self.categoryall = categoryall;
+                self.preferencespage
+                    .emit(PreferencesPageMsg::SetAppStreamSources(appstreamsources));
+
+                // Build the search index ahead of the first search
+                let pkgdb = self.pkgdb.clone();
+                let appdata = self.appdata.clone();
+                let appstreamkey = self.appstreamkey.clone();
+                let system = self.system.clone();
+                relm4::spawn(async move {
+                    flakes::refresh(&system, false).await;
+                    if let Err(e) = SearchIndex::open(&pkgdb, &appdata, &appstreamkey).await {
+                        warn!("Failed to build search index: {}", e);
+                    }
+                });
 
                 self.pkgpage.emit(PkgMsg::UpdateConfig(self.config.clone()));
                 self.updatepage
                     .emit(UpdatePageMsg::UpdateConfig(self.config.clone()));
+                self.historypage
+                    .emit(HistoryMsg::UpdateConfig(self.config.clone()));
                 sender.input(AppMsg::UpdateRecPkgs(recommendedapps));
                 let mut cat_guard = self.categories.guard();
                 cat_guard.clear();
@@ -831,17 +853,7 @@ impl Component for AppModel {
             }
             AppMsg::UpdateRecPkgs(pkgs) => {
                 info!("AppMsg::UpdateRecPkgs");
-                let appdata: HashMap<String, AppData> = self
-                    .appdata
-                    .iter()
-                    .filter_map(|(k, v)| {
-                        if pkgs.contains(k) {
-                            Some((k.to_string(), v.clone()))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
+                let appdata = self.appdata.clone();
                 let installeduser = self.installeduserpkgs.clone();
                 let installedsystem = self.installedsystempkgs.clone();
                 let poolref = self.pkgdb.clone();
@@ -859,26 +871,16 @@ impl Component for AppModel {
                                         .unwrap();
                                 pkgtiles.push(PkgTile {
                                     pkg: pkg.to_string(),
-                                    name: if let Some(name) = &data.name {
-                                        name.get("C").unwrap_or(&pname.0).to_string()
-                                    } else {
-                                        pname.0.to_string()
-                                    },
+                                    name: data.localname().unwrap_or(&pname.0).to_string(),
                                     pname: pname.0.to_string(),
-                                    icon: data
-                                        .icon
-                                        .as_ref()
-                                        .and_then(|x| x.cached.as_ref())
-                                        .map(|x| x[0].name.clone()),
+                                    icon: util::appicon(data),
                                     summary: data
-                                        .summary
-                                        .as_ref()
-                                        .and_then(|x| x.get("C"))
+                                        .localsummary()
                                         .map(|x| x.to_string())
                                         .unwrap_or_default(),
                                     installeduser: installeduser.contains_key(&match userpkgtype {
                                         UserPkgs::Env => pname.0,
-                                        UserPkgs::Profile => pkg.to_string(),
+                                        UserPkgs::Profile | UserPkgs::HomeManager => pkg.to_string(),
                                     }),
                                     installedsystem: installedsystem.contains(&pkg),
                                 })
@@ -891,41 +893,19 @@ impl Component for AppModel {
             AppMsg::OpenPkg(pkg) => {
                 info!("AppMsg::OpenPkg {}", pkg);
                 sender.input(AppMsg::CheckNetwork);
-                if let Ok(pool) = &SqlitePool::connect(&format!("sqlite://{}", self.pkgdb)).await {
-                    let pkgdata: Result<
-                        (
-                            String,
-                            String,
-                            String,
-                            String,
-                            String,
-                            String,
-                            String,
-                            String,
-                            String,
-                        ),
-                        _,
-                    > = sqlx::query_as(
-                        r#"
-SELECT pname, version, system, description, longdescription, homepage, license, platforms, maintainers
-FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute = $1
-                    "#,
-                    )
-                    .bind(&pkg)
-                    .fetch_one(pool)
-                    .await;
-
-                    if let Ok((
+                if let Ok(packagedb) = &PackageDb::connect(&self.pkgdb).await {
+                    if let Ok(PkgDetails {
                         pname,
                         version,
                         system,
                         description,
                         longdescription,
                         homepage,
-                        licensejson,
-                        platformsjson,
-                        maintainersjson,
-                    )) = pkgdata
+                        license: licensejson,
+                        platforms: platformsjson,
+                        maintainers: maintainersjson,
+                        ..
+                    }) = packagedb.details(&pkg).await
                     {
                         let mut name = pname.to_string();
                         let mut summary = if description.is_empty() {
@@ -944,34 +924,46 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                         let mut platforms = vec![];
                         let mut maintainers = vec![];
                         let mut launchable = None;
+                        let mut remoteicon = None;
+                        let mut developer = None;
+                        let mut agerating = None;
+                        let mut releases = vec![];
 
                         if let Some(data) = self.appdata.get(&pkg) {
-                            if let Some(n) = &data.name {
-                                if let Some(n) = n.get("C") {
-                                    name = n.to_string();
-                                }
+                            if let Some(n) = data.localname() {
+                                name = n.to_string();
                             }
-                            if let Some(s) = &data.summary {
-                                if let Some(s) = s.get("C") {
-                                    summary = Some(s.to_string());
-                                }
-                            }
-                            if let Some(d) = &data.description {
-                                if let Some(d) = d.get("C") {
-                                    description = Some(d.to_string());
-                                }
+                            if let Some(s) = data.localsummary() {
+                                summary = Some(s.to_string());
                             }
-                            if let Some(i) = &data.icon {
-                                if let Some(mut i) = i.cached.clone() {
-                                    i.sort_by(|x, y| x.height.cmp(&y.height));
-                                    if let Some(i) = i.last() {
-                                        icon = Some(format!(
-                                            "{}/icons/nixos/{}x{}/{}",
-                                            APPINFO, i.width, i.height, i.name
-                                        ));
-                                    }
-                                }
+                            if let Some(d) = data.localdescription() {
+                                description = Some(d.to_string());
                             }
+                            icon = util::appicon(data);
+                            remoteicon = data
+                                .icon
+                                .as_ref()
+                                .filter(|_| data.cachedicon().is_none())
+                                .and_then(|i| i.remoteurl())
+                                .filter(|url| {
+                                    util::remoteiconpath(url)
+                                        .map(|x| !Path::new(&x).is_file())
+                                        .unwrap_or(false)
+                                })
+                                .map(|x| x.to_string());
+                            developer = data.localdeveloper().map(|x| x.to_string());
+                            agerating = data.agerating();
+                            releases = data
+                                .sortedreleases()
+                                .into_iter()
+                                .filter_map(|r| {
+                                    Some(pkgpage::Release {
+                                        version: r.version.clone()?,
+                                        timestamp: r.timestamp,
+                                        description: r.localdescription().map(|x| x.to_string()),
+                                    })
+                                })
+                                .collect();
                             if let Some(s) = &data.screenshots {
                                 for s in s {
                                     if let Some(u) = &s.sourceimage {
@@ -1117,8 +1109,21 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                         if let Ok(pkglicense) = serde_json::from_str::<LicenseEnum>(&licensejson) {
                             addlicense(&pkglicense, &mut licenses);
                         }
+                        if licenses.is_empty() {
+                            if let Some(l) = self
+                                .appdata
+                                .get(&pkg)
+                                .and_then(|x| x.projectlicense.as_ref())
+                            {
+                                addlicense(&LicenseEnum::SingleStr(l.to_string()), &mut licenses);
+                            }
+                        }
 
                         let platformslst = serde_json::from_str::<Platform>(&platformsjson);
+                        let unsupported = platformslst
+                            .as_ref()
+                            .map(|p| !p.supports(&self.system))
+                            .unwrap_or(false);
                         if let Ok(p) = platformslst {
                             match p {
                                 Platform::Single(p) => {
@@ -1145,7 +1150,9 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                             }
                         }
                         platforms.sort();
-                        platforms.insert(0, system);
+                        if !system.is_empty() {
+                            platforms.insert(0, system);
+                        }
 
                         if let Ok(m) = serde_json::from_str::<Vec<PkgMaintainer>>(&maintainersjson)
                         {
@@ -1154,6 +1161,7 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                             }
                         }
 
+                        let source = flakes::source(&pkg).map(|x| x.to_string());
                         let out = PkgInitModel {
                             name,
                             version: if version.is_empty() {
@@ -1173,11 +1181,17 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                 Some(homepage)
                             },
                             platforms,
+                            unsupported,
+                            source,
                             licenses,
                             maintainers,
                             installeduserpkgs: self.installeduserpkgs.keys().cloned().collect(),
                             installedsystempkgs: self.installedsystempkgs.clone(),
                             launchable,
+                            developer,
+                            agerating,
+                            releases,
+                            remoteicon,
                         };
                         self.page = Page::PkgPage;
                         if self.viewstack.visible_child_name()
@@ -1205,49 +1219,9 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                 let syspkgtype = self.syspkgtype.clone();
                 let userpkgtype = self.userpkgtype.clone();
                 sender.oneshot_command(async move {
-                    let installedsystempkgs = if let Some(config) = &systemconfig {
-                        match syspkgtype {
-                            SystemPkgs::Flake => {
-                                let pkgs = nix_data::cache::flakes::getflakepkgs(&[config]).await;
-                                if let Ok(pkgs) = pkgs {
-                                    pkgs.keys().cloned().collect::<HashSet<String>>()
-                                } else {
-                                    HashSet::new()
-                                }
-                            }
-                            SystemPkgs::Legacy => {
-                                let pkgs = nix_data::cache::channel::getlegacypkgs(&[config]).await;
-                                if let Ok(pkgs) = pkgs {
-                                    pkgs.keys().cloned().collect::<HashSet<String>>()
-                                } else {
-                                    HashSet::new()
-                                }
-                            }
-                            _ => HashSet::new(),
-                        }
-                    } else {
-                        HashSet::new()
-                    };
-
-                    let installeduserpkgs = match userpkgtype {
-                        UserPkgs::Profile => {
-                            let pkgs = nix_data::cache::profile::getprofilepkgs_versioned().await;
-                            if let Ok(pkgs) = pkgs {
-                                pkgs
-                            } else {
-                                HashMap::new()
-                            }
-                        }
-                        UserPkgs::Env => {
-                            let pkgs = nix_data::cache::channel::getenvpkgs();
-                            if let Ok(pkgs) = pkgs {
-                                pkgs
-                            } else {
-                                HashMap::new()
-                            }
-                        }
-                    };
-                    AppAsyncMsg::UpdateInstalledPkgs(installedsystempkgs, installeduserpkgs)
+                    let installed =
+                        InstalledState::load(&systemconfig, &syspkgtype, &userpkgtype).await;
+                    AppAsyncMsg::UpdateInstalledPkgs(installed.system, installed.user)
                 });
             }
             AppMsg::UpdateInstalledPage => {
@@ -1257,6 +1231,7 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                 // let pool = SqlitePool::connect(&self.pkgdb).await.unwrap();
                 debug!("Installed user pkgs: {:?}", self.installeduserpkgs);
                 debug!("Installed system pkgs: {:?}", self.installedsystempkgs);
+                let held = holds::held();
                 if let Ok(pool) = &SqlitePool::connect(&format!("sqlite://{}", self.pkgdb)).await {
                     match self.userpkgtype {
                         UserPkgs::Env => {
@@ -1292,21 +1267,13 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                         };
                                         let mut icon = None;
                                         if let Some(data) = self.appdata.get(pkg) {
-                                            if let Some(n) = &data.name {
-                                                if let Some(n) = n.get("C") {
-                                                    name = n.to_string();
-                                                }
-                                            }
-                                            if let Some(s) = &data.summary {
-                                                if let Some(s) = s.get("C") {
-                                                    summary = Some(s.to_string());
-                                                }
+                                            if let Some(n) = data.localname() {
+                                                name = n.to_string();
                                             }
-                                            if let Some(i) = &data.icon {
-                                                if let Some(i) = &i.cached {
-                                                    icon = Some(i[0].name.clone());
-                                                }
+                                            if let Some(s) = data.localsummary() {
+                                                summary = Some(s.to_string());
                                             }
+                                            icon = util::appicon(data);
                                         }
                                         installeduseritems.push(InstalledItem {
                                             name: name.clone(),
@@ -1320,7 +1287,8 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                                 InstallType::User,
                                             )),
                                         });
-                                        if !installedver.eq(&newver) {
+                                        let isheld = held.contains(installedpname);
+                                        if !installedver.eq(&newver) || isheld {
                                             updateuseritems.push(UpdateItem {
                                                 name,
                                                 pname: installedpname.to_string(),
@@ -1330,6 +1298,8 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                                 pkgtype: InstallType::User,
                                                 verfrom: Some(installedver.to_string()),
                                                 verto: Some(newver),
+                                                key: Some(installedpname.to_string()),
+                                                held: isheld,
                                             });
                                         }
                                     }
@@ -1353,7 +1323,9 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                         .fetch_all(pool)
                                         .await
                                         .unwrap();
+                                        let isheld = held.contains(installedpname);
                                         if !possibleversions.contains(&(installedver.to_string(),))
+                                            || isheld
                                         {
                                             updateuseritems.push(UpdateItem {
                                                 name: installedpname.clone(),
@@ -1364,6 +1336,8 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                                 pkgtype: InstallType::User,
                                                 verfrom: Some(installedver.to_string()),
                                                 verto: None,
+                                                key: Some(installedpname.clone()),
+                                                held: isheld,
                                             });
                                         }
                                     }
@@ -1374,20 +1348,28 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                         UserPkgs::Profile => {
                             for installedpkg in self.installeduserpkgs.keys() {
                                 debug!("Checking package {}", installedpkg);
-                                let (pname, version): (String, String) = sqlx::query_as(
-                                    "SELECT pname, version FROM pkgs WHERE attribute = $1",
-                                )
-                                .bind(installedpkg)
-                                .fetch_one(pool)
-                                .await
-                                .unwrap();
-                                let (description,): (String,) = sqlx::query_as(
-                                    "SELECT description FROM meta WHERE attribute = $1",
-                                )
-                                .bind(installedpkg)
-                                .fetch_one(pool)
-                                .await
-                                .unwrap();
+                                // Packages from other flakes aren't in the nixpkgs database
+                                let flakepkg = flakes::source(installedpkg).is_some();
+                                let (pname, version, description) = if flakepkg {
+                                    let details = flakes::details(installedpkg).unwrap_or_default();
+                                    (details.pname, details.version, details.description)
+                                } else {
+                                    let (pname, version): (String, String) = sqlx::query_as(
+                                        "SELECT pname, version FROM pkgs WHERE attribute = $1",
+                                    )
+                                    .bind(installedpkg)
+                                    .fetch_one(pool)
+                                    .await
+                                    .unwrap();
+                                    let (description,): (String,) = sqlx::query_as(
+                                        "SELECT description FROM meta WHERE attribute = $1",
+                                    )
+                                    .bind(installedpkg)
+                                    .fetch_one(pool)
+                                    .await
+                                    .unwrap();
+                                    (pname, version, description)
+                                };
                                 let mut name = pname.to_string();
                                 let mut summary = if description.is_empty() {
                                     None
@@ -1396,21 +1378,13 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                 };
                                 let mut icon = None;
                                 if let Some(data) = self.appdata.get(installedpkg) {
-                                    if let Some(n) = &data.name {
-                                        if let Some(n) = n.get("C") {
-                                            name = n.to_string();
-                                        }
+                                    if let Some(n) = data.localname() {
+                                        name = n.to_string();
                                     }
-                                    if let Some(s) = &data.summary {
-                                        if let Some(s) = s.get("C") {
-                                            summary = Some(s.to_string());
-                                        }
-                                    }
-                                    if let Some(i) = &data.icon {
-                                        if let Some(i) = &i.cached {
-                                            icon = Some(i[0].name.clone());
-                                        }
+                                    if let Some(s) = data.localsummary() {
+                                        summary = Some(s.to_string());
                                     }
+                                    icon = util::appicon(data);
                                 }
                                 installeduseritems.push(InstalledItem {
                                     name: name.to_string(),
@@ -1423,7 +1397,7 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                         .installedpagebusy
                                         .contains(&(installedpkg.clone(), InstallType::User)),
                                 });
-                                if let Some(latest) = &self.nixpkgsdb {
+                                if let (false, Some(latest)) = (flakepkg, &self.nixpkgsdb) {
                                     if let Ok(latestpool) =
                                         &SqlitePool::connect(&format!("sqlite://{}", latest)).await
                                     {
@@ -1435,7 +1409,8 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                         .await
                                         .unwrap();
                                         debug!("PROFILE: {} {} {}", installedpkg, version, newver);
-                                        if version != newver {
+                                        let isheld = held.contains(installedpkg);
+                                        if version != newver || isheld {
                                             updateuseritems.push(UpdateItem {
                                                 name,
                                                 pname,
@@ -1445,12 +1420,56 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                                 pkgtype: InstallType::System,
                                                 verfrom: Some(version.clone()),
                                                 verto: Some(newver.clone()),
+                                                key: Some(installedpkg.clone()),
+                                                held: isheld,
                                             })
                                         }
                                     }
                                 }
                             }
                         }
+                        UserPkgs::HomeManager => {
+                            // Versions follow the home-manager channel or flake, so only list them
+                            for installedpkg in self.installeduserpkgs.keys() {
+                                let pname: sqlx::Result<(String,)> =
+                                    sqlx::query_as("SELECT pname FROM pkgs WHERE attribute = $1")
+                                        .bind(installedpkg)
+                                        .fetch_one(pool)
+                                        .await;
+                                let desc: sqlx::Result<(String,)> =
+                                    sqlx::query_as("SELECT description FROM meta WHERE attribute = $1")
+                                        .bind(installedpkg)
+                                        .fetch_one(pool)
+                                        .await;
+                                let pname = pname.map(|x| x.0).unwrap_or_else(|_| installedpkg.to_string());
+                                let mut name = pname.to_string();
+                                let mut summary = match desc {
+                                    Ok((description,)) if !description.is_empty() => Some(description),
+                                    _ => None,
+                                };
+                                let mut icon = None;
+                                if let Some(data) = self.appdata.get(installedpkg) {
+                                    if let Some(n) = data.localname() {
+                                        name = n.to_string();
+                                    }
+                                    if let Some(s) = data.localsummary() {
+                                        summary = Some(s.to_string());
+                                    }
+                                    icon = util::appicon(data);
+                                }
+                                installeduseritems.push(InstalledItem {
+                                    name,
+                                    pname,
+                                    pkg: Some(installedpkg.clone()),
+                                    summary,
+                                    icon,
+                                    pkgtype: InstallType::User,
+                                    busy: self
+                                        .installedpagebusy
+                                        .contains(&(installedpkg.clone(), InstallType::User)),
+                                });
+                            }
+                        }
                     }
 
                     installeduseritems
@@ -1478,21 +1497,13 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                 };
                                 let mut icon = None;
                                 if let Some(data) = self.appdata.get(installedpkg) {
-                                    if let Some(n) = &data.name {
-                                        if let Some(n) = n.get("C") {
-                                            name = n.to_string();
-                                        }
+                                    if let Some(n) = data.localname() {
+                                        name = n.to_string();
                                     }
-                                    if let Some(s) = &data.summary {
-                                        if let Some(s) = s.get("C") {
-                                            summary = Some(s.to_string());
-                                        }
-                                    }
-                                    if let Some(i) = &data.icon {
-                                        if let Some(i) = &i.cached {
-                                            icon = Some(i[0].name.clone());
-                                        }
+                                    if let Some(s) = data.localsummary() {
+                                        summary = Some(s.to_string());
                                     }
+                                    icon = util::appicon(data);
                                 }
                                 installedsystemitems.push(InstalledItem {
                                     name: name.to_string(),
@@ -1527,6 +1538,8 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                                 pkgtype: InstallType::System,
                                                 verfrom: Some(currver.clone()),
                                                 verto: Some(version.clone()),
+                                                key: None,
+                                                held: false,
                                             })
                                         }
                                     }
@@ -1542,16 +1555,18 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                 updatesystemitems.insert(
                                     0,
                                     UpdateItem {
-                                        name: String::from("NixOS System"),
+                                        name: gettext("NixOS System"),
                                         pname: String::new(),
                                         pkg: None,
-                                        summary: Some(String::from(
+                                        summary: Some(gettext(
                                             "NixOS internal packages and modules",
                                         )),
                                         icon: None,
                                         pkgtype: InstallType::System,
                                         verfrom: Some(old),
                                         verto: Some(new),
+                                        key: None,
+                                        held: false,
                                     },
                                 )
                             }
@@ -1561,16 +1576,18 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                 updatesystemitems.insert(
                                     0,
                                     UpdateItem {
-                                        name: String::from("NixOS System"),
+                                        name: gettext("NixOS System"),
                                         pname: String::new(),
                                         pkg: None,
-                                        summary: Some(String::from(
+                                        summary: Some(gettext(
                                             "NixOS internal packages and modules",
                                         )),
                                         icon: None,
                                         pkgtype: InstallType::System,
                                         verfrom: Some(old),
                                         verto: Some(new),
+                                        key: None,
+                                        held: false,
                                     },
                                 )
                             }
@@ -1599,6 +1616,7 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
             AppMsg::SetSearch(show) => {
                 self.set_searching(show);
                 if !show {
+                    self.update_searchtext(|x| x.clear());
                     if let Some(s) = self.viewstack.visible_child_name() {
                         if s == "search" {
                             self.viewstack.set_visible_child_name("explore");
@@ -1625,119 +1643,65 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                 debug!("Searching for: {}", search);
                 self.viewstack.set_visible_child_name("search");
                 self.set_searchquery(search.to_string());
+                let query = SearchQuery::parse(&search);
+                self.searchpage
+                    .emit(SearchPageMsg::SetFilters(query.filters.clone()));
                 let installeduserpkgs = self.installeduserpkgs.clone();
                 let installedsystempkgs = self.installedsystempkgs.clone();
                 let userpkgtype = self.userpkgtype.clone();
                 let pkgdb = self.pkgdb.clone();
                 let appdata = self.appdata.clone();
+                let appstreamkey = self.appstreamkey.clone();
                 sender.command(move |out, shutdown| {
                     let search = search.clone();
                     let installeduserpkgs = installeduserpkgs.clone();
                     let installedsystempkgs = installedsystempkgs;
                     let userpkgtype = userpkgtype.clone();
+                    let query = query.clone();
                     shutdown.register(async move {
-                        let searchsplit: Vec<String> = search.split(' ').filter(|x| x.len() > 1).map(|x| x.to_string()).collect();
-                        warn!("Searchsplit: {:?}", searchsplit);
-                        if let Ok(pkgpool) = &SqlitePool::connect(&format!("sqlite://{}", pkgdb)).await {
-                            let mut queryb: QueryBuilder<Sqlite> = QueryBuilder::new(
-                                "SELECT pkgs.attribute, pkgs.pname, description, version FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE (",
-                            );
-                            for (i, q) in searchsplit.iter().enumerate() {
-                                if i == searchsplit.len() - 1 {
-                                    queryb
-                                        .push(r#"pkgs.attribute LIKE "#)
-                                        .push_bind(format!("%{}%", q))
-                                        .push(r#" OR description LIKE "#)
-                                        .push_bind(format!("%{}%", q))
-                                        .push(")");
-                                } else {
-                                    queryb
-                                        .push(r#"pkgs.attribute LIKE "#)
-                                        .push_bind(format!("%{}%", q))
-                                        .push(r#" OR description LIKE "#)
-                                        .push_bind(format!("%{}%", q))
-                                        .push(r#") AND ("#);
-                                }
-                            }
-                            queryb.push("ORDER BY LENGTH(pkgs.attribute) ASC");
-                            let q: Vec<(String, String, String, String)> =
-                                queryb.build_query_as().fetch_all(pkgpool).await.unwrap();
-                            let mut outpkgs = Vec::new();
-                            for (i, (attr, pname, desc, _version)) in q.into_iter().enumerate() {
-                                if let Some(data) = appdata.get(&attr) {
-                                    outpkgs.push(SearchItem {
-                                        pkg: attr.to_string(),
-                                        pname: pname.to_string(),
-                                        name: if let Some(name) = &data.name { name.get("C").unwrap_or(&attr).to_string() } else { attr.to_string() },
-                                        summary: if desc.is_empty() { None } else { Some(desc) },
-                                        icon: data
-                                            .icon
-                                            .as_ref()
-                                            .and_then(|x| x.cached.as_ref())
-                                            .map(|x| x[0].name.clone()),
-                                        installeduser: match userpkgtype {
-                                          UserPkgs::Env => installeduserpkgs.contains_key(&pname),
-                                          UserPkgs::Profile => installeduserpkgs.contains_key(&attr)
+                        match SearchIndex::open(&pkgdb, &appdata, &appstreamkey).await {
+                            Ok(index) => {
+                                let installed = InstalledState {
+                                    system: installedsystempkgs.clone(),
+                                    user: installeduserpkgs.clone(),
+                                };
+                                let results = index
+                                    .search(&query, &installed, &userpkgtype)
+                                    .await
+                                    .unwrap_or_default();
+                                let outpkgs = results
+                                    .into_iter()
+                                    .map(|r| SearchItem {
+                                        name: db::displayname(&r.attribute, &r.pname, &appdata),
+                                        summary: match appdata.get(&r.attribute).and_then(|x| x.localsummary()) {
+                                            Some(s) => Some(s.to_string()),
+                                            None if r.description.is_empty() => None,
+                                            None => Some(r.description),
                                         },
-                                        installedsystem: installedsystempkgs.contains(&attr),
-                                    })
-                                } else {
-                                    outpkgs.push(SearchItem {
-                                        pkg: attr.to_string(),
-                                        pname: pname.to_string(),
-                                        name: pname.to_string(),
-                                        summary: if desc.is_empty() { None } else { Some(desc) },
-                                        icon: None,
+                                        icon: appdata.get(&r.attribute).and_then(util::appicon),
                                         installeduser: match userpkgtype {
-                                          UserPkgs::Env => installeduserpkgs.contains_key(&pname),
-                                          UserPkgs::Profile => installeduserpkgs.contains_key(&attr)
+                                          UserPkgs::Env => installeduserpkgs.contains_key(&r.pname),
+                                          UserPkgs::Profile | UserPkgs::HomeManager => installeduserpkgs.contains_key(&r.attribute)
                                         },
-                                        installedsystem: installedsystempkgs.contains(&attr),
-                                    });
-                                }
-                                if i >= 200 {
-                                    break;
-                                }
+                                        installedsystem: installedsystempkgs.contains(&r.attribute),
+                                        pkg: r.attribute,
+                                        pname: r.pname,
+                                        source: r.source,
+                                    })
+                                    .collect::<Vec<_>>();
+                                out.send(AppAsyncMsg::Search(search.to_string(), outpkgs));
                             }
-                            outpkgs.sort_by(|a, b| {
-                                let mut aleft = a.name.to_lowercase() + &a.pkg.to_lowercase();
-                                let mut bleft = b.name.to_lowercase() + &b.pkg.to_lowercase();
-                                for q in searchsplit.iter() {
-                                    let q = &q.to_lowercase();
-                                    if aleft.contains(q) {
-                                        aleft = aleft.replace(q, "");
-                                    } else {
-                                        aleft.push_str(q);
-                                    }
-                                    if bleft.contains(q) {
-                                        bleft = bleft.replace(q, "");
-                                    } else {
-                                        bleft.push_str(q);
-                                    }
-                                }
-                                let mut apoints = aleft.len() + 5;
-                                let mut bpoints = bleft.len() + 5;
-                                // for q in searchsplit.iter() {
-                                //     if a.name.contains(q) {
-                                //         apoints -= 1;
-                                //     }
-                                //     if b.name.contains(q) {
-                                //         bpoints -= 1;
-                                //     }
-                                // }
-                                if appdata.get(&a.pkg).is_some() {
-                                    apoints -= 5;
-                                }
-                                if appdata.get(&b.pkg).is_some() {
-                                    bpoints -= 5;
-                                }
-                                apoints.cmp(&bpoints)
-                            });
-                            out.send(AppAsyncMsg::Search(search.to_string(), outpkgs));
+                            Err(e) => warn!("Failed to open search index: {}", e),
                         }
                     }).drop_on_shutdown()
                 })
             }
+            AppMsg::ToggleSearchFilter(filter) => {
+                // Editing the search text starts the new search
+                let mut query = SearchQuery::parse(&self.searchquery);
+                query.toggle(filter);
+                self.update_searchtext(|x| *x = query.to_string());
+            }
             AppMsg::AddInstalledToWorkQueue(work) => {
                 let p = match work.pkgtype {
                     InstallType::User => work.pname.to_string(),
@@ -1786,21 +1750,11 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                     .unwrap();
                                 catrec.push(CategoryTile {
                                     pkg: pkg.to_string(),
-                                    name: if let Some(name) = &data.name {
-                                        name.get("C").unwrap_or(&pname.0).to_string()
-                                    } else {
-                                        pname.0.to_string()
-                                    },
+                                    name: data.localname().unwrap_or(&pname.0).to_string(),
                                     pname: pname.0,
-                                    icon: data
-                                        .icon
-                                        .as_ref()
-                                        .and_then(|x| x.cached.as_ref())
-                                        .map(|x| x[0].name.clone()),
+                                    icon: util::appicon(data),
                                     summary: data
-                                        .summary
-                                        .as_ref()
-                                        .and_then(|x| x.get("C"))
+                                        .localsummary()
                                         .map(|x| x.to_string()),
                                     installeduser: installeduser.contains_key(&pkg),
                                     installedsystem: installedsystem.contains(&pkg),
@@ -1833,21 +1787,11 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                     .unwrap();
                                 catall.push(CategoryTile {
                                     pkg: pkg.to_string(),
-                                    name: if let Some(name) = &data.name {
-                                        name.get("C").unwrap_or(&pname.0).to_string()
-                                    } else {
-                                        pname.0.to_string()
-                                    },
+                                    name: data.localname().unwrap_or(&pname.0).to_string(),
                                     pname: pname.0,
-                                    icon: data
-                                        .icon
-                                        .as_ref()
-                                        .and_then(|x| x.cached.as_ref())
-                                        .map(|x| x[0].name.clone()),
+                                    icon: util::appicon(data),
                                     summary: data
-                                        .summary
-                                        .as_ref()
-                                        .and_then(|x| x.get("C"))
+                                        .localsummary()
                                         .map(|x| x.to_string()),
                                     installeduser: installeduser.contains_key(&pkg),
                                     installedsystem: installedsystem.contains(&pkg),
@@ -1883,17 +1827,7 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
             }
             AppMsg::GetUnavailableItems(userpkgs, syspkgs, updatetype) => {
                 info!("AppMsg::GetUnavailableItems");
-                let appdata: HashMap<String, AppData> = self
-                    .appdata
-                    .iter()
-                    .filter_map(|(k, v)| {
-                        if syspkgs.contains_key(k) || userpkgs.contains_key(k) {
-                            Some((k.to_string(), v.clone()))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
+                let appdata = self.appdata.clone();
                 let poolref = self.pkgdb.clone();
                 relm4::spawn(async move {
                     let mut unavailableuser = vec![];
@@ -1911,33 +1845,17 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                 if let Ok(pname) = pname {
                                     unavailableuser.push(UnavailableItemModel {
                                         pkg: pkg.to_string(),
-                                        name: if let Some(name) = &data.name {
-                                            name.get("C").unwrap_or(&pname.0).to_string()
-                                        } else {
-                                            pname.0.to_string()
-                                        },
+                                        name: data.localname().unwrap_or(&pname.0).to_string(),
                                         pname: pname.0.to_string(),
-                                        icon: data
-                                            .icon
-                                            .as_ref()
-                                            .and_then(|x| x.cached.as_ref())
-                                            .map(|x| x[0].name.clone()),
+                                        icon: util::appicon(data),
                                         message: msg,
                                     })
                                 } else {
                                     unavailableuser.push(UnavailableItemModel {
                                         pkg: pkg.to_string(),
-                                        name: if let Some(name) = &data.name {
-                                            name.get("C").unwrap_or(&pkg).to_string()
-                                        } else {
-                                            pkg.to_string()
-                                        },
+                                        name: data.localname().unwrap_or(&pkg).to_string(),
                                         pname: String::new(),
-                                        icon: data
-                                            .icon
-                                            .as_ref()
-                                            .and_then(|x| x.cached.as_ref())
-                                            .map(|x| x[0].name.clone()),
+                                        icon: util::appicon(data),
                                         message: msg,
                                     })
                                 }
@@ -1963,33 +1881,17 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                                 if let Ok(pname) = pname {
                                     unavailablesys.push(UnavailableItemModel {
                                         pkg: pkg.to_string(),
-                                        name: if let Some(name) = &data.name {
-                                            name.get("C").unwrap_or(&pname.0).to_string()
-                                        } else {
-                                            pname.0.to_string()
-                                        },
+                                        name: data.localname().unwrap_or(&pname.0).to_string(),
                                         pname: pname.0.to_string(),
-                                        icon: data
-                                            .icon
-                                            .as_ref()
-                                            .and_then(|x| x.cached.as_ref())
-                                            .map(|x| x[0].name.clone()),
+                                        icon: util::appicon(data),
                                         message: msg,
                                     })
                                 } else {
                                     unavailablesys.push(UnavailableItemModel {
                                         pkg: pkg.to_string(),
-                                        name: if let Some(name) = &data.name {
-                                            name.get("C").unwrap_or(&pkg).to_string()
-                                        } else {
-                                            pkg.to_string()
-                                        },
+                                        name: data.localname().unwrap_or(&pkg).to_string(),
                                         pname: String::new(),
-                                        icon: data
-                                            .icon
-                                            .as_ref()
-                                            .and_then(|x| x.cached.as_ref())
-                                            .map(|x| x[0].name.clone()),
+                                        icon: util::appicon(data),
                                         message: msg,
                                     })
                                 }
@@ -2023,6 +1925,12 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                     AppAsyncMsg::SetNetwork(online)
                 });
             }
+            AppMsg::ShowTransaction => {
+                self.pkgpage.emit(PkgMsg::ShowTransaction);
+            }
+            AppMsg::SetTransactionCount(count) => {
+                self.set_transactioncount(count);
+            }
         }
     }
 
@@ -2066,7 +1974,7 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
                         item.installeduser =
                             self.installeduserpkgs.contains_key(match self.userpkgtype {
                                 UserPkgs::Env => &item.pname,
-                                UserPkgs::Profile => &item.pkg,
+                                UserPkgs::Profile | UserPkgs::HomeManager => &item.pkg,
                             });
                         item.installedsystem = self.installedsystempkgs.contains(&item.pkg);
                     }
@@ -2095,3 +2003,4 @@ FROM pkgs JOIN meta ON (pkgs.attribute = meta.attribute) WHERE pkgs.attribute =
 relm4::new_action_group!(MenuActionGroup, "menu");
 relm4::new_stateless_action!(AboutAction, MenuActionGroup, "about");
 relm4::new_stateless_action!(PreferencesAction, MenuActionGroup, "preferences");
+relm4::new_stateless_action!(HistoryAction, MenuActionGroup, "history");
@@ -1,20 +1,24 @@
 use super::window::AppMsg;
 use super::window::SystemPkgs;
-use crate::parse::packages::appsteamdata;
-use crate::parse::packages::AppData;
 use crate::ui::categories::PkgCategory;
 use crate::ui::window::UserPkgs;
+use gettextrs::gettext;
 use log::*;
 use nix_data::config::configfile::NixDataConfig;
+use nsc_core::appstream;
+use nsc_core::flakes;
+use nsc_core::packages::AppData;
 use rand::prelude::SliceRandom;
 use rand::thread_rng;
 use relm4::adw::prelude::*;
 use relm4::*;
 use sqlx::SqlitePool;
 use std::path::Path;
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, sync::Arc};
 
-pub struct WindowAsyncHandler;
+pub struct WindowAsyncHandler {
+    system: String,
+}
 
 #[derive(Debug)]
 pub enum WindowAsyncHandlerMsg {
@@ -23,18 +27,19 @@ pub enum WindowAsyncHandlerMsg {
 }
 
 impl Worker for WindowAsyncHandler {
-    type Init = ();
+    type Init = String;
     type Input = WindowAsyncHandlerMsg;
     type Output = AppMsg;
 
-    fn init(_params: Self::Init, _sender: relm4::ComponentSender<Self>) -> Self {
-        Self
+    fn init(system: Self::Init, _sender: relm4::ComponentSender<Self>) -> Self {
+        Self { system }
     }
 
     fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
         match msg {
             WindowAsyncHandlerMsg::CheckCache(syspkgs, userpkgs, _config) => {
                 info!("WindowAsyncHandlerMsg::CheckCache");
+                let system = self.system.clone();
                 relm4::spawn(async move {
                     let mut recpicks = vec![];
                     let mut catpicks: HashMap<PkgCategory, Vec<String>> = HashMap::new();
@@ -48,7 +53,7 @@ impl Worker for WindowAsyncHandler {
                             Err(e) => {
                                 error!("Error getting NixOS pkgs: {}", e);
                                 let _ = sender.output(AppMsg::LoadError(
-                                    String::from("Error retrieving NixOS package database"),
+                                    gettext("Error retrieving NixOS package database"),
                                     e.to_string(),
                                 ));
                                 return;
@@ -60,7 +65,7 @@ impl Worker for WindowAsyncHandler {
                             Err(e) => {
                                 error!("Error getting nixpkgs: {}", e);
                                 let _ = sender.output(AppMsg::LoadError(
-                                    String::from("Error retrieving nixpkgs package database"),
+                                    gettext("Error retrieving nixpkgs package database"),
                                     e.to_string(),
                                 ));
                                 return;
@@ -73,7 +78,7 @@ impl Worker for WindowAsyncHandler {
                         Err(e) => {
                             error!("Error connecting to pkgdb: {}", e);
                             let _ = sender.output(AppMsg::LoadError(
-                                String::from("Error connecting to package database"),
+                                gettext("Error connecting to package database"),
                                 e.to_string(),
                             ));
                             return;
@@ -88,7 +93,7 @@ impl Worker for WindowAsyncHandler {
                                 None
                             }
                         }
-                        UserPkgs::Env => None,
+                        UserPkgs::Env | UserPkgs::HomeManager => None,
                     };
 
                     let systemdb = match syspkgs {
@@ -117,7 +122,7 @@ impl Worker for WindowAsyncHandler {
                         Err(e) => {
                             error!("Error getting pkglist: {}", e);
                             let _ = sender.output(AppMsg::LoadError(
-                                String::from("Malformed package database"),
+                                gettext("Malformed package database"),
                                 e.to_string(),
                             ));
                             return;
@@ -135,23 +140,15 @@ impl Worker for WindowAsyncHandler {
                             Err(e) => {
                                 error!("Error getting package metadata: {}", e);
                                 let _ = sender.output(AppMsg::LoadError(
-                                    String::from("Malformed package database"),
+                                    gettext("Malformed package database"),
                                     e.to_string(),
                                 ));
                                 return;
                             }
                         };
-                    let appdata = match appsteamdata() {
-                        Ok(x) => x,
-                        Err(e) => {
-                            error!("Error getting appdata: {}", e);
-                            let _ = sender.output(AppMsg::LoadError(
-                                String::from("Error retrieving appstream data"),
-                                e.to_string(),
-                            ));
-                            return;
-                        }
-                    };
+                    // Missing AppStream data only leaves apps without icons and screenshots
+                    let appstream = appstream::load(&system);
+                    let appdata = appstream.apps;
                     let desktopenv = env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
 
                     let mut recpkgs = pkglist
@@ -438,13 +435,23 @@ impl Worker for WindowAsyncHandler {
                     recpicks.shuffle(&mut rng);
 
                     sender.output(AppMsg::Initialize(
-                        pkgdb, nixpkgsdb, systemdb, appdata, recpicks, catpicks, catpkgs,
+                        pkgdb,
+                        nixpkgsdb,
+                        systemdb,
+                        Arc::new(appdata),
+                        appstream.key,
+                        appstream.sources,
+                        recpicks,
+                        catpicks,
+                        catpkgs,
                     ));
                 });
             }
             WindowAsyncHandlerMsg::UpdateDB(syspkgs, userpkgs) => {
+                let system = self.system.clone();
                 relm4::spawn(async move {
                     let nixos = Path::new("/etc/NIXOS").exists();
+                    flakes::refresh(&system, true).await;
 
                     let _pkgdb = if nixos {
                         match nix_data::cache::nixos::nixospkgs().await {
@@ -452,7 +459,7 @@ impl Worker for WindowAsyncHandler {
                             Err(e) => {
                                 error!("Error getting NixOS pkgs: {}", e);
                                 sender.output(AppMsg::LoadError(
-                                    String::from("Error retrieving NixOS package database"),
+                                    gettext("Error retrieving NixOS package database"),
                                     e.to_string(),
                                 ));
                                 return;
@@ -464,7 +471,7 @@ impl Worker for WindowAsyncHandler {
                             Err(e) => {
                                 error!("Error getting nixpkgs: {}", e);
                                 sender.output(AppMsg::LoadError(
-                                    String::from("Error retrieving nixpkgs package database"),
+                                    gettext("Error retrieving nixpkgs package database"),
                                     e.to_string(),
                                 ));
                                 return;
@@ -480,7 +487,7 @@ impl Worker for WindowAsyncHandler {
                                 None
                             }
                         }
-                        UserPkgs::Env => None,
+                        UserPkgs::Env | UserPkgs::HomeManager => None,
                     };
 
                     let _systemdb = match syspkgs {
@@ -537,9 +544,9 @@ impl SimpleComponent for LoadErrorModel {
             set_secondary_text: Some(&model.msg2),
             set_use_markup: true,
             set_secondary_use_markup: true,
-            add_button: ("Retry", gtk::ResponseType::Accept),
+            add_button: (&gettext("Retry"), gtk::ResponseType::Accept),
             // add_button: ("Preferences", gtk::ResponseType::Help),
-            add_button: ("Quit", gtk::ResponseType::Close),
+            add_button: (&gettext("Quit"), gtk::ResponseType::Close),
             connect_response[sender] => move |_, resp| {
                 sender.input(match resp {
                     gtk::ResponseType::Accept => LoadErrorMsg::Retry,